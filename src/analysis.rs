@@ -0,0 +1,335 @@
+//! Evaluating the positions of a game in the background (e.g. for an
+//! evaluation bar)
+
+use crate::game::{san_line, BitMove, Board};
+use crate::proto::{ChessRequest, ChessUpdate, Evaluation, Score, SearchLimits};
+use crate::search::{cancellable_search, principal_variation};
+use crate::Player;
+use pleco::core::score::MATE_IN_MAX_PLY;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Instant;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task;
+
+/// Moves of the expected continuation
+pub const LINE_LENGTH: usize = 4;
+
+/// Deep enough to be useful while keeping the tablet responsive
+pub fn default_limits() -> SearchLimits {
+    SearchLimits {
+        movetime: Some(3000),
+        ..SearchLimits::depth(5)
+    }
+}
+
+/// Score and expected continuation. Mates are only noticed one ply after
+/// the mating move, so a mate found at `depth` is `depth - 1` plies away
+/// (at most). `None` if the game is over.
+pub fn evaluate(board: &Board, depth: u16) -> Option<(Score, Vec<BitMove>)> {
+    evaluate_unless_cancelled(board, depth, &AtomicBool::new(false))
+}
+
+/// `evaluate()` that gives up (with `None`) as soon as `cancelled` is set
+fn evaluate_unless_cancelled(
+    board: &Board,
+    depth: u16,
+    cancelled: &AtomicBool,
+) -> Option<(Score, Vec<BitMove>)> {
+    if board.generate_moves().is_empty() {
+        return None;
+    }
+    let depth = depth.max(1);
+    let search = |board: Board, depth: u16| cancellable_search(board, depth, false, cancelled);
+    let best = search(board.shallow_clone(), depth)?;
+    let line = principal_variation(board, best.bit_move, depth, LINE_LENGTH, |board, depth| {
+        search(board, depth).map_or_else(BitMove::null, |reply| reply.bit_move)
+    });
+    if cancelled.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    Some((white_score(board, best.score, depth), line))
+}
+
+/// `score` of a search `depth` deep for the side to move, from White's view
+/// (a mate counted as far away as `evaluate()` does)
+pub(crate) fn white_score(board: &Board, score: i16, depth: u16) -> Score {
+    let white_view = match Player::from(board.turn()) {
+        Player::White => score as i32,
+        Player::Black => -(score as i32),
+    };
+    if (score as i32).abs() >= MATE_IN_MAX_PLY {
+        let moves = depth as i32 / 2;
+        Score::Mate(if white_view > 0 { moves } else { -moves })
+    } else {
+        Score::Centipawns(white_view)
+    }
+}
+
+/// Reports an evaluation for one depth after another (so mates are found
+/// at their actual distance) until `limits` are reached or `cancelled` is
+/// set. A cancelled depth is given up at once.
+pub fn analyse(
+    board: &Board,
+    limits: SearchLimits,
+    cancelled: &AtomicBool,
+    mut report: impl FnMut(Evaluation),
+) {
+    let started = Instant::now();
+    let deadline = limits.budget(None).map(|budget| started + budget);
+    for depth in 1..=limits.depth.max(1) {
+        let depth_started = Instant::now();
+        let (score, line) = match evaluate_unless_cancelled(board, depth, cancelled) {
+            Some(evaluation) => evaluation,
+            None => return,
+        };
+        report(Evaluation::Search {
+            score,
+            depth,
+            line: san_line(board, &line),
+        });
+        if let Score::Mate(_) = score {
+            return; // Can't get more exact
+        }
+        if let Some(deadline) = deadline {
+            let next_depth_estimate =
+                depth_started.elapsed() * crate::search::DEPTH_GROWTH_ESTIMATE;
+            if Instant::now() + next_depth_estimate > deadline {
+                return;
+            }
+        }
+    }
+}
+
+/// Hand the first part to `proto::create_game()` as spectators. Every new
+/// position of the game gets analysed and the evaluations (including the
+/// verdicts of the game's tablebase) arrive on the receiver. An unfinished
+/// analysis is dropped when the next position arrives.
+pub fn create_analysis(
+    limits: SearchLimits,
+) -> (
+    (Sender<ChessUpdate>, Receiver<ChessRequest>),
+    Receiver<ChessUpdate>,
+) {
+    let (update_tx, mut update_rx) = channel::<ChessUpdate>(256);
+    // Never sends anything
+    let (_, request_rx) = channel::<ChessRequest>(1);
+    let (evaluation_tx, evaluation_rx) = channel::<ChessUpdate>(256);
+
+    // One worker analyses the positions one after another. Every position
+    // cancels the one before, so skipping through a game leaves no pile of
+    // searches behind.
+    let (position_tx, position_rx) = mpsc::channel::<(Board, Arc<AtomicBool>)>();
+    let worker_tx = evaluation_tx.clone();
+    task::spawn_blocking(move || {
+        for (board, cancelled) in position_rx {
+            analyse(&board, limits, &cancelled, |evaluation| {
+                worker_tx
+                    .blocking_send(ChessUpdate::Evaluation { evaluation })
+                    .ok();
+            });
+        }
+    });
+
+    task::spawn(async move {
+        let mut cancelled = Arc::new(AtomicBool::new(false));
+        while let Some(update) = update_rx.recv().await {
+            match update {
+                ChessUpdate::PlayerSwitch { ref fen, .. } => {
+                    cancelled.store(true, Ordering::Relaxed);
+                    let board = match Board::from_fen(fen) {
+                        Ok(board) => board,
+                        Err(e) => {
+                            warn!("Analysis failed to parse {}: {:?}", fen, e);
+                            continue;
+                        }
+                    };
+                    cancelled = Arc::new(AtomicBool::new(false));
+                    position_tx.send((board, cancelled.clone())).ok();
+                }
+                ChessUpdate::Outcome { outcome: Some(_) } => {
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+                evaluation @ ChessUpdate::Evaluation { .. } => {
+                    evaluation_tx.send(evaluation).await.ok();
+                }
+                _ => {}
+            }
+        }
+        // Game over (dropping the sender ends the worker)
+        cancelled.store(true, Ordering::Relaxed);
+    });
+
+    ((update_tx, request_rx), evaluation_rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    const SCHOLARS_MATE_FEN: &str =
+        "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+    const FOOLS_MATE_FEN: &str = "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2";
+
+    fn evaluations(fen: &str, limits: SearchLimits) -> Vec<Evaluation> {
+        let mut evaluations = vec![];
+        analyse(
+            &Board::from_fen(fen).unwrap(),
+            limits,
+            &AtomicBool::new(false),
+            |evaluation| evaluations.push(evaluation),
+        );
+        evaluations
+    }
+
+    #[test]
+    fn finds_mate_for_either_side() {
+        assert_eq!(
+            vec![Evaluation::Search {
+                score: Score::Mate(1),
+                depth: 2,
+                line: vec!["Qxf7#".to_owned()],
+            }]
+            .as_slice(),
+            &evaluations(SCHOLARS_MATE_FEN, SearchLimits::depth(3))[1..]
+        );
+        match evaluations(FOOLS_MATE_FEN, SearchLimits::depth(3)).last() {
+            Some(Evaluation::Search { score, line, .. }) => {
+                assert_eq!(*score, Score::Mate(-1));
+                assert_eq!(line[0], "Qh4#");
+            }
+            evaluation => panic!("Unexpected {:?}", evaluation),
+        }
+    }
+
+    #[test]
+    fn scores_from_whites_view() {
+        // Black is a queen down
+        let fen = "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1";
+        let evaluations = evaluations(fen, SearchLimits::depth(2));
+        assert_eq!(evaluations.len(), 2);
+        match evaluations[1] {
+            Evaluation::Search {
+                score: Score::Centipawns(centipawns),
+                depth: 2,
+                ref line,
+            } => {
+                assert!(centipawns > 500, "Score {}", centipawns);
+                assert_eq!(line.len(), LINE_LENGTH);
+            }
+            ref evaluation => panic!("Unexpected {:?}", evaluation),
+        }
+        assert_eq!(Score::Centipawns(-35).to_string(), "-0.35");
+        assert_eq!(Score::Mate(-2).to_string(), "#-2");
+    }
+
+    #[test]
+    fn game_over_has_no_evaluation() {
+        let checkmated = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+        assert!(evaluations(checkmated, SearchLimits::depth(2)).is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn analyses_every_new_position() {
+        let ((updates, _requests), mut evaluations) = create_analysis(SearchLimits::depth(2));
+        let fen = |fen: &str| ChessUpdate::PlayerSwitch {
+            player: Player::White,
+            fen: fen.to_owned(),
+        };
+        updates.send(fen(SCHOLARS_MATE_FEN)).await.unwrap();
+        // Depth 1 doesn't see the mate yet
+        let mut evaluation = None;
+        for _ in 0..2 {
+            evaluation = timeout(Duration::from_secs(5), evaluations.recv())
+                .await
+                .unwrap();
+        }
+        let evaluation = evaluation.unwrap();
+        assert!(
+            matches!(
+                evaluation,
+                ChessUpdate::Evaluation {
+                    evaluation: Evaluation::Search {
+                        score: Score::Mate(1),
+                        ..
+                    }
+                }
+            ),
+            "Unexpected {:?}",
+            evaluation
+        );
+
+        updates
+            .send(fen(
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            ))
+            .await
+            .unwrap();
+        for depth in 1..=2 {
+            let evaluation = timeout(Duration::from_secs(5), evaluations.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(
+                matches!(
+                    evaluation,
+                    ChessUpdate::Evaluation {
+                        evaluation: Evaluation::Search {
+                            score: Score::Centipawns(_),
+                            depth: reached,
+                            ..
+                        }
+                    } if reached == depth
+                ),
+                "Unexpected {:?}",
+                evaluation
+            );
+        }
+
+        // Done with the game
+        drop(updates);
+        assert!(timeout(Duration::from_secs(5), evaluations.recv())
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    async fn next_evaluation(evaluations: &mut Receiver<ChessUpdate>) -> Option<ChessUpdate> {
+        timeout(Duration::from_secs(5), evaluations.recv())
+            .await
+            .expect("No evaluation in time")
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn new_position_cancels_the_running_depth() {
+        // Would search for ages without being cancelled
+        let ((updates, _requests), mut evaluations) = create_analysis(SearchLimits::depth(30));
+        let fen = |fen: &str| ChessUpdate::PlayerSwitch {
+            player: Player::White,
+            fen: fen.to_owned(),
+        };
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+        updates.send(fen(start)).await.unwrap();
+        next_evaluation(&mut evaluations).await.unwrap();
+        updates.send(fen(SCHOLARS_MATE_FEN)).await.unwrap();
+        // Skips older depths of the start position
+        while !matches!(
+            next_evaluation(&mut evaluations).await.unwrap(),
+            ChessUpdate::Evaluation {
+                evaluation: Evaluation::Search {
+                    score: Score::Mate(1),
+                    ..
+                }
+            }
+        ) {}
+
+        updates.send(fen(start)).await.unwrap();
+        next_evaluation(&mut evaluations).await.unwrap();
+        drop(updates);
+        while next_evaluation(&mut evaluations).await.is_some() {}
+    }
+}
@@ -0,0 +1,371 @@
+//! Post-game analysis marking the inaccuracies, mistakes and blunders of a
+//! finished game in PGN
+
+use crate::analysis::analyse;
+use crate::game::{san_line, BitMove, Board, ChessOutcome};
+use crate::pgn::{find_move, from_moves, move_number, result, FEN_TAG};
+use crate::proto::{Evaluation, Score, SearchLimits};
+use crate::Player;
+use anyhow::Result;
+use chess_pgn_parser::{AnnotationSymbol, Game, GameMove, NAG};
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+
+/// Scores from here on (and mates) count as won. Giving away some of a won
+/// advantage is no mistake.
+const WON: i32 = 1000;
+
+/// How bad a move is compared to the best one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    /// The judgement of a move that gives away `loss` centipawns
+    pub fn of_loss(loss: i32) -> Option<Self> {
+        if loss >= 300 {
+            Some(Judgement::Blunder)
+        } else if loss >= 100 {
+            Some(Judgement::Mistake)
+        } else if loss >= 50 {
+            Some(Judgement::Inaccuracy)
+        } else {
+            None
+        }
+    }
+
+    pub fn annotation_symbol(self) -> AnnotationSymbol {
+        match self {
+            Judgement::Inaccuracy => AnnotationSymbol::Dubious,
+            Judgement::Mistake => AnnotationSymbol::Mistake,
+            Judgement::Blunder => AnnotationSymbol::Blunder,
+        }
+    }
+}
+
+impl fmt::Display for Judgement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Judgement::Inaccuracy => write!(f, "Inaccuracy"),
+            Judgement::Mistake => write!(f, "Mistake"),
+            Judgement::Blunder => write!(f, "Blunder"),
+        }
+    }
+}
+
+/// 4 plies or a second per position
+pub fn default_limits() -> SearchLimits {
+    SearchLimits {
+        movetime: Some(1000),
+        ..SearchLimits::depth(4)
+    }
+}
+
+/// Evaluates every position of a finished game within `limits` and marks
+/// the inaccuracies, mistakes and blunders with an annotation symbol and
+/// the better move. Every move gets the evaluation as comment and a NAG
+/// telling who stands better. Annotations already in the game are kept.
+pub fn annotate_game(game: &Game, limits: SearchLimits) -> Result<Game> {
+    let mut board = match game.tags.iter().find(|(name, _)| name == FEN_TAG) {
+        Some((_, fen)) => {
+            Board::from_fen(fen).map_err(|e| anyhow!("Invalid FEN tag {:?}: {:?}", fen, e))?
+        }
+        None => Board::default(),
+    };
+    let mut boards = vec![board.shallow_clone()];
+    let mut bit_moves = vec![];
+    for (index, game_move) in game.moves.iter().enumerate() {
+        let bit_move = find_move(&board, &game_move.move_.move_).ok_or_else(|| {
+            anyhow!(
+                "Move {} ({:?}) is not legal in {}",
+                index + 1,
+                game_move.move_.move_,
+                board.fen()
+            )
+        })?;
+        board.apply_move(bit_move);
+        boards.push(board.shallow_clone());
+        bit_moves.push(bit_move);
+    }
+    let mut moves: Vec<GameMove> = Vec::with_capacity(game.moves.len());
+    for (index, game_move) in game.moves.iter().enumerate() {
+        let (board, after) = (&boards[index], &boards[index + 1]);
+        let mover = Player::from(board.turn());
+        let played = san_line(board, &bit_moves[index..=index]).pop();
+        // A move was played, so the game wasn't over
+        let best = match search(board, limits) {
+            Some(best) => best,
+            None => bail!("Move {} was played after the game ended", index + 1),
+        };
+        let score_after = if game_over(after) {
+            None
+        } else if best.line.first() == played.as_ref() {
+            Some(after_move(best.score, mover))
+        } else {
+            // As deep as the best move for a fair comparison
+            search(after, SearchLimits::depth(best.depth.max(2) - 1)).map(|played| played.score)
+        };
+        let value_after = match score_after {
+            Some(score) => value(score),
+            None => game_over_value(after),
+        };
+        let loss = match mover {
+            Player::White => value(best.score) - value_after,
+            Player::Black => value_after - value(best.score),
+        };
+        let judgement = if best.line.first() == played.as_ref() {
+            None
+        } else {
+            Judgement::of_loss(loss)
+        };
+
+        let mut marked = game_move.move_.clone();
+        if marked.annotation_symbol.is_none() {
+            marked.annotation_symbol = judgement.map(Judgement::annotation_symbol);
+        }
+        let nag = game_move
+            .nag
+            .clone()
+            .or_else(|| score_after.map(assessment));
+        let mut comment: Vec<String> = game_move.comment.iter().cloned().collect();
+        if let Some(score) = score_after {
+            comment.push(eval_comment(score));
+        }
+        if let (Some(judgement), Some(best)) = (judgement, best.line.first()) {
+            comment.push(format!("{}. {} was best.", judgement, best));
+        }
+
+        let interrupted = match moves.last() {
+            Some(previous) => previous.comment.is_some() || !previous.variations.is_empty(),
+            None => true,
+        };
+        moves.push(GameMove {
+            number: move_number(board, interrupted),
+            move_: marked,
+            nag,
+            comment: if comment.is_empty() {
+                None
+            } else {
+                Some(comment.join(" "))
+            },
+            variations: game_move.variations.clone(),
+        });
+    }
+
+    let mut tags = game.tags.clone();
+    if !tags.iter().any(|(name, _)| name == "Annotator") {
+        tags.push(("Annotator".to_owned(), "chessmarkable".to_owned()));
+    }
+    Ok(Game {
+        tags,
+        comment: game.comment.clone(),
+        moves,
+        termination: game.termination,
+    })
+}
+
+/// Like `annotate_game()` for the moves of a game played from
+/// `starting_fen` (or the usual starting position)
+pub fn annotate_moves(
+    starting_fen: Option<&str>,
+    moves: &[BitMove],
+    outcome: Option<ChessOutcome>,
+    limits: SearchLimits,
+) -> Result<Game> {
+    annotate_game(&from_moves(starting_fen, moves, result(outcome))?, limits)
+}
+
+struct SearchResult {
+    score: Score,
+    depth: u16,
+    /// In SAN
+    line: Vec<String>,
+}
+
+/// The deepest evaluation `analyse()` reaches. `None` if the game is over.
+fn search(board: &Board, limits: SearchLimits) -> Option<SearchResult> {
+    let mut deepest = None;
+    analyse(board, limits, &AtomicBool::new(false), |evaluation| {
+        deepest = Some(evaluation)
+    });
+    match deepest {
+        Some(Evaluation::Search { score, depth, line }) => {
+            Some(SearchResult { score, depth, line })
+        }
+        _ => None,
+    }
+}
+
+fn game_over(board: &Board) -> bool {
+    board.generate_moves().is_empty()
+}
+
+/// Centipawns from White's view (limited to `WON`) for comparing
+fn value(score: Score) -> i32 {
+    match score {
+        Score::Centipawns(centipawns) => centipawns.clamp(-WON, WON),
+        Score::Mate(moves) if moves > 0 => WON,
+        Score::Mate(_) => -WON,
+    }
+}
+
+fn game_over_value(board: &Board) -> i32 {
+    match (board.checkmate(), Player::from(board.turn())) {
+        (true, Player::White) => -WON,
+        (true, Player::Black) => WON,
+        (false, _) => 0,
+    }
+}
+
+/// The score of the position after `mover` played the best move
+fn after_move(score: Score, mover: Player) -> Score {
+    match (score, mover) {
+        // One move closer to mating
+        (Score::Mate(moves), Player::White) if moves > 1 => Score::Mate(moves - 1),
+        (Score::Mate(moves), Player::Black) if moves < -1 => Score::Mate(moves + 1),
+        _ => score,
+    }
+}
+
+/// NAG for who stands better by how much
+fn assessment(score: Score) -> NAG {
+    let centipawns = value(score);
+    let white_ahead = centipawns > 0;
+    NAG(match centipawns.abs() {
+        0..=29 => 10,
+        30..=99 if white_ahead => 14,
+        30..=99 => 15,
+        100..=299 if white_ahead => 16,
+        100..=299 => 17,
+        _ if white_ahead => 18,
+        _ => 19,
+    })
+}
+
+/// As understood by most chess programs (e.g. "[%eval -1.25]" or
+/// "[%eval #3]")
+fn eval_comment(score: Score) -> String {
+    match score {
+        Score::Centipawns(centipawns) => {
+            format!("[%eval {:.2}]", centipawns as f32 / 100.0)
+        }
+        Score::Mate(moves) => format!("[%eval #{}]", moves),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{move_to, ChessGame};
+    use crate::pgn;
+    use chess_pgn_parser::{read_games, GameTermination, MoveNumber};
+
+    fn limits() -> SearchLimits {
+        SearchLimits::depth(3)
+    }
+
+    fn annotated(pgn: &str) -> Game {
+        annotate_game(&read_games(pgn).unwrap().remove(0), limits()).unwrap()
+    }
+
+    #[test]
+    fn marks_blunders_with_the_better_move() {
+        // Black ignores the mate threat
+        let game = annotated("1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0");
+        let blunder = &game.moves[5];
+        assert_eq!(
+            blunder.move_.annotation_symbol,
+            Some(AnnotationSymbol::Blunder)
+        );
+        let comment = blunder.comment.as_ref().unwrap();
+        assert!(comment.starts_with("[%eval #1] Blunder."), "{}", comment);
+        assert_eq!(blunder.nag, Some(NAG(18)));
+        // The mate itself is best
+        assert_eq!(game.moves[6].move_.annotation_symbol, None);
+        assert_eq!(game.moves[6].comment, None);
+        assert_eq!(game.moves[0].number, Some(MoveNumber::White(1)));
+        assert!(game
+            .tags
+            .contains(&("Annotator".to_owned(), "chessmarkable".to_owned())));
+
+        // Can be read again
+        let written = pgn::write(&game);
+        assert!(
+            written.contains("3... Nf6?? $18 {[%eval #1] Blunder."),
+            "{}",
+            written
+        );
+        assert_eq!(read_games(&written).unwrap(), vec![game]);
+    }
+
+    #[test]
+    fn keeps_existing_annotations() {
+        let game = annotated("1. e4 $1 {Best by test} e5 2. Bc4 Nc6 3. Qh5 Nf6! 4. Qxf7# 1-0");
+        assert_eq!(game.moves[0].nag, Some(NAG(1)));
+        assert!(game.moves[0]
+            .comment
+            .as_ref()
+            .unwrap()
+            .starts_with("Best by test [%eval"));
+        assert_eq!(game.moves[1].number, Some(MoveNumber::Black(1)));
+        assert_eq!(
+            game.moves[5].move_.annotation_symbol,
+            Some(AnnotationSymbol::Good)
+        );
+    }
+
+    #[test]
+    fn annotates_played_moves() {
+        // Fool's mate recovered from the positions (like a frontend would)
+        let mut game = ChessGame::default();
+        let mut positions = vec![game.fen()];
+        for uci in &["f2f3", "e7e5", "g2g4", "d8h4"] {
+            game.move_piece_uci(uci).unwrap();
+            positions.push(game.fen());
+        }
+        let mut board = Board::default();
+        let mut moves = vec![];
+        for fen in &positions[1..] {
+            let bit_move = move_to(&board, fen).unwrap();
+            board.apply_move(bit_move);
+            moves.push(bit_move);
+        }
+        assert_eq!(board.fen(), game.fen());
+
+        let annotated = annotate_moves(None, &moves, game.outcome(), limits()).unwrap();
+        assert_eq!(annotated.termination, GameTermination::BlackWins);
+        assert!(annotated
+            .tags
+            .contains(&("Result".to_owned(), "0-1".to_owned())));
+        assert_eq!(
+            annotated.moves[2].move_.annotation_symbol,
+            Some(AnnotationSymbol::Blunder)
+        );
+        assert!(annotated.moves[3].move_.is_checkmate);
+        let written = pgn::write(&annotated);
+        assert!(written.contains("2. g4?? $19"), "{}", written);
+        assert!(written.contains("{[%eval #-1] Blunder."), "{}", written);
+        assert!(written.ends_with("2... Qh4# 0-1\n"), "{}", written);
+    }
+
+    #[test]
+    fn illegal_moves_are_an_error() {
+        let game = read_games("1. e4 e5 2. Ke3 *").unwrap().remove(0);
+        assert!(annotate_game(&game, limits()).is_err());
+        assert!(annotate_moves(Some("not a position"), &[], None, limits()).is_err());
+    }
+
+    #[test]
+    fn judges_by_lost_centipawns() {
+        assert_eq!(Judgement::of_loss(30), None);
+        assert_eq!(Judgement::of_loss(60), Some(Judgement::Inaccuracy));
+        assert_eq!(Judgement::of_loss(150), Some(Judgement::Mistake));
+        assert_eq!(Judgement::of_loss(2000), Some(Judgement::Blunder));
+        assert_eq!(assessment(Score::Centipawns(-120)), NAG(17));
+        assert_eq!(assessment(Score::Centipawns(5)), NAG(10));
+        assert_eq!(eval_comment(Score::Centipawns(-125)), "[%eval -1.25]");
+    }
+}
@@ -0,0 +1,112 @@
+#[macro_use]
+extern crate log;
+
+use anyhow::{Context, Result};
+use chessmarkable::pgn;
+use chessmarkable::tournament::{play_match, read_openings, MatchConfig, Standings};
+use clap::Parser;
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Plays a match between two bots (built-in searchers or UCI engines)
+/// and prints the results with the elo difference.
+#[derive(Parser)]
+#[clap(author, version)]
+struct Opts {
+    #[clap(help = "YAML file with the contenders, games, openings and adjudication")]
+    config: PathBuf,
+    #[clap(long, short = 'n', help = "Number of games (overrides the config)")]
+    games: Option<u32>,
+    #[clap(
+        long,
+        short = 'o',
+        help = "File with a starting position (FEN or EPD) per line (overrides the config)"
+    )]
+    openings: Option<PathBuf>,
+    #[clap(long, short = 'p', help = "Write the games to this PGN file")]
+    pgn: Option<PathBuf>,
+}
+
+fn read_config(opts: &Opts) -> Result<MatchConfig> {
+    let file = File::open(&opts.config).context("Open config")?;
+    let mut config: MatchConfig = serde_yaml::from_reader(file).context("Deserialize config")?;
+    if let Some(games) = opts.games {
+        config.games = games;
+    }
+    if let Some(ref path) = opts.openings {
+        let text = std::fs::read_to_string(path).context("Read openings")?;
+        config.openings = read_openings(&text)?;
+    }
+    Ok(config)
+}
+
+fn main() {
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "WARN");
+    }
+    env_logger::init();
+    let opts = Opts::parse();
+
+    let config = match read_config(&opts) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Failed to read match {:?}: {:?}", opts.config, err);
+            std::process::exit(2);
+        }
+    };
+    let mut pgn_file = match opts.pgn {
+        Some(ref path) => match File::create(path) {
+            Ok(file) => Some(file),
+            Err(err) => {
+                error!("Failed to create {:?}: {:?}", path, err);
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to create tokio runtime");
+    let played = runtime.block_on(play_match(&config, |game| {
+        let (white, black) = if game.first_is_white {
+            (&config.first.name, &config.second.name)
+        } else {
+            (&config.second.name, &config.first.name)
+        };
+        println!(
+            "Game {}/{}: {} - {} {} ({})",
+            game.round,
+            config.games,
+            white,
+            black,
+            pgn::termination(game.ending.result()),
+            game.ending.reason
+        );
+        // Written at once to keep finished games should the match fail
+        if let Some(ref mut pgn_file) = pgn_file {
+            if let Err(err) = writeln!(pgn_file, "{}", pgn::write(&game.pgn)) {
+                error!("Failed to write game {}: {:?}", game.round, err);
+            }
+        }
+    }));
+    match played {
+        Ok(tally) => {
+            println!();
+            println!(
+                "{}",
+                Standings {
+                    config: &config,
+                    tally,
+                }
+            );
+        }
+        Err(err) => {
+            println!("Match failed: {:#}", err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+#[macro_use]
+extern crate log;
+
+use chessmarkable::event_log;
+use clap::Parser;
+use std::env;
+
+/// Replays an event log (see `--event-log` of chessmarkable) against a
+/// new game and checks that it produces the same updates.
+#[derive(Parser)]
+#[clap(author, version)]
+struct Opts {
+    #[clap(help = "Event log file with one JSON entry per line")]
+    event_log: std::path::PathBuf,
+}
+
+fn main() {
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "WARN");
+    }
+    env_logger::init();
+    let opts = Opts::parse();
+
+    let entries = match event_log::read(&opts.event_log) {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!("Failed to read event log {:?}: {:?}", opts.event_log, err);
+            std::process::exit(2);
+        }
+    };
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_time()
+        .build()
+        .expect("Failed to create tokio runtime");
+    match runtime.block_on(event_log::verify(&entries)) {
+        Ok(report) => {
+            println!(
+                "Reproduced: {} requests resulted in the same {} updates.",
+                report.requests, report.updates
+            );
+        }
+        Err(err) => {
+            println!("Not reproduced: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
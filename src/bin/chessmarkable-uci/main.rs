@@ -0,0 +1,504 @@
+#[macro_use]
+extern crate anyhow;
+#[macro_use]
+extern crate log;
+
+use anyhow::{Context, Result};
+use chessmarkable::game::ChessGame;
+use chessmarkable::proto::BotDifficulty;
+use chessmarkable::search::{cancellable_best_move, clock_budget, deepen};
+use clap::Parser;
+use pleco::BitMove;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Depth limit of "go infinite"
+const MAX_DEPTH: u16 = 30;
+
+/// Plays the difficulty presets of the tablet's bots (see `create_bot`) over
+/// the Universal Chess Interface on stdin/stdout. It searches with
+/// `search::cancellable_best_move` instead of pleco's searchers, so "stop"
+/// takes effect at once.
+#[derive(Parser)]
+#[clap(author, version)]
+struct Opts {
+    #[clap(
+        long,
+        short = 'd',
+        default_value = "Normal",
+        help = "Initial difficulty (Easy, Normal or Hard). Can be changed with the UCI option."
+    )]
+    difficulty: BotDifficulty,
+}
+
+enum Event {
+    Command(String),
+    /// A search finished another depth
+    Iteration {
+        search_id: u32,
+        depth: u16,
+        best_move: BitMove,
+        elapsed: Duration,
+    },
+    SearchDone {
+        search_id: u32,
+    },
+}
+
+#[derive(Default)]
+struct GoLimits {
+    depth: Option<u16>,
+    movetime: Option<u64>,
+    time: Option<u64>,
+    increment: u64,
+    moves_to_go: Option<u64>,
+    infinite: bool,
+}
+
+impl GoLimits {
+    fn parse(arguments: &[&str], white_to_move: bool) -> Self {
+        let mut limits = GoLimits::default();
+        let mut arguments = arguments.iter();
+        while let Some(argument) = arguments.next() {
+            let mut value = || arguments.next().and_then(|value| value.parse::<u64>().ok());
+            match *argument {
+                "depth" => limits.depth = value().map(|depth| depth as u16),
+                "movetime" => limits.movetime = value(),
+                "wtime" if white_to_move => limits.time = value(),
+                "btime" if !white_to_move => limits.time = value(),
+                "winc" if white_to_move => limits.increment = value().unwrap_or(0),
+                "binc" if !white_to_move => limits.increment = value().unwrap_or(0),
+                "movestogo" => limits.moves_to_go = value(),
+                "infinite" => limits.infinite = true,
+                _ => {} // E.g. the value of the other side's clock
+            }
+        }
+        limits
+    }
+
+    /// Thinking time for this move, if limited at all
+    fn budget(&self) -> Option<Duration> {
+        if let Some(movetime) = self.movetime {
+            return Some(Duration::from_millis(movetime));
+        }
+        let time = self.time?;
+        Some(clock_budget(time, self.increment, self.moves_to_go))
+    }
+}
+
+struct Search {
+    id: u32,
+    best_move: Option<BitMove>,
+    /// Only answer on "stop" (UCI demands that for "go infinite")
+    infinite: bool,
+    cancelled: Arc<AtomicBool>,
+}
+
+struct UciServer<W: Write> {
+    out: W,
+    events: Sender<Event>,
+    game: ChessGame,
+    difficulty: BotDifficulty,
+    /// Search the moves in parallel (see `cancellable_best_move`)
+    parallel: bool,
+    search: Option<Search>,
+    next_search_id: u32,
+}
+
+impl<W: Write> UciServer<W> {
+    fn new(out: W, events: Sender<Event>, difficulty: BotDifficulty) -> Self {
+        Self {
+            out,
+            events,
+            game: ChessGame::default(),
+            difficulty,
+            parallel: true,
+            search: None,
+            next_search_id: 0,
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        debug!("To GUI: {}", line);
+        writeln!(self.out, "{}", line).ok();
+        self.out.flush().ok();
+    }
+
+    /// Returns false on "quit"
+    fn handle(&mut self, event: Event) -> bool {
+        match event {
+            Event::Command(line) => {
+                debug!("From GUI: {}", line);
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                match tokens.first().copied() {
+                    Some("uci") => self.uci(),
+                    Some("isready") => self.send("readyok"),
+                    Some("ucinewgame") => self.game = ChessGame::default(),
+                    Some("setoption") => self.set_option(&tokens[1..]),
+                    Some("position") => {
+                        if let Err(e) = self.position(&tokens[1..]) {
+                            self.send(&format!("info string Invalid position: {:#}", e));
+                        }
+                    }
+                    Some("go") => self.go(&tokens[1..]),
+                    Some("stop") => self.finish_search(),
+                    Some("quit") => return false,
+                    _ => self.send(&format!("info string Unknown command: {}", line)),
+                }
+            }
+            Event::Iteration {
+                search_id,
+                depth,
+                best_move,
+                elapsed,
+            } => {
+                if let Some(ref mut search) = self.search {
+                    if search.id == search_id {
+                        search.best_move = Some(best_move);
+                        self.send(&format!(
+                            "info depth {} time {} pv {}",
+                            depth,
+                            elapsed.as_millis(),
+                            best_move.stringify()
+                        ));
+                    }
+                }
+            }
+            Event::SearchDone { search_id } => {
+                let is_current = self
+                    .search
+                    .as_ref()
+                    .is_some_and(|search| search.id == search_id && !search.infinite);
+                if is_current {
+                    self.finish_search();
+                }
+            }
+        }
+        true
+    }
+
+    fn uci(&mut self) {
+        self.send(&format!(
+            "id name chessMarkable {}",
+            env!("CARGO_PKG_VERSION")
+        ));
+        self.send(&format!("id author {}", env!("CARGO_PKG_AUTHORS")));
+        self.send(&format!(
+            "option name Difficulty type combo default {} var Easy var Normal var Hard",
+            self.difficulty
+        ));
+        self.send("option name ParallelSearch type check default true");
+        self.send("uciok");
+    }
+
+    fn set_option(&mut self, arguments: &[&str]) {
+        // setoption name <name> value <value> (names may contain spaces)
+        let line = arguments.join(" ");
+        let line = line.strip_prefix("name ").unwrap_or(&line);
+        let (name, value) = match line.find(" value ") {
+            Some(index) => (&line[..index], &line[index + " value ".len()..]),
+            None => (line, ""),
+        };
+        match (name.to_lowercase().as_str(), value.to_lowercase().as_str()) {
+            ("difficulty", difficulty) => match difficulty.parse() {
+                Ok(difficulty) => self.difficulty = difficulty,
+                Err(e) => self.send(&format!("info string {}", e)),
+            },
+            ("parallelsearch", "true") => self.parallel = true,
+            ("parallelsearch", "false") => self.parallel = false,
+            _ => self.send(&format!("info string Unsupported option: {}", line)),
+        }
+    }
+
+    fn position(&mut self, arguments: &[&str]) -> Result<()> {
+        let moves_index = arguments.iter().position(|argument| *argument == "moves");
+        let (setup, moves) = match moves_index {
+            Some(index) => (&arguments[..index], &arguments[index + 1..]),
+            None => (arguments, &[][..]),
+        };
+        let mut game = match setup.first().copied() {
+            Some("startpos") => ChessGame::default(),
+            Some("fen") => ChessGame::from_fen(&setup[1..].join(" "))?,
+            _ => bail!("Expected \"startpos\" or \"fen\""),
+        };
+        for uci_move in moves {
+            game.move_piece_uci(uci_move)?;
+        }
+        self.game = game;
+        Ok(())
+    }
+
+    fn go(&mut self, arguments: &[&str]) {
+        // Abandon a running search (the GUI should have sent "stop")
+        if let Some(search) = self.search.take() {
+            search.cancelled.store(true, Ordering::Relaxed);
+        }
+
+        let white_to_move = self.game.turn() == chessmarkable::Player::White;
+        let limits = GoLimits::parse(arguments, white_to_move);
+        // Without any limit, do exactly what the tablet bot does
+        let preset = self.difficulty.limits();
+        let max_depth = if limits.infinite {
+            MAX_DEPTH
+        } else {
+            limits.depth.unwrap_or(preset.depth)
+        };
+        let budget = match limits.budget() {
+            None if !limits.infinite && limits.depth.is_none() => preset.budget(None),
+            budget => budget,
+        };
+        let deadline = budget.map(|budget| Instant::now() + budget);
+        let iterate = limits.infinite || deadline.is_some();
+
+        let id = self.next_search_id;
+        self.next_search_id += 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.search = Some(Search {
+            id,
+            best_move: None,
+            infinite: limits.infinite,
+            cancelled: cancelled.clone(),
+        });
+
+        let board = self.game.board();
+        let parallel = self.parallel;
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let first_depth = if iterate { 1 } else { max_depth };
+            let mut stopped = false;
+            deepen(
+                &board,
+                first_depth..=max_depth,
+                deadline,
+                // A cancelled depth finds the null move
+                |board, depth| {
+                    cancellable_best_move(board, depth, parallel, &cancelled)
+                        .unwrap_or_else(BitMove::null)
+                },
+                |depth, best_move, elapsed| {
+                    let report = Event::Iteration {
+                        search_id: id,
+                        depth,
+                        best_move,
+                        elapsed,
+                    };
+                    // A cancelled depth found nothing worth reporting
+                    stopped = cancelled.load(Ordering::Relaxed) || events.send(report).is_err();
+                    !stopped
+                },
+            );
+            if !stopped {
+                events.send(Event::SearchDone { search_id: id }).ok();
+            }
+        });
+    }
+
+    fn finish_search(&mut self) {
+        let search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+        search.cancelled.store(true, Ordering::Relaxed);
+        match search.best_move {
+            Some(best_move) if !best_move.is_null() => {
+                self.send(&format!("bestmove {}", best_move.stringify()))
+            }
+            // Stopped before the first depth finished or no legal move
+            _ => match self.game.possible_moves().iter().next() {
+                Some(any_move) => self.send(&format!("bestmove {}", any_move.stringify())),
+                None => self.send("bestmove 0000"),
+            },
+        }
+    }
+}
+
+fn read_commands(events: Sender<Event>) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line.context("Read stdin") {
+            Ok(line) => line,
+            Err(e) => {
+                error!("{:?}", e);
+                break;
+            }
+        };
+        if events.send(Event::Command(line)).is_err() {
+            return;
+        }
+    }
+    events.send(Event::Command("quit".to_owned())).ok();
+}
+
+fn serve<W: Write>(server: &mut UciServer<W>, events: Receiver<Event>) {
+    for event in events {
+        if !server.handle(event) {
+            break;
+        }
+    }
+}
+
+fn main() {
+    // Logging goes to stderr which GUIs usually show or ignore
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "WARN");
+    }
+    env_logger::init();
+    let opts = Opts::parse();
+
+    let (events_tx, events_rx) = channel::<Event>();
+    let commands_tx = events_tx.clone();
+    thread::spawn(move || read_commands(commands_tx));
+
+    let mut server = UciServer::new(io::stdout(), events_tx, opts.difficulty);
+    serve(&mut server, events_rx);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    struct Session {
+        server: UciServer<Vec<u8>>,
+        events: Receiver<Event>,
+    }
+
+    impl Session {
+        fn new() -> Self {
+            let (events_tx, events) = channel::<Event>();
+            Self {
+                server: UciServer::new(vec![], events_tx, BotDifficulty::Normal),
+                events,
+            }
+        }
+
+        fn command(&mut self, line: &str) {
+            self.server.handle(Event::Command(line.to_owned()));
+        }
+
+        /// Handle search events until the predicate on the output is true
+        fn wait_until(&mut self, predicate: impl Fn(&[String]) -> bool) {
+            while !predicate(&self.output()) {
+                let event = self
+                    .events
+                    .recv_timeout(TIMEOUT)
+                    .expect("The search took too long");
+                self.server.handle(event);
+            }
+        }
+
+        fn wait_for_best_move(&mut self) -> String {
+            self.wait_until(|output| output.iter().any(|line| line.starts_with("bestmove")));
+            self.output()
+                .iter()
+                .find(|line| line.starts_with("bestmove"))
+                .unwrap()
+                .clone()
+        }
+
+        fn output(&self) -> Vec<String> {
+            String::from_utf8_lossy(&self.server.out)
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn handshake() {
+        let mut session = Session::new();
+        session.command("uci");
+        session.command("isready");
+        let output = session.output();
+        assert!(output[0].starts_with("id name chessMarkable"));
+        assert!(output.contains(
+            &"option name Difficulty type combo default Normal var Easy var Normal var Hard"
+                .to_owned()
+        ));
+        assert!(output.contains(&"option name ParallelSearch type check default true".to_owned()));
+        assert_eq!(output[output.len() - 2..], ["uciok", "readyok"]);
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        let mut session = Session::new();
+        session.command("position startpos moves e2e4 e7e5 f1c4 b8c6 d1h5 g8f6");
+        session.command("go depth 2");
+        assert_eq!(session.wait_for_best_move(), "bestmove h5f7");
+    }
+
+    #[test]
+    fn difficulty_decides_the_depth() {
+        let mut session = Session::new();
+        session.command("setoption name Difficulty value Easy");
+        session.command("setoption name ParallelSearch value false");
+        session.command("position fen rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+        session.command("go");
+        let best_move = session.wait_for_best_move();
+
+        let output = session.output();
+        // Deepens up to the preset depth within its time. Just like the bot
+        // on the tablet.
+        assert!(output[0].starts_with("info depth 1 "));
+        assert!(output[output.len() - 2].starts_with("info depth 2 "));
+        let mut game =
+            ChessGame::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1")
+                .unwrap();
+        game.move_piece_uci(best_move.trim_start_matches("bestmove "))
+            .expect("Best move is not legal");
+    }
+
+    #[test]
+    fn movetime_iterates_until_the_deadline() {
+        let mut session = Session::new();
+        session.command("position startpos");
+        let started = Instant::now();
+        session.command("go movetime 200 wtime 1000 btime 1000");
+        session.wait_for_best_move();
+        assert!(started.elapsed() < TIMEOUT);
+        assert!(session.output()[0].starts_with("info depth 1 "));
+    }
+
+    #[test]
+    fn infinite_search_answers_on_stop() {
+        let mut session = Session::new();
+        session.command("position startpos");
+        session.command("go infinite");
+        session.wait_until(|output| output.iter().any(|line| line.starts_with("info depth 2 ")));
+        assert!(!session
+            .output()
+            .iter()
+            .any(|line| line.starts_with("bestmove")));
+        session.command("stop");
+        assert!(session.output().last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn invalid_position_is_reported() {
+        let mut session = Session::new();
+        session.command("position startpos moves e2e5");
+        assert!(session.output()[0].starts_with("info string Invalid position"));
+    }
+
+    #[test]
+    fn clock_budget() {
+        let limits = GoLimits::parse(
+            &[
+                "wtime", "60000", "btime", "1000", "winc", "2000", "binc", "0",
+            ],
+            true,
+        );
+        assert_eq!(limits.budget(), Some(Duration::from_millis(3000)));
+        let limits = GoLimits::parse(&["wtime", "60000", "btime", "1000"], false);
+        assert_eq!(limits.budget(), Some(Duration::from_millis(33)));
+        let limits = GoLimits::parse(&["movetime", "500", "wtime", "60000"], true);
+        assert_eq!(limits.budget(), Some(Duration::from_millis(500)));
+        assert_eq!(GoLimits::parse(&["depth", "3"], true).budget(), None);
+    }
+}
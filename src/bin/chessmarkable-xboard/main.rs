@@ -0,0 +1,552 @@
+#[macro_use]
+extern crate log;
+
+use anyhow::Context;
+use chessmarkable::cecp::{find_coordinate_move, result};
+use chessmarkable::game::ChessGame;
+use chessmarkable::proto::BotDifficulty;
+use chessmarkable::search::{cancellable_best_move, clock_budget, deepen};
+use chessmarkable::Player;
+use clap::Parser;
+use pleco::BitMove;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Plays the same bots as the tablet (see `create_bot`) over the
+/// Chess Engine Communication Protocol (XBoard/WinBoard) on stdin/stdout.
+#[derive(Parser)]
+#[clap(author, version)]
+struct Opts {
+    #[clap(
+        long,
+        short = 'd',
+        default_value = "Normal",
+        help = "Difficulty (Easy, Normal or Hard). \"sd\" overrides its depth."
+    )]
+    difficulty: BotDifficulty,
+}
+
+enum Event {
+    Command(String),
+    /// A search finished another depth
+    Iteration {
+        search_id: u32,
+        depth: u16,
+        best_move: BitMove,
+        elapsed: Duration,
+    },
+    SearchDone {
+        search_id: u32,
+    },
+}
+
+/// Set with "level", "st" and "time"
+#[derive(Default)]
+struct TimeControl {
+    /// Moves per time control (0 for the whole game)
+    moves_per_session: u64,
+    increment: u64,
+    seconds_per_move: Option<u64>,
+    /// Our clock in milliseconds
+    time: Option<u64>,
+}
+
+impl TimeControl {
+    /// Thinking time for this move, if limited at all
+    fn budget(&self, moves_played: u64) -> Option<Duration> {
+        if let Some(seconds) = self.seconds_per_move {
+            return Some(Duration::from_secs(seconds));
+        }
+        let time = self.time?;
+        let moves_to_go = match self.moves_per_session {
+            0 => None,
+            session => Some(session - moves_played % session),
+        };
+        Some(clock_budget(time, self.increment, moves_to_go))
+    }
+}
+
+fn argument<T: FromStr>(tokens: &[&str], index: usize) -> Option<T> {
+    tokens.get(index).and_then(|value| value.parse().ok())
+}
+
+struct Search {
+    id: u32,
+    best_move: Option<BitMove>,
+    cancelled: Arc<AtomicBool>,
+}
+
+struct CecpServer<W: Write> {
+    out: W,
+    events: Sender<Event>,
+    game: ChessGame,
+    difficulty: BotDifficulty,
+    /// Side played by us (`None` in force mode)
+    engine_side: Option<Player>,
+    depth: Option<u16>,
+    time_control: TimeControl,
+    post: bool,
+    search: Option<Search>,
+    next_search_id: u32,
+}
+
+impl<W: Write> CecpServer<W> {
+    fn new(out: W, events: Sender<Event>, difficulty: BotDifficulty) -> Self {
+        Self {
+            out,
+            events,
+            game: ChessGame::default(),
+            difficulty,
+            engine_side: Some(Player::Black),
+            depth: None,
+            time_control: TimeControl::default(),
+            post: false,
+            search: None,
+            next_search_id: 0,
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        debug!("To GUI: {}", line);
+        writeln!(self.out, "{}", line).ok();
+        self.out.flush().ok();
+    }
+
+    /// Returns false on "quit"
+    fn handle(&mut self, event: Event) -> bool {
+        match event {
+            Event::Command(line) => {
+                debug!("From GUI: {}", line);
+                let tokens: Vec<&str> = line.split_whitespace().collect();
+                match tokens.first().copied() {
+                    Some("protover") => self.features(),
+                    Some("new") => {
+                        self.cancel_search();
+                        self.game = ChessGame::default();
+                        self.engine_side = Some(Player::Black);
+                        self.depth = None;
+                        self.time_control.seconds_per_move = None;
+                    }
+                    Some("force") => {
+                        self.cancel_search();
+                        self.engine_side = None;
+                    }
+                    Some("go") => {
+                        self.cancel_search();
+                        self.engine_side = Some(self.game.turn());
+                        self.think();
+                    }
+                    Some("playother") => {
+                        self.cancel_search();
+                        self.engine_side = Some(self.game.turn().other_player());
+                    }
+                    Some("usermove") => self.user_move(tokens.get(1).copied().unwrap_or("")),
+                    Some("setboard") => {
+                        self.cancel_search();
+                        match ChessGame::from_fen(&tokens[1..].join(" ")) {
+                            Ok(game) => self.game = game,
+                            Err(_) => self.send("tellusererror Illegal position"),
+                        }
+                    }
+                    Some("undo") => self.undo(1, &line),
+                    Some("remove") => self.undo(2, &line),
+                    Some("result") => {
+                        self.cancel_search();
+                        self.engine_side = None;
+                    }
+                    Some("level") => {
+                        self.time_control.moves_per_session = argument(&tokens, 1).unwrap_or(0);
+                        // Seconds of increment (the base time arrives with "time")
+                        self.time_control.increment = argument(&tokens, 3)
+                            .map(|seconds: f64| (seconds * 1000.0) as u64)
+                            .unwrap_or(0);
+                        self.time_control.seconds_per_move = None;
+                    }
+                    Some("st") => self.time_control.seconds_per_move = argument(&tokens, 1),
+                    Some("sd") => self.depth = argument(&tokens, 1),
+                    // Centiseconds
+                    Some("time") => {
+                        self.time_control.time = argument(&tokens, 1).map(|time: u64| time * 10)
+                    }
+                    Some("ping") => self.send(&format!("pong {}", tokens.get(1).unwrap_or(&""))),
+                    Some("post") => self.post = true,
+                    Some("nopost") => self.post = false,
+                    // Move now
+                    Some("?") => self.finish_search(),
+                    Some("quit") => return false,
+                    Some("xboard") | Some("accepted") | Some("rejected") | Some("otim")
+                    | Some("hard") | Some("easy") | Some("random") | Some("computer")
+                    | Some("name") | Some("rating") | Some("variant") | None => {}
+                    Some(command) => {
+                        // Moves without "usermove" (if the GUI ignored the feature)
+                        if find_coordinate_move(&self.game, command).is_some() {
+                            self.user_move(command);
+                        } else {
+                            self.send(&format!("Error (unknown command): {}", command));
+                        }
+                    }
+                }
+            }
+            Event::Iteration {
+                search_id,
+                depth,
+                best_move,
+                elapsed,
+            } => {
+                let post = self.post;
+                if let Some(ref mut search) = self.search {
+                    if search.id == search_id {
+                        search.best_move = Some(best_move);
+                        if post {
+                            // depth score time(centiseconds) nodes pv
+                            self.send(&format!(
+                                "{} 0 {} 0 {}",
+                                depth,
+                                elapsed.as_millis() / 10,
+                                best_move.stringify()
+                            ));
+                        }
+                    }
+                }
+            }
+            Event::SearchDone { search_id } => {
+                let is_current = self
+                    .search
+                    .as_ref()
+                    .is_some_and(|search| search.id == search_id);
+                if is_current {
+                    self.finish_search();
+                }
+            }
+        }
+        true
+    }
+
+    fn features(&mut self) {
+        self.send(&format!(
+            "feature myname=\"chessMarkable {}\" usermove=1 setboard=1 ping=1 playother=1 \
+             sigint=0 sigterm=0 colors=0 analyze=0 done=1",
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+
+    fn user_move(&mut self, text: &str) {
+        let played = find_coordinate_move(&self.game, text)
+            .context("Not a legal move")
+            .and_then(|bit_move| self.game.move_piece_uci(&bit_move.stringify()));
+        if played.is_err() {
+            self.send(&format!("Illegal move: {}", text));
+            return;
+        }
+        if let Some(outcome) = self.game.outcome() {
+            self.send(&result(outcome));
+        } else if self.engine_side == Some(self.game.turn()) {
+            self.think();
+        }
+    }
+
+    fn undo(&mut self, moves: u16, line: &str) {
+        self.cancel_search();
+        if self.game.undo(moves).is_err() {
+            self.send(&format!("Error (cannot undo): {}", line));
+        }
+    }
+
+    fn think(&mut self) {
+        if let Some(outcome) = self.game.outcome() {
+            self.send(&result(outcome));
+            return;
+        }
+
+        // Without a clock or "sd", do exactly what the tablet bot does
+        let preset = self.difficulty.limits();
+        let max_depth = self.depth.unwrap_or(preset.depth);
+        let moves_played = (self.game.total_moves() / 2) as u64;
+        let budget = match self.time_control.budget(moves_played) {
+            None if self.depth.is_none() => preset.budget(None),
+            budget => budget,
+        };
+        let deadline = budget.map(|budget| Instant::now() + budget);
+
+        let id = self.next_search_id;
+        self.next_search_id += 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.search = Some(Search {
+            id,
+            best_move: None,
+            cancelled: cancelled.clone(),
+        });
+
+        let board = self.game.board();
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let first_depth = if deadline.is_some() { 1 } else { max_depth };
+            let mut stopped = false;
+            deepen(
+                &board,
+                first_depth..=max_depth,
+                deadline,
+                // Cancelling stops the running depth (which then finds the
+                // null move)
+                |board, depth| {
+                    cancellable_best_move(board, depth, true, &cancelled)
+                        .unwrap_or_else(BitMove::null)
+                },
+                |depth, best_move, elapsed| {
+                    let report = Event::Iteration {
+                        search_id: id,
+                        depth,
+                        best_move,
+                        elapsed,
+                    };
+                    // A cancelled depth found nothing worth reporting
+                    stopped = cancelled.load(Ordering::Relaxed) || events.send(report).is_err();
+                    !stopped
+                },
+            );
+            if !stopped {
+                events.send(Event::SearchDone { search_id: id }).ok();
+            }
+        });
+    }
+
+    /// Drop a running search without moving
+    fn cancel_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn finish_search(&mut self) {
+        let search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+        search.cancelled.store(true, Ordering::Relaxed);
+        let best_move = match search.best_move {
+            Some(best_move) if !best_move.is_null() => best_move,
+            // Moved before the first depth finished
+            _ => match self.game.possible_moves().iter().next() {
+                Some(any_move) => *any_move,
+                None => return,
+            },
+        };
+        if self.game.move_piece_uci(&best_move.stringify()).is_err() {
+            error!("Search found illegal move {}", best_move);
+            return;
+        }
+        self.send(&format!("move {}", best_move.stringify()));
+        if let Some(outcome) = self.game.outcome() {
+            self.send(&result(outcome));
+        }
+    }
+}
+
+fn read_commands(events: Sender<Event>) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line.context("Read stdin") {
+            Ok(line) => line,
+            Err(e) => {
+                error!("{:?}", e);
+                break;
+            }
+        };
+        if events.send(Event::Command(line)).is_err() {
+            return;
+        }
+    }
+    events.send(Event::Command("quit".to_owned())).ok();
+}
+
+fn serve<W: Write>(server: &mut CecpServer<W>, events: Receiver<Event>) {
+    for event in events {
+        if !server.handle(event) {
+            break;
+        }
+    }
+}
+
+fn main() {
+    // Logging goes to stderr which GUIs usually show or ignore
+    if env::var("RUST_LOG").is_err() {
+        env::set_var("RUST_LOG", "WARN");
+    }
+    env_logger::init();
+    let opts = Opts::parse();
+
+    let (events_tx, events_rx) = channel::<Event>();
+    let commands_tx = events_tx.clone();
+    thread::spawn(move || read_commands(commands_tx));
+
+    let mut server = CecpServer::new(io::stdout(), events_tx, opts.difficulty);
+    serve(&mut server, events_rx);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    struct Session {
+        server: CecpServer<Vec<u8>>,
+        events: Receiver<Event>,
+    }
+
+    impl Session {
+        fn new() -> Self {
+            let (events_tx, events) = channel::<Event>();
+            Self {
+                server: CecpServer::new(vec![], events_tx, BotDifficulty::Easy),
+                events,
+            }
+        }
+
+        fn commands(&mut self, lines: &[&str]) {
+            for line in lines {
+                self.server.handle(Event::Command(line.to_string()));
+            }
+        }
+
+        /// Handle search events until we moved
+        fn wait_for_move(&mut self) -> String {
+            while !self.output().iter().any(|line| line.starts_with("move ")) {
+                let event = self
+                    .events
+                    .recv_timeout(TIMEOUT)
+                    .expect("The search took too long");
+                self.server.handle(event);
+            }
+            self.output()
+                .into_iter()
+                .find(|line| line.starts_with("move "))
+                .unwrap()
+        }
+
+        fn output(&self) -> Vec<String> {
+            String::from_utf8_lossy(&self.server.out)
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn features() {
+        let mut session = Session::new();
+        session.commands(&["xboard", "protover 2"]);
+        let output = session.output();
+        assert_eq!(output.len(), 1);
+        assert!(output[0].starts_with("feature myname=\"chessMarkable "));
+        assert!(output[0].contains(" usermove=1 setboard=1 "));
+        assert!(output[0].ends_with(" done=1"));
+    }
+
+    #[test]
+    fn answers_as_black() {
+        let mut session = Session::new();
+        session.commands(&[
+            "new",
+            "level 40 5 0",
+            "time 30000",
+            "otim 30000",
+            "usermove e2e4",
+        ]);
+        let engine_move = session.wait_for_move();
+        let mut game = ChessGame::default();
+        game.move_piece_uci("e2e4").unwrap();
+        game.move_piece_uci(engine_move.trim_start_matches("move "))
+            .expect("Engine move is not legal");
+        assert_eq!(session.server.game.fen(), game.fen());
+    }
+
+    #[test]
+    fn force_mode_only_follows() {
+        let mut session = Session::new();
+        session.commands(&["new", "force", "usermove e2e4", "usermove e7e5", "ping 3"]);
+        assert_eq!(session.output(), vec!["pong 3"]);
+        session.commands(&["remove"]);
+        assert_eq!(session.server.game.fen(), ChessGame::default().fen());
+        session.commands(&["undo"]);
+        assert!(session.output()[1].starts_with("Error (cannot undo)"));
+    }
+
+    #[test]
+    fn mates_from_a_position() {
+        let mut session = Session::new();
+        session.commands(&[
+            "new",
+            "force",
+            "setboard r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+            "sd 2",
+            "post",
+            "go",
+        ]);
+        assert_eq!(session.wait_for_move(), "move h5f7");
+        let output = session.output();
+        assert!(output[0].starts_with("2 0 "));
+        assert_eq!(output.last().unwrap(), "1-0 {White mates}");
+    }
+
+    #[test]
+    fn difficulty_decides_without_a_clock() {
+        let mut session = Session::new();
+        session.commands(&["new", "post", "usermove e2e4"]);
+        session.wait_for_move();
+        // Deepens up to the preset depth within its time. Just like the bot
+        // on the tablet.
+        let output = session.output();
+        assert!(output[0].starts_with("1 0 "));
+        assert!(
+            output[output.len() - 2].starts_with(&format!("{} 0 ", BotDifficulty::Easy.depth()))
+        );
+    }
+
+    #[test]
+    fn move_now_stops_the_search() {
+        let mut session = Session::new();
+        session.commands(&["new", "force", "sd 30", "st 600", "post", "go"]);
+        while session.output().is_empty() {
+            let event = session.events.recv_timeout(TIMEOUT).unwrap();
+            session.server.handle(event);
+        }
+        session.commands(&["?"]);
+        assert!(session.output().last().unwrap().starts_with("move "));
+        // The running depth gives up instead of searching on for minutes
+        let mut late_events = 0;
+        while session.events.recv_timeout(Duration::from_secs(2)).is_ok() {
+            late_events += 1;
+            assert!(late_events < 30, "The search kept going");
+        }
+    }
+
+    #[test]
+    fn rejects_illegal_moves_and_commands() {
+        let mut session = Session::new();
+        session.commands(&["new", "usermove e2e5", "fly"]);
+        assert_eq!(
+            session.output(),
+            vec!["Illegal move: e2e5", "Error (unknown command): fly"]
+        );
+    }
+
+    #[test]
+    fn time_control() {
+        let mut time_control = TimeControl {
+            moves_per_session: 40,
+            time: Some(60_000),
+            ..Default::default()
+        };
+        assert_eq!(time_control.budget(30), Some(Duration::from_millis(6000)));
+        time_control.seconds_per_move = Some(2);
+        assert_eq!(time_control.budget(30), Some(Duration::from_secs(2)));
+        assert_eq!(TimeControl::default().budget(0), None);
+    }
+}
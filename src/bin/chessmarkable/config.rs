@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+use chessmarkable::lichess::LichessConfig;
+use chessmarkable::proto::{BotConfig, BotDifficulty, HintConfig, SearchLimits};
+use chessmarkable::search::SearchAlgorithm;
+use chessmarkable::strength::Strength;
+use chessmarkable::tablebase::Tablebase;
+use chessmarkable::uci::{UciEngineConfig, UciOptionValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Settings the user writes by hand (in contrast to the savestates)
+#[derive(Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Enables playing online games
+    #[serde(default)]
+    pub lichess: Option<LichessConfig>,
+    /// External engine offered as additional bot difficulty
+    #[serde(default)]
+    pub uci_engine: Option<UciEngineConfig>,
+    /// Overrides `BotDifficulty::limits()` and adds opening books
+    #[serde(default)]
+    pub bots: HashMap<BotDifficulty, BotConfig>,
+    /// Replaces a difficulty with a bot playing like a human
+    #[serde(default)]
+    pub human_bots: HashMap<BotDifficulty, Strength>,
+    /// Makes the human bots play the same moves every time
+    #[serde(default)]
+    pub human_bot_seed: Option<u64>,
+    /// Directory with Syzygy tables. Shows the verdict in endgames and
+    /// lets the built-in bots play them perfectly.
+    #[serde(default)]
+    pub tablebase: Option<PathBuf>,
+    /// Limits of the search for hints (instead of 4 deep or 2 seconds)
+    #[serde(default)]
+    pub hint_limits: Option<SearchLimits>,
+    /// Removes the hint button from games against bots and local players
+    #[serde(default)]
+    pub disable_hints: bool,
+    /// Also shows the evaluation bar in games against bots and engines
+    /// (the PGN viewer always has it)
+    #[serde(default)]
+    pub evaluation_in_bot_games: bool,
+    /// Also shows the evaluation bar in games between local players
+    #[serde(default)]
+    pub evaluation_in_local_games: bool,
+    /// Removes the evaluation bar everywhere
+    #[serde(default)]
+    pub hide_evaluation: bool,
+    /// Limits of the background analysis (instead of 5 deep or 3 seconds)
+    #[serde(default)]
+    pub analysis_limits: Option<SearchLimits>,
+    /// Limits per position of the review after a game (instead of 4 deep
+    /// or 1 second)
+    #[serde(default)]
+    pub review_limits: Option<SearchLimits>,
+    /// Doesn't review finished games
+    #[serde(default)]
+    pub disable_review: bool,
+    /// Searcher of the built-in bots and hints (instead of AlphaBeta on the
+    /// reMarkable 1 and Jamboree otherwise)
+    #[serde(default)]
+    pub search_algorithm: Option<SearchAlgorithm>,
+    /// Threads of the multithreaded searchers (instead of one on the
+    /// reMarkable 1 and one per core otherwise)
+    #[serde(default)]
+    pub search_threads: Option<usize>,
+    /// Transposition table size in megabytes (instead of 4 on the
+    /// reMarkable 1 and 16 otherwise)
+    #[serde(default)]
+    pub table_size: Option<usize>,
+    /// Lets the built-in bots think on the user's time (instead of only on
+    /// devices with more than one core). Off saves battery.
+    #[serde(default)]
+    pub ponder: Option<bool>,
+}
+
+pub fn read() -> Result<Config> {
+    let file_path = &crate::CLI_OPTS.config_file;
+
+    if !file_path.exists() {
+        info!("Config file doesn't exist. Using defaults.");
+        Ok(Config::default())
+    } else {
+        let file = std::fs::File::open(file_path).context("Open file")?;
+        let config: Config = serde_yaml::from_reader(file).context("Deserialize file")?;
+        Ok(config)
+    }
+}
+
+/// The engine from the config file with the command line taking precedence
+pub fn uci_engine() -> Option<UciEngineConfig> {
+    let mut engine = match (&crate::CLI_OPTS.uci_engine, &crate::CONFIG.uci_engine) {
+        (Some(path), Some(config)) => UciEngineConfig {
+            path: path.clone(),
+            ..config.clone()
+        },
+        (Some(path), None) => UciEngineConfig::new(path.clone()),
+        (None, Some(config)) => config.clone(),
+        (None, None) => return None,
+    };
+    for option in &crate::CLI_OPTS.uci_option {
+        let (name, value) = option.split_at(option.find('=').unwrap_or(option.len()));
+        engine.options.insert(
+            name.trim().to_owned(),
+            UciOptionValue::Text(value.trim_start_matches('=').trim().to_owned()),
+        );
+    }
+    Some(engine)
+}
+
+/// How a built-in bot plays (the config file can change the defaults)
+pub fn bot_config(difficulty: BotDifficulty) -> BotConfig {
+    crate::CONFIG
+        .bots
+        .get(&difficulty)
+        .cloned()
+        .map(|config| BotConfig {
+            tablebase: config.tablebase.or_else(|| crate::CONFIG.tablebase.clone()),
+            ponder: Some(config.ponder.unwrap_or_else(ponder)),
+            ..config
+        })
+        .unwrap_or_else(|| BotConfig {
+            tablebase: crate::CONFIG.tablebase.clone(),
+            ponder: Some(ponder()),
+            ..difficulty.limits().into()
+        })
+}
+
+fn ponder() -> bool {
+    crate::CONFIG.ponder.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|cores| cores.get() > 1)
+            .unwrap_or(false)
+    })
+}
+
+/// The tablebase directory if configured. Fails if it contains no tables.
+pub fn tablebase() -> Result<Option<PathBuf>> {
+    match crate::CONFIG.tablebase {
+        Some(ref directory) => {
+            Tablebase::open(directory)?;
+            Ok(Some(directory.clone()))
+        }
+        None => Ok(None),
+    }
+}
+
+fn is_rm1() -> bool {
+    libremarkable::device::CURRENT_DEVICE.model == libremarkable::device::Model::Gen1
+}
+
+/// The searcher of the built-in bots and hints. The command line takes
+/// precedence over the config file.
+pub fn search_algorithm() -> SearchAlgorithm {
+    crate::CLI_OPTS
+        .search_algorithm
+        .or(crate::CONFIG.search_algorithm)
+        .unwrap_or(if is_rm1() {
+            // Multithreading only makes the single core busier
+            SearchAlgorithm::AlphaBeta
+        } else {
+            SearchAlgorithm::Jamboree
+        })
+}
+
+pub fn search_threads() -> usize {
+    crate::CLI_OPTS
+        .search_threads
+        .or(crate::CONFIG.search_threads)
+        .unwrap_or_else(|| {
+            if is_rm1() {
+                1
+            } else {
+                std::thread::available_parallelism()
+                    .map(|threads| threads.get())
+                    .unwrap_or(1)
+            }
+        })
+        .max(1)
+}
+
+pub fn table_size() -> usize {
+    crate::CLI_OPTS
+        .table_size
+        .or(crate::CONFIG.table_size)
+        .unwrap_or(if is_rm1() { 4 } else { 16 })
+}
+
+/// Applies the search threads and table size to all searches
+pub fn configure_search() {
+    let (algorithm, threads, table_size) = (search_algorithm(), search_threads(), table_size());
+    info!(
+        "Searching with {} on {} thread(s) and a {} MB transposition table",
+        algorithm, threads, table_size
+    );
+    if algorithm.is_multithreaded() && threads == 1 {
+        warn!("{} is multithreaded but only has one thread", algorithm);
+    }
+    if let Err(err) = chessmarkable::search::configure(threads, table_size) {
+        error!("Failed to configure the search: {:?}", err);
+    }
+}
+
+/// How hints get searched or `None` if they are disabled
+pub fn hints(algorithm: SearchAlgorithm) -> Option<HintConfig> {
+    if crate::CONFIG.disable_hints {
+        return None;
+    }
+    let limits = crate::CONFIG
+        .hint_limits
+        .unwrap_or_else(|| HintConfig::default().limits);
+    Some(HintConfig { limits, algorithm })
+}
+
+/// How the positions get analysed for the evaluation bar or `None` if it
+/// is not shown. Games only show it when `wanted` by the config file.
+pub fn analysis(wanted: bool) -> Option<SearchLimits> {
+    if crate::CONFIG.hide_evaluation || !wanted {
+        return None;
+    }
+    Some(
+        crate::CONFIG
+            .analysis_limits
+            .unwrap_or_else(chessmarkable::analysis::default_limits),
+    )
+}
+
+/// How finished games get reviewed or `None` if they are not
+pub fn review() -> Option<SearchLimits> {
+    if crate::CONFIG.disable_review {
+        return None;
+    }
+    Some(
+        crate::CONFIG
+            .review_limits
+            .unwrap_or_else(chessmarkable::annotation::default_limits),
+    )
+}
+
+pub fn human_strength(difficulty: BotDifficulty) -> Option<Strength> {
+    crate::CONFIG.human_bots.get(&difficulty).copied()
+}
+
+/// The configured seed or a new one for every game
+pub fn human_bot_seed() -> u64 {
+    crate::CONFIG.human_bot_seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_nanos() as u64)
+            .unwrap_or(1)
+    })
+}
@@ -8,6 +8,7 @@ extern crate lazy_static;
 extern crate log;
 
 mod canvas;
+mod config;
 mod savestates;
 mod scene;
 mod pgns;
@@ -17,6 +18,7 @@ use crate::scene::*;
 use clap::Parser;
 use lazy_static::lazy_static;
 use libremarkable::input::{ev::EvDevContext, InputDevice, InputEvent};
+use config::Config;
 use savestates::Savestates;
 use std::env;
 use std::process::Command;
@@ -56,6 +58,28 @@ pub struct Opts {
     )]
     savestates_file: std::path::PathBuf,
 
+    #[clap(
+    long,
+    short = 'c',
+    help = "Path to the config file (e.g. containing a Lichess token)",
+    default_value = "/home/root/.config/chessmarkable/config.yml"
+    )]
+    config_file: std::path::PathBuf,
+
+    #[clap(
+    long,
+    short = 'u',
+    help = "Path to a UCI engine (e.g. Stockfish) to play against. Overrides the one in the config file."
+    )]
+    uci_engine: Option<std::path::PathBuf>,
+
+    #[clap(
+    long,
+    short = 'o',
+    help = "Option for the UCI engine as NAME=VALUE (e.g. \"Skill Level=5\"). Can be repeated."
+    )]
+    uci_option: Vec<String>,
+
     #[clap(
     long,
     short = 'p',
@@ -63,12 +87,47 @@ pub struct Opts {
     default_value = "/home/root/.config/chessmarkable/pgn"
     )]
     pgn_location: std::path::PathBuf,
+
+    #[clap(
+    long,
+    short = 'e',
+    help = "Log every request and update of a game as JSON lines to this file (useful for bug reports)"
+    )]
+    event_log: Option<std::path::PathBuf>,
+
+    #[clap(
+    long,
+    help = "Searcher of the built-in bots and hints (Minimax, ParallelMinimax, AlphaBeta, Jamboree or Iterative). Overrides the config file."
+    )]
+    search_algorithm: Option<chessmarkable::search::SearchAlgorithm>,
+
+    #[clap(
+    long,
+    help = "Threads of the multithreaded searchers. Overrides the config file."
+    )]
+    search_threads: Option<usize>,
+
+    #[clap(
+    long,
+    help = "Size of the transposition table in megabytes (0 to disable). Overrides the config file."
+    )]
+    table_size: Option<usize>,
 }
 
 lazy_static! {
     pub static ref CLI_OPTS: Opts = Opts::parse();
     pub static ref SAVESTATES: std::sync::Mutex<Savestates> =
         std::sync::Mutex::new(Default::default());
+    pub static ref CONFIG: Config = match config::read() {
+        Ok(config) => config,
+        Err(err) => {
+            error!(
+                "Failed to read config file at {:?}: {:?}",
+                &CLI_OPTS.config_file, err
+            );
+            std::process::exit(1);
+        }
+    };
         // Underlays / Background layers
 }
 
@@ -119,6 +178,9 @@ fn main() {
         }
     };
 
+    lazy_static::initialize(&CONFIG);
+    config::configure_search();
+
     let mut canvas = Canvas::default();
 
     let (input_tx, input_rx) = std::sync::mpsc::channel::<InputEvent>();
@@ -149,13 +211,23 @@ fn main() {
 }
 
 fn update(
-    scene: Box<dyn Scene>,
+    mut scene: Box<dyn Scene>,
     canvas: &mut Canvas,
     only_exit_to_xochitl: bool,
 ) -> Box<dyn Scene> {
-    if let Some(game_scene) = scene.downcast_ref::<GameScene>() {
+    if let Some(game_scene) = scene.downcast_mut::<GameScene>() {
         if game_scene.back_button_pressed {
             return Box::new(MainMenuScene::new(only_exit_to_xochitl, false));
+        } else if game_scene.review_button_pressed {
+            if let Some((ref game, ref pgn)) = game_scene.review {
+                match ReplayScene::new(Some(game.clone()), Some(pgn.clone())) {
+                    Ok(replay_scene) => return Box::new(replay_scene),
+                    Err(err) => {
+                        error!("Failed to replay the review: {:?}", err);
+                        game_scene.review_button_pressed = false;
+                    }
+                }
+            }
         }
     } else if let Some(main_menu_scene) = scene.downcast_ref::<MainMenuScene>() {
         let pvp_rot_en = main_menu_scene.pvp_piece_rotation_enabled;
@@ -167,6 +239,10 @@ fn update(
             return Box::new(BoardSelectScene::new(GameMode::NormalBot, pvp_rot_en));
         } else if main_menu_scene.play_hard_button_pressed {
             return Box::new(BoardSelectScene::new(GameMode::HardBot, pvp_rot_en));
+        } else if main_menu_scene.play_engine_button_pressed {
+            return Box::new(BoardSelectScene::new(GameMode::UciEngine, pvp_rot_en));
+        } else if main_menu_scene.play_lichess_button_pressed {
+            return Box::new(GameScene::new(GameMode::Lichess, None, false, ColourChoice::White));
         } else if main_menu_scene.viewer_button_pressed {
             return Box::new(PgnSelectScene::new(None));
         } else if main_menu_scene.exit_xochitl_button_pressed {
@@ -187,41 +263,59 @@ fn update(
         if board_select_scene.select_slot_1_button_pressed {
             return Box::new(GameScene::new(
                 board_select_scene.selected_gamemode,
-                SavestateSlot::First,
+                Some(SavestateSlot::First),
                 board_select_scene.pvp_piece_rotation_enabled,
+                board_select_scene.colour_choice,
             ));
         } else if board_select_scene.select_slot_2_button_pressed {
             return Box::new(GameScene::new(
                 board_select_scene.selected_gamemode,
-                SavestateSlot::Second,
+                Some(SavestateSlot::Second),
                 board_select_scene.pvp_piece_rotation_enabled,
+                board_select_scene.colour_choice,
             ));
         } else if board_select_scene.select_slot_3_button_pressed {
             return Box::new(GameScene::new(
                 board_select_scene.selected_gamemode,
-                SavestateSlot::Third,
+                Some(SavestateSlot::Third),
                 board_select_scene.pvp_piece_rotation_enabled,
+                board_select_scene.colour_choice,
             ));
         } else if board_select_scene.reset_slot_1_button_pressed {
-            SAVESTATES.lock().unwrap().slot_1 = None;
+            let mut savestates = SAVESTATES.lock().unwrap();
+            savestates.slot_1 = None;
+            savestates.slot_1_colour = None;
+            savestates.slot_1_hints = None;
+            drop(savestates);
             return Box::new(GameScene::new(
                 board_select_scene.selected_gamemode,
-                SavestateSlot::First,
+                Some(SavestateSlot::First),
                 board_select_scene.pvp_piece_rotation_enabled,
+                board_select_scene.colour_choice,
             ));
         } else if board_select_scene.reset_slot_2_button_pressed {
-            SAVESTATES.lock().unwrap().slot_2 = None;
+            let mut savestates = SAVESTATES.lock().unwrap();
+            savestates.slot_2 = None;
+            savestates.slot_2_colour = None;
+            savestates.slot_2_hints = None;
+            drop(savestates);
             return Box::new(GameScene::new(
                 board_select_scene.selected_gamemode,
-                SavestateSlot::Second,
+                Some(SavestateSlot::Second),
                 board_select_scene.pvp_piece_rotation_enabled,
+                board_select_scene.colour_choice,
             ));
         } else if board_select_scene.reset_slot_3_button_pressed {
-            SAVESTATES.lock().unwrap().slot_3 = None;
+            let mut savestates = SAVESTATES.lock().unwrap();
+            savestates.slot_3 = None;
+            savestates.slot_3_colour = None;
+            savestates.slot_3_hints = None;
+            drop(savestates);
             return Box::new(GameScene::new(
                 board_select_scene.selected_gamemode,
-                SavestateSlot::Third,
+                Some(SavestateSlot::Third),
                 board_select_scene.pvp_piece_rotation_enabled,
+                board_select_scene.colour_choice,
             ));
         } else if board_select_scene.back_button_pressed {
             return Box::new(MainMenuScene::new(
@@ -229,43 +323,34 @@ fn update(
                 board_select_scene.pvp_piece_rotation_enabled,
             ));
         }
-    } else if let Some(board_select_scene) = scene.downcast_ref::<PgnSelectScene>() {
+    } else if let Some(board_select_scene) = scene.downcast_mut::<PgnSelectScene>() {
         let index_of_first_game = (board_select_scene.current_page_number * REPLAYS_PER_PAGE) as usize;
+        let pressed_button = [
+            board_select_scene.button_1_pressed,
+            board_select_scene.button_2_pressed,
+            board_select_scene.button_3_pressed,
+            board_select_scene.button_4_pressed,
+            board_select_scene.button_5_pressed,
+            board_select_scene.button_6_pressed,
+        ]
+        .iter()
+        .position(|pressed| *pressed);
         if board_select_scene.return_to_main_menu {
             return Box::new(MainMenuScene::new(
                 only_exit_to_xochitl,
                 false,
             ));
-        } else if board_select_scene.button_1_pressed {
-            return Box::new(ReplayScene::new(
-                Some(board_select_scene.game_vec.get(index_of_first_game).unwrap().clone()),
+        } else if let Some(button) = pressed_button {
+            match ReplayScene::new(
+                Some(board_select_scene.game_vec.get(index_of_first_game + button).unwrap().clone()),
                 board_select_scene.selected_pgn.clone()
-            ));
-        } else if board_select_scene.button_2_pressed {
-            return Box::new(ReplayScene::new(
-                Some(board_select_scene.game_vec.get(index_of_first_game + 1).unwrap().clone()),
-                board_select_scene.selected_pgn.clone()
-            ));
-        } else if board_select_scene.button_3_pressed {
-            return Box::new(ReplayScene::new(
-                Some(board_select_scene.game_vec.get(index_of_first_game + 2).unwrap().clone()),
-                board_select_scene.selected_pgn.clone()
-            ));
-        } else if board_select_scene.button_4_pressed {
-            return Box::new(ReplayScene::new(
-                Some(board_select_scene.game_vec.get(index_of_first_game + 3).unwrap().clone()),
-                board_select_scene.selected_pgn.clone()
-            ));
-        } else if board_select_scene.button_5_pressed {
-            return Box::new(ReplayScene::new(
-                Some(board_select_scene.game_vec.get(index_of_first_game + 4).unwrap().clone()),
-                board_select_scene.selected_pgn.clone()
-            ));
-        } else if board_select_scene.button_6_pressed {
-            return Box::new(ReplayScene::new(
-                Some(board_select_scene.game_vec.get(index_of_first_game + 5).unwrap().clone()),
-                board_select_scene.selected_pgn.clone()
-            ));
+            ) {
+                Ok(replay_scene) => return Box::new(replay_scene),
+                Err(err) => {
+                    error!("Failed to replay game: {:?}", err);
+                    board_select_scene.show_error(format!("Can't replay this game: {:#}", err));
+                }
+            }
         };
     } else if let Some(board_select_scene) = scene.downcast_ref::<ReplayScene>() {
         if board_select_scene.return_to_main_menu {
@@ -1,4 +1,5 @@
-use anyhow::{Result};
+use anyhow::{Context, Result};
+use chess_pgn_parser::Game;
 use serde::{Deserialize, Serialize};
 use std::path::{PathBuf};
 use glob::glob;
@@ -35,6 +36,15 @@ pub fn read(from: usize, to: usize) -> Result<Vec<Pgn>> {
     }
 }
 
+/// Writes the game into the pgn directory so it can be replayed later
+pub fn save(game: &Game, name: &str) -> Result<Pgn> {
+    let pgn_loc = &crate::CLI_OPTS.pgn_location;
+    std::fs::create_dir_all(pgn_loc).context("Create pgn directory")?;
+    let path = pgn_loc.join(format!("{}.pgn", name));
+    std::fs::write(&path, chessmarkable::pgn::write(game)).context("Write pgn")?;
+    Ok(Pgn { path })
+}
+
 pub fn total_number_of_pgn() -> u32 {
     let mut pages: u32 = 0;
     let ref pgn_loc = crate::CLI_OPTS.pgn_location;
@@ -1,4 +1,5 @@
 use anyhow::{Context, Result};
+use chessmarkable::Player;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -6,6 +7,20 @@ pub struct Savestates {
     pub slot_1: Option<String>,
     pub slot_2: Option<String>,
     pub slot_3: Option<String>,
+    /// Side of the user in games against a bot (White if missing)
+    #[serde(default)]
+    pub slot_1_colour: Option<Player>,
+    #[serde(default)]
+    pub slot_2_colour: Option<Player>,
+    #[serde(default)]
+    pub slot_3_colour: Option<Player>,
+    /// Hints taken by White and Black in the game of the slot
+    #[serde(default)]
+    pub slot_1_hints: Option<[u16; 2]>,
+    #[serde(default)]
+    pub slot_2_hints: Option<[u16; 2]>,
+    #[serde(default)]
+    pub slot_3_hints: Option<[u16; 2]>,
 }
 
 impl Default for Savestates {
@@ -14,6 +29,12 @@ impl Default for Savestates {
             slot_1: None,
             slot_2: None,
             slot_3: None,
+            slot_1_colour: None,
+            slot_2_colour: None,
+            slot_3_colour: None,
+            slot_1_hints: None,
+            slot_2_hints: None,
+            slot_3_hints: None,
         }
     }
 }
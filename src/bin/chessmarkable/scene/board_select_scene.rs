@@ -1,4 +1,4 @@
-use super::Scene;
+use super::{ColourChoice, GameMode, Scene};
 use crate::canvas::*;
 use libremarkable::input::{InputEvent, MultitouchEvent};
 
@@ -7,6 +7,8 @@ pub struct BoardSelectScene {
 
     pub selected_gamemode: crate::scene::GameMode,
     pub pvp_piece_rotation_enabled: bool,
+    /// Side of the user in new games against a bot
+    pub colour_choice: ColourChoice,
 
     select_slot_1_button_hitbox: Option<mxcfb_rect>,
     pub select_slot_1_button_pressed: bool,
@@ -22,6 +24,9 @@ pub struct BoardSelectScene {
     reset_slot_3_button_hitbox: Option<mxcfb_rect>,
     pub reset_slot_3_button_pressed: bool,
 
+    colour_button_hitbox: Option<mxcfb_rect>,
+    redraw_colour_button: bool,
+
     back_button_hitbox: Option<mxcfb_rect>,
     pub back_button_pressed: bool,
 
@@ -37,6 +42,7 @@ impl BoardSelectScene {
             drawn: false,
             selected_gamemode,
             pvp_piece_rotation_enabled,
+            colour_choice: ColourChoice::White,
             select_slot_1_button_hitbox: None,
             select_slot_1_button_pressed: false,
             select_slot_2_button_hitbox: None,
@@ -50,6 +56,8 @@ impl BoardSelectScene {
             reset_slot_2_button_pressed: false,
             reset_slot_3_button_hitbox: None,
             reset_slot_3_button_pressed: false,
+            colour_button_hitbox: None,
+            redraw_colour_button: false,
             back_button_hitbox: None,
             back_button_pressed: false,
             indicate_loading: false,
@@ -67,6 +75,40 @@ impl BoardSelectScene {
         );
         canvas.update_partial(&rect);
     }
+
+    /// The sides of PvP and online games are not picked here
+    fn has_colour_choice(&self) -> bool {
+        !matches!(self.selected_gamemode, GameMode::PvP | GameMode::Lichess)
+    }
+
+    fn draw_colour_button(&mut self, canvas: &mut Canvas) {
+        if let Some(old_hitbox) = self.colour_button_hitbox.take() {
+            canvas.fill_rect(
+                Point2 {
+                    x: Some(old_hitbox.left as i32),
+                    y: Some(old_hitbox.top as i32),
+                },
+                Vector2 {
+                    x: old_hitbox.width,
+                    y: old_hitbox.height,
+                },
+                color::WHITE,
+            );
+            canvas.update_partial(&old_hitbox);
+        }
+        let hitbox = canvas.draw_button(
+            Point2 {
+                x: None,
+                y: Some(1530),
+            },
+            &format!("Play as: {}", self.colour_choice),
+            50.0,
+            15,
+            40,
+        );
+        canvas.update_partial(&hitbox);
+        self.colour_button_hitbox = Some(hitbox);
+    }
 }
 
 impl Scene for BoardSelectScene {
@@ -77,6 +119,11 @@ impl Scene for BoardSelectScene {
             return;
         }
 
+        if self.redraw_colour_button {
+            self.draw_colour_button(canvas);
+            self.redraw_colour_button = false;
+        }
+
         if self.drawn {
             return;
         }
@@ -199,6 +246,10 @@ impl Scene for BoardSelectScene {
             50,
         ));
 
+        if self.has_colour_choice() {
+            self.draw_colour_button(canvas);
+        }
+
         canvas.update_full();
     }
 
@@ -236,6 +287,11 @@ impl Scene for BoardSelectScene {
                 {
                     self.reset_slot_3_button_pressed = true;
                     self.indicate_loading = true;
+                } else if self.colour_button_hitbox.is_some()
+                    && Canvas::is_hitting(position, self.colour_button_hitbox.unwrap())
+                {
+                    self.colour_choice = self.colour_choice.next();
+                    self.redraw_colour_button = true;
                 } else if self.back_button_hitbox.is_some()
                     && Canvas::is_hitting(position, self.back_button_hitbox.unwrap())
                 {
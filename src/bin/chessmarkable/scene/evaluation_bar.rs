@@ -0,0 +1,181 @@
+use crate::canvas::*;
+use chessmarkable::proto::{ChessUpdate, Evaluation, Score};
+use chessmarkable::Player;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::Receiver;
+
+/// Room left and right of a board with the bar. The bar sits in the left
+/// one.
+const SIDE_MARGIN: u32 = 30;
+const BAR_LEFT: i32 = 6;
+const BAR_WIDTH: u32 = 18;
+const TEXT_SIZE: f32 = 35.0;
+/// Baseline of the text below the board
+const TEXT_OFFSET: i32 = 45;
+/// Partial refreshes of e-ink are slow and flicker. New depths arrive a lot
+/// faster, so only the latest one within this time gets drawn.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(1500);
+
+/// Score and expected continuation of the position with a bar showing
+/// who is ahead
+pub struct EvaluationBar {
+    evaluations: Receiver<ChessUpdate>,
+    white_at_bottom: bool,
+    latest: Option<Evaluation>,
+    changed: bool,
+    last_drawn: Option<SystemTime>,
+    last_text_rect: Option<mxcfb_rect>,
+}
+
+/// Size of the board's squares. The board gets narrower to make room for
+/// the bar beside it.
+pub fn square_size(with_bar: bool) -> u32 {
+    let margins = if with_bar { SIDE_MARGIN * 2 } else { 0 };
+    (DISPLAYWIDTH as u32 - margins) / 8
+}
+
+/// The centered board (of `square_size(true)`)
+fn board_rect() -> mxcfb_rect {
+    let size = square_size(true) * 8;
+    mxcfb_rect {
+        left: (DISPLAYWIDTH as u32 - size) / 2,
+        top: (DISPLAYHEIGHT as u32 - size) / 2,
+        width: size,
+        height: size,
+    }
+}
+
+impl EvaluationBar {
+    /// Shows the evaluations of `chessmarkable::analysis::create_analysis()`
+    pub fn new(evaluations: Receiver<ChessUpdate>, white_at_bottom: bool) -> Self {
+        Self {
+            evaluations,
+            white_at_bottom,
+            latest: None,
+            changed: true,
+            last_drawn: None,
+            last_text_rect: None,
+        }
+    }
+
+    /// Draws the latest evaluation if it changed and the last redraw is
+    /// long enough ago
+    pub fn draw(&mut self, canvas: &mut Canvas) {
+        while let Ok(update) = self.evaluations.try_recv() {
+            if let ChessUpdate::Evaluation { evaluation } = update {
+                self.latest = Some(evaluation);
+                self.changed = true;
+            }
+        }
+        let throttled = self
+            .last_drawn
+            .and_then(|last_drawn| last_drawn.elapsed().ok())
+            .map(|elapsed| elapsed < REDRAW_INTERVAL)
+            .unwrap_or(false);
+        if !self.changed || throttled {
+            return;
+        }
+
+        let board = board_rect();
+        let (text, white_share) = match self.latest {
+            Some(ref evaluation) => (describe(evaluation), white_share(evaluation)),
+            None => ("Analysing...".to_owned(), 0.5),
+        };
+        if let Some(last_rect) = self.last_text_rect.take() {
+            canvas.fill_rect(
+                Point2 {
+                    x: Some(last_rect.left as i32),
+                    y: Some(last_rect.top as i32),
+                },
+                Vector2 {
+                    x: last_rect.width,
+                    y: last_rect.height,
+                },
+                color::WHITE,
+            );
+            canvas.update_partial(&last_rect);
+        }
+        let text_rect = canvas.draw_text(
+            Point2 {
+                x: Some(board.left as i32),
+                y: Some((board.top + board.height) as i32 + TEXT_OFFSET),
+            },
+            &text,
+            TEXT_SIZE,
+        );
+        canvas.update_partial(&text_rect);
+        self.last_text_rect = Some(text_rect);
+
+        // White's share on White's side of the board
+        let area = canvas.fill_rect(
+            Point2 {
+                x: Some(BAR_LEFT),
+                y: Some(board.top as i32),
+            },
+            Vector2 {
+                x: BAR_WIDTH,
+                y: board.height,
+            },
+            color::WHITE,
+        );
+        canvas.draw_rect(
+            Point2 {
+                x: Some(BAR_LEFT),
+                y: Some(board.top as i32),
+            },
+            Vector2 {
+                x: BAR_WIDTH,
+                y: board.height,
+            },
+            2,
+        );
+        let black_height = board.height - (board.height as f32 * white_share) as u32;
+        if black_height > 0 {
+            let black_top = if self.white_at_bottom {
+                board.top
+            } else {
+                board.top + board.height - black_height
+            };
+            canvas.fill_rect(
+                Point2 {
+                    x: Some(BAR_LEFT),
+                    y: Some(black_top as i32),
+                },
+                Vector2 {
+                    x: BAR_WIDTH,
+                    y: black_height,
+                },
+                color::BLACK,
+            );
+        }
+        canvas.update_partial(&area);
+        self.changed = false;
+        self.last_drawn = Some(SystemTime::now());
+    }
+}
+
+fn describe(evaluation: &Evaluation) -> String {
+    match evaluation {
+        Evaluation::Tablebase(verdict) => format!("Tablebase: {}", verdict),
+        Evaluation::Search { score, depth, line } => {
+            format!("{} (depth {})  {}", score, depth, line.join(" "))
+        }
+    }
+}
+
+/// Part of the bar for White (0.5 is equal)
+fn white_share(evaluation: &Evaluation) -> f32 {
+    match evaluation {
+        Evaluation::Tablebase(verdict) => match verdict.winner() {
+            Some(Player::White) => 1.0,
+            Some(Player::Black) => 0.0,
+            None => 0.5,
+        },
+        Evaluation::Search { score, .. } => match *score {
+            Score::Mate(moves) if moves > 0 => 1.0,
+            Score::Mate(_) => 0.0,
+            // Winning chances by centipawns (a pawn up is about 64%)
+            Score::Centipawns(centipawns) => 1.0 / (1.0 + 10f32.powf(-centipawns as f32 / 400.0)),
+        },
+    }
+}
@@ -1,16 +1,28 @@
+use super::evaluation_bar::{self, EvaluationBar};
 use super::Scene;
 use crate::canvas::*;
+use crate::pgns::{self, Pgn};
 use crate::CLI_OPTS;
+use chess_pgn_parser::Game;
+use chessmarkable::analysis::create_analysis;
+use chessmarkable::annotation::annotate_moves;
+use chessmarkable::game::move_to;
+use chessmarkable::pgn;
+use chessmarkable::lichess::{create_lichess_game, LichessClient, LichessGame};
 use chessmarkable::proto::*;
+use chessmarkable::strength::create_human_bot;
+use chessmarkable::uci::create_uci_bot;
 use chessmarkable::{Player, Square};
 use fxhash::{FxHashMap, FxHashSet};
 use libremarkable::image::{self, imageops::FilterType};
 use libremarkable::input::{InputEvent, MultitouchEvent};
-use pleco::bot_prelude::*;
 use pleco::{Board, Piece};
+use anyhow::{Context, Result};
 use std::time::{Duration, SystemTime};
 use tokio::runtime;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task;
 use crate::scene::piece_images::get_orig_piece_img;
 
 lazy_static! {
@@ -46,11 +58,46 @@ pub const ALL_PIECES: &[Piece] = &[
     Piece::WhitePawn,
 ];
 
+/// Below the board (which ends at about 1636)
+const BOT_THINKING_Y: i32 = 1690;
+/// Bots can finish several depths per second. Partial refreshes of e-ink
+/// are slow, so a new depth is drawn at most this often.
+const BOT_THINKING_REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
 #[inline]
 fn to_square(x: usize, y: usize) -> Square {
     Square::new(x, y).expect("to_square() failed")
 }
 
+/// A Lichess game that gets joined in the background
+struct LichessJoin {
+    /// Our side and the opponent's name
+    joined: oneshot::Receiver<Result<(Player, String)>>,
+    request_sender: Sender<ChessRequest>,
+    update_receiver: Receiver<ChessUpdate>,
+}
+
+/// The squares of the centered board with the bottom left one first
+fn board_hitboxes(square_size: u32, flipped: bool) -> Vec<Vec<mxcfb_rect>> {
+    let board_offset_x = (DISPLAYWIDTH as u32 - square_size * 8) / 2;
+    let board_offset_y = (DISPLAYHEIGHT as u32 - square_size * 8) / 2;
+    let mut piece_hitboxes = Vec::new();
+    for x in 0..8 {
+        let mut y_axis = Vec::new();
+        for y in 0..8 {
+            let (column, row) = if flipped { (7 - x, y) } else { (x, 7 - y) };
+            y_axis.push(mxcfb_rect {
+                left: board_offset_x + square_size * column,
+                top: board_offset_y + square_size * row,
+                width: square_size,
+                height: square_size,
+            });
+        }
+        piece_hitboxes.push(y_axis);
+    }
+    piece_hitboxes
+}
+
 enum GameBottomInfo {
     GameEnded(String),
     Info(String),
@@ -60,12 +107,70 @@ enum GameBottomInfo {
 #[derive(Clone, Copy, PartialEq)]
 pub enum GameMode {
     PvP = 0,
-    EasyBot = 2,
-    NormalBot = 4,
-    HardBot = 6,
+    /// Against an opponent on Lichess (the value is not used as bot depth)
+    Lichess = 1,
+    EasyBot = BotDifficulty::Easy as isize,
+    /// External engine configured by the user (the value is not used as bot depth)
+    UciEngine = 3,
+    NormalBot = BotDifficulty::Normal as isize,
+    HardBot = BotDifficulty::Hard as isize,
     // Could go up to about 8-10 (depending on the algo) before getting too slow. But probably fairly unbeatable then.
 }
 
+impl GameMode {
+    fn bot_difficulty(&self) -> Option<BotDifficulty> {
+        match self {
+            GameMode::EasyBot => Some(BotDifficulty::Easy),
+            GameMode::NormalBot => Some(BotDifficulty::Normal),
+            GameMode::HardBot => Some(BotDifficulty::Hard),
+            _ => None,
+        }
+    }
+}
+
+/// Side of the user in games against a bot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColourChoice {
+    White,
+    Black,
+    Random,
+}
+
+impl ColourChoice {
+    /// The next choice when tapping through them
+    pub fn next(self) -> Self {
+        match self {
+            ColourChoice::White => ColourChoice::Black,
+            ColourChoice::Black => ColourChoice::Random,
+            ColourChoice::Random => ColourChoice::White,
+        }
+    }
+
+    fn pick(self) -> Player {
+        match self {
+            ColourChoice::White => Player::White,
+            ColourChoice::Black => Player::Black,
+            ColourChoice::Random => {
+                let nanos = SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|since_epoch| since_epoch.subsec_nanos())
+                    .unwrap_or(0);
+                if nanos.is_multiple_of(2) {
+                    Player::White
+                } else {
+                    Player::Black
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ColourChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
 pub enum SavestateSlot {
     First,
     Second,
@@ -76,11 +181,19 @@ pub struct GameScene {
     board: Board,
     /// May be above zero when a fen was imported. Used to prevent panic on undo.
     game_mode: GameMode,
-    savestate_slot: SavestateSlot,
+    /// Online games are not saved locally
+    savestate_slot: Option<SavestateSlot>,
+    /// Side of the user in games against a bot (saved with the game)
+    user_colour: Player,
     first_draw: bool,
     back_button_hitbox: Option<mxcfb_rect>,
     undo_button_hitbox: Option<mxcfb_rect>,
     full_refresh_button_hitbox: Option<mxcfb_rect>,
+    hint_button_hitbox: Option<mxcfb_rect>,
+    /// The game answers `ChessRequest::Hint`
+    hints_enabled: bool,
+    /// Hints taken by White and Black (saved with the game)
+    hints_taken: [u16; 2],
     piece_hitboxes: Vec<Vec<mxcfb_rect>>,
     /// The squared that were visually affected and should be redrawn
     redraw_squares: FxHashSet<Square>,
@@ -116,35 +229,50 @@ pub struct GameScene {
     white_update_receiver: Option<Receiver<ChessUpdate>>,
     black_update_receiver: Option<Receiver<ChessUpdate>>,
     possible_moves: Vec<(Square, Square)>,
+    /// Until the Lichess game is joined (nobody can move before)
+    lichess_join: Option<LichessJoin>,
+    /// Shown when a spectator analyses the game
+    evaluation_bar: Option<EvaluationBar>,
+    /// Depth of the opponent bot's running search (`None` when it doesn't
+    /// think)
+    bot_thinking: Option<u16>,
+    redraw_bot_thinking: bool,
+    bot_thinking_drawn_at: Option<SystemTime>,
+    bot_thinking_last_rect: Option<mxcfb_rect>,
+    /// Every position since the start (without undone ones) to review the
+    /// game when it is over
+    positions: Vec<String>,
+    outcome: Option<ChessOutcome>,
+    /// `None` if finished games are not reviewed
+    review_limits: Option<SearchLimits>,
+    /// The review of the current game got started
+    reviewing: bool,
+    review_receiver: Option<oneshot::Receiver<Result<(Game, Pgn)>>>,
+    review_button_hitbox: Option<mxcfb_rect>,
+    pub review_button_pressed: bool,
+    /// The annotated game and where it got saved
+    pub review: Option<(Game, Pgn)>,
     runtime: runtime::Runtime,
 }
 
 impl GameScene {
     pub fn new(
         game_mode: GameMode,
-        savestate_slot: SavestateSlot,
+        savestate_slot: Option<SavestateSlot>,
         pvp_piece_rotation_enabled: bool,
+        colour_choice: ColourChoice,
     ) -> Self {
+        let analysis = match game_mode {
+            // Analysing online games would be cheating
+            GameMode::Lichess => None,
+            GameMode::PvP => crate::config::analysis(crate::CONFIG.evaluation_in_local_games),
+            _ => crate::config::analysis(crate::CONFIG.evaluation_in_bot_games),
+        };
         // Size of board
-        let square_size = DISPLAYWIDTH as u32 / 8;
+        let square_size = evaluation_bar::square_size(analysis.is_some());
         let piece_padding = square_size / 10;
         let overlay_padding = square_size / 20;
 
-        // Calculate hitboxes
-        let mut piece_hitboxes = Vec::new();
-        for x in 0..8 {
-            let mut y_axis = Vec::new();
-            for y in 0..8 {
-                y_axis.push(mxcfb_rect {
-                    left: ((DISPLAYWIDTH as u32 - square_size * 8) / 2) + square_size * x,
-                    top: ((DISPLAYHEIGHT as u32 - square_size * 8) / 2) + square_size * (7 - y),
-                    width: square_size,
-                    height: square_size,
-                });
-            }
-            piece_hitboxes.push(y_axis);
-        }
-
         // Create resized images
         let mut img_pieces: FxHashMap<char, image::DynamicImage> = Default::default();
         for piece in ALL_PIECES.iter() {
@@ -177,19 +305,57 @@ impl GameScene {
         let img_piece_moved_to =
             IMG_PIECE_MOVED_TO.resize(square_size, square_size, FilterType::Lanczos3);
 
-        // Create game (will run on as many theads as the cpu has cores)
+        // Create game (searches run on the blocking threads and the
+        // multithreaded searchers also on their own)
         let runtime = runtime::Builder::new_multi_thread()
             .thread_name("tokio_game_scene")
-            //.max_threads(2)
+            .worker_threads(crate::config::search_threads())
             .build()
             .expect("Failed to create tokio runtime");
 
-        let starting_fen = match savestate_slot {
-            SavestateSlot::First => crate::SAVESTATES.lock().unwrap().slot_1.clone(),
-            SavestateSlot::Second => crate::SAVESTATES.lock().unwrap().slot_2.clone(),
-            SavestateSlot::Third => crate::SAVESTATES.lock().unwrap().slot_3.clone(),
+        let (starting_fen, saved_colour, saved_hints) = {
+            let savestates = crate::SAVESTATES.lock().unwrap();
+            match savestate_slot {
+                Some(SavestateSlot::First) => (savestates.slot_1.clone(), savestates.slot_1_colour, savestates.slot_1_hints),
+                Some(SavestateSlot::Second) => (savestates.slot_2.clone(), savestates.slot_2_colour, savestates.slot_2_hints),
+                Some(SavestateSlot::Third) => (savestates.slot_3.clone(), savestates.slot_3_colour, savestates.slot_3_hints),
+                None => (None, None, None),
+            }
+        };
+        // A resumed game keeps counting its hints
+        let hints_taken = match starting_fen {
+            Some(_) => saved_hints.unwrap_or_default(),
+            None => [0, 0],
+        };
+        // A resumed game keeps its sides
+        let user_colour = match (&starting_fen, saved_colour) {
+            (Some(_), Some(saved_colour)) => saved_colour,
+            _ => colour_choice.pick(),
+        };
+        let bot_colour = user_colour.other_player();
+        let mut initial_info = GameBottomInfo::Info("White starts".to_owned());
+        // Hints search like the built-in bot would
+        let hints = crate::config::hints(crate::config::search_algorithm());
+        let mut hints_enabled = false;
+        let tablebase = match crate::config::tablebase() {
+            Ok(tablebase) => tablebase,
+            Err(err) => {
+                error!("Failed to open the tablebase: {:?}", err);
+                initial_info = GameBottomInfo::Error(format!("[Tablebase] {:#}", err));
+                None
+            }
+        };
+
+        let (spectator, evaluations) = match analysis {
+            Some(limits) => {
+                let _runtime = runtime.enter();
+                let (spectator, evaluations) = create_analysis(limits);
+                (spectator, Some(evaluations))
+            }
+            None => (stubbed_spectator(), None),
         };
 
+        let mut lichess_join = None;
         let white_request_sender: Option<Sender<ChessRequest>>;
         let black_request_sender: Option<Sender<ChessRequest>>;
         let white_update_receiver: Option<Receiver<ChessUpdate>>;
@@ -202,15 +368,20 @@ impl GameScene {
             let (black_update_tx, black_update_rx) = channel::<ChessUpdate>(256);
             let (black_request_tx, black_request_rx) = channel::<ChessRequest>(256);
 
+            hints_enabled = hints.is_some();
             runtime.spawn(create_game(
                 (white_update_tx, white_request_rx),
                 (black_update_tx, black_request_rx),
-                stubbed_spectator(),
+                spectator,
                 ChessConfig {
                     starting_fen,
                     can_black_undo: true,
                     can_white_undo: true,
                     allow_undo_after_loose: true,
+                    event_log: CLI_OPTS.event_log.clone(),
+                    tablebase: tablebase.clone(),
+                    hints,
+                    hints_taken,
                 },
             ));
 
@@ -219,56 +390,161 @@ impl GameScene {
             white_update_receiver = Some(white_update_rx);
             black_update_receiver = Some(black_update_rx);
         //Self::spawn_bot_thread(bot_job_rx, bot_move_tx); // TODO
+        } else if game_mode == GameMode::Lichess {
+            let (update_tx, update_rx) = channel::<ChessUpdate>(256);
+            let (request_tx, request_rx) = channel::<ChessRequest>(256);
+
+            let (joined_tx, joined_rx) = oneshot::channel();
+            runtime.spawn(async move {
+                let joined = task::spawn_blocking(Self::join_lichess_game)
+                    .await
+                    .context("Join the game")
+                    .and_then(|joined| joined);
+                let lichess_game = match joined {
+                    Ok(lichess_game) => lichess_game,
+                    Err(err) => {
+                        joined_tx.send(Err(err)).ok();
+                        return;
+                    }
+                };
+                let side = (lichess_game.me, lichess_game.opponent.clone());
+                if joined_tx.send(Ok(side)).is_err() {
+                    return; // Left before the game was joined
+                }
+                if let Err(err) = create_lichess_game(lichess_game, (update_tx, request_rx)).await {
+                    error!("Lichess game failed: {:?}", err);
+                }
+            });
+            lichess_join = Some(LichessJoin {
+                joined: joined_rx,
+                request_sender: request_tx,
+                update_receiver: update_rx,
+            });
+            initial_info = GameBottomInfo::Info("Joining a Lichess game...".to_owned());
+            white_request_sender = None;
+            black_request_sender = None;
+            white_update_receiver = None;
+            black_update_receiver = None;
         } else {
-            let (white_update_tx, white_update_rx) = channel::<ChessUpdate>(256);
-            let (white_request_tx, white_request_rx) = channel::<ChessRequest>(256);
+            let (user_update_tx, user_update_rx) = channel::<ChessUpdate>(256);
+            let (user_request_tx, user_request_rx) = channel::<ChessRequest>(256);
+            if user_colour == Player::Black {
+                initial_info = GameBottomInfo::Info("You play Black".to_owned());
+            }
 
             // Use multithreaded algo when not rM 1
-            let bot = if libremarkable::device::CURRENT_DEVICE.model
-                == libremarkable::device::Model::Gen1
+            let bot = if game_mode == GameMode::UciEngine {
+                let engine = crate::config::uci_engine().expect("No UCI engine configured");
+                debug!("The Bot will use the UCI engine {:?}", engine.path);
+                match runtime.block_on(create_uci_bot(
+                    bot_colour,
+                    engine,
+                    Duration::from_millis(CLI_OPTS.bot_reaction_delay.into()),
+                )) {
+                    Ok(bot) => bot,
+                    Err(err) => {
+                        error!("Failed to start the UCI engine: {:?}", err);
+                        initial_info = GameBottomInfo::Error(format!("[Engine] {:#}", err));
+                        stubbed_spectator()
+                    }
+                }
+            } else if let Some(strength) = game_mode
+                .bot_difficulty()
+                .and_then(crate::config::human_strength)
             {
-                debug!("The Bot will use the AlphaBeta algorithm (singlethreaded)");
+                let seed = crate::config::human_bot_seed();
+                debug!(
+                    "The Bot will play like a human of strength {} (seed {})",
+                    strength, seed
+                );
+                initial_info = GameBottomInfo::Info(format!(
+                    "You play {} against {}",
+                    user_colour, strength
+                ));
                 runtime
-                    .block_on(create_bot::<AlphaBetaSearcher>(
-                        Player::Black,
-                        game_mode as u16,
+                    .block_on(create_human_bot(
+                        bot_colour,
+                        strength.settings(),
+                        seed,
                         Duration::from_millis(CLI_OPTS.bot_reaction_delay.into()),
                     ))
                     .expect("Failed to initialize bot task")
             } else {
-                debug!("The Bot will use the Jamboree algorithm (multithreaded)");
-                runtime
-                    .block_on(create_bot::<JamboreeSearcher>(
-                        Player::Black,
-                        game_mode as u16,
-                        Duration::from_millis(CLI_OPTS.bot_reaction_delay.into()),
+                let bot_config = crate::config::bot_config(game_mode.bot_difficulty().unwrap());
+                let algorithm = crate::config::search_algorithm();
+                debug!("The Bot will use the {} algorithm", algorithm);
+                let create = |bot_config: BotConfig| {
+                    let delay = Duration::from_millis(CLI_OPTS.bot_reaction_delay.into());
+                    runtime.block_on(create_bot_with_algorithm(
+                        algorithm,
+                        bot_colour,
+                        bot_config,
+                        delay,
                     ))
-                    .expect("Failed to initialize bot task")
+                };
+                match create(bot_config.clone()) {
+                    Ok(bot) => bot,
+                    Err(err) => {
+                        // The opening book or tablebase. Play without them.
+                        error!("Failed to initialize bot: {:?}", err);
+                        initial_info = GameBottomInfo::Error(format!("[Bot] {:#}", err));
+                        create(BotConfig {
+                            book: None,
+                            tablebase: None,
+                            ..bot_config
+                        })
+                        .expect("Failed to initialize bot task")
+                    }
+                }
             };
 
+            hints_enabled = hints.is_some();
+            let user = (user_update_tx, user_request_rx);
+            let (white, black) = if user_colour == Player::White {
+                (user, bot)
+            } else {
+                (bot, user)
+            };
             runtime.spawn(create_game(
-                (white_update_tx, white_request_rx),
-                bot,
-                stubbed_spectator(),
+                white,
+                black,
+                spectator,
                 ChessConfig {
                     starting_fen,
-                    can_black_undo: false,
-                    can_white_undo: true,
+                    can_black_undo: user_colour == Player::Black,
+                    can_white_undo: user_colour == Player::White,
                     allow_undo_after_loose: true,
+                    event_log: CLI_OPTS.event_log.clone(),
+                    tablebase: tablebase.clone(),
+                    hints,
+                    hints_taken,
                 },
             ));
 
-            white_request_sender = Some(white_request_tx);
-            black_request_sender = None;
-            white_update_receiver = Some(white_update_rx);
-            black_update_receiver = None;
+            if user_colour == Player::White {
+                white_request_sender = Some(user_request_tx);
+                black_request_sender = None;
+                white_update_receiver = Some(user_update_rx);
+                black_update_receiver = None;
+            } else {
+                white_request_sender = None;
+                black_request_sender = Some(user_request_tx);
+                white_update_receiver = None;
+                black_update_receiver = Some(user_update_rx);
+            }
         }
 
+        // The local player's pieces sit at the bottom
+        let flipped = white_request_sender.is_none() && black_request_sender.is_some();
+        let evaluation_bar = evaluations.map(|evaluations| EvaluationBar::new(evaluations, !flipped));
+        let piece_hitboxes = board_hitboxes(square_size, flipped);
+
         Self {
             board: Board::default(), // Temporary default (usually stays that but will change when having a custom fen)
             first_draw: true,
             game_mode,
             savestate_slot,
+            user_colour,
             piece_hitboxes,
             piece_padding,
             overlay_padding,
@@ -290,10 +566,13 @@ impl GameScene {
             back_button_hitbox: None,
             undo_button_hitbox: None,
             full_refresh_button_hitbox: None,
+            hint_button_hitbox: None,
+            hints_enabled,
+            hints_taken,
             back_button_pressed: false,
             force_full_refresh: None,
             draw_game_bottom_info_delay_until: Some(SystemTime::now() + Duration::from_secs(2)),
-            draw_game_bottom_info: Some(GameBottomInfo::Info("White starts".to_owned())),
+            draw_game_bottom_info: Some(initial_info),
             draw_game_bottom_info_last_rect: None,
             draw_game_bottom_info_clear_at: None,
             is_game_over: false,
@@ -303,11 +582,40 @@ impl GameScene {
             white_request_sender,
             white_update_receiver,
             possible_moves: vec![],
+            lichess_join,
+            evaluation_bar,
+            bot_thinking: None,
+            redraw_bot_thinking: false,
+            bot_thinking_drawn_at: None,
+            bot_thinking_last_rect: None,
+            positions: vec![],
+            outcome: None,
+            review_limits: crate::config::review(),
+            reviewing: false,
+            review_receiver: None,
+            review_button_hitbox: None,
+            review_button_pressed: false,
+            review: None,
         }
     }
 
+    /// Blocks until the next ongoing game on Lichess is joined (so better
+    /// not on the UI thread)
+    fn join_lichess_game() -> Result<LichessGame> {
+        let config = crate::CONFIG
+            .lichess
+            .clone()
+            .context("No Lichess token configured")?;
+        let client = LichessClient::new(config);
+        let ongoing_game = client
+            .next_game()?
+            .context("No ongoing game. Start one on lichess.org first.")?;
+        client.join_game(&ongoing_game.game_id)
+    }
+
     fn handle_outcome(&mut self, outcome: Option<ChessOutcome>) {
         debug!("Outcome: {:?}", outcome);
+        self.outcome = outcome;
 
         if let Some(outcome) = outcome {
             if let ChessOutcome::Checkmate { winner } = outcome {
@@ -336,13 +644,258 @@ impl GameScene {
                     None,
                 );
                 self.is_game_over = true;
+            } else if let ChessOutcome::Draw = outcome {
+                if self.is_game_over {
+                    return; // This is not new
+                }
+
+                self.show_bottom_game_info(
+                    GameBottomInfo::GameEnded("Draw!".to_owned()),
+                    None,
+                    None,
+                );
+                self.is_game_over = true;
+            } else if let ChessOutcome::Aborted { who: Some(who) } = outcome {
+                if self.is_game_over {
+                    return; // This is not new
+                }
+
+                // E.g. resigned or ran out of time in an online game
+                self.show_bottom_game_info(
+                    GameBottomInfo::GameEnded(format!("{} lost the game!", who)),
+                    None,
+                    None,
+                );
+                self.is_game_over = true;
             }
         } else if self.is_game_over {
             // Probably undone a move. Is not gameover anymore
             self.is_game_over = false;
+            self.reviewing = false;
         }
     }
 
+    /// Remembers the position. Going back to an earlier one means the
+    /// moves after it got undone.
+    fn track_position(&mut self, fen: &str) {
+        match self.positions.iter().position(|position| position == fen) {
+            Some(index) => self.positions.truncate(index + 1),
+            None => self.positions.push(fen.to_owned()),
+        }
+    }
+
+    /// Annotates the finished game in the background and saves it to the
+    /// pgn directory
+    fn start_review(&mut self) {
+        let limits = match self.review_limits {
+            Some(limits) if !self.reviewing && self.positions.len() > 1 => limits,
+            _ => return,
+        };
+        self.reviewing = true;
+        let positions = self.positions.clone();
+        let outcome = self.outcome;
+        let hints_taken = self.hints_taken;
+        let (review_tx, review_rx) = oneshot::channel();
+        self.runtime.spawn_blocking(move || {
+            let review = || -> Result<(Game, Pgn)> {
+                let mut board = Board::from_fen(&positions[0])
+                    .map_err(|e| anyhow!("Invalid FEN {}: {:?}", positions[0], e))?;
+                let starting_fen = if positions[0] == Board::default().fen() {
+                    None
+                } else {
+                    Some(positions[0].as_str())
+                };
+                let mut moves = vec![];
+                for fen in &positions[1..] {
+                    let bit_move =
+                        move_to(&board, fen).ok_or_else(|| anyhow!("No move leads to {}", fen))?;
+                    board.apply_move(bit_move);
+                    moves.push(bit_move);
+                }
+                let mut game = annotate_moves(starting_fen, &moves, outcome, limits)?;
+                pgn::tag_hints_taken(&mut game, hints_taken);
+                let seconds = SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|since_epoch| since_epoch.as_secs())
+                    .unwrap_or(0);
+                let pgn = pgns::save(&game, &format!("review-{}", seconds))?;
+                Ok((game, pgn))
+            };
+            review_tx.send(review()).ok();
+        });
+        self.review_receiver = Some(review_rx);
+        self.show_bottom_game_info(
+            GameBottomInfo::Info("Reviewing the game...".to_owned()),
+            Some(Duration::from_secs(3)),
+            None,
+        );
+    }
+
+    /// Hands the board to the local player once the Lichess game is joined.
+    /// Playing Black turns the board around.
+    fn poll_lichess_join(&mut self) {
+        let result = match self.lichess_join {
+            Some(ref mut lichess_join) => match lichess_join.joined.try_recv() {
+                Ok(result) => result,
+                Err(oneshot::error::TryRecvError::Empty) => return,
+                Err(oneshot::error::TryRecvError::Closed) => Err(anyhow!("Joining stopped")),
+            },
+            None => return,
+        };
+        let lichess_join = self.lichess_join.take().unwrap();
+        match result {
+            Ok((me, opponent)) => {
+                info!("Joined a Lichess game as {}", me);
+                self.user_colour = me;
+                if me == Player::White {
+                    self.white_request_sender = Some(lichess_join.request_sender);
+                    self.white_update_receiver = Some(lichess_join.update_receiver);
+                } else {
+                    self.black_request_sender = Some(lichess_join.request_sender);
+                    self.black_update_receiver = Some(lichess_join.update_receiver);
+                    self.piece_hitboxes = board_hitboxes(self.piece_hitboxes[0][0].width, true);
+                    self.redraw_all_squares = true;
+                }
+                self.show_bottom_game_info(
+                    GameBottomInfo::Info(format!("Playing {} against {}", me, opponent)),
+                    None,
+                    None,
+                );
+            }
+            Err(err) => {
+                error!("Failed to join a Lichess game: {:?}", err);
+                self.show_bottom_game_info(
+                    GameBottomInfo::Error(format!("[Lichess] {}", err)),
+                    None,
+                    None,
+                );
+            }
+        }
+    }
+
+    /// Shows the review button once the review is done
+    fn poll_review(&mut self, canvas: &mut Canvas) {
+        let result = match self.review_receiver {
+            Some(ref mut review_receiver) => match review_receiver.try_recv() {
+                Ok(result) => result,
+                Err(oneshot::error::TryRecvError::Empty) => return,
+                Err(oneshot::error::TryRecvError::Closed) => Err(anyhow!("Review stopped")),
+            },
+            None => return,
+        };
+        self.review_receiver = None;
+        match result {
+            Ok((game, pgn)) => {
+                info!("Review saved to {:?}", pgn.path);
+                self.review = Some((game, pgn));
+                if self.review_button_hitbox.is_none() {
+                    self.draw_review_button(canvas);
+                }
+                self.show_bottom_game_info(
+                    GameBottomInfo::Info("Review saved to the PGN viewer.".to_owned()),
+                    None,
+                    Some(Duration::from_secs(10)),
+                );
+            }
+            Err(e) => {
+                error!("Review failed: {:?}", e);
+                self.show_bottom_game_info(
+                    GameBottomInfo::Error(format!("Review failed: {}", e)),
+                    None,
+                    Some(Duration::from_secs(10)),
+                );
+            }
+        }
+    }
+
+    /// Replaces the hint button (not needed anymore) or comes after the
+    /// refresh button
+    fn draw_review_button(&mut self, canvas: &mut Canvas) {
+        if let Some(hint_button_hitbox) = self.hint_button_hitbox.take() {
+            canvas.fill_rect(
+                Point2 {
+                    x: Some(hint_button_hitbox.left as i32),
+                    y: Some(hint_button_hitbox.top as i32),
+                },
+                Vector2 {
+                    x: hint_button_hitbox.width,
+                    y: hint_button_hitbox.height,
+                },
+                color::WHITE,
+            );
+            canvas.update_partial(&hint_button_hitbox);
+        }
+        let refresh_button_hitbox = match self.full_refresh_button_hitbox {
+            Some(hitbox) => hitbox,
+            None => return,
+        };
+        let review_button_hitbox = canvas.draw_button(
+            Point2 {
+                x: Some(refresh_button_hitbox.left as i32 + refresh_button_hitbox.width as i32 + 50),
+                y: Some(90),
+            },
+            "Review",
+            75.0,
+            10,
+            20,
+        );
+        canvas.update_partial(&review_button_hitbox);
+        self.review_button_hitbox = Some(review_button_hitbox);
+    }
+
+    /// Shows a bot's search (or stops showing it on `None`)
+    fn set_bot_thinking(&mut self, depth: Option<u16>) {
+        if self.bot_thinking != depth {
+            self.bot_thinking = depth;
+            self.redraw_bot_thinking = true;
+        }
+    }
+
+    /// Replaces the thinking indicator. A new depth waits for the redraw
+    /// interval, removing it happens at once.
+    fn draw_bot_thinking(&mut self, canvas: &mut Canvas) {
+        if !self.redraw_bot_thinking {
+            return;
+        }
+        let throttled = self
+            .bot_thinking_drawn_at
+            .and_then(|drawn_at| drawn_at.elapsed().ok())
+            .map(|elapsed| elapsed < BOT_THINKING_REDRAW_INTERVAL)
+            .unwrap_or(false);
+        if self.bot_thinking.is_some() && throttled {
+            return;
+        }
+
+        if let Some(last_rect) = self.bot_thinking_last_rect.take() {
+            canvas.fill_rect(
+                Point2 {
+                    x: Some(last_rect.left as i32),
+                    y: Some(last_rect.top as i32),
+                },
+                Vector2 {
+                    x: last_rect.width,
+                    y: last_rect.height,
+                },
+                color::WHITE,
+            );
+            canvas.update_partial(&last_rect);
+        }
+        if let Some(depth) = self.bot_thinking {
+            let rect = canvas.draw_text(
+                Point2 {
+                    x: None,
+                    y: Some(BOT_THINKING_Y),
+                },
+                &format!("thinking\u{2026} depth {}", depth),
+                35.0,
+            );
+            canvas.update_partial(&rect);
+            self.bot_thinking_last_rect = Some(rect);
+            self.bot_thinking_drawn_at = Some(SystemTime::now());
+        }
+        self.redraw_bot_thinking = false;
+    }
+
     /// Depending on the durations of show_after and clear_after,
     /// previous text can be removed with a delay before displaying
     /// a new one or the new text can be removed after some time.
@@ -548,6 +1101,7 @@ impl GameScene {
                 .send(ChessRequest::MovePiece {
                     source: src,
                     destination: dest,
+                    promotion: None,
                 })
                 .await
                 .ok();
@@ -636,7 +1190,10 @@ impl GameScene {
                         self.set_move_hints(selected_square);
                     }
                 }
-                ChessUpdate::Outcome { outcome } => self.handle_outcome(outcome),
+                ChessUpdate::Outcome { outcome } => {
+                    self.set_bot_thinking(None);
+                    self.handle_outcome(outcome)
+                }
                 ChessUpdate::MovePieceFailedResponse { fen, message } => {
                     self.update_board(&fen);
                     self.show_bottom_game_info(
@@ -649,6 +1206,7 @@ impl GameScene {
                     player,
                     moved_piece_source,
                     moved_piece_destination,
+                    ..
                 } => {
                     let is_local_user = self.is_local_user(player);
                     if !is_local_user {
@@ -663,7 +1221,14 @@ impl GameScene {
                     info!("{} (is_local_user: {}) made a move", player, is_local_user);
                 }
                 ChessUpdate::PlayerSwitch { player, ref fen } => {
+                    // Sent after every move and undo
+                    self.set_bot_thinking(None);
                     self.update_board(fen);
+                    self.track_position(fen);
+                    // Sent after the outcome
+                    if self.is_game_over {
+                        self.start_review();
+                    }
                     // TODO: Better message depending on game mode
                     if !self.is_game_over {
                         let message = if !self.is_local_user(player) {
@@ -708,6 +1273,55 @@ impl GameScene {
                     Some(Duration::from_secs(10)),
                 ),
                 ChessUpdate::CurrentTotalMovesReponse { .. } => {}
+                ChessUpdate::ChatMessage { username, text } => self.show_bottom_game_info(
+                    GameBottomInfo::Info(format!("{}: {}", username, text)),
+                    None,
+                    Some(Duration::from_secs(10)),
+                ),
+                ChessUpdate::Hint {
+                    source,
+                    destination,
+                    line,
+                    hints_taken,
+                } => {
+                    self.hints_taken[player as usize] = hints_taken;
+                    // Shown like a move of the opponent
+                    self.clear_last_moved_hints();
+                    self.last_move_from = Some(source);
+                    self.last_move_to = Some(destination);
+                    self.redraw_squares.insert(source);
+                    self.redraw_squares.insert(destination);
+                    self.show_bottom_game_info(
+                        GameBottomInfo::Info(format!(
+                            "Hint: {} ({} taken)",
+                            line.join(" "),
+                            hints_taken
+                        )),
+                        None,
+                        Some(Duration::from_secs(10)),
+                    );
+                }
+                ChessUpdate::HintFailedResponse { message } => self.show_bottom_game_info(
+                    GameBottomInfo::Error(format!("No hint: {}", message)),
+                    None,
+                    Some(Duration::from_secs(10)),
+                ),
+                ChessUpdate::Evaluation {
+                    evaluation: Evaluation::Tablebase(verdict),
+                } => self.show_bottom_game_info(
+                    GameBottomInfo::Info(format!("Tablebase: {}", verdict)),
+                    None,
+                    Some(Duration::from_secs(5)),
+                ),
+                // Only the analysis sends these
+                ChessUpdate::Evaluation {
+                    evaluation: Evaluation::Search { .. },
+                } => {}
+                ChessUpdate::BotThinking { player, thinking } => {
+                    if !self.is_local_user(player) {
+                        self.set_bot_thinking(Some(thinking.depth));
+                    }
+                }
             }
         }
     }
@@ -734,13 +1348,34 @@ impl Scene for GameScene {
                         {
                             // Save game
                             let fen = self.board.fen();
+                            let colour = if self.game_mode == GameMode::PvP {
+                                None
+                            } else {
+                                Some(self.user_colour)
+                            };
                             let mut savesstates = crate::SAVESTATES.lock().unwrap();
                             match self.savestate_slot {
-                                SavestateSlot::First => savesstates.slot_1 = Some(fen),
-                                SavestateSlot::Second => savesstates.slot_2 = Some(fen),
-                                SavestateSlot::Third => savesstates.slot_3 = Some(fen),
+                                Some(SavestateSlot::First) => {
+                                    savesstates.slot_1 = Some(fen);
+                                    savesstates.slot_1_colour = colour;
+                                    savesstates.slot_1_hints = Some(self.hints_taken);
+                                }
+                                Some(SavestateSlot::Second) => {
+                                    savesstates.slot_2 = Some(fen);
+                                    savesstates.slot_2_colour = colour;
+                                    savesstates.slot_2_hints = Some(self.hints_taken);
+                                }
+                                Some(SavestateSlot::Third) => {
+                                    savesstates.slot_3 = Some(fen);
+                                    savesstates.slot_3_colour = colour;
+                                    savesstates.slot_3_hints = Some(self.hints_taken);
+                                }
+                                None => {}
                             }
-                            if let Err(err) = crate::savestates::write(&savesstates) {
+                            if self.savestate_slot.is_none() {
+                                // The game continues online
+                                self.back_button_pressed = true;
+                            } else if let Err(err) = crate::savestates::write(&savesstates) {
                                 error!("Failed to write savestates file!");
                                 self.show_bottom_game_info(
                                     GameBottomInfo::Error(format!("{}", err)),
@@ -758,10 +1393,11 @@ impl Scene for GameScene {
                             let undo_count: u16 = if self.game_mode == GameMode::PvP {
                                 1
                             } else {
-                                if let Player::Black = self.board.turn().into() {
-                                    1
-                                } else {
+                                // Also take back the bot's reply when it is the user's turn
+                                if Player::from(self.board.turn()) == self.user_colour {
                                     2
+                                } else {
+                                    1
                                 }
                             };
                             let sender = if self.is_game_over {
@@ -799,6 +1435,36 @@ impl Scene for GameScene {
                                 });
                             }
                         }
+                        if self.review_button_hitbox.is_some()
+                            && Canvas::is_hitting(finger.pos, self.review_button_hitbox.unwrap())
+                        {
+                            self.review_button_pressed = true;
+                        }
+                        if self.hint_button_hitbox.is_some()
+                            && Canvas::is_hitting(finger.pos, self.hint_button_hitbox.unwrap())
+                        {
+                            let sender = match self.board.turn().into() {
+                                Player::Black => self.black_request_sender.clone(),
+                                Player::White => self.white_request_sender.clone(),
+                            };
+                            match sender {
+                                Some(sender) if !self.is_game_over => {
+                                    self.runtime.spawn(async move {
+                                        sender.send(ChessRequest::Hint).await.ok();
+                                    });
+                                    self.show_bottom_game_info(
+                                        GameBottomInfo::Info("Thinking about a hint...".to_owned()),
+                                        None,
+                                        None,
+                                    );
+                                }
+                                _ => self.show_bottom_game_info(
+                                    GameBottomInfo::Info("You can't get a hint right now.".to_owned()),
+                                    None,
+                                    Some(Duration::from_secs(3)),
+                                ),
+                            }
+                        }
                         if self.full_refresh_button_hitbox.is_some()
                             && Canvas::is_hitting(
                                 finger.pos,
@@ -925,11 +1591,32 @@ impl Scene for GameScene {
                     ),
                     y: Some(90),
                 },
-                "Refresh Screen",
+                // Shorter to leave room for the hint button
+                if self.hints_enabled {
+                    "Refresh"
+                } else {
+                    "Refresh Screen"
+                },
                 75.0,
                 10,
                 20,
             ));
+            if self.hints_enabled {
+                self.hint_button_hitbox = Some(canvas.draw_button(
+                    Point2 {
+                        x: Some(
+                            self.full_refresh_button_hitbox.unwrap().left as i32
+                                + self.full_refresh_button_hitbox.unwrap().width as i32
+                                + 50,
+                        ),
+                        y: Some(90),
+                    },
+                    "Hint",
+                    75.0,
+                    10,
+                    20,
+                ));
+            }
             self.redraw_all_squares = true;
             self.draw_board(canvas);
             canvas.update_full();
@@ -938,6 +1625,7 @@ impl Scene for GameScene {
             self.force_full_refresh = Some(SystemTime::now() + Duration::from_millis(250));
         }
 
+        self.poll_lichess_join();
         // Handle received `ChessUpdate`s
         if self.white_update_receiver.is_some() {
             let mut update_receiver = self.white_update_receiver.take().unwrap();
@@ -949,6 +1637,10 @@ impl Scene for GameScene {
             self.handle_updates(Player::Black, &mut update_receiver);
             self.black_update_receiver = Some(update_receiver);
         }
+        if let Some(ref mut evaluation_bar) = self.evaluation_bar {
+            evaluation_bar.draw(canvas);
+        }
+        self.poll_review(canvas);
 
         // Apply bot move
         /*
@@ -991,6 +1683,8 @@ impl Scene for GameScene {
             self.redraw_all_squares = false;
         }
 
+        self.draw_bot_thinking(canvas);
+
         // Do forced refresh on request
         if self.force_full_refresh.is_some() && self.force_full_refresh.unwrap() < SystemTime::now()
         {
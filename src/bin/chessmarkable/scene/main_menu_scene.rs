@@ -15,6 +15,10 @@ pub struct MainMenuScene {
     pub play_normal_button_pressed: bool,
     play_hard_button_hitbox: Option<mxcfb_rect>,
     pub play_hard_button_pressed: bool,
+    play_engine_button_hitbox: Option<mxcfb_rect>,
+    pub play_engine_button_pressed: bool,
+    play_lichess_button_hitbox: Option<mxcfb_rect>,
+    pub play_lichess_button_pressed: bool,
     viewer_button_hitbox: Option<mxcfb_rect>,
     pub viewer_button_pressed: bool,
 
@@ -42,6 +46,10 @@ impl MainMenuScene {
             play_normal_button_pressed: false,
             play_hard_button_hitbox: None,
             play_hard_button_pressed: false,
+            play_engine_button_hitbox: None,
+            play_engine_button_pressed: false,
+            play_lichess_button_hitbox: None,
+            play_lichess_button_pressed: false,
             viewer_button_hitbox: None,
             viewer_button_pressed: false,
             exit_button_hitbox: None,
@@ -212,6 +220,34 @@ impl Scene for MainMenuScene {
             50,
         ));
 
+        if crate::config::uci_engine().is_some() {
+            // Only offered when an engine is configured
+            self.play_engine_button_hitbox = Some(canvas.draw_button(
+                Point2 {
+                    x: Some(50),
+                    y: Some(90),
+                },
+                "Engine",
+                75.0,
+                10,
+                20,
+            ));
+        }
+
+        if crate::CONFIG.lichess.is_some() {
+            // Only offered when a token is configured
+            self.play_lichess_button_hitbox = Some(canvas.draw_button(
+                Point2 {
+                    x: Some(DISPLAYWIDTH as i32 - 300),
+                    y: Some(90),
+                },
+                "Lichess",
+                75.0,
+                10,
+                20,
+            ));
+        }
+
         if self.only_exit_to_xochitl {
             self.exit_xochitl_button_hitbox = Some(canvas.draw_button(
                 Point2 {
@@ -265,6 +301,14 @@ impl Scene for MainMenuScene {
                     && Canvas::is_hitting(position, self.play_hard_button_hitbox.unwrap())
                 {
                     self.play_hard_button_pressed = true;
+                } else if self.play_engine_button_hitbox.is_some()
+                    && Canvas::is_hitting(position, self.play_engine_button_hitbox.unwrap())
+                {
+                    self.play_engine_button_pressed = true;
+                } else if self.play_lichess_button_hitbox.is_some()
+                    && Canvas::is_hitting(position, self.play_lichess_button_hitbox.unwrap())
+                {
+                    self.play_lichess_button_pressed = true;
                 } else if self.viewer_button_hitbox.is_some()
                     && Canvas::is_hitting(position, self.viewer_button_hitbox.unwrap())
                 {
@@ -1,4 +1,5 @@
 mod board_select_scene;
+mod evaluation_bar;
 mod game_scene;
 mod main_menu_scene;
 mod pgn_select_scene;
@@ -6,7 +7,7 @@ mod replay_scene;
 mod piece_images;
 
 pub use board_select_scene::BoardSelectScene;
-pub use game_scene::{GameMode, GameScene, SavestateSlot};
+pub use game_scene::{ColourChoice, GameMode, GameScene, SavestateSlot};
 pub use main_menu_scene::MainMenuScene;
 pub use pgn_select_scene::PgnSelectScene;
 pub use replay_scene::ReplayScene;
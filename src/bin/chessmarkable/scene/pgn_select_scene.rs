@@ -45,6 +45,8 @@ pub struct PgnSelectScene {
     pub return_to_main_menu: bool,
 
     indicate_loading: bool,
+    /// Why the chosen game couldn't be opened
+    error: Option<String>,
 }
 
 impl PgnSelectScene {
@@ -72,6 +74,7 @@ impl PgnSelectScene {
             back_button_hitbox: None,
             return_to_main_menu: false,
             indicate_loading: false,
+            error: None,
             selected_pgn_changed,
             selected_pgn,
             pgn_vec: vec![],
@@ -148,21 +151,27 @@ impl Scene for PgnSelectScene {
                 let mut file = File::open(self.selected_pgn.as_ref().unwrap().path.to_str().unwrap()).unwrap();
                 let mut png_file_contents = String::new();
                 file.read_to_string(&mut png_file_contents).expect("Unable to read file");
-                //Library doesn't play nice with comments inside brackets
-                //This gets rid of up to two levels of bracket nesting
-                let re = Regex::new(r"\((?:[^)(]|\((?:[^)(]|\([^)(]*\))*\))*\)").unwrap();
-                let result = re.replace_all(png_file_contents.as_str(), "");
                 let re = Regex::new(r"\n").unwrap();
-                let result = re.replace_all(&result, " ");
+                let result = re.replace_all(png_file_contents.as_str(), " ");
                 let re = Regex::new(r"\s\s").unwrap();
                 let result = re.replace_all(&result, " ");
-                self.game_vec = match read_games(&result) {
+                let parse = |text: &str| match read_games(text) {
                     Ok(games) => games,
                     Err(e) => {
                         println!("{:?}", e);
                         vec![]
                     }
                 };
+                let with_variations = parse(&result);
+                //Library doesn't play nice with comments after variations
+                //Without up to two levels of bracket nesting, more games may get parsed
+                let re = Regex::new(r"\((?:[^)(]|\((?:[^)(]|\([^)(]*\))*\))*\)").unwrap();
+                let without_variations = parse(&re.replace_all(&result, ""));
+                self.game_vec = if without_variations.len() > with_variations.len() {
+                    without_variations
+                } else {
+                    with_variations
+                };
                 self.total_pages = (self.game_vec.len() as f64 / REPLAYS_PER_PAGE as f64).ceil() as u32;
                 self.selected_pgn_changed = false;
             }
@@ -201,6 +210,10 @@ impl Scene for PgnSelectScene {
             "chessMarkable",
             150.0,
         );
+        // Shown until the next redraw
+        if let Some(error) = self.error.take() {
+            canvas.draw_multi_line_text(None, 1520, &error, 60, 2, 40.0, 0.6);
+        }
         let back_button_text = match choose_pgn_mode {
             true => "Main Menu",
             false => "PGNs"
@@ -329,6 +342,19 @@ impl Scene for PgnSelectScene {
 }
 
 impl PgnSelectScene {
+    /// Stays on the games and tells why the chosen one can't be opened
+    pub fn show_error(&mut self, message: String) {
+        self.button_1_pressed = false;
+        self.button_2_pressed = false;
+        self.button_3_pressed = false;
+        self.button_4_pressed = false;
+        self.button_5_pressed = false;
+        self.button_6_pressed = false;
+        self.indicate_loading = false;
+        self.error = Some(message);
+        self.drawn = false;
+    }
+
     fn go_to_next_page(&mut self) {
         if self.current_page_number + 1 < self.total_pages {
             self.drawn = false;
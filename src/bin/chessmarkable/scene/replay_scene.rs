@@ -16,20 +16,54 @@ use crate::scene::game_scene::IMG_PIECE_SELECTED;
 use crate::scene::game_scene::IMG_PIECE_MOVEHINT;
 use crate::scene::game_scene::IMG_PIECE_MOVED_FROM;
 use crate::pgns::Pgn;
+use crate::scene::evaluation_bar::{self, EvaluationBar};
+use chessmarkable::analysis::create_analysis;
+use chessmarkable::proto::ChessUpdate;
+use tokio::runtime;
+use tokio::sync::mpsc::Sender;
+use anyhow::{Context, Result};
 
 
+/// More variations of a move are not offered (to fit on the board)
+const MAX_BRANCH_CHOICES: usize = 8;
+/// Moves of the move list shown over the board per row (two full moves)
+const MOVE_LIST_COLUMNS: usize = 4;
+const MOVE_LIST_ROWS: usize = 12;
+const MOVE_LIST_PAGE_SIZE: usize = MOVE_LIST_COLUMNS * MOVE_LIST_ROWS;
+
 #[inline]
 fn to_square(x: usize, y: usize) -> Square {
     Square::new(x, y).expect("to_square() failed")
 }
 
+/// An entry of the menu shown over the board when the next move has
+/// variations
+#[derive(Clone, Copy)]
+enum BranchChoice {
+    /// One of `Replay::alternatives()`
+    Play(usize),
+    LeaveVariation,
+    Cancel,
+}
+
+/// A tap target of the move list
+#[derive(Clone, Copy)]
+enum MoveListAction {
+    /// Position after this many moves
+    GoTo(usize),
+    Page(usize),
+    Start,
+    End,
+    Close,
+}
+
 pub struct ReplayScene {
     board: Board,
     first_draw: bool,
     back_button_hitbox: Option<mxcfb_rect>,
     undo_button_hitbox: Option<mxcfb_rect>,
     next_move_button_hitbox: Option<mxcfb_rect>,
-    reset_button_hitbox: Option<mxcfb_rect>,
+    moves_button_hitbox: Option<mxcfb_rect>,
     full_refresh_button_hitbox: Option<mxcfb_rect>,
     piece_hitboxes: Vec<Vec<mxcfb_rect>>,
     /// The squared that were visually affected and should be redrawn
@@ -58,16 +92,35 @@ pub struct ReplayScene {
     is_game_over: bool,
     possible_moves: Vec<(Square, Square)>,
     replay: Replay,
+    /// Draw the menu to choose the next move
+    branch_menu_requested: bool,
+    /// Hitboxes of the shown menu (empty if there is none)
+    branch_menu: Vec<(mxcfb_rect, BranchChoice)>,
+    /// Page of the move list shown over the board (`None` when closed)
+    move_list_page: Option<usize>,
+    redraw_move_list: bool,
+    move_list: Vec<(mxcfb_rect, MoveListAction)>,
     pub selected_pgn: Option<Pgn>,
+    /// Gets every shown position
+    analysis: Option<Sender<ChessUpdate>>,
+    evaluation_bar: Option<EvaluationBar>,
+    /// Keeps the analysis running
+    _runtime: Option<runtime::Runtime>,
 }
 
 impl ReplayScene {
+    /// Fails if the game can't be replayed (e.g. it starts from an invalid
+    /// FEN)
     pub fn new(
         replay_info: Option<Game>,
         selected_pgn: Option<Pgn>,
-    ) -> Self {
+    ) -> Result<Self> {
+        let replay = Replay::new(replay_info.context("Couldn't read Replay Info")?)?;
+        let board = Board::from_fen(&replay.fen())
+            .map_err(|e| anyhow!("Invalid start position: {:?}", e))?;
+        let analysis_limits = crate::config::analysis(true);
         // Size of board
-        let square_size = DISPLAYWIDTH as u32 / 8;
+        let square_size = evaluation_bar::square_size(analysis_limits.is_some());
         let piece_padding = square_size / 10;
         let overlay_padding = square_size / 20;
 
@@ -113,9 +166,24 @@ impl ReplayScene {
         let img_piece_moved_to =
             IMG_PIECE_MOVED_TO.resize(square_size, square_size, FilterType::Lanczos3);
 
+        let (runtime, analysis, evaluation_bar) = match analysis_limits {
+            Some(limits) => {
+                let runtime = runtime::Builder::new_multi_thread()
+                    .thread_name("tokio_replay_analysis")
+                    .build()
+                    .expect("Failed to create tokio runtime");
+                let ((analysis, _), evaluations) = {
+                    let _runtime = runtime.enter();
+                    create_analysis(limits)
+                };
+                (Some(runtime), Some(analysis), Some(EvaluationBar::new(evaluations, true)))
+            }
+            None => (None, None, None),
+        };
+
         //Replay Info
-        Self {
-            board: Board::default(), // Temporary default (usually stays that but will change when having a custom fen)
+        let mut scene = Self {
+            board,
             first_draw: true,
             piece_hitboxes,
             piece_padding,
@@ -135,16 +203,41 @@ impl ReplayScene {
             back_button_hitbox: None,
             undo_button_hitbox: None,
             next_move_button_hitbox: None,
-            reset_button_hitbox: None,
+            moves_button_hitbox: None,
             full_refresh_button_hitbox: None,
             move_comment: None,
             return_to_main_menu: false,
             force_full_refresh: None,
             is_game_over: false,
             possible_moves: vec![],
-            replay: Replay::new(replay_info.expect("Couldn't read Replay Info")),
+            replay,
             move_comment_last_rect: None,
+            branch_menu_requested: false,
+            branch_menu: vec![],
+            move_list_page: None,
+            redraw_move_list: false,
+            move_list: vec![],
             selected_pgn,
+            analysis,
+            evaluation_bar,
+            _runtime: runtime,
+        };
+        // Shows the comment before the first move
+        let response = scene.replay.go_to_start();
+        scene.play_replay_move(response);
+        Ok(scene)
+    }
+
+    /// Lets the evaluation bar show the current position
+    fn analyse_position(&self) {
+        if let Some(ref analysis) = self.analysis {
+            let update = ChessUpdate::PlayerSwitch {
+                player: self.board.turn().into(),
+                fen: self.board.fen(),
+            };
+            if let Err(e) = analysis.try_send(update) {
+                warn!("Failed to analyse position: {}", e);
+            }
         }
     }
 
@@ -357,26 +450,273 @@ impl ReplayScene {
         self.board = new_board;
     }
 
+    /// Plays the next move or lets the user choose when there are
+    /// variations (or the end of one is reached)
+    fn next_move(&mut self) {
+        let alternatives = self.replay.alternatives();
+        if alternatives.len() > 1 || (alternatives.is_empty() && self.replay.in_variation()) {
+            self.branch_menu_requested = true;
+        } else {
+            let response = self.replay.play_replay_move();
+            self.play_replay_move(response);
+        }
+    }
+
+    /// Covers the board with a button per choice
+    fn draw_branch_menu(&mut self, canvas: &mut Canvas) {
+        let board = self.full_board_rect();
+        canvas.fill_rect(
+            Point2 {
+                x: Some(board.left as i32),
+                y: Some(board.top as i32),
+            },
+            Vector2 {
+                x: board.width,
+                y: board.height,
+            },
+            color::WHITE,
+        );
+        let mut choices: Vec<(String, BranchChoice)> = self
+            .replay
+            .alternatives()
+            .into_iter()
+            .take(MAX_BRANCH_CHOICES)
+            .enumerate()
+            .map(|(alternative, san)| {
+                let text = if alternative == 0 {
+                    format!("{} (this line)", san)
+                } else {
+                    san
+                };
+                (text, BranchChoice::Play(alternative))
+            })
+            .collect();
+        if self.replay.in_variation() {
+            choices.push(("Leave variation".to_owned(), BranchChoice::LeaveVariation));
+        }
+        choices.push(("Cancel".to_owned(), BranchChoice::Cancel));
+
+        canvas.draw_text(
+            Point2 {
+                x: None,
+                y: Some(board.top as i32 + 100),
+            },
+            "Choose the next move",
+            60.0,
+        );
+        self.branch_menu = choices
+            .into_iter()
+            .enumerate()
+            .map(|(i, (text, choice))| {
+                let hitbox =
+                    canvas.draw_box_button(board.top as i32 + 150 + i as i32 * 120, 100, &text, 50.0);
+                (hitbox, choice)
+            })
+            .collect();
+        canvas.update_partial(&board);
+        self.branch_menu_requested = false;
+    }
+
+    fn on_branch_choice(&mut self, choice: BranchChoice) {
+        self.branch_menu.clear();
+        self.redraw_all_squares = true;
+        let response = match choice {
+            BranchChoice::Play(alternative) => self.replay.play_alternative(alternative),
+            BranchChoice::LeaveVariation => self.replay.leave_variation(),
+            BranchChoice::Cancel => return,
+        };
+        self.play_replay_move(response);
+    }
+
+    /// A menu or the move list covers the board and takes all taps
+    fn is_overlay_shown(&self) -> bool {
+        !self.branch_menu.is_empty() || self.move_list_page.is_some()
+    }
+
+    fn on_overlay_tap(&mut self, pos: Point2<u16>) {
+        let choice = self
+            .branch_menu
+            .iter()
+            .find(|(hitbox, _)| Canvas::is_hitting(pos, *hitbox))
+            .map(|(_, choice)| *choice);
+        if let Some(choice) = choice {
+            self.on_branch_choice(choice);
+        }
+        let action = self
+            .move_list
+            .iter()
+            .find(|(hitbox, _)| Canvas::is_hitting(pos, *hitbox))
+            .map(|(_, action)| *action);
+        if let Some(action) = action {
+            self.on_move_list_action(action);
+        }
+    }
+
+    /// Opens the move list on the page of the current move
+    fn show_move_list(&mut self) {
+        self.move_list_page = Some(self.replay.ply().saturating_sub(1) / MOVE_LIST_PAGE_SIZE);
+        self.redraw_move_list = true;
+    }
+
+    /// Covers the board with a page of the moves of the current line. The
+    /// current move is framed.
+    fn draw_move_list(&mut self, canvas: &mut Canvas) {
+        let board = self.full_board_rect();
+        canvas.fill_rect(
+            Point2 {
+                x: Some(board.left as i32),
+                y: Some(board.top as i32),
+            },
+            Vector2 {
+                x: board.width,
+                y: board.height,
+            },
+            color::WHITE,
+        );
+        let moves = self.replay.line_moves();
+        let pages = moves.len().div_ceil(MOVE_LIST_PAGE_SIZE).max(1);
+        let page = self.move_list_page.unwrap_or(0).min(pages - 1);
+        self.move_list_page = Some(page);
+        self.move_list.clear();
+
+        canvas.draw_text(
+            Point2 {
+                x: None,
+                y: Some(board.top as i32 + 80),
+            },
+            &format!("Moves (page {}/{})", page + 1, pages),
+            50.0,
+        );
+        let cell_width = board.width / MOVE_LIST_COLUMNS as u32;
+        let cell_height = 90;
+        let first = page * MOVE_LIST_PAGE_SIZE;
+        for (i, san) in moves.iter().enumerate().skip(first).take(MOVE_LIST_PAGE_SIZE) {
+            let cell = mxcfb_rect {
+                left: board.left + ((i - first) % MOVE_LIST_COLUMNS) as u32 * cell_width,
+                top: board.top + 120 + ((i - first) / MOVE_LIST_COLUMNS) as u32 * cell_height,
+                width: cell_width,
+                height: cell_height,
+            };
+            canvas.draw_text(
+                Point2 {
+                    x: Some(cell.left as i32 + 25),
+                    y: Some(cell.top as i32 + 60),
+                },
+                san,
+                40.0,
+            );
+            if i + 1 == self.replay.ply() {
+                canvas.draw_rect(
+                    Point2 {
+                        x: Some(cell.left as i32 + 5),
+                        y: Some(cell.top as i32 + 5),
+                    },
+                    Vector2 {
+                        x: cell.width - 10,
+                        y: cell.height - 10,
+                    },
+                    3,
+                );
+            }
+            self.move_list.push((cell, MoveListAction::GoTo(i + 1)));
+        }
+
+        let mut actions = vec![("Start", MoveListAction::Start)];
+        if page > 0 {
+            actions.push(("<<", MoveListAction::Page(page - 1)));
+        }
+        if page + 1 < pages {
+            actions.push((">>", MoveListAction::Page(page + 1)));
+        }
+        actions.push(("End", MoveListAction::End));
+        actions.push(("Close", MoveListAction::Close));
+        let button_width = board.width as i32 / actions.len() as i32;
+        for (i, (text, action)) in actions.into_iter().enumerate() {
+            let hitbox = canvas.draw_button(
+                Point2 {
+                    x: Some(board.left as i32 + i as i32 * button_width + 50),
+                    y: Some((board.top + board.height) as i32 - 80),
+                },
+                text,
+                50.0,
+                15,
+                30,
+            );
+            self.move_list.push((hitbox, action));
+        }
+        canvas.update_partial(&board);
+        self.redraw_move_list = false;
+    }
+
+    fn on_move_list_action(&mut self, action: MoveListAction) {
+        let response = match action {
+            MoveListAction::GoTo(ply) => self.replay.go_to_ply(ply),
+            MoveListAction::Start => self.replay.reset(),
+            MoveListAction::End => self.replay.go_to_end(),
+            MoveListAction::Page(page) => {
+                self.move_list_page = Some(page);
+                self.redraw_move_list = true;
+                return;
+            }
+            MoveListAction::Close => {
+                self.close_move_list();
+                return;
+            }
+        };
+        self.close_move_list();
+        self.play_replay_move(response);
+    }
+
+    fn close_move_list(&mut self) {
+        self.move_list_page = None;
+        self.move_list.clear();
+        self.redraw_all_squares = true;
+    }
+
     fn play_replay_move(&mut self, replay_response: ReplayResponse) {
         self.update_board(&replay_response.fen);
         self.clear_state_post_move();
-        self.move_comment = replay_response.comment;
+        // The annotated move first (e.g. "3... Nf6?? Very poor move")
+        let annotated_move = if replay_response.annotation_symbol.is_some() || replay_response.nag.is_some() {
+            let played = self.replay.ply().checked_sub(1).and_then(|ply| self.replay.line_moves().get(ply).cloned());
+            let played = played.or_else(|| replay_response.annotation_symbol.map(str::to_owned));
+            Some(match (played, replay_response.nag) {
+                (Some(played), Some(nag)) => format!("{} {}", played, nag),
+                (played, nag) => played.or(nag).unwrap_or_default(),
+            })
+        } else {
+            None
+        };
+        let texts: Vec<String> = vec![
+            replay_response.game_comment,
+            replay_response.warning,
+            annotated_move,
+            replay_response.comment,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        self.move_comment = if texts.is_empty() { None } else { Some(texts.join(" - ")) };
         self.last_move_from = replay_response.last_move_from;
         self.last_move_to = replay_response.last_move_to;
+        self.analyse_position();
     }
 }
 
 impl Scene for ReplayScene {
     fn on_input(&mut self, event: InputEvent) {
         match event {
+            // Menus and the move list only take taps on them
+            InputEvent::GPIO { .. } if self.is_overlay_shown() => {}
+            InputEvent::MultitouchEvent {
+                event: MultitouchEvent::Release { finger },
+            } if self.is_overlay_shown() => self.on_overlay_tap(finger.pos),
+            InputEvent::MultitouchEvent { .. } if self.is_overlay_shown() => {}
             InputEvent::GPIO { event } => {
                 match event {
                     GPIOEvent::Press { button } => {
                         match button {
-                            PhysicalButton::RIGHT => {
-                                let response = self.replay.play_replay_move();
-                                self.play_replay_move(response);
-                            },
+                            PhysicalButton::RIGHT => self.next_move(),
                             PhysicalButton::LEFT => {
                                 let response = self.replay.undo_move();
                                 self.play_replay_move(response);
@@ -415,15 +755,13 @@ impl Scene for ReplayScene {
                             self.next_move_button_hitbox.unwrap(),
                         )
                         {
-                            let response = self.replay.play_replay_move();
-                            self.play_replay_move(response);
-                        } else if self.reset_button_hitbox.is_some()
+                            self.next_move();
+                        } else if self.moves_button_hitbox.is_some()
                             && Canvas::is_hitting(
                             finger.pos,
-                            self.reset_button_hitbox.unwrap(),
+                            self.moves_button_hitbox.unwrap(),
                         ) {
-                            let response = self.replay.reset();
-                            self.play_replay_move(response);
+                            self.show_move_list();
                         } else if self.undo_button_hitbox.is_some()
                             && Canvas::is_hitting(
                             finger.pos,
@@ -538,7 +876,7 @@ impl Scene for ReplayScene {
                 10,
                 20,
             ));
-            self.reset_button_hitbox = Some(canvas.draw_button(
+            self.moves_button_hitbox = Some(canvas.draw_button(
                 Point2 {
                     x: Some(
                         self.back_button_hitbox.unwrap().left as i32 +
@@ -547,7 +885,7 @@ impl Scene for ReplayScene {
                     ),
                     y: Some(1770),
                 },
-                "Reset",
+                "Moves",
                 60.0,
                 50,
                 50,
@@ -555,7 +893,7 @@ impl Scene for ReplayScene {
             self.undo_button_hitbox = Some(canvas.draw_button(
                 Point2 {
                     x: Some(
-                        self.reset_button_hitbox.unwrap().left as i32
+                        self.moves_button_hitbox.unwrap().left as i32
                             - 200
                     ),
                     y: Some(1780),
@@ -568,8 +906,8 @@ impl Scene for ReplayScene {
             self.next_move_button_hitbox = Some(canvas.draw_button(
                 Point2 {
                     x: Some(
-                        self.reset_button_hitbox.unwrap().left as i32
-                            + self.reset_button_hitbox.unwrap().width as i32
+                        self.moves_button_hitbox.unwrap().left as i32
+                            + self.moves_button_hitbox.unwrap().width as i32
                             + 150
                     ),
                     y: Some(1780),
@@ -596,6 +934,17 @@ impl Scene for ReplayScene {
             self.redraw_all_squares = false;
         }
 
+        if self.branch_menu_requested {
+            self.draw_branch_menu(canvas);
+        }
+        if self.redraw_move_list {
+            self.draw_move_list(canvas);
+        }
+
+        if let Some(ref mut evaluation_bar) = self.evaluation_bar {
+            evaluation_bar.draw(canvas);
+        }
+
         // Do forced refresh on request
         if self.force_full_refresh.is_some() && self.force_full_refresh.unwrap() < SystemTime::now()
         {
@@ -635,7 +984,8 @@ impl Scene for ReplayScene {
                     40,
                     comment,
                     95,
-                    7, //Comments longer than this will cut into the game screen - Hence a ~660ish characters limit
+                    // Comments longer than this will cut into the game screen - Hence a ~660ish characters limit.
+                    7,
                     35.0,
                     0.6
                 );
@@ -0,0 +1,332 @@
+//! Opening books in the [Polyglot format](http://hgm.nubati.net/book_format.html)
+//! so bots don't play the same openings every game.
+
+use crate::polyglot_random::RANDOM64;
+use anyhow::{Context, Result};
+use pleco::core::CastleType;
+use pleco::tools::prng::PRNG;
+use pleco::{BitMove, Board, PieceType, Player, SQ};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const ENTRY_SIZE: usize = 16;
+const CASTLING_OFFSET: usize = 768;
+const EN_PASSANT_OFFSET: usize = 772;
+const TURN_OFFSET: usize = 780;
+
+/// How to pick among the moves the book knows for a position
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum BookSelection {
+    /// Always the move with the highest weight
+    Best,
+    /// Randomly with the chance given by the weights
+    #[default]
+    Weighted,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BookConfig {
+    /// A Polyglot `.bin` file
+    pub path: PathBuf,
+    #[serde(default)]
+    pub selection: BookSelection,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BookEntry {
+    key: u64,
+    raw_move: u16,
+    weight: u16,
+}
+
+pub struct OpeningBook {
+    /// Sorted by key (as required by the format)
+    entries: Vec<BookEntry>,
+    selection: BookSelection,
+    rng: PRNG,
+}
+
+impl OpeningBook {
+    pub fn open(config: &BookConfig, seed: u64) -> Result<Self> {
+        let bytes = std::fs::read(&config.path)
+            .with_context(|| format!("Read opening book {:?}", config.path))?;
+        Self::from_bytes(&bytes, config.selection, seed)
+            .with_context(|| format!("Opening book {:?}", config.path))
+    }
+
+    pub fn from_bytes(bytes: &[u8], selection: BookSelection, seed: u64) -> Result<Self> {
+        ensure!(
+            bytes.len().is_multiple_of(ENTRY_SIZE),
+            "Not a Polyglot book (size is no multiple of {} bytes)",
+            ENTRY_SIZE
+        );
+        let mut entries: Vec<BookEntry> = bytes
+            .chunks(ENTRY_SIZE)
+            .map(|entry| {
+                let mut key = [0u8; 8];
+                key.copy_from_slice(&entry[0..8]);
+                BookEntry {
+                    key: u64::from_be_bytes(key),
+                    raw_move: u16::from_be_bytes([entry[8], entry[9]]),
+                    weight: u16::from_be_bytes([entry[10], entry[11]]),
+                }
+            })
+            .collect();
+        // Tolerate unsorted files
+        entries.sort_by_key(|entry| entry.key);
+        Ok(Self {
+            entries,
+            selection,
+            // The generator doesn't work with zero
+            rng: PRNG::init(seed.max(1)),
+        })
+    }
+
+    /// Legal moves of the book for the position and their weights
+    pub fn moves(&self, board: &Board) -> Vec<(BitMove, u16)> {
+        let key = polyglot_key(board);
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let legal_moves = board.generate_moves();
+        self.entries[start..]
+            .iter()
+            .take_while(|entry| entry.key == key)
+            .filter_map(|entry| {
+                legal_moves
+                    .iter()
+                    .find(|legal_move| encode_move(**legal_move) == entry.raw_move)
+                    .map(|legal_move| (*legal_move, entry.weight))
+            })
+            .collect()
+    }
+
+    /// A book move for the position (if the book knows it)
+    pub fn choose(&mut self, board: &Board) -> Option<BitMove> {
+        let moves = self.moves(board);
+        match self.selection {
+            BookSelection::Best => moves
+                .iter()
+                .filter(|(_, weight)| *weight > 0)
+                // The first one on equal weights
+                .fold(None, |best: Option<(BitMove, u16)>, candidate| match best {
+                    Some(best) if best.1 >= candidate.1 => Some(best),
+                    _ => Some(*candidate),
+                })
+                .map(|(bit_move, _)| bit_move),
+            BookSelection::Weighted => {
+                let total: u64 = moves.iter().map(|(_, weight)| *weight as u64).sum();
+                if total == 0 {
+                    return None;
+                }
+                let mut pick = self.rng.rand() % total;
+                for (bit_move, weight) in moves {
+                    if pick < weight as u64 {
+                        return Some(bit_move);
+                    }
+                    pick -= weight as u64;
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Polyglot encoding of a move (castling is king onto rook like in pleco)
+fn encode_move(bit_move: BitMove) -> u16 {
+    let square = |sq: SQ| (sq.rank_idx_of_sq() as u16) << 3 | sq.file_idx_of_sq() as u16;
+    let promotion = if bit_move.is_promo() {
+        match bit_move.promo_piece() {
+            PieceType::N => 1,
+            PieceType::B => 2,
+            PieceType::R => 3,
+            _ => 4,
+        }
+    } else {
+        0
+    };
+    promotion << 12 | square(bit_move.get_src()) << 6 | square(bit_move.get_dest())
+}
+
+/// Hash of the position as used by Polyglot books
+pub fn polyglot_key(board: &Board) -> u64 {
+    let mut key = 0;
+    for index in 0..64u8 {
+        let piece = board.piece_at_sq(SQ(index));
+        if let Some(player) = piece.player() {
+            let kind = 2 * (piece.type_of() as usize - 1) + (player == Player::White) as usize;
+            key ^= RANDOM64[64 * kind + index as usize];
+        }
+    }
+
+    let castling = [
+        (Player::White, CastleType::KingSide),
+        (Player::White, CastleType::QueenSide),
+        (Player::Black, CastleType::KingSide),
+        (Player::Black, CastleType::QueenSide),
+    ];
+    for (offset, (player, side)) in castling.iter().enumerate() {
+        if board.can_castle(*player, *side) {
+            key ^= RANDOM64[CASTLING_OFFSET + offset];
+        }
+    }
+
+    // Only if a pawn could actually capture there
+    let en_passant = board.ep_square();
+    if en_passant.is_okay() {
+        let file = en_passant.file_idx_of_sq();
+        let capturing_rank = match board.turn() {
+            Player::White => en_passant.rank_idx_of_sq() - 1,
+            Player::Black => en_passant.rank_idx_of_sq() + 1,
+        };
+        let can_capture = [file.wrapping_sub(1), file + 1]
+            .iter()
+            .filter(|file| **file < 8)
+            .any(|file| {
+                let piece = board.piece_at_sq(SQ(capturing_rank * 8 + file));
+                piece.type_of() == PieceType::P && piece.player() == Some(board.turn())
+            });
+        if can_capture {
+            key ^= RANDOM64[EN_PASSANT_OFFSET + file as usize];
+        }
+    }
+
+    if board.turn() == Player::White {
+        key ^= RANDOM64[TURN_OFFSET];
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test vectors of the format specification
+    const REFERENCE_KEYS: [(&str, u64); 9] = [
+        (
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            0x463b96181691fc9c,
+        ),
+        (
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            0x823c9b50fd114196,
+        ),
+        (
+            "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2",
+            0x0756b94461c50fb0,
+        ),
+        (
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2",
+            0x662fafb965db29d4,
+        ),
+        (
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            0x22a48b5a8e47ff78,
+        ),
+        (
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR b kq - 0 3",
+            0x652a607ca3f242c1,
+        ),
+        (
+            "rnbq1bnr/ppp1pkpp/8/3pPp2/8/8/PPPPKPPP/RNBQ1BNR w - - 0 4",
+            0x00fdd303c946bdd9,
+        ),
+        (
+            "rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3",
+            0x3c8123ea7b067637,
+        ),
+        (
+            "rnbqkbnr/p1pppppp/8/8/P6P/R1p5/1P1PPPP1/1NBQKBNR b Kkq - 0 4",
+            0x5c3f9b829b279560,
+        ),
+    ];
+
+    const START_KEY: u64 = 0x463b96181691fc9c;
+
+    fn entry(key: u64, raw_move: u16, weight: u16) -> Vec<u8> {
+        let mut bytes = key.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&raw_move.to_be_bytes());
+        bytes.extend_from_slice(&weight.to_be_bytes());
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes
+    }
+
+    /// Polyglot move from square indices (a1 = 0)
+    fn raw_move(source: u16, destination: u16) -> u16 {
+        source << 6 | destination
+    }
+
+    fn book(selection: BookSelection, seed: u64) -> OpeningBook {
+        let bytes = [
+            entry(START_KEY, raw_move(12, 28), 3),          // e2e4
+            entry(START_KEY, raw_move(11, 27), 1),          // d2d4
+            entry(START_KEY, raw_move(12, 20), 0),          // e2e3 (never played)
+            entry(START_KEY, raw_move(12, 36), 100),        // e2e5 (illegal)
+            entry(0x823c9b50fd114196, raw_move(52, 36), 1), // e7e5
+        ]
+        .concat();
+        OpeningBook::from_bytes(&bytes, selection, seed).unwrap()
+    }
+
+    #[test]
+    fn keys_match_the_specification() {
+        for (fen, key) in REFERENCE_KEYS.iter() {
+            let board = Board::from_fen(fen).unwrap();
+            assert_eq!(polyglot_key(&board), *key, "Key of {}", fen);
+        }
+    }
+
+    #[test]
+    fn keys_follow_played_moves() {
+        let mut board = Board::start_pos();
+        for uci_move in &["e2e4", "d7d5", "e4e5", "f7f5", "e1e2", "e8f7"] {
+            assert!(board.apply_uci_move(uci_move));
+        }
+        assert_eq!(polyglot_key(&board), REFERENCE_KEYS[6].1);
+    }
+
+    #[test]
+    fn best_move_of_the_book() {
+        let mut book = book(BookSelection::Best, 1);
+        let mut board = Board::start_pos();
+        assert_eq!(book.moves(&board).len(), 3);
+        assert_eq!(book.choose(&board).unwrap().stringify(), "e2e4");
+        board.apply_uci_move("e2e4");
+        assert_eq!(book.choose(&board).unwrap().stringify(), "e7e5");
+        board.apply_uci_move("e7e5");
+        assert_eq!(book.choose(&board), None);
+    }
+
+    #[test]
+    fn weighted_moves_by_seed() {
+        let board = Board::start_pos();
+        let played: Vec<String> = (1..40)
+            .map(|seed| {
+                book(BookSelection::Weighted, seed)
+                    .choose(&board)
+                    .unwrap()
+                    .stringify()
+            })
+            .collect();
+        assert!(played.contains(&"e2e4".to_owned()));
+        assert!(played.contains(&"d2d4".to_owned()));
+        assert!(played
+            .iter()
+            .all(|played| played == "e2e4" || played == "d2d4"));
+        // Reproducible
+        let again = book(BookSelection::Weighted, 5).choose(&board).unwrap();
+        assert_eq!(again.stringify(), played[4]);
+    }
+
+    #[test]
+    fn castling_is_encoded_as_king_takes_rook() {
+        let board = Board::from_fen("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1").unwrap();
+        let bytes = entry(polyglot_key(&board), raw_move(4, 7), 1);
+        let mut book = OpeningBook::from_bytes(&bytes, BookSelection::Best, 1).unwrap();
+        let castling = book.choose(&board).unwrap();
+        assert!(castling.is_king_castle());
+    }
+
+    #[test]
+    fn broken_book_is_an_error() {
+        assert!(OpeningBook::from_bytes(&[0; 17], BookSelection::Best, 1).is_err());
+    }
+}
@@ -0,0 +1,923 @@
+//! Use an external engine speaking the
+//! [Chess Engine Communication Protocol](https://www.gnu.org/software/xboard/engine-intf.html)
+//! (also known as XBoard or WinBoard protocol, e.g. GNU Chess or Crafty)
+//! as bot opponent.
+
+use crate::engine_process::{EngineProcess, Position, Search, START_FEN};
+use crate::game::{BitMove, ChessGame, ChessOutcome, Promotion};
+use crate::proto::{ChessRequest, ChessUpdate, MAX_REJECTED_MOVES};
+use crate::Player;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task;
+use tokio::time::{timeout_at, Instant};
+
+/// How long to wait for features before assuming a protocol version 1 engine
+pub const FEATURE_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long an engine may take after announcing more features with "done=0"
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Thinking time per move if neither `depth` nor `seconds_per_move` is configured
+pub const DEFAULT_SECONDS_PER_MOVE: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CecpEngineConfig {
+    /// Executable of the engine
+    pub path: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Search depth per move ("sd")
+    #[serde(default)]
+    pub depth: Option<u16>,
+    /// Thinking time per move ("st")
+    #[serde(default)]
+    pub seconds_per_move: Option<u32>,
+}
+
+impl CecpEngineConfig {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            args: vec![],
+            depth: None,
+            seconds_per_move: None,
+        }
+    }
+
+    fn limit_commands(&self) -> Vec<String> {
+        let mut commands = vec![];
+        if let Some(depth) = self.depth {
+            commands.push(format!("sd {}", depth));
+        }
+        match (self.depth, self.seconds_per_move) {
+            (_, Some(seconds)) => commands.push(format!("st {}", seconds)),
+            (None, None) => commands.push(format!("st {}", DEFAULT_SECONDS_PER_MOVE)),
+            (Some(_), None) => {}
+        }
+        commands
+    }
+}
+
+/// Split `feature a=1 b="some text"` into its names and values
+fn parse_features(line: &str) -> Vec<(String, String)> {
+    let mut features = vec![];
+    let mut rest = line.strip_prefix("feature").unwrap_or(line).trim_start();
+    while let Some(equals) = rest.find('=') {
+        let name = rest[..equals].trim().to_owned();
+        rest = &rest[equals + 1..];
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            rest = quoted.get(end + 1..).unwrap_or("");
+            &quoted[..end]
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+        features.push((name, value.to_owned()));
+        rest = rest.trim_start();
+    }
+    features
+}
+
+/// The game result as written after "result" (e.g. "1-0 {White mates}")
+pub fn result(outcome: ChessOutcome) -> String {
+    match outcome {
+        ChessOutcome::Checkmate {
+            winner: Player::White,
+        } => "1-0 {White mates}".to_owned(),
+        ChessOutcome::Checkmate {
+            winner: Player::Black,
+        } => "0-1 {Black mates}".to_owned(),
+        ChessOutcome::Stalemate => "1/2-1/2 {Stalemate}".to_owned(),
+        ChessOutcome::Draw => "1/2-1/2 {Draw}".to_owned(),
+        ChessOutcome::Aborted {
+            who: Some(Player::White),
+        } => "0-1 {White resigns}".to_owned(),
+        ChessOutcome::Aborted {
+            who: Some(Player::Black),
+        } => "1-0 {Black resigns}".to_owned(),
+        ChessOutcome::Aborted { who: None } => "* {Game aborted}".to_owned(),
+    }
+}
+
+/// Find a legal move in coordinate notation as used by CECP
+/// (e.g. "e2e4", "e7e8q" or "O-O")
+pub fn find_coordinate_move(game: &ChessGame, text: &str) -> Option<BitMove> {
+    let king_side = match text {
+        "O-O" | "0-0" => true,
+        "O-O-O" | "0-0-0" => false,
+        _ => return game.find_uci_move(&text.to_lowercase()),
+    };
+    // pleco moves the king onto the rook
+    game.possible_moves()
+        .iter()
+        .find(|legal_move| {
+            legal_move.is_castle()
+                && (legal_move.get_dest().file() > legal_move.get_src().file()) == king_side
+        })
+        .copied()
+}
+
+/// What the engine answered to "go"
+enum Answer {
+    Move(BitMove),
+    Resign,
+}
+
+/// A running engine process
+struct CecpEngine {
+    process: EngineProcess,
+    usermove: bool,
+    setboard: bool,
+}
+
+impl CecpEngine {
+    async fn start(config: &CecpEngineConfig) -> Result<Self> {
+        let mut engine = Self {
+            process: EngineProcess::spawn(&config.path, &config.args).await?,
+            usermove: false,
+            setboard: false,
+        };
+
+        engine.send("xboard").await?;
+        engine.send("protover 2").await?;
+        let mut deadline = Instant::now() + FEATURE_TIMEOUT;
+        loop {
+            let line = match timeout_at(
+                deadline,
+                engine
+                    .process
+                    .read_until(|line| line.starts_with("feature ")),
+            )
+            .await
+            {
+                Ok(line) => line?,
+                Err(_) => {
+                    warn!("Engine sent no (complete) features. Assuming protocol version 1.");
+                    break;
+                }
+            };
+            let mut done = false;
+            for (name, value) in parse_features(&line) {
+                let accepted = match (name.as_str(), value.as_str()) {
+                    // Moves are always exchanged in coordinate notation
+                    ("san", "1") => false,
+                    // Signals are never sent
+                    ("sigint", "1") | ("sigterm", "1") => false,
+                    ("usermove", value) => {
+                        engine.usermove = value == "1";
+                        true
+                    }
+                    ("setboard", value) => {
+                        engine.setboard = value == "1";
+                        true
+                    }
+                    ("myname", value) => {
+                        engine.process.name = value.to_owned();
+                        true
+                    }
+                    ("done", "0") => {
+                        deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+                        true
+                    }
+                    ("done", _) => {
+                        done = true;
+                        true
+                    }
+                    _ => true,
+                };
+                let reply = if accepted { "accepted" } else { "rejected" };
+                engine.send(&format!("{} {}", reply, name)).await?;
+            }
+            if done {
+                break;
+            }
+        }
+        Ok(engine)
+    }
+
+    async fn send(&mut self, command: &str) -> Result<()> {
+        self.process.send(command).await
+    }
+
+    /// Start a new game at `fen` with the engine not playing any side yet
+    async fn reset(&mut self, fen: &str, config: &CecpEngineConfig) -> Result<()> {
+        self.send("new").await?;
+        self.send("force").await?;
+        for command in config.limit_commands() {
+            self.send(&command).await?;
+        }
+        if fen != START_FEN {
+            ensure!(
+                self.setboard,
+                "Engine doesn't support starting from a position (feature setboard)"
+            );
+            self.send(&format!("setboard {}", fen)).await?;
+        }
+        Ok(())
+    }
+
+    async fn user_move(&mut self, uci_move: &str) -> Result<()> {
+        if self.usermove {
+            self.send(&format!("usermove {}", uci_move)).await
+        } else {
+            self.send(uci_move).await
+        }
+    }
+
+    /// Let the engine play the side to move in `game` and put it back into
+    /// force mode. Once `stop` fires (or its sender is dropped) the engine
+    /// is asked to move now ("?").
+    async fn go(&mut self, game: &ChessGame, stop: oneshot::Receiver<()>) -> Result<Answer> {
+        self.send("go").await?;
+        let is_answer = |line: &str| {
+            line.starts_with("move ")
+                || line == "resign"
+                || line.starts_with("Illegal move")
+                || line.starts_with("Error")
+        };
+        let answered = tokio::select! {
+            line = self.process.read_until(is_answer) => Some(line),
+            _ = stop => None,
+        };
+        let line = match answered {
+            Some(line) => line?,
+            None => {
+                self.send("?").await?;
+                self.process.read_until(is_answer).await?
+            }
+        };
+        self.send("force").await?;
+        if let Some(engine_move) = line.strip_prefix("move ") {
+            let engine_move = engine_move.trim();
+            match find_coordinate_move(game, engine_move) {
+                Some(bit_move) => Ok(Answer::Move(bit_move)),
+                None => bail!("Engine wants to play illegal move {}", engine_move),
+            }
+        } else if line == "resign" {
+            Ok(Answer::Resign)
+        } else {
+            bail!("Engine complained: {}", line)
+        }
+    }
+
+    async fn quit(self) {
+        self.process.quit("quit").await;
+    }
+}
+
+/// Bring the engine from `known` (what it was told so far) to `wanted`:
+/// Take back the moves that differ and tell it the new ones. Starts a new
+/// game if the starting position differs.
+async fn sync(
+    engine: &mut CecpEngine,
+    known: &mut Option<Position>,
+    wanted: &Position,
+    config: &CecpEngineConfig,
+) -> Result<()> {
+    // Has to be set up again should this fail half way
+    let common = match known.take() {
+        Some(known) if known.fen == wanted.fen => {
+            let common = known
+                .moves
+                .iter()
+                .zip(&wanted.moves)
+                .take_while(|(known, wanted)| known == wanted)
+                .count();
+            for _ in common..known.moves.len() {
+                engine.send("undo").await?;
+            }
+            common
+        }
+        _ => {
+            engine.reset(&wanted.fen, config).await?;
+            0
+        }
+    };
+    for uci_move in &wanted.moves[common..] {
+        engine.user_move(uci_move).await?;
+    }
+    *known = Some(wanted.clone());
+    Ok(())
+}
+
+/// Let `engine` play the side to move in `game`
+fn start_search(
+    mut engine: CecpEngine,
+    game: ChessGame,
+    position: u64,
+    positions: Arc<AtomicU64>,
+    min_reaction_delay: Duration,
+) -> Search<CecpEngine, Answer> {
+    Search::spawn(position, |stop| async move {
+        let started = SystemTime::now();
+        let answer = engine.go(&game, stop).await;
+        let elapsed = started.elapsed().unwrap_or(Duration::new(0, 0));
+        if elapsed < min_reaction_delay && positions.load(Ordering::SeqCst) == position {
+            tokio::time::sleep(min_reaction_delay - elapsed).await;
+        }
+        (engine, answer)
+    })
+}
+
+/// Like `proto::create_bot()` but the moves come from an external CECP
+/// (XBoard) engine. Fails if the engine can't be started. Should the engine
+/// stop working or resign later on, the bot aborts the game. The engine
+/// searches on its own task, so it's told to move now ("?") once the
+/// position changes (e.g. by an undo) and its move is taken back. A
+/// rejected move is searched again (up to `MAX_REJECTED_MOVES` times) as
+/// long as the position stays.
+pub async fn create_cecp_bot(
+    me: Player,
+    config: CecpEngineConfig,
+    min_reaction_delay: Duration,
+) -> Result<(Sender<ChessUpdate>, Receiver<ChessRequest>)> {
+    let engine = CecpEngine::start(&config).await?;
+    let (update_tx, mut update_rx) = channel::<ChessUpdate>(256);
+    let (request_tx, request_rx) = channel::<ChessRequest>(256);
+
+    task::spawn(async move {
+        let name = engine.process.name.clone();
+        info!("CECP bot ({}) spawned for {}", name, me);
+        // Taken by the running search
+        let mut engine = Some(engine);
+        // The game as the engine knows it (`None` if it has to be set up again)
+        let mut engine_position: Option<Position> = None;
+        // The game as it is (followed even while the engine searches)
+        let mut position: Option<Position> = None;
+        let mut current_outcome: Option<ChessOutcome> = None;
+        // Increased for every position to drop searches of old ones
+        let positions = Arc::new(AtomicU64::new(0));
+        let mut search: Option<Search<CecpEngine, Answer>> = None;
+        // Position the bot has to move in and how often its move got rejected
+        let mut own_turn: Option<(String, u32)> = None;
+        // Started once the engine is back from the search of an old position
+        let mut search_wanted = false;
+        // The engine has to follow the game (its own moves it knows already)
+        let mut sync_wanted = false;
+
+        macro_rules! abort {
+            ($message: expr) => {
+                let message: String = $message;
+                error!("{}", message);
+                request_tx.send(ChessRequest::Abort { message }).await.ok();
+                break;
+            };
+        }
+
+        loop {
+            // Once the engine is back (and not after a result, as engines don't
+            // necessarily continue then)
+            let follow = (sync_wanted || search_wanted) && current_outcome.is_none();
+            if let (true, Some(idle), Some(current)) = (follow, engine.as_mut(), position.as_ref())
+            {
+                sync_wanted = false;
+                let in_sync = engine_position
+                    .as_ref()
+                    .is_some_and(|known| known.fen == current.fen && known.moves == current.moves);
+                if !in_sync {
+                    if let Err(e) = sync(idle, &mut engine_position, current, &config).await {
+                        abort!(format!("Engine {} failed: {:#}", name, e));
+                    }
+                }
+            }
+            if search_wanted && search.is_none() {
+                search_wanted = false;
+                search = Some(start_search(
+                    engine.take().expect("Only the search takes the engine"),
+                    position
+                        .as_ref()
+                        .expect("Followed on the own turn")
+                        .game
+                        .clone(),
+                    positions.load(Ordering::SeqCst),
+                    positions.clone(),
+                    min_reaction_delay,
+                ));
+            }
+            let searched = async {
+                match search {
+                    Some(ref mut search) => (&mut search.task).await,
+                    None => future::pending().await,
+                }
+            };
+            let update = tokio::select! {
+                update = update_rx.recv() => update,
+                searched = searched => {
+                    let searched_position = search.take().map(|search| search.position);
+                    let answer = match searched {
+                        Ok((returned, answer)) => {
+                            engine = Some(returned);
+                            answer
+                        }
+                        Err(e) => {
+                            abort!(format!("Engine {} failed to search: {}", name, e));
+                        }
+                    };
+                    let is_current = searched_position == Some(positions.load(Ordering::SeqCst));
+                    match answer {
+                        // The engine played it on its board
+                        Ok(Answer::Move(bit_move)) => {
+                            if let Some(ref mut known) = engine_position {
+                                known.moves.push(bit_move.stringify());
+                            }
+                            if !is_current {
+                                info!("CECP bot takes back the move of an old position");
+                                sync_wanted = true;
+                                continue;
+                            }
+                            if request_tx
+                                .send(ChessRequest::MovePiece {
+                                    source: bit_move.get_src().into(),
+                                    destination: bit_move.get_dest().into(),
+                                    promotion: Promotion::of(bit_move),
+                                })
+                                .await
+                                .is_err()
+                            {
+                                break;
+                            }
+                        }
+                        Ok(Answer::Resign) if is_current => {
+                            abort!(format!("Engine {} resigned", name));
+                        }
+                        Err(e) if is_current => {
+                            abort!(format!("Engine {} failed: {:#}", name, e));
+                        }
+                        // Set up again
+                        Ok(Answer::Resign) | Err(_) => {
+                            info!("CECP bot dropped the search of an old position");
+                            engine_position = None;
+                        }
+                    }
+                    continue;
+                }
+            };
+            let update = match update {
+                Some(update) => update,
+                None => break,
+            };
+            match update {
+                ChessUpdate::PlayerMovedAPiece {
+                    player,
+                    moved_piece_source,
+                    moved_piece_destination,
+                    moved_piece_promotion,
+                } => {
+                    sync_wanted |= player != me;
+                    if let Some(ref mut current) = position {
+                        let played = current.play(
+                            moved_piece_source,
+                            moved_piece_destination,
+                            moved_piece_promotion,
+                        );
+                        if let Err(e) = played {
+                            warn!("Engine game is out of sync: {:?}", e);
+                            position = None;
+                        }
+                    }
+                }
+                ChessUpdate::PlayerSwitch { player, ref fen } => {
+                    positions.fetch_add(1, Ordering::SeqCst);
+                    if let Some(ref mut search) = search {
+                        search.stop();
+                    }
+                    own_turn = None;
+                    search_wanted = false;
+                    if current_outcome.is_some() {
+                        continue;
+                    }
+                    sync_wanted = true;
+                    // Undone moves (announced after this update) are taken
+                    // back. Otherwise start over.
+                    if let Some(ref mut current) = position {
+                        while current.game.fen() != *fen && !current.moves.is_empty() {
+                            current.undo(1).ok();
+                        }
+                        if current.game.fen() != *fen {
+                            position = None;
+                        }
+                    }
+                    if position.is_none() {
+                        position = match Position::new(fen) {
+                            Ok(position) => Some(position),
+                            Err(e) => {
+                                abort!(format!("Engine got an invalid board: {}", e));
+                            }
+                        };
+                    }
+                    if player == me {
+                        own_turn = Some((fen.clone(), 0));
+                        search_wanted = true;
+                    }
+                }
+                ChessUpdate::MovePieceFailedResponse { message, fen } => {
+                    error!("A move from the engine was rejected: {}", message);
+                    // Still its turn in the same position. Otherwise it keeps
+                    // playing once it's its turn again.
+                    let rejected = match own_turn {
+                        Some((ref own_fen, ref mut rejected)) if *own_fen == fen => rejected,
+                        _ => continue,
+                    };
+                    *rejected += 1;
+                    if *rejected > MAX_REJECTED_MOVES
+                        || search.is_some()
+                        || current_outcome.is_some()
+                    {
+                        continue;
+                    }
+                    info!("CECP bot searches its position again");
+                    search_wanted = true;
+                }
+                ChessUpdate::Outcome { outcome } => {
+                    if let Some(outcome) = outcome {
+                        positions.fetch_add(1, Ordering::SeqCst);
+                        if let Some(ref mut search) = search {
+                            search.stop();
+                        }
+                        search_wanted = false;
+                        if let Some(ref mut idle) = engine {
+                            if idle
+                                .send(&format!("result {}", result(outcome)))
+                                .await
+                                .is_err()
+                            {
+                                break;
+                            }
+                        }
+                        // Engines don't necessarily continue after a result
+                        engine_position = None;
+                    }
+                    current_outcome = outcome;
+                }
+                _ => {}
+            }
+        }
+        // Get the engine back from a running search to quit it
+        if let Some(search) = search.take() {
+            engine = search.finish().await;
+        }
+        if let Some(engine) = engine {
+            engine.quit().await;
+        }
+        info!("CECP bot task has ended");
+    });
+
+    Ok((update_tx, request_rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::*;
+
+    /// Logs everything it receives to the file given as first argument
+    /// and answers each "go" with the next of the other arguments. Moves
+    /// starting with "~" are only answered on "?". Crashes when running
+    /// out of moves.
+    const FAKE_ENGINE: &str = r#"#!/bin/sh
+log="$1"
+shift
+while read -r line; do
+    echo "$line" >> "$log"
+    case "$line" in
+        "protover 2") echo 'feature myname="Fake Engine 1.0" usermove=1 san=1 done=0'; echo "feature setboard=1 done=1" ;;
+        go)
+            [ $# -eq 0 ] && exit 1
+            case "$1" in
+                "~"*) thinking="${1#"~"}" ;;
+                *) echo "1 13 0 100 $1"; echo "move $1" ;;
+            esac
+            shift ;;
+        "?") [ -n "$thinking" ] && echo "move $thinking"; thinking="" ;;
+        quit) exit 0 ;;
+    esac
+done
+"#;
+
+    fn fake_engine_config(engine: &FakeEngine, moves: &[&str]) -> CecpEngineConfig {
+        let mut config = CecpEngineConfig::new(engine.path());
+        config.args = engine.args(moves);
+        config
+    }
+
+    #[test]
+    fn features_are_parsed() {
+        assert_eq!(
+            parse_features(r#"feature myname="Some Engine 2.1" usermove=1  done=0"#),
+            vec![
+                ("myname".to_owned(), "Some Engine 2.1".to_owned()),
+                ("usermove".to_owned(), "1".to_owned()),
+                ("done".to_owned(), "0".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn engine_answers_moves() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let bot = create_cecp_bot(
+            Player::Black,
+            fake_engine_config(&engine, &["e7e5", "b8c6"]),
+            Duration::from_millis(0),
+        )
+        .await
+        .unwrap();
+
+        let (white, white_connection) = ScriptedPlayer::new("White");
+        let (spectators, spectators_connection) = ScriptedPlayer::new("Spectators");
+        let game = spawn_game(white_connection, bot, spectators_connection, config());
+        let mut white = white
+            .run(vec![
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(mv("E2", "E4")),
+                Step::SkipUntil("the engine move", |update| {
+                    *update == moved(Player::Black, "E7", "E5")
+                }),
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(mv("G1", "F3")),
+                Step::SkipUntil("the second engine move", |update| {
+                    *update == moved(Player::Black, "B8", "C6")
+                }),
+            ])
+            .await;
+        white.disconnect();
+        game_result(game).await.unwrap();
+        drop(spectators);
+        engine.wait_for_quit().await;
+
+        assert_eq!(
+            engine.received(),
+            vec![
+                "xboard",
+                "protover 2",
+                "accepted myname",
+                "accepted usermove",
+                "rejected san",
+                "accepted done",
+                "accepted setboard",
+                "accepted done",
+                "new",
+                "force",
+                "st 1",
+                "usermove e2e4",
+                "go",
+                "force",
+                "usermove g1f3",
+                "go",
+                "force",
+                "quit",
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn engine_castles_from_a_fen() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let mut engine_config = fake_engine_config(&engine, &["O-O"]);
+        engine_config.depth = Some(5);
+        let bot = create_cecp_bot(Player::White, engine_config, Duration::from_millis(0))
+            .await
+            .unwrap();
+
+        let fen = "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1";
+        let (black, black_connection) = ScriptedPlayer::new("Black");
+        let (spectators, spectators_connection) = ScriptedPlayer::new("Spectators");
+        let mut game_config = config();
+        game_config.starting_fen = Some(fen.to_owned());
+        let game = spawn_game(bot, black_connection, spectators_connection, game_config);
+        // pleco moves the king onto the rook when castling
+        let black = black
+            .run(vec![Step::SkipUntil("castling", |update| {
+                *update == moved(Player::White, "E1", "H1")
+            })])
+            .await;
+        black
+            .send(ChessRequest::Abort {
+                message: "Done".to_owned(),
+            })
+            .await;
+        game_result(game).await.unwrap();
+        drop(spectators);
+        engine.wait_for_quit().await;
+
+        let received = engine.received();
+        assert!(received.contains(&format!("setboard {}", fen)));
+        assert!(received.contains(&"sd 5".to_owned()));
+        assert!(!received.iter().any(|command| command.starts_with("st ")));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn undo_and_result_reach_the_engine() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let bot = create_cecp_bot(
+            Player::Black,
+            fake_engine_config(&engine, &["e7e5", "e7e5", "d8h4"]),
+            Duration::from_millis(0),
+        )
+        .await
+        .unwrap();
+
+        let (white, white_connection) = ScriptedPlayer::new("White");
+        let (spectators, spectators_connection) = ScriptedPlayer::new("Spectators");
+        let mut game_config = config();
+        game_config.can_white_undo = true;
+        let game = spawn_game(white_connection, bot, spectators_connection, game_config);
+        let mut white = white
+            .run(vec![
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(mv("F2", "F3")),
+                Step::SkipUntil("the engine move", |update| {
+                    *update == moved(Player::Black, "E7", "E5")
+                }),
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(ChessRequest::UndoMoves { moves: 2 }),
+                Step::SkipUntil("the undo", |update| {
+                    matches!(update, ChessUpdate::MovesUndone { moves: 2, .. })
+                }),
+                Step::Send(mv("F2", "F3")),
+                Step::SkipUntil("the engine move", |update| {
+                    *update == moved(Player::Black, "E7", "E5")
+                }),
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(mv("G2", "G4")),
+                Step::SkipUntil("the mate", |update| {
+                    *update
+                        == ChessUpdate::Outcome {
+                            outcome: Some(ChessOutcome::Checkmate {
+                                winner: Player::Black,
+                            }),
+                        }
+                }),
+            ])
+            .await;
+        white.disconnect();
+        game_result(game).await.unwrap();
+        drop(spectators);
+        engine.wait_for_quit().await;
+
+        assert_eq!(
+            played(&engine),
+            vec![
+                "usermove f2f3",
+                "go",
+                "force",
+                "undo",
+                "undo",
+                "usermove f2f3",
+                "go",
+                "force",
+                "usermove g2g4",
+                "go",
+                "force",
+                "result 0-1 {Black mates}",
+                "quit",
+            ]
+        );
+    }
+
+    /// The commands after setting up the game
+    fn played(engine: &FakeEngine) -> Vec<String> {
+        engine
+            .received()
+            .into_iter()
+            .skip_while(|command| command != "st 1")
+            .skip(1)
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn undo_stops_the_engine_and_takes_back_its_move() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let engine_config = fake_engine_config(&engine, &["~e7e5", "d7d5"]);
+        let (updates, mut requests) =
+            create_cecp_bot(Player::Black, engine_config, Duration::from_millis(0))
+                .await
+                .unwrap();
+
+        let mut game = ChessGame::default();
+        updates
+            .send(switch(Player::White, &game.fen()))
+            .await
+            .unwrap();
+        send_move(&updates, &mut game, "e2e4").await;
+        engine.wait_for("go").await;
+
+        // Taken back while the engine thinks (in the order the game sends it)
+        game.undo(1).unwrap();
+        updates
+            .send(switch(Player::White, &game.fen()))
+            .await
+            .unwrap();
+        let undone = ChessUpdate::MovesUndone {
+            who: Player::White,
+            moves: 1,
+        };
+        updates.send(undone).await.unwrap();
+        send_move(&updates, &mut game, "d2d4").await;
+        assert_eq!(next_bot_move(&mut requests).await, mv("D7", "D5"));
+
+        drop(updates);
+        engine.wait_for_quit().await;
+        assert_eq!(
+            played(&engine),
+            vec![
+                "usermove e2e4",
+                "go",
+                "?",
+                "force",
+                // Its own move and the one taken back
+                "undo",
+                "undo",
+                "usermove d2d4",
+                "go",
+                "force",
+                "quit",
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejected_engine_move_is_searched_again() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let engine_config = fake_engine_config(&engine, &["e7e5", "c7c5"]);
+        let (updates, mut requests) =
+            create_cecp_bot(Player::Black, engine_config, Duration::from_millis(0))
+                .await
+                .unwrap();
+
+        let mut game = ChessGame::default();
+        updates
+            .send(switch(Player::White, &game.fen()))
+            .await
+            .unwrap();
+        send_move(&updates, &mut game, "e2e4").await;
+        assert_eq!(next_bot_move(&mut requests).await, mv("E7", "E5"));
+        let rejected = ChessUpdate::MovePieceFailedResponse {
+            message: "Denied".to_owned(),
+            fen: game.fen(),
+        };
+        updates.send(rejected).await.unwrap();
+        assert_eq!(next_bot_move(&mut requests).await, mv("C7", "C5"));
+
+        drop(updates);
+        engine.wait_for_quit().await;
+        assert_eq!(
+            played(&engine),
+            vec![
+                "usermove e2e4",
+                "go",
+                "force",
+                "undo",
+                "go",
+                "force",
+                "quit"
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn engine_failing_aborts_the_game() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        // Has no move to answer with and crashes instead
+        let bot = create_cecp_bot(
+            Player::Black,
+            fake_engine_config(&engine, &[]),
+            Duration::from_millis(0),
+        )
+        .await
+        .unwrap();
+
+        let (white, white_connection) = ScriptedPlayer::new("White");
+        let (spectators, spectators_connection) = ScriptedPlayer::new("Spectators");
+        let game = spawn_game(white_connection, bot, spectators_connection, config());
+        let mut white = white
+            .run(vec![
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(mv("E2", "E4")),
+            ])
+            .await;
+        // The bot aborts which ends the game
+        white.expect_closed().await;
+        game_result(game).await.unwrap();
+        drop(spectators);
+    }
+
+    #[tokio::test]
+    async fn missing_engine_is_an_error() {
+        let engine_config = CecpEngineConfig::new(PathBuf::from("/nonexistent/gnuchess"));
+        assert!(
+            create_cecp_bot(Player::Black, engine_config, Duration::from_millis(0))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! Talking line by line to external engines (see `uci` and `cecp`)
+
+use crate::game::{ChessGame, Promotion};
+use crate::Square;
+use anyhow::{Context, Result};
+use std::future::Future;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::oneshot;
+use tokio::task::{self, JoinHandle};
+use tokio::time::timeout;
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// How long an engine may take to exit after being asked to quit
+pub const QUIT_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long an engine may take to answer when its search is stopped as the
+/// bot ends
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub(crate) struct EngineProcess {
+    /// Path until the engine tells its name
+    pub name: String,
+    /// Killed when dropped
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl EngineProcess {
+    pub async fn spawn(path: &Path, args: &[String]) -> Result<Self> {
+        let mut child = Command::new(path)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Start engine {:?}", path))?;
+        let stdin = child.stdin.take().context("Engine stdin")?;
+        let stdout = BufReader::new(child.stdout.take().context("Engine stdout")?).lines();
+        Ok(Self {
+            name: path.display().to_string(),
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    pub async fn send(&mut self, command: &str) -> Result<()> {
+        debug!("To engine: {}", command);
+        self.stdin
+            .write_all(format!("{}\n", command).as_bytes())
+            .await
+            .context("Write to engine")?;
+        self.stdin.flush().await.context("Write to engine")
+    }
+
+    /// Skip lines until one matches
+    pub async fn read_until(&mut self, predicate: impl Fn(&str) -> bool) -> Result<String> {
+        loop {
+            let line = self
+                .stdout
+                .next_line()
+                .await
+                .context("Read from engine")?
+                .context("Engine quit unexpectedly")?;
+            let line = line.trim();
+            if predicate(line) {
+                return Ok(line.to_owned());
+            }
+        }
+    }
+
+    /// Give the engine a moment to quit by itself before it gets killed
+    pub async fn quit(mut self, command: &str) {
+        self.send(command).await.ok();
+        timeout(QUIT_TIMEOUT, self.child.wait()).await.ok();
+    }
+}
+
+/// A search of an engine `E` on its own task, which hands the engine back
+/// with the result
+pub(crate) struct Search<E, T> {
+    /// The position it was started for
+    pub position: u64,
+    stop: Option<oneshot::Sender<()>>,
+    pub task: JoinHandle<(E, Result<T>)>,
+}
+
+impl<E: Send + 'static, T: Send + 'static> Search<E, T> {
+    /// `search` gets a receiver that fires once it should stop
+    pub fn spawn<F>(position: u64, search: impl FnOnce(oneshot::Receiver<()>) -> F) -> Self
+    where
+        F: Future<Output = (E, Result<T>)> + Send + 'static,
+    {
+        let (stop, stop_rx) = oneshot::channel();
+        Self {
+            position,
+            stop: Some(stop),
+            task: task::spawn(search(stop_rx)),
+        }
+    }
+
+    /// The engine still answers, but the result belongs to an old position
+    pub fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop.send(()).ok();
+        }
+    }
+
+    /// Stop and get the engine back (`None` if it didn't answer in time,
+    /// which kills it)
+    pub async fn finish(mut self) -> Option<E> {
+        self.stop();
+        match timeout(STOP_TIMEOUT, &mut self.task).await {
+            Ok(Ok((engine, _))) => Some(engine),
+            _ => {
+                self.task.abort();
+                None
+            }
+        }
+    }
+}
+
+/// The game as an engine gets it: A starting position and the moves since then
+#[derive(Clone)]
+pub(crate) struct Position {
+    pub fen: String,
+    pub game: ChessGame,
+    /// In UCI notation
+    pub moves: Vec<String>,
+}
+
+impl Position {
+    pub fn new(fen: &str) -> Result<Self> {
+        Ok(Self {
+            fen: fen.to_owned(),
+            game: ChessGame::from_fen(fen)?,
+            moves: vec![],
+        })
+    }
+
+    pub fn play(
+        &mut self,
+        source: Square,
+        destination: Square,
+        promotion: Option<Promotion>,
+    ) -> Result<()> {
+        let uci_move = self
+            .game
+            .uci_move(source, destination, promotion)
+            .context("Move unknown to the engine position")?;
+        self.game.move_piece(source, destination, promotion)?;
+        self.moves.push(uci_move);
+        Ok(())
+    }
+
+    pub fn undo(&mut self, moves: u16) -> Result<()> {
+        ensure!(
+            moves as usize <= self.moves.len(),
+            "Undid more moves than known"
+        );
+        self.game.undo(moves)?;
+        self.moves.truncate(self.moves.len() - moves as usize);
+        Ok(())
+    }
+}
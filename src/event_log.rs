@@ -0,0 +1,510 @@
+use crate::proto::{replay_game, ChessConfig, ChessRequest, ChessUpdate};
+use crate::Player;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+/// How long `verify()` waits for a single expected update before
+/// declaring the replay as diverged.
+const REPLAY_UPDATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LogEvent {
+    /// Always the first entry. Contains everything needed to recreate the game.
+    GameStarted { config: ChessConfig },
+    /// A request that `create_game` took from its queue (in processing order).
+    Request(ChessRequest),
+    /// An update that `create_game` sent out.
+    Update(ChessUpdate),
+    /// `create_game` took the result of the search for the hint request
+    /// with this number (counted from 1). Searched in the background, so
+    /// this is where it was taken between the requests.
+    HintSearched { request: u64 },
+}
+
+/// One line of an event log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub seq: u64,
+    /// Milliseconds since the unix epoch
+    pub timestamp: u64,
+    /// The sender of a request or the recipient of an update.
+    /// `None` means the spectators.
+    pub player: Option<Player>,
+    pub event: LogEvent,
+}
+
+/// Writes every event as a JSON line to a file. The file gets flushed
+/// after each entry so it is usable even when the app crashed.
+pub struct EventLog {
+    file: File,
+    next_seq: u64,
+}
+
+impl EventLog {
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(directory) = path.parent() {
+            if !directory.as_os_str().is_empty() && !directory.exists() {
+                std::fs::create_dir_all(directory).context("Create directory for event log")?;
+            }
+        }
+        let file = File::create(path).context("Create event log file")?;
+        info!("Logging game events to {:?}", path);
+        Ok(Self { file, next_seq: 0 })
+    }
+
+    pub fn game_started(&mut self, config: &ChessConfig) {
+        self.append(
+            None,
+            LogEvent::GameStarted {
+                config: config.clone(),
+            },
+        );
+    }
+
+    pub fn request(&mut self, sender: Option<Player>, request: &ChessRequest) {
+        self.append(sender, LogEvent::Request(request.clone()));
+    }
+
+    pub fn update(&mut self, recipient: Option<Player>, update: &ChessUpdate) {
+        self.append(recipient, LogEvent::Update(update.clone()));
+    }
+
+    pub fn hint_searched(&mut self, player: Player, request: u64) {
+        self.append(Some(player), LogEvent::HintSearched { request });
+    }
+
+    /// A broken log should never take down the game it is logging.
+    fn append(&mut self, player: Option<Player>, event: LogEvent) {
+        let entry = LogEntry {
+            seq: self.next_seq,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            player,
+            event,
+        };
+        self.next_seq += 1;
+
+        let result = serde_json::to_string(&entry)
+            .context("Serialize entry")
+            .and_then(|line| writeln!(self.file, "{}", line).context("Write entry"))
+            .and_then(|_| self.file.flush().context("Flush file"));
+        if let Err(e) = result {
+            warn!(
+                "Failed to write event #{} to the event log: {:?}",
+                entry.seq, e
+            );
+        }
+    }
+}
+
+pub fn read(path: &Path) -> Result<Vec<LogEntry>> {
+    let file = File::open(path).context("Open event log")?;
+    let mut entries = vec![];
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.context("Read event log")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: LogEntry = serde_json::from_str(&line)
+            .with_context(|| format!("Parse line {} of event log", index + 1))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Summary of a successful `verify()`
+#[derive(Debug)]
+pub struct VerifyReport {
+    pub requests: usize,
+    pub updates: usize,
+}
+
+/// Plays all requests of a log against a fresh game and checks that
+/// every recipient gets exactly the updates that were logged.
+///
+/// Requests are sent one by one and the updates caused by one request
+/// are awaited before sending the next, so the ordering of the original
+/// session is reproduced regardless of task scheduling. Likewise, the
+/// replayed game takes the result of a hint search only where the logged
+/// one did.
+pub async fn verify(entries: &[LogEntry]) -> Result<VerifyReport> {
+    let mut config = match entries.first() {
+        Some(LogEntry {
+            event: LogEvent::GameStarted { config },
+            ..
+        }) => config.clone(),
+        _ => bail!("The event log doesn't start with a GameStarted entry"),
+    };
+    config.event_log = None; // Don't overwrite anything while replaying
+
+    let (white_update_tx, white_update_rx) = channel::<ChessUpdate>(1024);
+    let (white_request_tx, white_request_rx) = channel::<ChessRequest>(1024);
+    let (black_update_tx, black_update_rx) = channel::<ChessUpdate>(1024);
+    let (black_request_tx, black_request_rx) = channel::<ChessRequest>(1024);
+    let (spectators_update_tx, spectators_update_rx) = channel::<ChessUpdate>(1024);
+    let (spectators_request_tx, spectators_request_rx) = channel::<ChessRequest>(1024);
+
+    let (hints_released_tx, hints_released_rx) = watch::channel(0);
+
+    let game = tokio::spawn(replay_game(
+        (white_update_tx, white_request_rx),
+        (black_update_tx, black_request_rx),
+        (spectators_update_tx, spectators_request_rx),
+        config,
+        Some(hints_released_rx),
+    ));
+
+    let mut receivers = [white_update_rx, black_update_rx, spectators_update_rx];
+    let mut senders = Some([white_request_tx, black_request_tx, spectators_request_tx]);
+    let mut report = VerifyReport {
+        requests: 0,
+        updates: 0,
+    };
+
+    // Updates before the first request are the ones sent on game start
+    let ends_step = |entry: &LogEntry| {
+        matches!(
+            entry.event,
+            LogEvent::Request(_) | LogEvent::HintSearched { .. }
+        )
+    };
+    for step in entries[1..].split_inclusive(ends_step) {
+        let (end, expected_updates) = match step.last() {
+            Some(last) if ends_step(last) => (Some(last), &step[..step.len() - 1]),
+            _ => (None, step),
+        };
+
+        for entry in expected_updates {
+            let expected = match entry.event {
+                LogEvent::Update(ref update) => update,
+                _ => bail!("Unexpected entry #{} in event log", entry.seq),
+            };
+            let receiver = &mut receivers[channel_index(entry.player)];
+            match timeout(REPLAY_UPDATE_TIMEOUT, receiver.recv()).await {
+                Ok(Some(ref actual)) if same_update(actual, expected) => report.updates += 1,
+                Ok(Some(actual)) => bail!(
+                    "Diverged at entry #{}: {} should have received {:?} but got {:?}",
+                    entry.seq,
+                    recipient_name(entry.player),
+                    expected,
+                    actual
+                ),
+                Ok(None) => bail!(
+                    "Diverged at entry #{}: game stopped before {} received {:?}",
+                    entry.seq,
+                    recipient_name(entry.player),
+                    expected
+                ),
+                Err(_) => bail!(
+                    "Diverged at entry #{}: {} never received {:?}",
+                    entry.seq,
+                    recipient_name(entry.player),
+                    expected
+                ),
+            }
+        }
+
+        match end {
+            Some(LogEntry {
+                event: LogEvent::Request(request),
+                player,
+                ..
+            }) => {
+                let sender: &Sender<ChessRequest> = match senders {
+                    Some(ref senders) => &senders[channel_index(*player)],
+                    None => bail!("A request was logged after the game ended"),
+                };
+                sender.send(request.clone()).await.ok();
+                report.requests += 1;
+                if let ChessRequest::Abort { .. } = request {
+                    senders = None;
+                }
+            }
+            Some(LogEntry {
+                event: LogEvent::HintSearched { request },
+                ..
+            }) => {
+                hints_released_tx.send(*request).ok();
+            }
+            _ => {}
+        }
+    }
+
+    // Closing the request channels ends the game. Anything still arriving wasn't logged.
+    drop(senders);
+    for (index, receiver) in receivers.iter_mut().enumerate() {
+        if let Some(update) = drain(receiver).await {
+            bail!(
+                "Replay sent an update to {} that was not logged: {:?}",
+                recipient_name(channel_player(index)),
+                update
+            );
+        }
+    }
+    game.await.context("Join game task")??;
+    Ok(report)
+}
+
+async fn drain(receiver: &mut Receiver<ChessUpdate>) -> Option<ChessUpdate> {
+    timeout(REPLAY_UPDATE_TIMEOUT, receiver.recv())
+        .await
+        .unwrap_or_default()
+}
+
+/// Hints searched with a time limit may differ between runs
+fn same_update(actual: &ChessUpdate, expected: &ChessUpdate) -> bool {
+    match (actual, expected) {
+        (
+            ChessUpdate::Hint { hints_taken, .. },
+            ChessUpdate::Hint {
+                hints_taken: expected_hints_taken,
+                ..
+            },
+        ) => hints_taken == expected_hints_taken,
+        _ => actual == expected,
+    }
+}
+
+fn channel_index(player: Option<Player>) -> usize {
+    match player {
+        Some(Player::White) => 0,
+        Some(Player::Black) => 1,
+        None => 2,
+    }
+}
+
+fn channel_player(index: usize) -> Option<Player> {
+    match index {
+        0 => Some(Player::White),
+        1 => Some(Player::Black),
+        _ => None,
+    }
+}
+
+fn recipient_name(player: Option<Player>) -> String {
+    match player {
+        Some(player) => player.to_string(),
+        None => "Spectators".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{create_game, SearchLimits};
+    use crate::Square;
+
+    fn config(event_log: Option<&Path>) -> ChessConfig {
+        ChessConfig {
+            starting_fen: None,
+            can_black_undo: true,
+            can_white_undo: true,
+            allow_undo_after_loose: true,
+            event_log: event_log.map(Path::to_path_buf),
+            tablebase: None,
+            hints: None,
+            hints_taken: [0, 0],
+        }
+    }
+
+    fn mv(source: &str, destination: &str) -> ChessRequest {
+        ChessRequest::MovePiece {
+            source: source.parse::<Square>().unwrap(),
+            destination: destination.parse::<Square>().unwrap(),
+            promotion: None,
+        }
+    }
+
+    async fn await_update(receiver: &mut Receiver<ChessUpdate>, pred: fn(&ChessUpdate) -> bool) {
+        while !pred(&receiver.recv().await.unwrap()) {}
+    }
+
+    /// Plays a short session with logging enabled and returns the log
+    async fn record_session(path: &Path) -> Vec<LogEntry> {
+        let (white_update_tx, mut white_update_rx) = channel::<ChessUpdate>(256);
+        let (white_request_tx, white_request_rx) = channel::<ChessRequest>(256);
+        let (black_update_tx, mut black_update_rx) = channel::<ChessUpdate>(256);
+        let (black_request_tx, black_request_rx) = channel::<ChessRequest>(256);
+        let game = tokio::spawn(create_game(
+            (white_update_tx, white_request_rx),
+            (black_update_tx, black_request_rx),
+            crate::proto::stubbed_spectator(),
+            config(Some(path)),
+        ));
+
+        // Each request waits for its last update, since the order of
+        // requests from different players is not deterministic otherwise.
+        white_request_tx.send(mv("E2", "E4")).await.unwrap();
+        await_update(&mut black_update_rx, |u| {
+            matches!(u, ChessUpdate::PossibleMoves { .. })
+        })
+        .await;
+        black_request_tx.send(mv("E7", "E5")).await.unwrap();
+        await_update(&mut white_update_rx, |u| {
+            matches!(u, ChessUpdate::PossibleMoves { .. })
+        })
+        .await;
+        white_request_tx.send(mv("E1", "E3")).await.unwrap(); // Illegal
+        await_update(&mut white_update_rx, |u| {
+            matches!(u, ChessUpdate::MovePieceFailedResponse { .. })
+        })
+        .await;
+        let undo = ChessRequest::UndoMoves { moves: 1 };
+        black_request_tx.send(undo).await.unwrap(); // Not black's turn
+        await_update(&mut black_update_rx, |u| {
+            matches!(u, ChessUpdate::UndoMovesFailedResponse { .. })
+        })
+        .await;
+        let undo = ChessRequest::UndoMoves { moves: 2 };
+        white_request_tx.send(undo).await.unwrap();
+        await_update(&mut white_update_rx, |u| {
+            matches!(u, ChessUpdate::MovesUndone { .. })
+        })
+        .await;
+        white_request_tx
+            .send(ChessRequest::Abort {
+                message: "Done".to_owned(),
+            })
+            .await
+            .unwrap();
+        game.await.unwrap().unwrap();
+        read(path).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn logged_session_is_reproduced() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries = record_session(&dir.path().join("game.jsonl")).await;
+
+        assert_eq!(
+            entries[0].event,
+            LogEvent::GameStarted {
+                config: config(Some(&dir.path().join("game.jsonl")))
+            }
+        );
+        assert!(entries
+            .windows(2)
+            .all(|pair| pair[0].seq + 1 == pair[1].seq));
+        let report = verify(&entries).await.unwrap();
+        assert_eq!(report.requests, 6);
+        assert_eq!(
+            report.updates,
+            entries
+                .iter()
+                .filter(|entry| matches!(entry.event, LogEvent::Update(_)))
+                .count()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hint_followed_at_once_by_a_move_is_reproduced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("game.jsonl");
+        let (white_update_tx, mut white_update_rx) = channel::<ChessUpdate>(256);
+        let (white_request_tx, white_request_rx) = channel::<ChessRequest>(256);
+        let (black_update_tx, mut black_update_rx) = channel::<ChessUpdate>(256);
+        let (_black_request_tx, black_request_rx) = channel::<ChessRequest>(256);
+        let game = tokio::spawn(create_game(
+            (white_update_tx, white_request_rx),
+            (black_update_tx, black_request_rx),
+            crate::proto::stubbed_spectator(),
+            ChessConfig {
+                hints: Some(SearchLimits::depth(3).into()),
+                ..config(Some(&path))
+            },
+        ));
+
+        // The hint arrives before or after the rejection
+        white_request_tx.send(ChessRequest::Hint).await.unwrap();
+        white_request_tx.send(mv("E1", "E3")).await.unwrap(); // Illegal
+        let (mut hinted, mut rejected) = (false, false);
+        while !(hinted && rejected) {
+            match white_update_rx.recv().await.unwrap() {
+                ChessUpdate::Hint { .. } => hinted = true,
+                ChessUpdate::MovePieceFailedResponse { .. } => rejected = true,
+                _ => {}
+            }
+        }
+        // The hint is dropped for the old position (if the game still takes it)
+        white_request_tx.send(ChessRequest::Hint).await.unwrap();
+        white_request_tx.send(mv("E2", "E4")).await.unwrap();
+        await_update(&mut black_update_rx, |u| {
+            matches!(u, ChessUpdate::PossibleMoves { .. })
+        })
+        .await;
+        white_request_tx
+            .send(ChessRequest::Abort {
+                message: "Done".to_owned(),
+            })
+            .await
+            .unwrap();
+        game.await.unwrap().unwrap();
+        let entries = read(&path).unwrap();
+
+        // However quickly the hints are found
+        for _ in 0..2 {
+            let report = verify(&entries).await.unwrap();
+            assert_eq!(report.requests, 5);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tampered_log_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut entries = record_session(&dir.path().join("game.jsonl")).await;
+
+        let undo_response = entries
+            .iter_mut()
+            .find(|entry| {
+                matches!(
+                    entry.event,
+                    LogEvent::Update(ChessUpdate::UndoMovesFailedResponse { .. })
+                )
+            })
+            .unwrap();
+        undo_response.event = LogEvent::Update(ChessUpdate::UndoMovesFailedResponse {
+            message: "Something else".to_owned(),
+        });
+        let err = verify(&entries).await.unwrap_err();
+        assert!(err.to_string().contains("Diverged"), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unwritable_log_does_not_stop_the_game() {
+        let dir = tempfile::tempdir().unwrap();
+        let not_a_directory = dir.path().join("file");
+        std::fs::write(&not_a_directory, "").unwrap();
+        let (white_update_tx, _white_update_rx) = channel::<ChessUpdate>(256);
+        let (white_request_tx, white_request_rx) = channel::<ChessRequest>(256);
+        let (black_update_tx, mut black_update_rx) = channel::<ChessUpdate>(256);
+        let (_black_request_tx, black_request_rx) = channel::<ChessRequest>(256);
+        let game = tokio::spawn(create_game(
+            (white_update_tx, white_request_rx),
+            (black_update_tx, black_request_rx),
+            crate::proto::stubbed_spectator(),
+            config(Some(&not_a_directory.join("game.jsonl"))),
+        ));
+
+        white_request_tx.send(mv("E2", "E4")).await.unwrap();
+        await_update(&mut black_update_rx, |u| {
+            matches!(u, ChessUpdate::PossibleMoves { .. })
+        })
+        .await;
+        white_request_tx
+            .send(ChessRequest::Abort {
+                message: "Done".to_owned(),
+            })
+            .await
+            .unwrap();
+        game.await.unwrap().unwrap();
+    }
+}
@@ -1,23 +1,56 @@
 pub use crate::{Player, Square};
-use anyhow::Result;
+use anyhow::{Context, Result};
 pub use pleco::{BitMove, Board, File, Piece, PieceType, Player as PlecoPlayer, Rank, SQ};
 use serde::{Deserialize, Serialize};
+use shakmaty::fen::Fen;
+use shakmaty::san::SanPlus;
+use shakmaty::uci::UciMove;
+use shakmaty::{CastlingMode, Chess, PositionError};
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ChessOutcome {
     Checkmate { winner: Player },
     Stalemate,
+    /// Agreed or declared by a remote server (e.g. in an online game)
+    Draw,
     Aborted { who: Option<Player> },
 }
 
+/// The piece a pawn turns into on the last rank
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Promotion {
+    Queen,
+    Rook,
+    Bishop,
+    Knight,
+}
+
+impl Promotion {
+    /// What `bit_move` promotes to (`None` if it isn't a promotion)
+    pub fn of(bit_move: BitMove) -> Option<Promotion> {
+        if !bit_move.is_promo() {
+            return None;
+        }
+        Some(match bit_move.promo_piece() {
+            PieceType::R => Promotion::Rook,
+            PieceType::B => Promotion::Bishop,
+            PieceType::N => Promotion::Knight,
+            _ => Promotion::Queen,
+        })
+    }
+}
+
 /// Wrapper around plecos board.
 /// Aims to be panic safe and not synchronize any internal data, meaning:
 ///  - no background tasks
 ///  - no changes without a mut access
+#[derive(Clone)]
 pub struct ChessGame {
     board: pleco::Board,
     board_moves_played_offset: u16,
     outcome: Option<ChessOutcome>,
+    /// Hints taken by White and Black
+    hints_taken: [u16; 2],
 }
 
 impl Default for ChessGame {
@@ -26,6 +59,7 @@ impl Default for ChessGame {
             board: Board::default(),
             board_moves_played_offset: 0,
             outcome: None,
+            hints_taken: [0, 0],
         }
     }
 }
@@ -74,6 +108,21 @@ impl ChessGame {
         self.board.generate_moves()
     }
 
+    pub fn hints_taken(&self, player: Player) -> u16 {
+        self.hints_taken[player as usize]
+    }
+
+    /// Continue counting from the hints of a saved game (White's first)
+    pub fn restore_hints_taken(&mut self, hints_taken: [u16; 2]) {
+        self.hints_taken = hints_taken;
+    }
+
+    /// Count a hint for the record. Returns the player's hints so far.
+    pub fn take_hint(&mut self, player: Player) -> u16 {
+        self.hints_taken[player as usize] += 1;
+        self.hints_taken(player)
+    }
+
     pub fn player_left(&mut self, player: Player) {
         if self.outcome.is_none() {
             self.outcome = Some(ChessOutcome::Aborted { who: Some(player) });
@@ -123,45 +172,30 @@ impl ChessGame {
         }
     }
 
-    pub fn move_piece_by_type(&mut self, piece: Piece, destination: Square, src_col: Option<File>, src_row: Option<Rank>) -> Result<(Square, Square)> {
+    /// Move the piece on `source`. `promotion` picks the piece a pawn
+    /// becomes (`None` takes whichever promotion comes last).
+    pub fn move_piece(
+        &mut self,
+        source: Square,
+        destination: Square,
+        promotion: Option<Promotion>,
+    ) -> Result<()> {
+        ensure!(
+            self.piece_on_square(self.turn(), source),
+            "The playing player has no piece on the source square!"
+        );
+        ensure!(source != destination, "Move does not actually move");
         ensure!(
             self.outcome.is_none(),
             "Can't do move since the game has already ended."
         );
-        let piece_locations = self.board.get_piece_locations();
-        let mut piece_type_locations = vec![];
-        for loc in piece_locations {
-            if loc.1 == piece {
-                piece_type_locations.push(loc.0.0)
-            }
-        }
-        // Find a legal move for `source` and `destination`
-        // (i.e. including promotions or other special data)
-        let mut candidate_moves: Vec<BitMove> = Vec::new();
-        for legal_move in self.board.generate_moves().iter() {
-            if piece_type_locations.contains(&legal_move.get_src_u8()) && legal_move.get_dest_u8() == destination.0 {
-                candidate_moves.push(legal_move.clone());
-            }
-        }
-        if candidate_moves.is_empty() {
-            return Err(anyhow!("Move not found as possibility"));
-        }
-        let mut selected_move: Option<&BitMove> = None;
-        if candidate_moves.len() == 1 {
-            selected_move = candidate_moves.first();
-        }
-        if selected_move.is_none() && src_col.is_some() {
-            selected_move = candidate_moves.iter().find(|bmove| bmove.src_col() == src_col.unwrap());
-        }
-        if selected_move.is_none() && src_row.is_some() {
-            selected_move = candidate_moves.iter().find(|bmove| bmove.src_row() == src_row.unwrap());
-        }
-        if selected_move.is_none() {
-            return Err(anyhow!("Move not found as possibility"));
-        }
-        let selected_move = selected_move.unwrap();
 
-        self.board.apply_move(selected_move.to_owned());
+        let selected_move = match self.find_move(source, destination, promotion) {
+            Some(bit_move) => bit_move,
+            None => return Err(anyhow!("Move not found as possibility")),
+        };
+
+        self.board.apply_move(selected_move);
         if let Err(e) = self.board.is_okay() {
             self.undo(1)?;
             return Err(anyhow!(
@@ -171,43 +205,127 @@ impl ChessGame {
         }
 
         self.update_game_outcome();
-        Ok((Square::from(SQ(selected_move.get_src_u8()) ), destination))
+        Ok(())
     }
 
-    pub fn move_piece(&mut self, source: Square, destination: Square) -> Result<()> {
-        ensure!(
-            self.piece_on_square(self.turn(), source),
-            "The playing player has no piece on the source square!"
-        );
-        ensure!(source != destination, "Move does not actually move");
-        ensure!(
-            self.outcome.is_none(),
-            "Can't do move since the game has already ended."
-        );
-
-        // Find a legal move for `source` and `destination`
-        // (i.e. including promotions or other special data)
+    /// Find a legal move for `source` and `destination`
+    /// (i.e. including promotions or other special data)
+    pub fn find_move(
+        &self,
+        source: Square,
+        destination: Square,
+        promotion: Option<Promotion>,
+    ) -> Option<BitMove> {
         let mut selected_move: Option<BitMove> = None;
         for legal_move in self.board.generate_moves().iter() {
-            if legal_move.get_src_u8() == source.0 && legal_move.get_dest_u8() == destination.0 {
-                selected_move = Some(legal_move.clone());
+            if legal_move.get_src_u8() == source.0
+                && legal_move.get_dest_u8() == destination.0
+                && (promotion.is_none() || Promotion::of(*legal_move) == promotion)
+            {
+                selected_move = Some(*legal_move);
             }
         }
-        if selected_move.is_none() {
-            return Err(anyhow!("Move not found as possibility"));
-        }
-        let selected_move = selected_move.unwrap();
+        selected_move
+    }
 
-        self.board.apply_move(selected_move);
-        if let Err(e) = self.board.is_okay() {
-            self.undo(1)?;
-            return Err(anyhow!(
-                "Board got into illegal state after move. Reason: \"{:?}\"",
-                e
-            ));
-        }
+    /// The move in UCI notation (e.g. "e2e4" or "e7e8q") if it is legal
+    pub fn uci_move(
+        &self,
+        source: Square,
+        destination: Square,
+        promotion: Option<Promotion>,
+    ) -> Option<String> {
+        self.find_move(source, destination, promotion)
+            .map(|bit_move| bit_move.stringify())
+    }
 
+    /// Find a legal move given in UCI notation (e.g. "e1g1" or "e7e8q")
+    pub fn find_uci_move(&self, uci: &str) -> Option<BitMove> {
+        self.board
+            .generate_moves()
+            .iter()
+            .find(|legal_move| legal_move.stringify() == uci)
+            .copied()
+    }
+
+    /// Apply a move given in UCI notation. Returns source, destination and
+    /// promotion as used by `move_piece()` (castling moves the king onto
+    /// the rook).
+    pub fn move_piece_uci(&mut self, uci: &str) -> Result<(Square, Square, Option<Promotion>)> {
+        ensure!(
+            self.outcome.is_none(),
+            "Can't do move since the game has already ended."
+        );
+        let selected_move = self
+            .find_uci_move(uci)
+            .ok_or_else(|| anyhow!("Move {} not found as possibility", uci))?;
+
+        self.board.apply_move(selected_move);
         self.update_game_outcome();
-        Ok(())
+        Ok((
+            selected_move.get_src().into(),
+            selected_move.get_dest().into(),
+            Promotion::of(selected_move),
+        ))
+    }
+
+    /// Set an outcome decided outside of the board (e.g. by a server)
+    pub fn set_outcome(&mut self, outcome: Option<ChessOutcome>) {
+        self.outcome = outcome;
+    }
+}
+
+/// The legal move on `board` that leads to the position `fen` (e.g. to
+/// recover the moves of a game from its positions). Only pieces, side to
+/// move and castling rights are compared since FENs of other sources may
+/// write the en passant square and the clocks differently.
+pub fn move_to(board: &Board, fen: &str) -> Option<BitMove> {
+    fn position(fen: &str) -> Vec<&str> {
+        fen.split_whitespace().take(3).collect()
     }
+    let wanted = position(fen);
+    board.generate_moves().iter().copied().find(|bit_move| {
+        let mut after = board.shallow_clone();
+        after.apply_move(*bit_move);
+        position(&after.fen()) == wanted
+    })
+}
+
+/// The same position in shakmaty (for its notation and tablebase support)
+pub(crate) fn shakmaty_position(board: &Board) -> Result<Chess> {
+    let fen = board.fen();
+    fen.parse::<Fen>()
+        .with_context(|| format!("Parse FEN {}", fen))?
+        .into_position(CastlingMode::Standard)
+        // pleco keeps en passant squares where no capture is possible
+        .or_else(PositionError::ignore_invalid_ep_square)
+        .map_err(|e| anyhow!("Invalid position {}: {}", fen, e))
+}
+
+/// Moves played one after another from `board` in standard algebraic
+/// notation (e.g. "Nf3" or "exd8=Q+")
+pub fn san_line(board: &Board, moves: &[BitMove]) -> Vec<String> {
+    let mut position = match shakmaty_position(board) {
+        Ok(position) => position,
+        Err(e) => {
+            warn!("Falling back to UCI notation: {:?}", e);
+            return moves.iter().map(|bit_move| bit_move.stringify()).collect();
+        }
+    };
+    moves
+        .iter()
+        .map(|bit_move| {
+            let uci = bit_move.stringify();
+            match uci
+                .parse::<UciMove>()
+                .ok()
+                .and_then(|uci_move| uci_move.to_move(&position).ok())
+            {
+                Some(legal_move) => {
+                    SanPlus::from_move_and_play_unchecked(&mut position, legal_move).to_string()
+                }
+                None => uci,
+            }
+        })
+        .collect()
 }
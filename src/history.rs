@@ -0,0 +1,308 @@
+//! The moves of a game as a bot follows them, so it knows which positions
+//! would repeat
+
+use crate::game::{BitMove, Board, Promotion};
+use crate::Square;
+use anyhow::Result;
+use pleco::bots::alphabeta::alpha_beta_search;
+use pleco::core::score::INFINITE;
+
+/// Depth of the searches judging whether a draw by repetition is welcome
+pub const REPETITION_DEPTH: u16 = 3;
+/// Advantage in centipawns from which a bot avoids repeating positions (and
+/// below the negative of which it seeks them)
+pub const DRAW_MARGIN: i32 = 50;
+
+/// Positions since the start of the game (or since a position the bot
+/// couldn't follow)
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameHistory {
+    /// The start and the position after every move
+    fens: Vec<String>,
+    /// Zobrist key of every position in `fens`
+    keys: Vec<u64>,
+    moves: Vec<BitMove>,
+}
+
+impl GameHistory {
+    pub fn new(fen: &str) -> Result<Self> {
+        let board = parse(fen)?;
+        Ok(Self {
+            fens: vec![fen.to_owned()],
+            keys: vec![board.zobrist()],
+            moves: vec![],
+        })
+    }
+
+    /// The current position replayed from the start, so the board knows
+    /// the moves before
+    pub fn board(&self) -> Board {
+        let mut board = parse(&self.fens[0]).expect("Start position was parsed before");
+        for bit_move in &self.moves {
+            board.apply_move(*bit_move);
+        }
+        board
+    }
+
+    pub fn moves(&self) -> &[BitMove] {
+        &self.moves
+    }
+
+    /// Plays the move from `source` to `destination` (picked like
+    /// `ChessGame::move_piece()` does)
+    pub fn play(
+        &mut self,
+        source: Square,
+        destination: Square,
+        promotion: Option<Promotion>,
+    ) -> Result<()> {
+        let mut board = self.board();
+        let bit_move = board
+            .generate_moves()
+            .iter()
+            .rev()
+            .find(|legal_move| {
+                legal_move.get_src_u8() == source.0
+                    && legal_move.get_dest_u8() == destination.0
+                    && (promotion.is_none() || Promotion::of(**legal_move) == promotion)
+            })
+            .copied()
+            .ok_or_else(|| anyhow!("Move from {} to {} is not legal", source, destination))?;
+        board.apply_move(bit_move);
+        self.fens.push(board.fen());
+        self.keys.push(board.zobrist());
+        self.moves.push(bit_move);
+        Ok(())
+    }
+
+    /// Makes `fen` the current position. Goes back to it if it was played
+    /// before (i.e. after an undo) and otherwise starts over from it.
+    pub fn sync(&mut self, fen: &str) -> Result<()> {
+        // The move counters make equal FENs the same point of the game
+        match self.fens.iter().rposition(|played| played == fen) {
+            Some(index) => {
+                self.fens.truncate(index + 1);
+                self.keys.truncate(index + 1);
+                self.moves.truncate(index);
+            }
+            None => {
+                debug!("Following the game from {} on", fen);
+                *self = Self::new(fen)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// How often the position after `bit_move` occurred before
+    pub fn repetitions(&self, bit_move: BitMove) -> usize {
+        let mut board = self.board();
+        board.apply_move(bit_move);
+        let key = board.zobrist();
+        // Positions before the last capture or pawn move can't come back
+        let reversible = board.rule_50().max(0) as usize;
+        self.keys
+            .iter()
+            .rev()
+            .take(reversible)
+            .filter(|played| **played == key)
+            .count()
+    }
+
+    /// Whether `bit_move` draws by threefold repetition
+    pub fn repeats(&self, bit_move: BitMove) -> bool {
+        self.repetitions(bit_move) >= 2
+    }
+
+    /// `best_move` unless a repetition changes the picture. When ahead, it
+    /// avoids positions that occurred before, since the opponent could
+    /// repeat them a third time. When behind, it draws by threefold
+    /// repetition unless that move is much worse than `best_move`.
+    pub fn consider_repetitions(&self, best_move: BitMove) -> BitMove {
+        let board = self.board();
+        let moves: Vec<BitMove> = board.generate_moves().iter().copied().collect();
+        if moves
+            .iter()
+            .all(|bit_move| self.repetitions(*bit_move) == 0)
+        {
+            return best_move;
+        }
+        let score = |bit_move: BitMove| {
+            let mut after = board.shallow_clone();
+            after.apply_move(bit_move);
+            -(alpha_beta_search(
+                &mut after,
+                -INFINITE as i16,
+                INFINITE as i16,
+                REPETITION_DEPTH - 1,
+            )
+            .score as i32)
+        };
+        let best_score = score(best_move);
+
+        if self.repetitions(best_move) > 0 {
+            if best_score <= DRAW_MARGIN {
+                return best_move;
+            }
+            // Ahead. Play on unless every other move is worse than a draw.
+            match moves
+                .into_iter()
+                .filter(|bit_move| self.repetitions(*bit_move) == 0)
+                .map(|bit_move| (score(bit_move), bit_move))
+                .max_by_key(|(score, _)| *score)
+            {
+                Some((score, other)) if score > 0 => {
+                    info!("Bot avoids repeating with {} ({})", other, score);
+                    other
+                }
+                _ => best_move,
+            }
+        } else {
+            if best_score >= -DRAW_MARGIN {
+                return best_move;
+            }
+            // Behind. A draw is better, unless the opponent could just
+            // take the repeating move as a gift.
+            match moves
+                .into_iter()
+                .filter(|bit_move| self.repeats(*bit_move))
+                .map(|bit_move| (score(bit_move), bit_move))
+                .max_by_key(|(score, _)| *score)
+            {
+                Some((score, draw)) if score >= best_score - DRAW_MARGIN => {
+                    info!("Bot repeats with {} to get a draw ({})", draw, score);
+                    draw
+                }
+                _ => best_move,
+            }
+        }
+    }
+}
+
+fn parse(fen: &str) -> Result<Board> {
+    Board::from_fen(fen).map_err(|e| anyhow!("Invalid FEN {}: {:?}", fen, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    /// White is a queen up and the kings can walk back and forth
+    const QUEEN_UP_FEN: &str = "4k3/8/8/8/8/8/8/3QK3 w - - 0 1";
+
+    fn sq(square: &str) -> Square {
+        square.parse().unwrap()
+    }
+
+    fn play(history: &mut GameHistory, moves: &[(&str, &str)]) {
+        for (source, destination) in moves {
+            history.play(sq(source), sq(destination), None).unwrap();
+        }
+    }
+
+    fn find(history: &GameHistory, source: &str, destination: &str) -> BitMove {
+        history
+            .board()
+            .generate_moves()
+            .iter()
+            .copied()
+            .find(|bit_move| {
+                bit_move.get_src_u8() == sq(source).0 && bit_move.get_dest_u8() == sq(destination).0
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn replays_the_moves_and_goes_back_on_undo() {
+        let mut history = GameHistory::new(START_FEN).unwrap();
+        play(&mut history, &[("E2", "E4"), ("E7", "E5")]);
+        assert_eq!(history.moves().len(), 2);
+        assert_eq!(history.board().moves_played(), 2);
+        let after_e4 = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+
+        history.sync(after_e4).unwrap();
+        assert_eq!(history.moves().len(), 1);
+        assert_eq!(history.board().fen(), after_e4);
+
+        // Unknown positions start a new history
+        history.sync(QUEEN_UP_FEN).unwrap();
+        assert!(history.moves().is_empty());
+        assert_eq!(history.board().fen(), QUEEN_UP_FEN);
+    }
+
+    #[test]
+    fn counts_repetitions() {
+        let mut history = GameHistory::new(START_FEN).unwrap();
+        play(&mut history, &[("G1", "F3"), ("G8", "F6")]);
+        assert_eq!(history.repetitions(find(&history, "F3", "G1")), 0);
+        play(&mut history, &[("F3", "G1")]);
+        assert_eq!(history.repetitions(find(&history, "F6", "G8")), 1);
+        assert_eq!(history.repetitions(find(&history, "E7", "E5")), 0);
+        // Seen only once before is no draw yet
+        assert!(!history.repeats(find(&history, "F6", "G8")));
+        play(
+            &mut history,
+            &[("F6", "G8"), ("G1", "F3"), ("G8", "F6"), ("F3", "G1")],
+        );
+        assert_eq!(history.repetitions(find(&history, "F6", "G8")), 2);
+        assert!(history.repeats(find(&history, "F6", "G8")));
+    }
+
+    #[test]
+    fn avoids_repetitions_when_ahead() {
+        let mut history = GameHistory::new(QUEEN_UP_FEN).unwrap();
+        play(
+            &mut history,
+            &[("E1", "F1"), ("E8", "F8"), ("F1", "E1"), ("F8", "E8")],
+        );
+        let repeating = find(&history, "E1", "F1");
+        assert_eq!(history.repetitions(repeating), 1);
+        let played = history.consider_repetitions(repeating);
+        assert_ne!(played, repeating);
+        assert_eq!(history.repetitions(played), 0);
+    }
+
+    #[test]
+    fn seeks_repetitions_when_behind() {
+        // White is a queen down
+        let mut history = GameHistory::new("3qk3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        play(
+            &mut history,
+            &[("E1", "F1"), ("E8", "F8"), ("F1", "E1"), ("F8", "E8")],
+        );
+        let not_repeating = find(&history, "E1", "E2");
+        // Once more is no draw
+        assert_eq!(history.consider_repetitions(not_repeating), not_repeating);
+
+        play(
+            &mut history,
+            &[("E1", "F1"), ("E8", "F8"), ("F1", "E1"), ("F8", "E8")],
+        );
+        assert!(!history.repeats(not_repeating));
+        let played = history.consider_repetitions(not_repeating);
+        assert_eq!(played, find(&history, "E1", "F1"));
+    }
+
+    #[test]
+    fn does_not_repeat_with_a_blunder() {
+        // White is behind, but repeating with Ra2 loses the rook to the queen
+        let mut history = GameHistory::new("6k1/8/8/8/8/1q6/8/R5K1 w - - 0 1").unwrap();
+        play(
+            &mut history,
+            &[
+                ("A1", "A2"),
+                ("G8", "H8"),
+                ("A2", "A1"),
+                ("H8", "G8"),
+                ("A1", "A2"),
+                ("G8", "H8"),
+                ("A2", "A1"),
+                ("H8", "G8"),
+            ],
+        );
+        let blunder = find(&history, "A1", "A2");
+        assert!(history.repeats(blunder));
+        let best_move = find(&history, "G1", "H1");
+        assert_eq!(history.consider_repetitions(best_move), best_move);
+    }
+}
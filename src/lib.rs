@@ -7,12 +7,30 @@ extern crate anyhow;
 extern crate log;
 extern crate serde;
 
+mod engine_process;
 mod player;
+mod polyglot_random;
 mod square;
 
+pub mod analysis;
+pub mod annotation;
+pub mod book;
+pub mod cecp;
+pub mod event_log;
 pub mod game;
+pub mod history;
+pub mod lichess;
+pub mod pgn;
 pub mod proto;
 pub mod replay;
+pub mod search;
+pub mod strength;
+pub mod tablebase;
+pub mod tournament;
+pub mod uci;
+
+#[cfg(test)]
+mod test_harness;
 
 pub use player::Player;
 pub use square::Square;
@@ -0,0 +1,966 @@
+//! Play online games with the [Lichess Board API](https://lichess.org/api#tag/Board).
+//!
+//! `create_lichess_game()` takes the place of `proto::create_game()` for
+//! the local player. The opponent plays on Lichess, so the server is the
+//! authority for moves and the outcome. Its event stream (`gameFull`,
+//! `gameState` and `chatLine`) gets turned into `ChessUpdate`s and a
+//! `ChessRequest::MovePiece` is sent to the server as UCI move.
+
+use crate::game::{ChessGame, ChessOutcome};
+use crate::proto::{ChessRequest, ChessUpdate};
+use crate::Player;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task;
+
+pub const DEFAULT_BASE_URL: &str = "https://lichess.org";
+/// Game streams send a keep alive line every few seconds. Without any line
+/// for this long, the connection is considered lost.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn default_base_url() -> String {
+    DEFAULT_BASE_URL.to_owned()
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LichessConfig {
+    /// Personal API token with the scope "board:play"
+    pub token: String,
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+}
+
+/// An entry of "/api/account/playing"
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OngoingGame {
+    pub game_id: String,
+    pub is_my_turn: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LichessPlayer {
+    /// Missing for the Lichess AI
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub ai_level: Option<u8>,
+}
+
+impl LichessPlayer {
+    pub fn display_name(&self) -> String {
+        match (&self.name, self.ai_level) {
+            (Some(name), _) => name.clone(),
+            (None, Some(level)) => format!("Stockfish level {}", level),
+            (None, None) => "Anonymous".to_owned(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct GameState {
+    /// All moves of the game in UCI notation, separated by spaces
+    pub moves: String,
+    pub status: String,
+    #[serde(default)]
+    pub winner: Option<String>,
+}
+
+/// One line of the stream "/api/board/game/stream/{gameId}"
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum GameEvent {
+    #[serde(rename_all = "camelCase")]
+    GameFull {
+        id: String,
+        white: LichessPlayer,
+        black: LichessPlayer,
+        /// Either a FEN or "startpos"
+        initial_fen: String,
+        state: GameState,
+    },
+    GameState(GameState),
+    ChatLine {
+        username: String,
+        text: String,
+    },
+    /// Events that are not relevant here (e.g. "opponentGone")
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Clone)]
+pub struct LichessClient {
+    config: LichessConfig,
+    agent: ureq::Agent,
+}
+
+impl LichessClient {
+    pub fn new(config: LichessConfig) -> Self {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(10))
+            .timeout_read(READ_TIMEOUT)
+            .build();
+        Self { config, agent }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.base_url.trim_end_matches('/'), path)
+    }
+
+    fn authorization(&self) -> String {
+        format!("Bearer {}", self.config.token)
+    }
+
+    fn get(&self, path: &str) -> Result<ureq::Response> {
+        self.agent
+            .get(&self.url(path))
+            .set("Authorization", &self.authorization())
+            .call()
+            .map_err(describe_error)
+    }
+
+    fn post(&self, path: &str) -> Result<()> {
+        self.agent
+            .post(&self.url(path))
+            .set("Authorization", &self.authorization())
+            .call()
+            .map_err(describe_error)?;
+        Ok(())
+    }
+
+    /// The id (lowercase username) of the account the token belongs to
+    pub fn account_id(&self) -> Result<String> {
+        #[derive(Deserialize)]
+        struct Account {
+            id: String,
+        }
+        let account: Account = self
+            .get("/api/account")?
+            .into_json()
+            .context("Parse account")?;
+        Ok(account.id)
+    }
+
+    pub fn ongoing_games(&self) -> Result<Vec<OngoingGame>> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Playing {
+            now_playing: Vec<OngoingGame>,
+        }
+        let playing: Playing = self
+            .get("/api/account/playing")?
+            .into_json()
+            .context("Parse ongoing games")?;
+        Ok(playing.now_playing)
+    }
+
+    /// Pick the ongoing game that most likely should be played now
+    pub fn next_game(&self) -> Result<Option<OngoingGame>> {
+        let games = self.ongoing_games()?;
+        Ok(games
+            .iter()
+            .find(|game| game.is_my_turn)
+            .or_else(|| games.first())
+            .cloned())
+    }
+
+    /// Open the event stream of a game and wait for its initial state
+    pub fn join_game(&self, game_id: &str) -> Result<LichessGame> {
+        let account_id = self.account_id()?;
+        let response = self.get(&format!("/api/board/game/stream/{}", game_id))?;
+        let mut events = EventStream {
+            reader: Box::new(BufReader::new(response.into_reader())),
+        };
+
+        let (white, black, initial_fen, state) = match events.next_event()? {
+            Some(GameEvent::GameFull {
+                white,
+                black,
+                initial_fen,
+                state,
+                ..
+            }) => (white, black, initial_fen, state),
+            Some(event) => bail!("Expected the full game first but got {:?}", event),
+            None => bail!("The game stream ended before it started"),
+        };
+        let (me, opponent) = if white.id.as_ref() == Some(&account_id) {
+            (Player::White, black)
+        } else if black.id.as_ref() == Some(&account_id) {
+            (Player::Black, white)
+        } else {
+            bail!("You ({}) are not playing in game {}", account_id, game_id);
+        };
+
+        Ok(LichessGame {
+            client: self.clone(),
+            id: game_id.to_owned(),
+            me,
+            opponent: opponent.display_name(),
+            initial_fen: if initial_fen == "startpos" {
+                None
+            } else {
+                Some(initial_fen)
+            },
+            state,
+            events,
+        })
+    }
+
+    pub fn make_move(&self, game_id: &str, uci_move: &str) -> Result<()> {
+        self.post(&format!("/api/board/game/{}/move/{}", game_id, uci_move))
+    }
+
+    pub fn resign(&self, game_id: &str) -> Result<()> {
+        self.post(&format!("/api/board/game/{}/resign", game_id))
+    }
+}
+
+/// Use the message Lichess puts into the body of errors (e.g. `{"error":"Not your turn"}`)
+fn describe_error(error: ureq::Error) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let body = response.into_string().unwrap_or_default();
+            let message = serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|json| json["error"].as_str().map(|error| error.to_owned()))
+                .unwrap_or(body);
+            anyhow!("Lichess answered with {}: {}", code, message)
+        }
+        ureq::Error::Transport(transport) => {
+            anyhow!("Failed to connect to Lichess: {}", transport)
+        }
+    }
+}
+
+/// Newline delimited JSON. Empty lines are keep alive messages.
+struct EventStream {
+    reader: Box<dyn BufRead + Send>,
+}
+
+impl EventStream {
+    /// Returns `None` when the stream ended
+    fn next_event(&mut self) -> Result<Option<GameEvent>> {
+        self.next_event_unless(|| false)
+    }
+
+    /// `next_event()` that also returns `None` once `stop` returns true.
+    /// It is asked after every line (keep alive ones included).
+    fn next_event_unless(&mut self, stop: impl Fn() -> bool) -> Result<Option<GameEvent>> {
+        loop {
+            if stop() {
+                return Ok(None);
+            }
+            let mut line = String::new();
+            if self
+                .reader
+                .read_line(&mut line)
+                .context("Read game stream")?
+                == 0
+            {
+                return Ok(None);
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            return serde_json::from_str(&line)
+                .with_context(|| format!("Parse game event {:?}", line.trim()))
+                .map(Some);
+        }
+    }
+}
+
+/// A joined game. Use it with `create_lichess_game()`.
+pub struct LichessGame {
+    client: LichessClient,
+    pub id: String,
+    pub me: Player,
+    /// Name of the opponent
+    pub opponent: String,
+    initial_fen: Option<String>,
+    state: GameState,
+    events: EventStream,
+}
+
+/// Outcome as decided by Lichess (`None` while the game is running)
+fn server_outcome(state: &GameState) -> Option<ChessOutcome> {
+    let winner = match state.winner.as_deref() {
+        Some("white") => Some(Player::White),
+        Some("black") => Some(Player::Black),
+        _ => None,
+    };
+    match (state.status.as_str(), winner) {
+        ("created", _) | ("started", _) => None,
+        ("mate", Some(winner)) => Some(ChessOutcome::Checkmate { winner }),
+        ("stalemate", _) => Some(ChessOutcome::Stalemate),
+        ("draw", _) | ("outoftime", None) => Some(ChessOutcome::Draw),
+        // Resigned, timed out, etc.
+        (_, Some(winner)) => Some(ChessOutcome::Aborted {
+            who: Some(winner.other_player()),
+        }),
+        (_, None) => Some(ChessOutcome::Aborted { who: None }),
+    }
+}
+
+fn new_board(initial_fen: &Option<String>) -> Result<ChessGame> {
+    match initial_fen {
+        Some(fen) => ChessGame::from_fen(fen),
+        None => Ok(ChessGame::default()),
+    }
+}
+
+/// Keeps a local `ChessGame` in line with the moves reported by Lichess
+struct Mirror {
+    initial_fen: Option<String>,
+    game: ChessGame,
+    moves: Vec<String>,
+}
+
+impl Mirror {
+    /// Apply a new state from Lichess and return the updates for the local player
+    fn sync(&mut self, state: &GameState, me: Player) -> Result<Vec<ChessUpdate>> {
+        let mut updates = vec![];
+        let prev_outcome = self.game.outcome();
+        let moves: Vec<String> = state.moves.split_whitespace().map(String::from).collect();
+
+        let common = self
+            .moves
+            .iter()
+            .zip(moves.iter())
+            .take_while(|(known, new)| known == new)
+            .count();
+        if common < self.moves.len() {
+            // Takeback on Lichess. Replay the remaining moves.
+            let undone = self.moves.len() - common;
+            self.game = new_board(&self.initial_fen)?;
+            for uci_move in &self.moves[..common] {
+                self.game.move_piece_uci(uci_move)?;
+            }
+            self.moves.truncate(common);
+            self.game.set_outcome(None);
+            updates.push(ChessUpdate::MovesUndone {
+                who: me.other_player(),
+                moves: undone as u16,
+            });
+        }
+
+        for uci_move in &moves[common..] {
+            let player = self.game.turn();
+            let (source, destination, promotion) = self
+                .game
+                .move_piece_uci(uci_move)
+                .context("Lichess reported an unexpected move")?;
+            self.moves.push(uci_move.clone());
+            updates.push(ChessUpdate::PlayerMovedAPiece {
+                player,
+                moved_piece_source: source,
+                moved_piece_destination: destination,
+                moved_piece_promotion: promotion,
+            });
+        }
+
+        let new_outcome = server_outcome(state).or_else(|| self.game.outcome());
+        self.game.set_outcome(new_outcome);
+        if new_outcome != prev_outcome {
+            updates.push(ChessUpdate::Outcome {
+                outcome: new_outcome,
+            });
+        }
+
+        if !updates.is_empty() {
+            updates.push(ChessUpdate::PlayerSwitch {
+                player: self.game.turn(),
+                fen: self.game.fen(),
+            });
+            if self.game.turn() == me && new_outcome.is_none() {
+                updates.push(self.possible_moves());
+            }
+        }
+        Ok(updates)
+    }
+
+    fn possible_moves(&self) -> ChessUpdate {
+        ChessUpdate::PossibleMoves {
+            possible_moves: self
+                .game
+                .possible_moves()
+                .iter()
+                .map(|bit_move| (bit_move.get_src().into(), bit_move.get_dest().into()))
+                .collect(),
+        }
+    }
+}
+
+/// Serve the local player of a game on Lichess.
+/// Ends when the local player disconnects (the game continues online),
+/// resigns with `ChessRequest::Abort` or the connection to Lichess is lost.
+pub async fn create_lichess_game(
+    lichess_game: LichessGame,
+    player: (Sender<ChessUpdate>, Receiver<ChessRequest>),
+) -> Result<()> {
+    let LichessGame {
+        client,
+        id,
+        me,
+        initial_fen,
+        state,
+        mut events,
+        ..
+    } = lichess_game;
+    let (update_tx, mut request_rx) = player;
+
+    // Read the blocking stream on its own thread. It stops with the next
+    // line after the game is left.
+    let (event_tx, mut event_rx) = channel::<GameEvent>(256);
+    task::spawn_blocking(move || loop {
+        match events.next_event_unless(|| event_tx.is_closed()) {
+            Ok(Some(event)) => {
+                if event_tx.blocking_send(event).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Stopped reading the game stream: {:?}", e);
+                return;
+            }
+        }
+    });
+
+    let mut mirror = Mirror {
+        game: new_board(&initial_fen)?,
+        initial_fen,
+        moves: vec![],
+    };
+
+    macro_rules! send {
+        ($msg: expr) => {
+            if update_tx.send($msg).await.is_err() {
+                break;
+            }
+        };
+    }
+
+    let mut initial_updates = mirror.sync(&state, me)?;
+    if initial_updates.is_empty() {
+        // Nothing played yet
+        initial_updates.push(ChessUpdate::PlayerSwitch {
+            player: mirror.game.turn(),
+            fen: mirror.game.fen(),
+        });
+        if mirror.game.turn() == me {
+            initial_updates.push(mirror.possible_moves());
+        }
+    }
+    for update in initial_updates {
+        if update_tx.send(update).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    info!("Lichess game {} initialized. Playing as {}.", id, me);
+
+    loop {
+        tokio::select! {
+            event = event_rx.recv() => match event {
+                Some(GameEvent::GameFull { state, .. }) | Some(GameEvent::GameState(state)) => {
+                    for update in mirror.sync(&state, me)? {
+                        send!(update);
+                    }
+                }
+                Some(GameEvent::ChatLine { username, text }) => {
+                    send!(ChessUpdate::ChatMessage { username, text });
+                }
+                Some(GameEvent::Other) => {}
+                None => {
+                    if mirror.game.outcome().is_none() {
+                        send!(ChessUpdate::GenericErrorResponse {
+                            message: "Lost the connection to Lichess.".to_owned(),
+                        });
+                    }
+                    break;
+                }
+            },
+            request = request_rx.recv() => match request {
+                Some(ChessRequest::CurrentBoard) => {
+                    send!(ChessUpdate::Board { fen: mirror.game.fen() });
+                }
+                Some(ChessRequest::CurrentTotalMoves) => {
+                    send!(ChessUpdate::CurrentTotalMovesReponse {
+                        total_moves: mirror.game.total_moves()
+                    });
+                }
+                Some(ChessRequest::CurrentOutcome) => {
+                    send!(ChessUpdate::Outcome { outcome: mirror.game.outcome() });
+                }
+                Some(ChessRequest::MovePiece {
+                    source,
+                    destination,
+                    promotion,
+                }) => {
+                    let uci_move = if mirror.game.turn() != me {
+                        Err(anyhow!("It's not your turn."))
+                    } else if mirror.game.outcome().is_some() {
+                        Err(anyhow!("The game has already ended."))
+                    } else {
+                        mirror.game.uci_move(source, destination, promotion)
+                            .ok_or_else(|| anyhow!("Move not found as possibility"))
+                    };
+                    let result = match uci_move {
+                        Ok(uci_move) => {
+                            // Lichess confirms the move in the game stream
+                            let client = client.clone();
+                            let id = id.clone();
+                            task::spawn_blocking(move || client.make_move(&id, &uci_move))
+                                .await
+                                .context("Send move")?
+                        }
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        send!(ChessUpdate::MovePieceFailedResponse {
+                            message: format!("Denied: {}", e),
+                            fen: mirror.game.fen(),
+                        });
+                    }
+                }
+                Some(ChessRequest::UndoMoves { .. }) => {
+                    send!(ChessUpdate::UndoMovesFailedResponse {
+                        message: "Takebacks are not supported in online games.".to_owned(),
+                    });
+                }
+                Some(ChessRequest::Hint) => {
+                    send!(ChessUpdate::HintFailedResponse {
+                        message: "Hints are not allowed in online games.".to_owned(),
+                    });
+                }
+                // The local player is no bot
+                Some(ChessRequest::ReportThinking { .. }) => {}
+                Some(ChessRequest::Abort { .. }) => {
+                    let client = client.clone();
+                    let id = id.clone();
+                    task::spawn_blocking(move || client.resign(&id))
+                        .await
+                        .context("Resign")??;
+                    break;
+                }
+                None => break, // Local player left. The game continues on Lichess.
+            },
+        }
+    }
+
+    info!("Stopped playing Lichess game {}", id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+
+    const TOKEN: &str = "lip_testtoken";
+    const STREAM_TIMEOUT: Duration = Duration::from_secs(5);
+    const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(50);
+
+    #[derive(Default)]
+    struct MockState {
+        /// The move the stream is paused on
+        awaiting: Option<String>,
+        /// Moves and resignations the server accepted
+        posted: Vec<String>,
+        /// A stream with keep alive lines was closed by the client
+        stream_closed: bool,
+        shutdown: bool,
+    }
+
+    /// Serves a recorded game stream on a local port.
+    /// Besides the NDJSON lines the recording may contain the directives
+    /// "#await <uci>" (pause until the move was posted),
+    /// "#await-close" (keep the stream open until the mock is dropped) and
+    /// "#keep-alive" (send empty lines until the client closes the stream).
+    struct MockLichess {
+        base_url: String,
+        state: Arc<(Mutex<MockState>, Condvar)>,
+    }
+
+    impl MockLichess {
+        fn start(recording: &'static str) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let base_url = format!("http://{}", listener.local_addr().unwrap());
+            let state = Arc::new((Mutex::new(MockState::default()), Condvar::new()));
+            let server_state = state.clone();
+            thread::spawn(move || {
+                for connection in listener.incoming() {
+                    let state = server_state.clone();
+                    thread::spawn(move || handle(connection.unwrap(), recording, state));
+                }
+            });
+            Self { base_url, state }
+        }
+
+        fn client(&self) -> LichessClient {
+            LichessClient::new(LichessConfig {
+                token: TOKEN.to_owned(),
+                base_url: self.base_url.clone(),
+            })
+        }
+
+        fn posted(&self) -> Vec<String> {
+            self.state.0.lock().unwrap().posted.clone()
+        }
+
+        fn wait_for_closed_stream(&self) {
+            let (lock, condvar) = &*self.state;
+            let (state, _) = condvar
+                .wait_timeout_while(lock.lock().unwrap(), STREAM_TIMEOUT, |state| {
+                    !state.stream_closed
+                })
+                .unwrap();
+            let stream_closed = state.stream_closed;
+            drop(state);
+            assert!(stream_closed, "The game stream is still read");
+        }
+    }
+
+    impl Drop for MockLichess {
+        fn drop(&mut self) {
+            self.state.0.lock().unwrap().shutdown = true;
+            self.state.1.notify_all();
+        }
+    }
+
+    fn respond(connection: &mut TcpStream, status: &str, body: &str) {
+        write!(
+            connection,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )
+        .ok();
+    }
+
+    fn handle(
+        mut connection: TcpStream,
+        recording: &'static str,
+        state: Arc<(Mutex<MockState>, Condvar)>,
+    ) {
+        // Requests have no body, so the head is all there is
+        let mut head = vec![];
+        let mut byte = [0u8];
+        while !head.ends_with(b"\r\n\r\n") && connection.read(&mut byte).unwrap_or(0) == 1 {
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head).to_lowercase();
+        let request_line: Vec<&str> = head.lines().next().unwrap_or("").split(' ').collect();
+        let (method, path) = (request_line[0], request_line.get(1).copied().unwrap_or(""));
+
+        if !head.contains(&format!("authorization: bearer {}", TOKEN)) {
+            respond(
+                &mut connection,
+                "401 Unauthorized",
+                r#"{"error":"No such token"}"#,
+            );
+            return;
+        }
+
+        let (lock, condvar) = &*state;
+        let path: Vec<&str> = path.trim_start_matches("/api/").split('/').collect();
+        match (method, &path[..]) {
+            ("get", ["account"]) => respond(
+                &mut connection,
+                "200 OK",
+                r#"{"id":"tablet","username":"Tablet"}"#,
+            ),
+            ("get", ["board", "game", "stream", _]) => {
+                write!(
+                    connection,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n"
+                )
+                .ok();
+                for line in recording.lines() {
+                    if let Some(uci_move) = line.strip_prefix("#await ") {
+                        let mut state = lock.lock().unwrap();
+                        state.awaiting = Some(uci_move.to_owned());
+                        let (state, _) = condvar
+                            .wait_timeout_while(state, STREAM_TIMEOUT, |state| {
+                                state.awaiting.is_some() && !state.shutdown
+                            })
+                            .unwrap();
+                        if state.awaiting.is_some() {
+                            return;
+                        }
+                    } else if line == "#await-close" {
+                        let state = lock.lock().unwrap();
+                        let _ = condvar
+                            .wait_timeout_while(state, STREAM_TIMEOUT, |state| !state.shutdown)
+                            .unwrap();
+                        return;
+                    } else if line == "#keep-alive" {
+                        while writeln!(connection)
+                            .and_then(|_| connection.flush())
+                            .is_ok()
+                        {
+                            if lock.lock().unwrap().shutdown {
+                                return;
+                            }
+                            thread::sleep(KEEP_ALIVE_INTERVAL);
+                        }
+                        lock.lock().unwrap().stream_closed = true;
+                        condvar.notify_all();
+                        return;
+                    } else if writeln!(connection, "{}", line).is_err() {
+                        return;
+                    }
+                }
+            }
+            ("post", ["board", "game", _, "move", uci_move]) => {
+                let mut state = lock.lock().unwrap();
+                if state.awaiting.as_deref() == Some(*uci_move) {
+                    state.awaiting = None;
+                    state.posted.push(uci_move.to_string());
+                    condvar.notify_all();
+                    respond(&mut connection, "200 OK", r#"{"ok":true}"#);
+                } else {
+                    respond(
+                        &mut connection,
+                        "400 Bad Request",
+                        r#"{"error":"Not your turn, or game already over"}"#,
+                    );
+                }
+            }
+            ("post", ["board", "game", _, "resign"]) => {
+                lock.lock().unwrap().posted.push("resign".to_owned());
+                respond(&mut connection, "200 OK", r#"{"ok":true}"#);
+            }
+            _ => respond(&mut connection, "404 Not Found", r#"{"error":"Not found"}"#),
+        }
+    }
+
+    async fn join(
+        mock: &MockLichess,
+        game_id: &'static str,
+    ) -> (Player, ScriptedPlayer, task::JoinHandle<Result<()>>) {
+        let client = mock.client();
+        let lichess_game = task::spawn_blocking(move || client.join_game(game_id))
+            .await
+            .unwrap()
+            .expect("Failed to join game");
+        let me = lichess_game.me;
+        let (player, connection) = ScriptedPlayer::new("Tablet");
+        let game = tokio::spawn(create_lichess_game(lichess_game, connection));
+        (me, player, game)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn plays_a_recorded_game() {
+        let mock = MockLichess::start(include_str!("../testdata/lichess/scholars_mate.ndjson"));
+        let (me, player, game) = join(&mock, "sCh0LArs").await;
+        assert_eq!(me, Player::White);
+
+        let player = player
+            .run(vec![
+                Step::Expect(ChessUpdate::PlayerSwitch {
+                    player: Player::White,
+                    fen: ChessGame::default().fen(),
+                }),
+                Step::ExpectMatching("possible moves", is_possible_moves),
+                Step::Send(mv("E2", "E4")),
+                Step::Expect(moved(Player::White, "E2", "E4")),
+                Step::ExpectMatching("the switch to black", is_player_switch),
+                Step::Expect(moved(Player::Black, "E7", "E5")),
+                Step::ExpectMatching("the switch to white", is_player_switch),
+                Step::ExpectMatching("possible moves", is_possible_moves),
+                Step::Expect(ChessUpdate::ChatMessage {
+                    username: "Opponent".to_owned(),
+                    text: "Good luck, have fun".to_owned(),
+                }),
+                Step::Send(mv("F1", "C4")),
+                Step::SkipUntil("the knight move", |update| {
+                    *update == moved(Player::Black, "B8", "C6")
+                }),
+                Step::Send(mv("D1", "H5")),
+                Step::SkipUntil("the other knight move", |update| {
+                    *update == moved(Player::Black, "G8", "F6")
+                }),
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(mv("H5", "F7")),
+                Step::Expect(moved(Player::White, "H5", "F7")),
+                Step::Expect(ChessUpdate::Outcome {
+                    outcome: Some(ChessOutcome::Checkmate {
+                        winner: Player::White,
+                    }),
+                }),
+                Step::ExpectMatching("the final board", is_player_switch),
+            ])
+            .await;
+
+        // The stream ends after the game which ends the adapter without an error
+        let mut player = player;
+        player.expect_closed().await;
+        game_result(game).await.unwrap();
+        assert_eq!(mock.posted(), vec!["e2e4", "f1c4", "d1h5", "h5f7"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn takeback_chat_and_resignation_as_black() {
+        let mock = MockLichess::start(include_str!(
+            "../testdata/lichess/takeback_and_resign.ndjson"
+        ));
+        let (me, player, game) = join(&mock, "t4k3B4ck").await;
+        assert_eq!(me, Player::Black);
+
+        let mut player = player
+            .run(vec![
+                // Moves played before joining
+                Step::Expect(moved(Player::White, "E2", "E4")),
+                Step::ExpectMatching("the switch to black", is_player_switch),
+                Step::ExpectMatching("possible moves", is_possible_moves),
+                Step::Send(mv("E7", "E5")),
+                Step::Expect(moved(Player::Black, "E7", "E5")),
+                Step::ExpectMatching("the switch to white", is_player_switch),
+                // Takeback accepted on Lichess
+                Step::Expect(ChessUpdate::MovesUndone {
+                    who: Player::White,
+                    moves: 1,
+                }),
+                Step::ExpectMatching("the switch to black", is_player_switch),
+                Step::ExpectMatching("possible moves", is_possible_moves),
+                Step::Expect(ChessUpdate::ChatMessage {
+                    username: "Opponent".to_owned(),
+                    text: "Sorry, I have to go".to_owned(),
+                }),
+                Step::Expect(ChessUpdate::Outcome {
+                    outcome: Some(ChessOutcome::Aborted {
+                        who: Some(Player::White),
+                    }),
+                }),
+                Step::ExpectMatching("the final board", is_player_switch),
+            ])
+            .await;
+        player.expect_closed().await;
+        game_result(game).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejected_moves_are_reported() {
+        let mock = MockLichess::start(include_str!("../testdata/lichess/no_moves_yet.ndjson"));
+        let (_, player, game) = join(&mock, "n0M0v3s0").await;
+
+        let mut player = player
+            .run(vec![
+                Step::ExpectMatching("the start", is_player_switch),
+                Step::ExpectMatching("possible moves", is_possible_moves),
+                // Illegal locally
+                Step::Send(mv("E2", "E5")),
+                Step::ExpectMatching("a local rejection", |update| {
+                    matches!(update, ChessUpdate::MovePieceFailedResponse { message, .. }
+                        if message.contains("not found"))
+                }),
+                // Rejected by the server
+                Step::Send(mv("E2", "E4")),
+                Step::ExpectMatching("a rejection by Lichess", |update| {
+                    matches!(update, ChessUpdate::MovePieceFailedResponse { message, .. }
+                        if message.contains("Not your turn"))
+                }),
+                Step::Send(ChessRequest::UndoMoves { moves: 1 }),
+                Step::ExpectMatching("no takebacks", |update| {
+                    matches!(update, ChessUpdate::UndoMovesFailedResponse { .. })
+                }),
+                Step::ExpectSilence,
+                Step::Send(ChessRequest::Abort {
+                    message: "Resign".to_owned(),
+                }),
+            ])
+            .await;
+        player.expect_closed().await;
+        game_result(game).await.unwrap();
+        assert_eq!(mock.posted(), vec!["resign"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn leaving_closes_the_game_stream() {
+        let mock = MockLichess::start(include_str!("../testdata/lichess/keep_alive.ndjson"));
+        let (_, player, game) = join(&mock, "n0M0v3s0").await;
+        let player = player
+            .run(vec![
+                Step::ExpectMatching("the start", is_player_switch),
+                Step::ExpectMatching("possible moves", is_possible_moves),
+            ])
+            .await;
+        drop(player);
+        game_result(game).await.unwrap();
+        task::spawn_blocking(move || mock.wait_for_closed_stream())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn keep_alive_lines_can_stop_the_stream() {
+        let mut events = EventStream {
+            reader: Box::new(BufReader::new(std::io::repeat(b'\n'))),
+        };
+        let lines = std::cell::Cell::new(0);
+        let event = events
+            .next_event_unless(|| {
+                lines.set(lines.get() + 1);
+                lines.get() > 3
+            })
+            .unwrap();
+        assert_eq!(event, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn joining_requires_a_valid_token() {
+        let mock = MockLichess::start(include_str!("../testdata/lichess/no_moves_yet.ndjson"));
+        let client = LichessClient::new(LichessConfig {
+            token: "lip_wrong".to_owned(),
+            base_url: mock.base_url.clone(),
+        });
+        let error = task::spawn_blocking(move || client.join_game("n0M0v3s0"))
+            .await
+            .unwrap()
+            .err()
+            .expect("Joined with a wrong token");
+        assert!(error.to_string().contains("No such token"), "{}", error);
+    }
+
+    #[test]
+    fn outcomes_of_lichess() {
+        let outcome = |status: &str, winner: Option<&str>| {
+            server_outcome(&GameState {
+                moves: String::new(),
+                status: status.to_owned(),
+                winner: winner.map(String::from),
+            })
+        };
+        assert_eq!(outcome("started", None), None);
+        assert_eq!(
+            outcome("mate", Some("black")),
+            Some(ChessOutcome::Checkmate {
+                winner: Player::Black
+            })
+        );
+        assert_eq!(outcome("outoftime", None), Some(ChessOutcome::Draw));
+        assert_eq!(
+            outcome("outoftime", Some("white")),
+            Some(ChessOutcome::Aborted {
+                who: Some(Player::Black)
+            })
+        );
+        assert_eq!(
+            outcome("aborted", None),
+            Some(ChessOutcome::Aborted { who: None })
+        );
+    }
+}
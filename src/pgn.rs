@@ -0,0 +1,546 @@
+//! Moves of PGN games (as read by `chess_pgn_parser`) on pleco boards and
+//! writing games back to PGN
+
+use crate::game::{BitMove, Board, ChessOutcome, PieceType, SQ};
+use crate::Player;
+use anyhow::Result;
+use chess_pgn_parser::{
+    AnnotationSymbol, File, Game, GameMove, GameTermination, MarkedMove, Move, MoveNumber,
+    MoveSequence, Piece, Rank, Square, NAG,
+};
+
+/// Lines of PGN files shouldn't be longer
+const MAX_LINE_LENGTH: usize = 80;
+/// Starting position of games not starting from the usual one
+pub const FEN_TAG: &str = "FEN";
+/// Hints taken by White and Black (not a standard tag)
+pub const HINT_TAGS: [&str; 2] = ["WhiteHints", "BlackHints"];
+
+const FILES: [File; 8] = [
+    File::A,
+    File::B,
+    File::C,
+    File::D,
+    File::E,
+    File::F,
+    File::G,
+    File::H,
+];
+const RANKS: [Rank; 8] = [
+    Rank::R1,
+    Rank::R2,
+    Rank::R3,
+    Rank::R4,
+    Rank::R5,
+    Rank::R6,
+    Rank::R7,
+    Rank::R8,
+];
+
+fn piece_type(piece: Piece) -> PieceType {
+    match piece {
+        Piece::Pawn => PieceType::P,
+        Piece::Knight => PieceType::N,
+        Piece::Bishop => PieceType::B,
+        Piece::Rook => PieceType::R,
+        Piece::Queen => PieceType::Q,
+        Piece::King => PieceType::K,
+    }
+}
+
+fn piece(piece_type: PieceType) -> Piece {
+    match piece_type {
+        PieceType::N => Piece::Knight,
+        PieceType::B => Piece::Bishop,
+        PieceType::R => Piece::Rook,
+        PieceType::Q => Piece::Queen,
+        PieceType::K => Piece::King,
+        _ => Piece::Pawn,
+    }
+}
+
+fn square(sq: SQ) -> Square {
+    Square::new_known(FILES[sq.file() as usize], RANKS[sq.rank() as usize])
+}
+
+/// The legal move on `board` a PGN move stands for. `None` if it is not
+/// legal or ambiguous.
+pub fn find_move(board: &Board, pgn_move: &Move) -> Option<BitMove> {
+    let legal_moves = board.generate_moves();
+    let mut candidates = legal_moves.iter().filter(|bit_move| match pgn_move {
+        Move::CastleKingside => bit_move.is_king_castle(),
+        Move::CastleQueenside => bit_move.is_queen_castle(),
+        Move::BasicMove {
+            piece,
+            to,
+            from,
+            promoted_to,
+            ..
+        } => {
+            let source = bit_move.get_src();
+            let destination = bit_move.get_dest();
+            let promotion = if bit_move.is_promo() {
+                Some(bit_move.promo_piece())
+            } else {
+                None
+            };
+            !bit_move.is_castle()
+                && board.piece_at_sq(source).type_of() == piece_type(*piece)
+                && to.file().map(|file| file as u8) == Some(destination.file() as u8)
+                && to.rank().map(|rank| rank as u8) == Some(destination.rank() as u8)
+                && from
+                    .file()
+                    .is_none_or(|file| file as u8 == source.file() as u8)
+                && from
+                    .rank()
+                    .is_none_or(|rank| rank as u8 == source.rank() as u8)
+                && promoted_to.map(piece_type) == promotion
+        }
+    });
+    let found = candidates.next().copied();
+    match candidates.next() {
+        Some(_) => None,
+        None => found,
+    }
+}
+
+/// A legal move on `board` as written in PGN (with no more source square
+/// than needed and marked checks)
+pub fn marked_move(board: &Board, bit_move: BitMove) -> MarkedMove {
+    let pgn_move = if bit_move.is_king_castle() {
+        Move::CastleKingside
+    } else if bit_move.is_queen_castle() {
+        Move::CastleQueenside
+    } else {
+        let source = bit_move.get_src();
+        let destination = bit_move.get_dest();
+        let moved = board.piece_at_sq(source).type_of();
+        // Sources of the same kind of piece that could go there as well
+        let others: Vec<SQ> = board
+            .generate_moves()
+            .iter()
+            .filter(|other| {
+                other.get_dest() == destination
+                    && other.get_src() != source
+                    && !other.is_castle()
+                    && board.piece_at_sq(other.get_src()).type_of() == moved
+            })
+            .map(|other| other.get_src())
+            .collect();
+        let from = if moved == PieceType::P {
+            if bit_move.is_capture() {
+                Square::new_file(FILES[source.file() as usize])
+            } else {
+                Square::XX
+            }
+        } else if others.is_empty() {
+            Square::XX
+        } else if others.iter().all(|other| other.file() != source.file()) {
+            Square::new_file(FILES[source.file() as usize])
+        } else if others.iter().all(|other| other.rank() != source.rank()) {
+            Square::new_rank(RANKS[source.rank() as usize])
+        } else {
+            square(source)
+        };
+        Move::BasicMove {
+            piece: piece(moved),
+            to: square(destination),
+            from,
+            is_capture: bit_move.is_capture(),
+            promoted_to: if bit_move.is_promo() {
+                Some(piece(bit_move.promo_piece()))
+            } else {
+                None
+            },
+        }
+    };
+
+    let mut after = board.shallow_clone();
+    after.apply_move(bit_move);
+    let is_checkmate = after.in_check() && after.generate_moves().is_empty();
+    MarkedMove {
+        move_: pgn_move,
+        is_check: after.in_check() && !is_checkmate,
+        is_checkmate,
+        annotation_symbol: None,
+    }
+}
+
+/// The game in PGN (e.g. "1. e4 e5 2. Nf3 {Main line} *")
+pub fn write(game: &Game) -> String {
+    let mut pgn = String::new();
+    for (name, value) in &game.tags {
+        pgn.push_str(&format!(
+            "[{} \"{}\"]\n",
+            name,
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        ));
+    }
+    if !game.tags.is_empty() {
+        pgn.push('\n');
+    }
+
+    let mut tokens = vec![];
+    if let Some(ref comment) = game.comment {
+        tokens.push(comment_token(comment));
+    }
+    move_tokens(&game.moves, &mut tokens);
+    tokens.push(termination(game.termination).to_owned());
+
+    let mut line_length = 0;
+    for token in tokens {
+        if line_length > 0 && line_length + 1 + token.len() > MAX_LINE_LENGTH {
+            pgn.push('\n');
+            line_length = 0;
+        } else if line_length > 0 {
+            pgn.push(' ');
+            line_length += 1;
+        }
+        line_length += token.len();
+        pgn.push_str(&token);
+    }
+    pgn.push('\n');
+    pgn
+}
+
+/// A game of `moves` played from `starting_fen` (or the usual starting
+/// position) with the Seven Tag Roster. Unknown tags are "?".
+pub fn from_moves(
+    starting_fen: Option<&str>,
+    moves: &[BitMove],
+    result: GameTermination,
+) -> Result<Game> {
+    let mut board = match starting_fen {
+        Some(fen) => Board::from_fen(fen).map_err(|e| anyhow!("Invalid FEN {:?}: {:?}", fen, e))?,
+        None => Board::default(),
+    };
+    let mut tags: Vec<(String, String)> = [
+        ("Event", "?"),
+        ("Site", "?"),
+        ("Date", "????.??.??"),
+        ("Round", "?"),
+        ("White", "?"),
+        ("Black", "?"),
+        ("Result", termination(result)),
+    ]
+    .iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect();
+    if let Some(fen) = starting_fen {
+        tags.push(("SetUp".to_owned(), "1".to_owned()));
+        tags.push((FEN_TAG.to_owned(), fen.to_owned()));
+    }
+
+    let mut game_moves = Vec::with_capacity(moves.len());
+    for bit_move in moves {
+        ensure!(
+            board.generate_moves().contains(bit_move),
+            "Move {} is not legal in {}",
+            bit_move,
+            board.fen()
+        );
+        let number = move_number(&board, game_moves.is_empty());
+        game_moves.push(marked_move(&board, *bit_move).numbered(number));
+        board.apply_move(*bit_move);
+    }
+    Ok(Game {
+        tags,
+        comment: None,
+        moves: game_moves,
+        termination: result,
+    })
+}
+
+/// Sets the tag or adds it if it is missing
+pub fn set_tag(game: &mut Game, name: &str, value: &str) {
+    match game.tags.iter_mut().find(|(tag, _)| tag == name) {
+        Some((_, existing)) => *existing = value.to_owned(),
+        None => game.tags.push((name.to_owned(), value.to_owned())),
+    }
+}
+
+/// Records the hints White and Black took (unless there were none)
+pub fn tag_hints_taken(game: &mut Game, hints_taken: [u16; 2]) {
+    if hints_taken == [0, 0] {
+        return;
+    }
+    for (tag, hints) in HINT_TAGS.iter().zip(hints_taken.iter()) {
+        set_tag(game, tag, &hints.to_string());
+    }
+}
+
+/// Number of the move played on `board`. Moves of White always have one,
+/// moves of Black only after anything that interrupts the moves (like a
+/// comment or the start of the game).
+pub fn move_number(board: &Board, interrupted: bool) -> Option<MoveNumber> {
+    let full_move = board
+        .fen()
+        .split_whitespace()
+        .nth(5)
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(1);
+    match Player::from(board.turn()) {
+        Player::White => Some(MoveNumber::White(full_move)),
+        Player::Black if interrupted => Some(MoveNumber::Black(full_move)),
+        Player::Black => None,
+    }
+}
+
+/// Result of a game with `outcome` (unknown while it goes on)
+pub fn result(outcome: Option<ChessOutcome>) -> GameTermination {
+    match outcome {
+        Some(ChessOutcome::Checkmate {
+            winner: Player::White,
+        }) => GameTermination::WhiteWins,
+        Some(ChessOutcome::Checkmate {
+            winner: Player::Black,
+        }) => GameTermination::BlackWins,
+        Some(ChessOutcome::Stalemate) | Some(ChessOutcome::Draw) => GameTermination::DrawnGame,
+        Some(ChessOutcome::Aborted { .. }) | None => GameTermination::Unknown,
+    }
+}
+
+/// Result as used in PGN and its "Result" tag
+pub fn termination(termination: GameTermination) -> &'static str {
+    match termination {
+        GameTermination::WhiteWins => "1-0",
+        GameTermination::BlackWins => "0-1",
+        GameTermination::DrawnGame => "1/2-1/2",
+        GameTermination::Unknown => "*",
+    }
+}
+
+fn move_tokens(moves: &[GameMove], tokens: &mut Vec<String>) {
+    for game_move in moves {
+        // Kept on the same line
+        tokens.push(match game_move.number {
+            Some(MoveNumber::White(number)) => format!("{}. {}", number, san(&game_move.move_)),
+            Some(MoveNumber::Black(number)) => format!("{}... {}", number, san(&game_move.move_)),
+            None => san(&game_move.move_),
+        });
+        if let Some(ref nag) = game_move.nag {
+            tokens.push(format!("${}", nag.0));
+        }
+        if let Some(ref comment) = game_move.comment {
+            tokens.push(comment_token(comment));
+        }
+        for variation in &game_move.variations {
+            variation_tokens(variation, tokens);
+        }
+    }
+}
+
+fn variation_tokens(variation: &MoveSequence, tokens: &mut Vec<String>) {
+    let first = tokens.len();
+    if let Some(ref comment) = variation.comment {
+        tokens.push(comment_token(comment));
+    }
+    move_tokens(&variation.moves, tokens);
+    if tokens.len() == first {
+        return;
+    }
+    tokens[first].insert(0, '(');
+    tokens.last_mut().unwrap().push(')');
+}
+
+fn comment_token(comment: &str) -> String {
+    // Can't be escaped
+    format!("{{{}}}", comment.trim().replace('}', ")"))
+}
+
+/// The move in standard algebraic notation (e.g. "Nxf7+" or "e8=Q")
+pub fn san(marked_move: &MarkedMove) -> String {
+    let mut san = match marked_move.move_ {
+        Move::CastleKingside => "O-O".to_owned(),
+        Move::CastleQueenside => "O-O-O".to_owned(),
+        Move::BasicMove {
+            piece,
+            ref to,
+            ref from,
+            is_capture,
+            promoted_to,
+        } => {
+            let mut san = piece_letter(piece).to_owned();
+            if let Some(file) = from.file() {
+                san.push(FILE_LETTERS[file as usize]);
+            }
+            if let Some(rank) = from.rank() {
+                san.push(RANK_DIGITS[rank as usize]);
+            }
+            if is_capture {
+                san.push('x');
+            }
+            if let (Some(file), Some(rank)) = (to.file(), to.rank()) {
+                san.push(FILE_LETTERS[file as usize]);
+                san.push(RANK_DIGITS[rank as usize]);
+            }
+            if let Some(promoted_to) = promoted_to {
+                san.push('=');
+                san.push_str(piece_letter(promoted_to));
+            }
+            san
+        }
+    };
+    if marked_move.is_checkmate {
+        san.push('#');
+    } else if marked_move.is_check {
+        san.push('+');
+    }
+    if let Some(ref symbol) = marked_move.annotation_symbol {
+        san.push_str(annotation_symbol(symbol));
+    }
+    san
+}
+
+const FILE_LETTERS: [char; 8] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
+const RANK_DIGITS: [char; 8] = ['1', '2', '3', '4', '5', '6', '7', '8'];
+
+fn piece_letter(piece: Piece) -> &'static str {
+    match piece {
+        Piece::Pawn => "",
+        Piece::Knight => "N",
+        Piece::Bishop => "B",
+        Piece::Rook => "R",
+        Piece::Queen => "Q",
+        Piece::King => "K",
+    }
+}
+
+pub fn annotation_symbol(symbol: &AnnotationSymbol) -> &'static str {
+    match symbol {
+        AnnotationSymbol::Blunder => "??",
+        AnnotationSymbol::Mistake => "?",
+        AnnotationSymbol::Dubious => "?!",
+        AnnotationSymbol::Interesting => "!?",
+        AnnotationSymbol::Good => "!",
+        AnnotationSymbol::Brilliant => "!!",
+    }
+}
+
+/// What the common NAGs stand for
+pub fn nag_text(nag: &NAG) -> Option<&'static str> {
+    Some(match nag.0 {
+        1 => "Good move",
+        2 => "Poor move",
+        3 => "Very good move",
+        4 => "Very poor move",
+        5 => "Speculative move",
+        6 => "Questionable move",
+        7 => "Forced move",
+        8 => "Singular move",
+        9 => "Worst move",
+        10 => "Drawish position",
+        11 => "Equal chances, quiet position",
+        12 => "Equal chances, active position",
+        13 => "Unclear position",
+        14 => "White is slightly better",
+        15 => "Black is slightly better",
+        16 => "White is moderately better",
+        17 => "Black is moderately better",
+        18 => "White is decisively better",
+        19 => "Black is decisively better",
+        20 => "White has a crushing advantage",
+        21 => "Black has a crushing advantage",
+        22 => "White is in zugzwang",
+        23 => "Black is in zugzwang",
+        32 => "White has a development advantage",
+        33 => "Black has a development advantage",
+        36 => "White has the initiative",
+        37 => "Black has the initiative",
+        40 => "White has the attack",
+        41 => "Black has the attack",
+        44 => "White has compensation for the material",
+        45 => "Black has compensation for the material",
+        132 => "White has counterplay",
+        133 => "Black has counterplay",
+        138 => "White is in time trouble",
+        139 => "Black is in time trouble",
+        140 => "With the idea",
+        146 => "Novelty",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess_pgn_parser::read_games;
+
+    const GAME: &str = r#"[Event "Casual game"]
+[White "Alice \"A\""]
+[Black "Bob"]
+[Result "1-0"]
+
+{Scholar's mate} 1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6?? $4 {Loses at once} (3... g6
+4. Qf3 Nf6) 4. Qxf7# 1-0
+"#;
+
+    #[test]
+    fn writes_what_it_reads() {
+        let game = read_games(GAME).unwrap().remove(0);
+        assert_eq!(game.tags[1].1, "Alice \"A\"");
+        let written = write(&game);
+        assert_eq!(written, GAME);
+        assert_eq!(read_games(&written).unwrap(), vec![game]);
+    }
+
+    #[test]
+    fn hints_are_tagged() {
+        let mut game = from_moves(None, &[], GameTermination::Unknown).unwrap();
+        tag_hints_taken(&mut game, [0, 0]);
+        assert_eq!(game.tags.len(), 7);
+        tag_hints_taken(&mut game, [2, 0]);
+        assert!(write(&game).contains("[WhiteHints \"2\"]\n[BlackHints \"0\"]\n"));
+    }
+
+    #[test]
+    fn finds_moves_as_written() {
+        let game = read_games(GAME).unwrap().remove(0);
+        let mut board = Board::default();
+        for game_move in &game.moves {
+            let bit_move = find_move(&board, &game_move.move_.move_).unwrap();
+            let marked = marked_move(&board, bit_move);
+            assert_eq!(marked.move_, game_move.move_.move_);
+            assert_eq!(marked.is_checkmate, game_move.move_.is_checkmate);
+            board.apply_move(bit_move);
+        }
+        assert!(board.checkmate());
+    }
+
+    #[test]
+    fn disambiguates_only_when_needed() {
+        let board = |fen: &str| Board::from_fen(fen).unwrap();
+        let san_of = |fen: &str, uci: &str| {
+            let board = board(fen);
+            let bit_move = board
+                .generate_moves()
+                .iter()
+                .find(|bit_move| bit_move.stringify() == uci)
+                .copied()
+                .unwrap();
+            san(&marked_move(&board, bit_move))
+        };
+        // Knights on b1 and f3 can both go to d2
+        let knights = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(san_of(knights, "b1d2"), "Nbd2");
+        assert_eq!(san_of(knights, "b1c3"), "Nc3");
+        // Rooks on the same file
+        let rooks = "4k3/R7/8/8/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san_of(rooks, "a1a4"), "R1a4");
+        assert_eq!(san_of("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1g1"), "O-O");
+        assert_eq!(
+            san_of("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q"),
+            "axb8=Q+"
+        );
+        assert_eq!(san_of("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2", "e5d6"), "exd6");
+
+        // Both rooks could move to a4
+        let ambiguous = Move::new(Piece::Rook, Square::A4);
+        assert_eq!(find_move(&board(rooks), &ambiguous), None);
+        let promotion = Move::new(Piece::Pawn, Square::A8);
+        assert_eq!(
+            find_move(&board("4k3/P7/8/8/8/8/8/4K3 w - - 0 1"), &promotion),
+            None
+        );
+    }
+}
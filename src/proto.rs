@@ -1,22 +1,61 @@
-use crate::game::ChessGame;
-use crate::game::Player as PlecoPlayer;
-pub use crate::game::{ChessOutcome, SQ};
+use crate::analysis::white_score;
+use crate::book::{BookConfig, OpeningBook};
+use crate::event_log::EventLog;
+use crate::game::{san_line, BitMove, Board, ChessGame};
+use crate::history::GameHistory;
+use crate::search::{
+    clock_budget, counting_search, deepen, principal_variation, remembered_best_move,
+    SearchAlgorithm,
+};
+use crate::tablebase::{Tablebase, TablebaseVerdict};
+pub use crate::game::{ChessOutcome, Promotion, SQ};
 use crate::{Player, Square};
 use anyhow::{Context, Result};
+use pleco::bots::{
+    AlphaBetaSearcher, IterativeSearcher, JamboreeSearcher, MiniMaxSearcher,
+    ParallelMiniMaxSearcher,
+};
 use pleco::tools::Searcher;
 use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::fmt;
+use std::future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use tokio_stream::{StreamExt, wrappers::ReceiverStream};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::watch;
 use tokio::task;
 
-#[derive(Clone, Debug)]
+/// Moves shown for a hint (the suggestion and the expected continuation)
+pub const HINT_LINE_LENGTH: usize = 3;
+/// Searches of a position a bot starts again after its move got rejected
+pub(crate) const MAX_REJECTED_MOVES: u32 = 3;
+/// How often a bot checks whether its pondering finished
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ChessConfig {
     pub starting_fen: Option<String>,
     pub can_black_undo: bool,
     pub can_white_undo: bool,
     pub allow_undo_after_loose: bool,
+    /// Write every request and update as JSON lines to this file.
+    /// See `event_log::verify()` to reproduce a game from it.
+    pub event_log: Option<PathBuf>,
+    /// Directory with Syzygy tables to report the verdict of endgames
+    /// in `ChessUpdate::Evaluation`
+    #[serde(default)]
+    pub tablebase: Option<PathBuf>,
+    /// How hints are searched. `None` turns them off.
+    #[serde(default)]
+    pub hints: Option<HintConfig>,
+    /// Hints White and Black took before (e.g. in a saved game)
+    #[serde(default)]
+    pub hints_taken: [u16; 2],
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -24,9 +63,19 @@ pub enum ChessRequest {
     CurrentBoard,
     CurrentTotalMoves,
     CurrentOutcome,
-    MovePiece { source: Square, destination: Square },
+    /// `promotion` picks the piece a pawn becomes (e.g. to underpromote)
+    MovePiece {
+        source: Square,
+        destination: Square,
+        #[serde(default)]
+        promotion: Option<Promotion>,
+    },
     Abort { message: String },
     UndoMoves { moves: u16 },
+    /// Ask for a suggested move. Only on the own turn.
+    Hint,
+    /// Progress of a bot's search to show to everyone. Only on the own turn.
+    ReportThinking { thinking: BotThinking },
 }
 
 impl ChessRequest {
@@ -49,6 +98,8 @@ pub enum ChessUpdate {
         player: Player,
         moved_piece_source: Square,
         moved_piece_destination: Square,
+        #[serde(default)]
+        moved_piece_promotion: Option<Promotion>,
     },
     /// Signal that a new player is now playing. The boar is the
     /// most recent one which can also be retreived by requesting a
@@ -81,6 +132,76 @@ pub enum ChessUpdate {
     },
     CurrentTotalMovesReponse {
         total_moves: u16,
+    },
+    /// Chat of an online game
+    ChatMessage {
+        username: String,
+        text: String,
+    },
+    /// Response to `ChessRequest::Hint`
+    Hint {
+        source: Square,
+        destination: Square,
+        /// The suggested move and the expected continuation in SAN
+        line: Vec<String>,
+        /// Hints the player took in this game (including this one)
+        hints_taken: u16,
+    },
+    HintFailedResponse {
+        message: String,
+    },
+    /// How the current position stands. Sent after `PlayerSwitch`.
+    Evaluation {
+        evaluation: Evaluation,
+    },
+    /// A bot is still searching its move (sent after every depth)
+    BotThinking {
+        player: Player,
+        thinking: BotThinking,
+    },
+}
+
+/// Progress of a bot's search. External engines may leave out what they
+/// don't tell.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BotThinking {
+    /// Deepest finished search
+    pub depth: u16,
+    /// Positions searched so far
+    pub nodes: Option<u64>,
+    pub nodes_per_second: Option<u64>,
+    /// Best move so far in SAN
+    pub best_move: Option<String>,
+    pub score: Option<Score>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Evaluation {
+    /// Result with perfect play according to the endgame tablebase
+    Tablebase(TablebaseVerdict),
+    /// Result of a search (see `analysis::create_analysis()`)
+    Search {
+        score: Score,
+        depth: u16,
+        /// Expected continuation in SAN
+        line: Vec<String>,
+    },
+}
+
+/// From White's view
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Score {
+    Centipawns(i32),
+    /// Moves until mate. Negative if Black mates.
+    Mate(i32),
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Score::Centipawns(centipawns) => write!(f, "{:+.2}", *centipawns as f32 / 100.0),
+            Score::Mate(moves) => write!(f, "#{}", moves),
+        }
     }
 }
 
@@ -89,12 +210,26 @@ pub async fn create_game(
     black: (Sender<ChessUpdate>, Receiver<ChessRequest>),
     spectators: (Sender<ChessUpdate>, Receiver<ChessRequest>),
     config: ChessConfig,
+) -> Result<()> {
+    replay_game(white, black, spectators, config, None).await
+}
+
+/// `create_game()` that takes the result of a hint search only once
+/// `hints_released` has the number of its hint request (see
+/// `LogEvent::HintSearched`), so a replay takes it where the logged game did
+pub(crate) async fn replay_game(
+    white: (Sender<ChessUpdate>, Receiver<ChessRequest>),
+    black: (Sender<ChessUpdate>, Receiver<ChessRequest>),
+    spectators: (Sender<ChessUpdate>, Receiver<ChessRequest>),
+    config: ChessConfig,
+    mut hints_released: Option<watch::Receiver<u64>>,
 ) -> Result<()> {
     let mut game = if let Some(ref fen) = config.starting_fen {
         ChessGame::from_fen(fen)?
     } else {
         ChessGame::default()
     };
+    game.restore_hints_taken(config.hints_taken);
 
     let (white_tx, white_rx) = white;
     let (black_tx, black_rx) = black;
@@ -108,11 +243,56 @@ pub async fn create_game(
     let mut spectators_rx = ReceiverStream::new(spectators_rx);
     let mut combined_rx = ReceiverStream::new(combined_rx);
 
+    // Like writing to it, opening the log may fail without ending the game
+    let mut event_log = config.event_log.as_ref().and_then(|path| {
+        EventLog::create(path)
+            .map_err(|err| warn!("No event log for this game: {:#}", err))
+            .ok()
+    });
+    if let Some(ref mut event_log) = event_log {
+        event_log.game_started(&config);
+    }
+    let tablebase = match config.tablebase {
+        Some(ref directory) => Some(Tablebase::open(directory)?),
+        None => None,
+    };
+
+    // Send to a player or the spectators (`None`)
+    macro_rules! send_to {
+        ($recipient: expr, $msg: expr) => {
+            let recipient: Option<Player> = $recipient;
+            let msg: ChessUpdate = $msg;
+            if let Some(ref mut event_log) = event_log {
+                event_log.update(recipient, &msg);
+            }
+            match recipient {
+                Some(Player::White) => white_tx.send(msg).await.ok(),
+                Some(Player::Black) => black_tx.send(msg).await.ok(),
+                None => spectators_tx.send(msg).await.ok(),
+            };
+        };
+    }
+
     macro_rules! send_to_everyone {
         ($msg: expr) => {
-            white_tx.send($msg.clone()).await.ok();
-            black_tx.send($msg.clone()).await.ok();
-            spectators_tx.send($msg).await.ok();
+            let msg: ChessUpdate = $msg;
+            send_to!(Some(Player::White), msg.clone());
+            send_to!(Some(Player::Black), msg.clone());
+            send_to!(None, msg);
+        };
+    }
+
+    // Tell everyone the verdict on endgames the tablebase knows
+    macro_rules! send_evaluation {
+        () => {
+            let verdict = tablebase
+                .as_ref()
+                .and_then(|tablebase| tablebase.verdict(&game.board()));
+            if let Some(verdict) = verdict {
+                send_to_everyone!(ChessUpdate::Evaluation {
+                    evaluation: Evaluation::Tablebase(verdict),
+                });
+            }
         };
     }
 
@@ -172,59 +352,108 @@ pub async fn create_game(
         player: game.turn(),
         fen: game.fen()
     });
+    send_evaluation!();
     // Send the starting player his possible moves
     let possible_moves: Vec<_> = game
         .possible_moves()
         .iter()
         .map(|bit_move| (bit_move.get_src().into(), bit_move.get_dest().into()))
         .collect();
-    match game.turn() {
-        PlecoPlayer::White => white_tx.clone(),
-        PlecoPlayer::Black => black_tx.clone(),
-    }
-    .send(ChessUpdate::PossibleMoves { possible_moves })
-    .await
-    .ok();
+    send_to!(Some(game.turn()), ChessUpdate::PossibleMoves { possible_moves });
 
     info!("Game initialized. Handling requests...");
 
+    // Running hint search with the position, player and number of the
+    // hint request it is for
+    let mut hint_search: Option<(String, Player, u64, task::JoinHandle<Vec<BitMove>>)> = None;
+    let mut hint_requests = 0;
+
     // Handle inputs
     loop {
-        let (sender, request): (Option<Player>, ChessRequest) = match combined_rx.next().await {
-            Some(res) => res,
-            None => {
-                break; // No senders connected anymore
+        let hint_searched = async {
+            match hint_search {
+                Some((_, _, request, ref mut search)) => {
+                    let line = search.await;
+                    if let Some(ref mut released) = hints_released {
+                        released.wait_for(|released| *released == request).await.ok();
+                    }
+                    line
+                }
+                None => future::pending().await,
+            }
+        };
+        let (sender, request): (Option<Player>, ChessRequest) = tokio::select! {
+            next = combined_rx.next() => match next {
+                Some(res) => res,
+                None => {
+                    break; // No senders connected anymore
+                }
+            },
+            line = hint_searched => {
+                let (fen, player, request, _) = hint_search.take().expect("Searched above");
+                if let Some(ref mut event_log) = event_log {
+                    event_log.hint_searched(player, request);
+                }
+                // A failed hint must not end the game
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        error!("Hint search failed: {:?}", err);
+                        send_to!(Some(player), ChessUpdate::HintFailedResponse {
+                            message: "The search failed.".to_owned(),
+                        });
+                        continue;
+                    }
+                };
+                // The hint of an old position would be wrong
+                if game.fen() != fen || game.outcome().is_some() {
+                    info!("Dropped the hint for an old position");
+                    continue;
+                }
+                match line.first() {
+                    Some(suggested) => {
+                        send_to!(Some(player), ChessUpdate::Hint {
+                            source: suggested.get_src().into(),
+                            destination: suggested.get_dest().into(),
+                            line: san_line(&game.board(), &line),
+                            hints_taken: game.take_hint(player),
+                        });
+                    }
+                    None => {
+                        send_to!(Some(player), ChessUpdate::HintFailedResponse {
+                            message: "There is no move to suggest.".to_owned(),
+                        });
+                    }
+                }
+                continue;
             }
         };
+        if let Some(ref mut event_log) = event_log {
+            event_log.request(sender, &request);
+        }
 
         if sender.is_none() && !request.available_to_spectator() {
-            spectators_tx
-                .send(ChessUpdate::GenericErrorResponse {
+            send_to!(
+                None,
+                ChessUpdate::GenericErrorResponse {
                     message: "Spectators can't send this kind of request!".to_owned(),
-                })
-                .await
-                .ok();
+                }
+            );
             continue;
         }
 
         macro_rules! send_to_sender {
             ($msg: expr) => {
-                match sender {
-                    Some(player) => match player {
-                        Player::White => white_tx.send($msg).await.ok(),
-                        Player::Black => black_tx.send($msg).await.ok(),
-                    },
-                    None => spectators_tx.send($msg).await.ok(),
-                };
+                send_to!(sender, $msg);
             };
         }
 
         macro_rules! send_to_other_player {
             ($msg: expr) => {
-                match sender.context("Send to the other player")? {
-                    Player::White => black_tx.send($msg).await.ok(),
-                    Player::Black => white_tx.send($msg).await.ok(),
-                };
+                send_to!(
+                    Some(sender.context("Send to the other player")?.other_player()),
+                    $msg
+                );
             };
         }
 
@@ -232,16 +461,19 @@ pub async fn create_game(
         match request {
             ChessRequest::CurrentBoard => {
                 send_to_sender!(ChessUpdate::Board { fen: game.fen() });
+                continue;
             }
             ChessRequest::CurrentTotalMoves => {
                 send_to_sender!(ChessUpdate::CurrentTotalMovesReponse {
                     total_moves: game.total_moves()
                 });
+                continue;
             }
             ChessRequest::CurrentOutcome => {
                 send_to_sender!(ChessUpdate::Outcome {
                     outcome: game.outcome()
                 });
+                continue;
             }
             _ => {} // Should be handles for a player request
         }
@@ -253,15 +485,21 @@ pub async fn create_game(
             ChessRequest::MovePiece {
                 source,
                 destination,
+                promotion,
             } => {
                 let prev_outcome = game.outcome();
-                match game.move_piece(source, destination) {
+                // Tell everyone the promotion that was actually played
+                let played = game
+                    .find_move(source, destination, promotion)
+                    .and_then(Promotion::of);
+                match game.move_piece(source, destination, promotion) {
                     Ok(_) => {
                         // Dunno why, but rust won't compile when using just "Ok". Error in the matrix??
                         send_to_everyone!(ChessUpdate::PlayerMovedAPiece {
                             player: sender,
                             moved_piece_source: source,
                             moved_piece_destination: destination,
+                            moved_piece_promotion: played,
                         });
                         let new_outcome = game.outcome();
                         if prev_outcome != new_outcome {
@@ -275,6 +513,7 @@ pub async fn create_game(
                             player: game.turn(),
                             fen: game.fen(),
                         });
+                        send_evaluation!();
 
                         if new_outcome.is_none() {
                             // Send possible moves to player
@@ -298,6 +537,38 @@ pub async fn create_game(
                     }
                 };
             }
+            ChessRequest::Hint => {
+                hint_requests += 1;
+                let hints = match config.hints {
+                    Some(hints) => hints,
+                    None => {
+                        send_to_sender!(ChessUpdate::HintFailedResponse {
+                            message: "Hints are turned off in this game.".to_owned(),
+                        });
+                        continue;
+                    }
+                };
+                if game.turn() != sender || game.outcome().is_some() {
+                    send_to_sender!(ChessUpdate::HintFailedResponse {
+                        message: "You can only get a hint when it's your turn.".to_owned(),
+                    });
+                    continue;
+                }
+                // Searched in the background (replacing an earlier search)
+                // and sent once done
+                let board = game.board();
+                let search = task::spawn_blocking(move || hints.line(&board));
+                hint_search = Some((game.fen(), sender, hint_requests, search));
+            }
+            ChessRequest::ReportThinking { thinking } => {
+                // Reports of a search that just finished are outdated
+                if game.turn() == sender && game.outcome().is_none() {
+                    send_to_everyone!(ChessUpdate::BotThinking {
+                        player: sender,
+                        thinking
+                    });
+                }
+            }
             ChessRequest::Abort { .. /* message */ } => {
                 game.player_left(sender);
                 break;
@@ -339,19 +610,14 @@ pub async fn create_game(
                             player: game.turn(),
                             fen: game.fen()
                         });
+                        send_evaluation!();
                         // Send the starting player his possible moves
                         let possible_moves: Vec<_> = game
                             .possible_moves()
                             .iter()
                             .map(|bit_move| (bit_move.get_src().into(), bit_move.get_dest().into()))
                             .collect();
-                        match game.turn() {
-                            PlecoPlayer::White => white_tx.clone(),
-                            PlecoPlayer::Black => black_tx.clone(),
-                        }
-                        .send(ChessUpdate::PossibleMoves { possible_moves })
-                        .await
-                        .ok();
+                        send_to!(Some(game.turn()), ChessUpdate::PossibleMoves { possible_moves });
                         // Notify everyone of undo
                         send_to_everyone!(ChessUpdate::MovesUndone {
                             who: sender,
@@ -372,57 +638,596 @@ pub async fn create_game(
     Ok(())
 }
 
+/// Difficulties of `create_bot()`. The value is the search depth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BotDifficulty {
+    Easy = 2,
+    Normal = 4,
+    Hard = 6,
+}
+
+impl BotDifficulty {
+    pub fn depth(&self) -> u16 {
+        *self as u16
+    }
+
+    /// Default limits of the bot. Searches up to `depth()`, but stops
+    /// early in complicated positions.
+    pub fn limits(&self) -> SearchLimits {
+        let movetime = match self {
+            BotDifficulty::Easy => 1000,
+            BotDifficulty::Normal => 2500,
+            BotDifficulty::Hard => 5000,
+        };
+        SearchLimits {
+            movetime: Some(movetime),
+            ..SearchLimits::depth(self.depth())
+        }
+    }
+}
+
+impl std::str::FromStr for BotDifficulty {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "easy" => Ok(BotDifficulty::Easy),
+            "normal" => Ok(BotDifficulty::Normal),
+            "hard" => Ok(BotDifficulty::Hard),
+            _ => Err(anyhow!(
+                "Difficulty is neither \"Easy\", \"Normal\" nor \"Hard\""
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for BotDifficulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// How long `create_bot()` thinks about a move. Without any time limit it
+/// searches exactly `depth` deep. Otherwise it searches one depth after
+/// another until the time is up or `depth` is reached.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchLimits {
+    /// Maximum search depth
+    pub depth: u16,
+    /// Thinking time per move in milliseconds
+    #[serde(default)]
+    pub movetime: Option<u64>,
+    /// Thinking time for the whole game in milliseconds (like a chess clock)
+    #[serde(default)]
+    pub clock: Option<u64>,
+    /// Milliseconds added to `clock` after each move
+    #[serde(default)]
+    pub increment: u64,
+}
+
+impl SearchLimits {
+    /// Fixed depth search
+    pub fn depth(depth: u16) -> Self {
+        Self {
+            depth,
+            movetime: None,
+            clock: None,
+            increment: 0,
+        }
+    }
+
+    /// Thinking time for the next move given the time left on the clock
+    pub fn budget(&self, clock_left: Option<u64>) -> Option<Duration> {
+        let movetime = self.movetime.map(Duration::from_millis);
+        let clock = clock_left.map(|left| clock_budget(left, self.increment, None));
+        match (movetime, clock) {
+            (Some(movetime), Some(clock)) => Some(movetime.min(clock)),
+            (movetime, clock) => movetime.or(clock),
+        }
+    }
+}
+
+/// How `ChessRequest::Hint` searches
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HintConfig {
+    #[serde(flatten)]
+    pub limits: SearchLimits,
+    #[serde(default)]
+    pub algorithm: SearchAlgorithm,
+}
+
+impl Default for HintConfig {
+    fn default() -> Self {
+        Self {
+            limits: SearchLimits {
+                movetime: Some(2000),
+                ..SearchLimits::depth(4)
+            },
+            algorithm: SearchAlgorithm::default(),
+        }
+    }
+}
+
+impl From<SearchLimits> for HintConfig {
+    fn from(limits: SearchLimits) -> Self {
+        Self {
+            limits,
+            algorithm: SearchAlgorithm::default(),
+        }
+    }
+}
+
+impl HintConfig {
+    /// The suggested move followed by the expected continuation
+    /// (empty if there is no legal move)
+    pub fn line(&self, board: &Board) -> Vec<BitMove> {
+        let algorithm = self.algorithm;
+        let deadline = self.limits.budget(None).map(|budget| Instant::now() + budget);
+        let max_depth = self.limits.depth.max(1);
+        let first_depth = if deadline.is_some() { 1 } else { max_depth };
+        if board.generate_moves().is_empty() {
+            return vec![];
+        }
+        let mut reached_depth = first_depth;
+        let best_move = deepen(
+            board,
+            first_depth..=max_depth,
+            deadline,
+            |board, depth| algorithm.best_move(board, depth),
+            |depth, _, _| {
+                reached_depth = depth;
+                true
+            },
+        );
+        match best_move {
+            Some(best_move) if !best_move.is_null() => principal_variation(
+                board,
+                best_move,
+                reached_depth,
+                HINT_LINE_LENGTH,
+                |board, depth| algorithm.best_move(board, depth),
+            ),
+            _ => vec![],
+        }
+    }
+}
+
+/// Everything about how `create_bot()` plays
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BotConfig {
+    #[serde(flatten)]
+    pub limits: SearchLimits,
+    /// Play from this opening book as long as it knows the position
+    #[serde(default)]
+    pub book: Option<BookConfig>,
+    /// Directory with Syzygy tables for perfect play in endgames
+    #[serde(default)]
+    pub tablebase: Option<PathBuf>,
+    /// Search the position after the expected reply on the opponent's time
+    /// (`None` leaves it to the frontend and doesn't ponder here)
+    #[serde(default)]
+    pub ponder: Option<bool>,
+}
+
+impl From<SearchLimits> for BotConfig {
+    fn from(limits: SearchLimits) -> Self {
+        Self {
+            limits,
+            book: None,
+            tablebase: None,
+            ponder: None,
+        }
+    }
+}
+
+/// What a bot found out on the opponent's time
+#[derive(Default)]
+struct Pondered {
+    /// Position after the expected reply
+    fen: Option<String>,
+    /// Deepest finished search of it
+    best: Option<(u16, BitMove)>,
+    /// Depth reached for the last own move (the reply is searched less deep)
+    last_depth: u16,
+}
+
+/// Locks `mutex` unless that takes until after `deadline`
+fn lock_until<T>(mutex: &Mutex<T>, deadline: Option<Instant>) -> Option<MutexGuard<'_, T>> {
+    loop {
+        match mutex.try_lock() {
+            Ok(guard) => return Some(guard),
+            Err(TryLockError::Poisoned(e)) => return Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return None;
+                }
+                thread::sleep(LOCK_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Fails if the opening book or the tablebase can't be read
 pub async fn create_bot<T: Searcher>(
     me: Player,
-    depth: u16,
+    config: BotConfig,
     min_reaction_delay: Duration,
 ) -> Result<(Sender<ChessUpdate>, Receiver<ChessRequest>)> {
+    let limits = config.limits;
+    let mut book = match config.book {
+        Some(ref book) => {
+            let seed = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_nanos() as u64)
+                .unwrap_or(1);
+            Some(OpeningBook::open(book, seed)?)
+        }
+        None => None,
+    };
+    let tablebase = match config.tablebase {
+        Some(ref directory) => Some(Tablebase::open(directory)?),
+        None => None,
+    };
+    // Shared with pondering, which gets the same time as a move
+    let clock_left = Arc::new(Mutex::new(limits.clock));
+    let pondered = Arc::new(Mutex::new(Pondered::default()));
+    // Held while pondering, so a ponder hit can wait for the running depth
+    // (until the deadline of the move)
+    let pondering = Arc::new(Mutex::new(()));
+
+    let think_clock_left = clock_left.clone();
+    let think_pondered = pondered.clone();
+    let think_pondering = pondering.clone();
+    let think = move |history: GameHistory,
+                      still_wanted: &dyn Fn() -> bool,
+                      report: &dyn Fn(BotThinking)| {
+        let board = history.board();
+        let tablebase_move = tablebase
+            .as_ref()
+            .and_then(|tablebase| tablebase.best_move(&board));
+        if let Some(bit_move) = tablebase_move {
+            info!("Bot plays tablebase move {}", bit_move);
+            return bit_move;
+        }
+        if let Some(bit_move) = book.as_mut().and_then(|book| book.choose(&board)) {
+            info!("Bot plays book move {}", bit_move);
+            return bit_move;
+        }
+        let started = SystemTime::now();
+        let budget = limits.budget(*think_clock_left.lock().unwrap());
+        let deadline = budget.map(|budget| Instant::now() + budget);
+        let max_depth = limits.depth.max(1);
+        let fen = board.fen();
+        let ponder_hit = if think_pondered.lock().unwrap().fen.as_ref() == Some(&fen) {
+            // Otherwise takes what the pondering finished so far
+            if lock_until(&think_pondering, deadline).is_none() {
+                info!("Bot stops waiting for the pondering");
+            }
+            think_pondered.lock().unwrap().best
+        } else {
+            None
+        };
+        let (mut reached_depth, pondered_move) = match ponder_hit {
+            Some((depth, bit_move)) => {
+                info!("Ponder hit with a search {} deep", depth);
+                (depth, Some(bit_move))
+            }
+            None => (0, None),
+        };
+        let out_of_time = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+        let bit_move = if reached_depth >= max_depth || (pondered_move.is_some() && out_of_time) {
+            pondered_move
+        } else {
+            // Depth by depth (even without a deadline) to stop in between once
+            // the position is gone. Searched like pleco's searchers, but
+            // counting the nodes and keeping the score to report.
+            let parallel =
+                SearchAlgorithm::of::<T>().is_some_and(SearchAlgorithm::is_multithreaded);
+            let nodes = AtomicU64::new(0);
+            let score = Cell::new(0);
+            let search_started = Instant::now();
+            deepen(
+                &board,
+                reached_depth + 1..=max_depth,
+                deadline,
+                |board, depth| {
+                    let never_cancelled = AtomicBool::new(false);
+                    match counting_search(board, depth, parallel, &never_cancelled, &nodes) {
+                        Some(best) => {
+                            score.set(best.score);
+                            best.bit_move
+                        }
+                        None => BitMove::null(),
+                    }
+                },
+                |depth, bit_move, _| {
+                    reached_depth = depth;
+                    let nodes = nodes.load(Ordering::Relaxed);
+                    let millis = search_started.elapsed().as_millis().max(1) as u64;
+                    report(BotThinking {
+                        depth,
+                        nodes: Some(nodes),
+                        nodes_per_second: Some(nodes * 1000 / millis),
+                        best_move: san_line(&board, &[bit_move]).pop(),
+                        score: Some(white_score(&board, score.get(), depth)),
+                    });
+                    still_wanted()
+                },
+            )
+        }
+        .filter(|bit_move| !bit_move.is_null())
+        .unwrap_or_else(|| {
+            // Out of time before the first depth. Any move beats none.
+            warn!("Bot searched no depth and plays any move");
+            board.generate_moves().iter().next().copied().unwrap_or_else(BitMove::null)
+        });
+        let bit_move = history.consider_repetitions(bit_move);
+        {
+            let mut pondered = think_pondered.lock().unwrap();
+            *pondered = Pondered {
+                last_depth: reached_depth,
+                ..Pondered::default()
+            };
+        }
+        let elapsed = started.elapsed().unwrap_or(Duration::new(0, 0));
+        info!(
+            "Bot searched {} deep in {:?} (budget: {:?})",
+            reached_depth, elapsed, budget
+        );
+        let mut clock_left = think_clock_left.lock().unwrap();
+        *clock_left =
+            clock_left.map(|left| left.saturating_sub(elapsed.as_millis() as u64) + limits.increment);
+        bit_move
+    };
+    if config.ponder != Some(true) {
+        return Ok(spawn_bot(me, min_reaction_delay, think));
+    }
+
+    let ponder = move |history: GameHistory, still_wanted: &dyn Fn() -> bool| {
+        let _pondering = pondering.lock().unwrap_or_else(|e| e.into_inner());
+        // Not longer than searching the move, so neither a ponder hit nor
+        // the next search wait long for it
+        let deadline = limits
+            .budget(*clock_left.lock().unwrap())
+            .map(|budget| Instant::now() + budget);
+        let mut board = history.board();
+        let depth = pondered.lock().unwrap().last_depth.saturating_sub(1).max(1);
+        let reply = remembered_best_move::<T>(board.shallow_clone(), depth);
+        if reply.is_null() || !still_wanted() {
+            return;
+        }
+        board.apply_move(reply);
+        if board.generate_moves().is_empty() {
+            return;
+        }
+        debug!("Bot ponders on the reply {}", reply);
+        {
+            let mut pondered = pondered.lock().unwrap();
+            pondered.fen = Some(board.fen());
+            pondered.best = None;
+        }
+        deepen(
+            &board,
+            1..=limits.depth.max(1),
+            deadline,
+            remembered_best_move::<T>,
+            |depth, bit_move, _| {
+                pondered.lock().unwrap().best = Some((depth, bit_move));
+                still_wanted()
+            },
+        );
+    };
+    Ok(spawn_pondering_bot(me, min_reaction_delay, think, ponder))
+}
+
+/// `create_bot()` with the searcher picked at runtime
+pub async fn create_bot_with_algorithm(
+    algorithm: SearchAlgorithm,
+    me: Player,
+    config: BotConfig,
+    min_reaction_delay: Duration,
+) -> Result<(Sender<ChessUpdate>, Receiver<ChessRequest>)> {
+    match algorithm {
+        SearchAlgorithm::Minimax => {
+            create_bot::<MiniMaxSearcher>(me, config, min_reaction_delay).await
+        }
+        SearchAlgorithm::ParallelMinimax => {
+            create_bot::<ParallelMiniMaxSearcher>(me, config, min_reaction_delay).await
+        }
+        SearchAlgorithm::AlphaBeta => {
+            create_bot::<AlphaBetaSearcher>(me, config, min_reaction_delay).await
+        }
+        SearchAlgorithm::Jamboree => {
+            create_bot::<JamboreeSearcher>(me, config, min_reaction_delay).await
+        }
+        SearchAlgorithm::Iterative => {
+            create_bot::<IterativeSearcher>(me, config, min_reaction_delay).await
+        }
+    }
+}
+
+/// Plays as `me` with the moves from `think` (which runs on a blocking
+/// thread and gets the moves played so far). Its progress reports are
+/// passed on to the game.
+pub(crate) fn spawn_bot<F>(
+    me: Player,
+    min_reaction_delay: Duration,
+    think: F,
+) -> (Sender<ChessUpdate>, Receiver<ChessRequest>)
+where
+    F: FnMut(GameHistory, &dyn Fn() -> bool, &dyn Fn(BotThinking)) -> BitMove + Send + 'static,
+{
+    spawn_pondering_bot(me, min_reaction_delay, think, |_, _| {})
+}
+
+/// `spawn_bot()` that also runs `ponder` (on a blocking thread as well)
+/// whenever it's the opponent's turn. Pondering stops like a search once
+/// the opponent moved. Every search belongs to the position it was started for. It
+/// gets dropped once the position changes (e.g. by an undo) or the game
+/// ends, and `think` should return early when its second argument says
+/// the move isn't wanted anymore. A rejected move is searched again (up to
+/// `MAX_REJECTED_MOVES` times) as long as the position stays.
+pub(crate) fn spawn_pondering_bot<F, P>(
+    me: Player,
+    min_reaction_delay: Duration,
+    think: F,
+    ponder: P,
+) -> (Sender<ChessUpdate>, Receiver<ChessRequest>)
+where
+    F: FnMut(GameHistory, &dyn Fn() -> bool, &dyn Fn(BotThinking)) -> BitMove + Send + 'static,
+    P: FnMut(GameHistory, &dyn Fn() -> bool) + Send + 'static,
+{
     let (update_tx, mut update_rx) = channel::<ChessUpdate>(256);
     let (request_tx, request_rx) = channel::<ChessRequest>(256);
+    // Shared with the searches, which run one after another
+    let think = Arc::new(Mutex::new(think));
+    let ponder = Arc::new(Mutex::new(ponder));
 
     task::spawn(async move {
         info!("Bot spawned for {}", me);
         let mut current_outcome: Option<ChessOutcome> = None;
-        while let Some(update) = update_rx.recv().await {
+        let mut history: Option<GameHistory> = None;
+        // Increased for every position to drop searches of old ones
+        let position = Arc::new(AtomicU64::new(0));
+        let mut search: Option<task::JoinHandle<Option<BitMove>>> = None;
+        // Position the bot has to move in and how often its move got rejected
+        let mut own_turn: Option<(String, u32)> = None;
+        let start_search = |history: GameHistory, current: u64| {
+            let position = position.clone();
+            let think = think.clone();
+            let request_tx = request_tx.clone();
+            task::spawn_blocking(move || {
+                let still_wanted = || position.load(Ordering::SeqCst) == current;
+                let report = |thinking| {
+                    if still_wanted() {
+                        request_tx
+                            .blocking_send(ChessRequest::ReportThinking { thinking })
+                            .ok();
+                    }
+                };
+                let started = SystemTime::now();
+                let bit_move = {
+                    let mut think = think.lock().unwrap_or_else(|e| e.into_inner());
+                    if !still_wanted() {
+                        return None; // Waited for the search of an old position
+                    }
+                    think(history, &still_wanted, &report)
+                };
+                let elapsed = started.elapsed().unwrap_or(Duration::new(0, 0));
+
+                if elapsed < min_reaction_delay {
+                    thread::sleep(min_reaction_delay - elapsed);
+                }
+                Some(bit_move).filter(|_| still_wanted())
+            })
+        };
+        loop {
+            let searched = async {
+                match search {
+                    Some(ref mut search) => search.await,
+                    None => future::pending().await,
+                }
+            };
+            let update = tokio::select! {
+                update = update_rx.recv() => update,
+                searched = searched => {
+                    search = None;
+                    match searched.context("Blocking heavy calculation") {
+                        Ok(Some(bit_move)) => {
+                            let sent = request_tx
+                                .send(ChessRequest::MovePiece {
+                                    source: bit_move.get_src().into(),
+                                    destination: bit_move.get_dest().into(),
+                                    promotion: Promotion::of(bit_move),
+                                })
+                                .await;
+                            if sent.is_err() {
+                                break; // Game is gone
+                            }
+                        }
+                        Ok(None) => info!("Bot dropped the search of an old position"),
+                        Err(e) => error!("Bot failed to search: {:?}", e),
+                    }
+                    continue;
+                }
+            };
+            let update = match update {
+                Some(update) => update,
+                None => break,
+            };
             match update {
+                ChessUpdate::PlayerMovedAPiece {
+                    moved_piece_source,
+                    moved_piece_destination,
+                    moved_piece_promotion,
+                    ..
+                } => {
+                    if let Some(ref mut history) = history {
+                        let played = history.play(
+                            moved_piece_source,
+                            moved_piece_destination,
+                            moved_piece_promotion,
+                        );
+                        if let Err(e) = played {
+                            // Starts over from the next position
+                            debug!("Bot failed to follow a move: {:?}", e);
+                        }
+                    }
+                }
                 ChessUpdate::PlayerSwitch { player, ref fen } => {
-                    if player == me && current_outcome.is_none() {
-                        let board = pleco::Board::from_fen(fen)
-                            .expect("Bot failed to parse the provided fen");
-
-                        let bit_move = task::spawn_blocking(move || {
-                            let started = SystemTime::now();
-                            let bit_move = T::best_move(board, depth);
-                            let elapsed = started.elapsed().unwrap_or(Duration::new(0, 0));
-
-                            if elapsed < min_reaction_delay {
-                                thread::sleep(min_reaction_delay - elapsed);
-                            } else {
-                                info!("Bot took a long time to think: {:?}", elapsed);
+                    let current = position.fetch_add(1, Ordering::SeqCst) + 1;
+                    own_turn = None;
+                    let synced = match history.as_mut() {
+                        Some(history) => history.sync(fen),
+                        None => GameHistory::new(fen).map(|new| history = Some(new)),
+                    };
+                    if let Err(e) = synced {
+                        error!("Bot failed to parse the provided fen {}: {:?}", fen, e);
+                        history = None;
+                        continue;
+                    }
+                    if current_outcome.is_some() {
+                        continue;
+                    }
+                    let history = history.clone().expect("Synced above");
+                    if player != me {
+                        let position = position.clone();
+                        let ponder = ponder.clone();
+                        task::spawn_blocking(move || {
+                            let still_wanted = || position.load(Ordering::SeqCst) == current;
+                            let mut ponder = ponder.lock().unwrap_or_else(|e| e.into_inner());
+                            if still_wanted() {
+                                ponder(history, &still_wanted);
                             }
-                            bit_move
-                        })
-                        .await
-                        .context("Blocking heavy calculation")
-                        .unwrap();
-
-                        request_tx
-                            .send(ChessRequest::MovePiece {
-                                source: bit_move.get_src().into(),
-                                destination: bit_move.get_dest().into(),
-                            })
-                            .await
-                            .expect("Bot failed to send move");
+                        });
+                        continue;
                     }
+                    own_turn = Some((fen.clone(), 0));
+                    search = Some(start_search(history, current));
                 }
-                ChessUpdate::MovePieceFailedResponse { message, .. } => {
+                ChessUpdate::MovePieceFailedResponse { message, fen } => {
                     error!("A move from the bot was rejected: {}", message);
-                    break;
+                    // Still its turn in the same position. Otherwise it keeps
+                    // playing once it's its turn again.
+                    let rejected = match own_turn {
+                        Some((ref own_fen, ref mut rejected)) if *own_fen == fen => rejected,
+                        _ => continue,
+                    };
+                    *rejected += 1;
+                    if *rejected > MAX_REJECTED_MOVES || search.is_some() || current_outcome.is_some() {
+                        continue;
+                    }
+                    let history = match history.as_mut().map(|history| history.sync(&fen)) {
+                        Some(Ok(())) => history.clone().expect("Synced above"),
+                        _ => continue,
+                    };
+                    info!("Bot searches its position again");
+                    search = Some(start_search(history, position.load(Ordering::SeqCst)));
                 }
                 ChessUpdate::Outcome { outcome } => {
                     if outcome.is_some() {
                         info!("Bot detected that the game ended");
-                    //break;
+                        position.fetch_add(1, Ordering::SeqCst);
                     } else {
                         info!("Game continues. Bot will continue playing.");
                     }
@@ -431,10 +1236,12 @@ pub async fn create_bot<T: Searcher>(
                 _ => {}
             }
         }
+        // Stop any search
+        position.fetch_add(1, Ordering::SeqCst);
         info!("Bot task has ended");
     });
 
-    Ok((update_tx, request_rx))
+    (update_tx, request_rx)
 }
 
 pub fn stubbed_spectator() -> (Sender<ChessUpdate>, Receiver<ChessRequest>) {
@@ -444,3 +1251,1012 @@ pub fn stubbed_spectator() -> (Sender<ChessUpdate>, Receiver<ChessRequest>) {
     let (_, request_rx) = channel::<ChessRequest>(1);
     (update_tx, request_rx)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::BookSelection;
+    use crate::test_harness::*;
+    use pleco::bot_prelude::AlphaBetaSearcher;
+
+    const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const AFTER_E4_FEN: &str = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+    const FOOLS_MATE_FEN: &str = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+
+    fn sq(square: &str) -> Square {
+        square.parse().unwrap()
+    }
+
+    /// Skip the updates sent on game start
+    async fn skip_game_start(game: &mut TestGame) {
+        game.white.skip_until("possible moves", is_possible_moves).await;
+        game.black.skip_until("player switch", is_player_switch).await;
+        game.spectators.skip_until("player switch", is_player_switch).await;
+    }
+
+    /// Play 1. f3 e5 2. g4 Qh4# and skip all updates for it
+    async fn play_fools_mate(game: &mut TestGame) {
+        for (i, (source, destination)) in [("F2", "F3"), ("E7", "E5"), ("G2", "G4"), ("D8", "H4")]
+            .iter()
+            .enumerate()
+        {
+            let (mover, other) = if i % 2 == 0 {
+                (&mut game.white, &mut game.black)
+            } else {
+                (&mut game.black, &mut game.white)
+            };
+            mover.send(mv(source, destination)).await;
+            mover.skip_until("player switch", is_player_switch).await;
+            other.skip_until("player switch", is_player_switch).await;
+            game.spectators
+                .skip_until("player switch", is_player_switch)
+                .await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn start_notifies_everyone_and_white_gets_possible_moves() {
+        let mut game = TestGame::start(config());
+        let switch = ChessUpdate::PlayerSwitch {
+            player: Player::White,
+            fen: START_FEN.to_owned(),
+        };
+        game.white.expect(switch.clone()).await;
+        let possible_moves = game.white.expect_matching("possible moves", is_possible_moves).await;
+        if let ChessUpdate::PossibleMoves { possible_moves } = possible_moves {
+            assert_eq!(possible_moves.len(), 20);
+            assert!(possible_moves.contains(&(sq("E2"), sq("E4"))));
+        }
+        game.black.expect(switch.clone()).await;
+        game.black.expect_silence().await;
+        game.spectators
+            .run(vec![Step::Expect(switch), Step::ExpectSilence])
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn starting_fen_decides_who_starts() {
+        let mut game = TestGame::start(ChessConfig {
+            starting_fen: Some(AFTER_E4_FEN.to_owned()),
+            ..config()
+        });
+        let switch = ChessUpdate::PlayerSwitch {
+            player: Player::Black,
+            fen: AFTER_E4_FEN.to_owned(),
+        };
+        game.white.expect(switch.clone()).await;
+        game.white.expect_silence().await;
+        game.black.expect(switch).await;
+        game.black.expect_matching("possible moves", is_possible_moves).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_starting_fen_fails_the_game() {
+        let game = TestGame::start(ChessConfig {
+            starting_fen: Some("not a fen".to_owned()),
+            ..config()
+        });
+        assert!(game_result(game.game).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hint_suggests_mate() {
+        let mut game = TestGame::start(ChessConfig {
+            starting_fen: Some(
+                "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4".to_owned(),
+            ),
+            ..config()
+        });
+        skip_game_start(&mut game).await;
+
+        game.white.send(ChessRequest::Hint).await;
+        game.white
+            .expect(ChessUpdate::Hint {
+                source: sq("H5"),
+                destination: sq("F7"),
+                line: vec!["Qxf7#".to_owned()],
+                hints_taken: 1,
+            })
+            .await;
+        // Only the one asking learns about it
+        game.black.expect_silence().await;
+        game.spectators.expect_silence().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hints_are_counted_per_player() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        let is_hint = |u: &ChessUpdate| matches!(u, ChessUpdate::Hint { .. });
+        game.white.send(ChessRequest::Hint).await;
+        let line = match game.white.next().await {
+            ChessUpdate::Hint {
+                line, hints_taken, ..
+            } => {
+                assert_eq!(hints_taken, 1);
+                line
+            }
+            update => panic!("Expected a hint but got {:?}", update),
+        };
+        assert!(!line.is_empty() && line.len() <= HINT_LINE_LENGTH);
+        game.white.send(ChessRequest::Hint).await;
+        game.white
+            .expect_matching("second hint", |u| {
+                matches!(u, ChessUpdate::Hint { hints_taken: 2, .. })
+            })
+            .await;
+
+        // Not on the opponent's turn
+        game.black.send(ChessRequest::Hint).await;
+        game.black
+            .expect_matching("failed hint", |u| {
+                matches!(u, ChessUpdate::HintFailedResponse { .. })
+            })
+            .await;
+
+        game.white.send(mv("E2", "E4")).await;
+        game.black.skip_until("possible moves", is_possible_moves).await;
+        game.black.send(ChessRequest::Hint).await;
+        game.black.skip_until("black's first hint", is_hint).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn game_goes_on_while_a_hint_is_searched() {
+        // Takes a moment without pruning
+        let slow_hints = HintConfig {
+            limits: SearchLimits::depth(3),
+            algorithm: SearchAlgorithm::Minimax,
+        };
+        let mut game = TestGame::start(ChessConfig {
+            hints: Some(slow_hints),
+            ..config()
+        });
+        skip_game_start(&mut game).await;
+
+        game.white.send(ChessRequest::Hint).await;
+        game.white.send(ChessRequest::CurrentBoard).await;
+        game.white
+            .expect(ChessUpdate::Board {
+                fen: START_FEN.to_owned(),
+            })
+            .await;
+        game.white.send(mv("E2", "E4")).await;
+        game.black.skip_until("possible moves", is_possible_moves).await;
+        game.black.send(ChessRequest::Hint).await;
+        game.black
+            .skip_until("hint", |u| matches!(u, ChessUpdate::Hint { .. }))
+            .await;
+
+        // The hint for the start position is outdated
+        game.white
+            .skip_until("player switch", is_player_switch)
+            .await;
+        game.white.expect_silence().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hints_can_be_turned_off() {
+        let mut game = TestGame::start(ChessConfig {
+            hints: None,
+            ..config()
+        });
+        skip_game_start(&mut game).await;
+        game.white.send(ChessRequest::Hint).await;
+        game.white
+            .expect(ChessUpdate::HintFailedResponse {
+                message: "Hints are turned off in this game.".to_owned(),
+            })
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spectators_get_no_hints() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+        game.spectators.send(ChessRequest::Hint).await;
+        game.spectators
+            .expect_matching("error", |u| {
+                matches!(u, ChessUpdate::GenericErrorResponse { .. })
+            })
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn move_is_broadcast_and_other_player_gets_possible_moves() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        game.white.send(mv("E2", "E4")).await;
+        let moved = ChessUpdate::PlayerMovedAPiece {
+            player: Player::White,
+            moved_piece_source: sq("E2"),
+            moved_piece_destination: sq("E4"),
+            moved_piece_promotion: None,
+        };
+        let switch = ChessUpdate::PlayerSwitch {
+            player: Player::Black,
+            fen: AFTER_E4_FEN.to_owned(),
+        };
+        for player in [&mut game.white, &mut game.spectators].iter_mut() {
+            player.expect(moved.clone()).await;
+            player.expect(switch.clone()).await;
+            player.expect_silence().await;
+        }
+        game.black.expect(moved).await;
+        game.black.expect(switch).await;
+        game.black.expect_matching("possible moves", is_possible_moves).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn illegal_move_is_only_reported_to_sender() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        game.white.send(mv("E2", "E5")).await;
+        game.white
+            .expect_matching("failed move", |u| {
+                matches!(u, ChessUpdate::MovePieceFailedResponse { fen, .. } if fen == START_FEN)
+            })
+            .await;
+        game.black.expect_silence().await;
+        game.spectators.expect_silence().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn moving_out_of_turn_is_rejected() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        game.black.send(mv("E7", "E5")).await;
+        game.black
+            .expect_matching("failed move", |u| {
+                matches!(u, ChessUpdate::MovePieceFailedResponse { .. })
+            })
+            .await;
+        game.white.expect_silence().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spectators_can_only_query() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        assert!(!mv("E2", "E4").available_to_spectator());
+        game.spectators.send(mv("E2", "E4")).await;
+        game.spectators
+            .expect_matching("error", |u| {
+                matches!(u, ChessUpdate::GenericErrorResponse { .. })
+            })
+            .await;
+        game.spectators.send(ChessRequest::UndoMoves { moves: 1 }).await;
+        game.spectators
+            .expect_matching("error", |u| {
+                matches!(u, ChessUpdate::GenericErrorResponse { .. })
+            })
+            .await;
+        game.white.expect_silence().await;
+        game.black.expect_silence().await;
+
+        game.spectators.send(ChessRequest::CurrentBoard).await;
+        game.spectators
+            .expect(ChessUpdate::Board {
+                fen: START_FEN.to_owned(),
+            })
+            .await;
+        game.spectators.send(ChessRequest::CurrentTotalMoves).await;
+        game.spectators
+            .expect(ChessUpdate::CurrentTotalMovesReponse { total_moves: 0 })
+            .await;
+    }
+
+    /// Answering a spectator used to fall through to the player requests,
+    /// which ended the game since spectators are no player
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spectator_queries_do_not_end_the_game() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        game.spectators.send(ChessRequest::CurrentTotalMoves).await;
+        game.spectators
+            .expect(ChessUpdate::CurrentTotalMovesReponse { total_moves: 0 })
+            .await;
+        game.white.send(mv("E2", "E4")).await;
+        game.black
+            .skip_until("possible moves", is_possible_moves)
+            .await;
+        assert!(!game.game.is_finished());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn players_can_query_the_game() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        game.black.send(ChessRequest::CurrentBoard).await;
+        game.black
+            .expect(ChessUpdate::Board {
+                fen: START_FEN.to_owned(),
+            })
+            .await;
+        game.white.send(ChessRequest::CurrentOutcome).await;
+        game.white.expect(ChessUpdate::Outcome { outcome: None }).await;
+        game.white.send(mv("E2", "E4")).await;
+        game.white.skip_until("player switch", is_player_switch).await;
+        game.white.send(ChessRequest::CurrentTotalMoves).await;
+        game.white
+            .expect(ChessUpdate::CurrentTotalMovesReponse { total_moves: 1 })
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn undo_requires_permission() {
+        let mut game = TestGame::start(ChessConfig {
+            can_black_undo: false,
+            ..config()
+        });
+        skip_game_start(&mut game).await;
+        game.white.send(mv("E2", "E4")).await;
+        game.black.skip_until("possible moves", is_possible_moves).await;
+
+        game.black.send(ChessRequest::UndoMoves { moves: 1 }).await;
+        game.black
+            .expect(ChessUpdate::UndoMovesFailedResponse {
+                message: "You are not permitted to do that in this game.".to_owned(),
+            })
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn undo_only_on_own_turn() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+        game.white.send(mv("E2", "E4")).await;
+        game.white.skip_until("player switch", is_player_switch).await;
+
+        game.white.send(ChessRequest::UndoMoves { moves: 1 }).await;
+        game.white
+            .expect(ChessUpdate::UndoMovesFailedResponse {
+                message: "You can only undo when you are playing or it's game over.".to_owned(),
+            })
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn undo_is_broadcast() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+        game.white.send(mv("E2", "E4")).await;
+        game.black.skip_until("possible moves", is_possible_moves).await;
+        game.white.skip_until("player switch", is_player_switch).await;
+        game.spectators.skip_until("player switch", is_player_switch).await;
+
+        game.black.send(ChessRequest::UndoMoves { moves: 1 }).await;
+        let switch = ChessUpdate::PlayerSwitch {
+            player: Player::White,
+            fen: START_FEN.to_owned(),
+        };
+        let undone = ChessUpdate::MovesUndone {
+            who: Player::Black,
+            moves: 1,
+        };
+        game.white.expect(switch.clone()).await;
+        game.white.expect_matching("possible moves", is_possible_moves).await;
+        game.white.expect(undone.clone()).await;
+        game.black.expect(switch).await;
+        game.black.expect(undone).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn undo_more_than_played_is_rejected() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        game.white.send(ChessRequest::UndoMoves { moves: 1 }).await;
+        game.white
+            .expect_matching("failed undo", |u| {
+                matches!(u, ChessUpdate::UndoMovesFailedResponse { .. })
+            })
+            .await;
+        game.black.expect_silence().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn checkmate_ends_the_game() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+        play_fools_mate(&mut game).await;
+
+        game.white.send(ChessRequest::CurrentOutcome).await;
+        game.white
+            .expect(ChessUpdate::Outcome {
+                outcome: Some(ChessOutcome::Checkmate {
+                    winner: Player::Black,
+                }),
+            })
+            .await;
+        game.white.send(mv("A2", "A3")).await;
+        game.white
+            .expect_matching("failed move", |u| {
+                matches!(u, ChessUpdate::MovePieceFailedResponse { fen, .. } if fen == FOOLS_MATE_FEN)
+            })
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn undo_after_loose_if_allowed() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+        play_fools_mate(&mut game).await;
+
+        game.white.send(ChessRequest::UndoMoves { moves: 2 }).await;
+        game.white.expect(ChessUpdate::Outcome { outcome: None }).await;
+        game.white
+            .skip_until("undo", |u| matches!(u, ChessUpdate::MovesUndone { .. }))
+            .await;
+        game.black.expect(ChessUpdate::Outcome { outcome: None }).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn undo_after_loose_if_not_allowed() {
+        let mut game = TestGame::start(ChessConfig {
+            allow_undo_after_loose: false,
+            ..config()
+        });
+        skip_game_start(&mut game).await;
+        play_fools_mate(&mut game).await;
+
+        game.white.send(ChessRequest::UndoMoves { moves: 2 }).await;
+        game.white
+            .expect(ChessUpdate::UndoMovesFailedResponse {
+                message: "You can only undo when you are playing.".to_owned(),
+            })
+            .await;
+        game.black.expect_silence().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn abort_ends_the_game() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        game.black
+            .send(ChessRequest::Abort {
+                message: "Bye".to_owned(),
+            })
+            .await;
+        game.white.expect_closed().await;
+        game.black.expect_closed().await;
+        game.spectators.expect_closed().await;
+        game_result(game.game).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn white_disconnecting_aborts_the_game() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+
+        game.white.disconnect();
+        game.black.expect_closed().await;
+        game_result(game.game).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_answers_moves() {
+        let (mut white, white_connection) = ScriptedPlayer::new("White");
+        let bot = create_bot::<AlphaBetaSearcher>(
+            Player::Black,
+            SearchLimits::depth(2).into(),
+            Duration::from_millis(0),
+        )
+        .await
+        .unwrap();
+        let _game = spawn_game(white_connection, bot, stubbed_spectator(), config());
+
+        white.skip_until("possible moves", is_possible_moves).await;
+        white
+            .run(vec![
+                Step::Send(mv("E2", "E4")),
+                Step::SkipUntil("bot move", |u| {
+                    matches!(u, ChessUpdate::PlayerMovedAPiece { player: Player::Black, .. })
+                }),
+                Step::ExpectMatching("switch to white", |u| {
+                    matches!(u, ChessUpdate::PlayerSwitch { player: Player::White, .. })
+                }),
+                Step::ExpectMatching("possible moves", is_possible_moves),
+                Step::ExpectSilence,
+            ])
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_as_white_starts() {
+        let (mut black, black_connection) = ScriptedPlayer::new("Black");
+        let bot = create_bot::<AlphaBetaSearcher>(
+            Player::White,
+            SearchLimits::depth(2).into(),
+            Duration::from_millis(0),
+        )
+        .await
+        .unwrap();
+        let _game = spawn_game(bot, black_connection, stubbed_spectator(), config());
+
+        black
+            .skip_until("bot move", |u| {
+                matches!(u, ChessUpdate::PlayerMovedAPiece { player: Player::White, .. })
+            })
+            .await;
+        black.skip_until("possible moves", is_possible_moves).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_stops_searching_in_time() {
+        let (mut white, white_connection) = ScriptedPlayer::new("White");
+        let limits = SearchLimits {
+            movetime: Some(200),
+            ..SearchLimits::depth(30)
+        };
+        let bot =
+            create_bot::<AlphaBetaSearcher>(Player::Black, limits.into(), Duration::from_millis(0))
+            .await
+            .unwrap();
+        let _game = spawn_game(white_connection, bot, stubbed_spectator(), config());
+
+        white.skip_until("possible moves", is_possible_moves).await;
+        white.send(mv("E2", "E4")).await;
+        white
+            .skip_until("bot move", |u| {
+                matches!(u, ChessUpdate::PlayerMovedAPiece { player: Player::Black, .. })
+            })
+            .await;
+    }
+
+    /// Takes a while for every move (unless the move isn't wanted anymore)
+    fn slow_bot(me: Player) -> (Sender<ChessUpdate>, Receiver<ChessRequest>) {
+        spawn_bot(me, Duration::from_millis(0), |history, still_wanted, _| {
+            for _ in 0..20 {
+                if !still_wanted() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            history.board().generate_moves()[0]
+        })
+    }
+
+    async fn next_request(requests: &mut Receiver<ChessRequest>) -> ChessRequest {
+        tokio::time::timeout(DEFAULT_TIMEOUT, requests.recv())
+            .await
+            .expect("No request in time")
+            .expect("Bot has ended")
+    }
+
+    /// Skips the progress reports before the move
+    async fn next_move(requests: &mut Receiver<ChessRequest>) -> (Square, Square) {
+        loop {
+            match next_request(requests).await {
+                ChessRequest::MovePiece {
+                    source,
+                    destination,
+                    ..
+                } => return (source, destination),
+                ChessRequest::ReportThinking { .. } => {}
+                request => panic!("Unexpected request {:?}", request),
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_drops_moves_of_old_positions() {
+        let (updates, mut requests) = slow_bot(Player::Black);
+        updates.send(switch(Player::Black, AFTER_E4_FEN)).await.unwrap();
+        // Undone while the bot thinks
+        updates.send(switch(Player::White, START_FEN)).await.unwrap();
+        let after_d4 = "rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq - 0 1";
+        updates.send(switch(Player::Black, after_d4)).await.unwrap();
+
+        assert!(matches!(
+            next_request(&mut requests).await,
+            ChessRequest::MovePiece { .. }
+        ));
+        // Only the move for the current position
+        assert!(
+            tokio::time::timeout(SILENCE_DURATION * 2, requests.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_keeps_playing_after_rejected_move() {
+        let (updates, mut requests) = slow_bot(Player::Black);
+        updates.send(switch(Player::Black, AFTER_E4_FEN)).await.unwrap();
+        next_request(&mut requests).await;
+        updates
+            .send(ChessUpdate::MovePieceFailedResponse {
+                message: "Denied".to_owned(),
+                fen: AFTER_E4_FEN.to_owned(),
+            })
+            .await
+            .unwrap();
+        updates.send(switch(Player::Black, AFTER_E4_FEN)).await.unwrap();
+        assert!(matches!(
+            next_request(&mut requests).await,
+            ChessRequest::MovePiece { .. }
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_searches_again_after_rejected_move() {
+        let (updates, mut requests) = slow_bot(Player::Black);
+        updates.send(switch(Player::Black, AFTER_E4_FEN)).await.unwrap();
+        next_request(&mut requests).await;
+        let rejected = || ChessUpdate::MovePieceFailedResponse {
+            message: "Denied".to_owned(),
+            fen: AFTER_E4_FEN.to_owned(),
+        };
+        for _ in 0..MAX_REJECTED_MOVES {
+            updates.send(rejected()).await.unwrap();
+            assert!(matches!(
+                next_request(&mut requests).await,
+                ChessRequest::MovePiece { .. }
+            ));
+        }
+        // Gives up on the position in the end
+        updates.send(rejected()).await.unwrap();
+        assert!(
+            tokio::time::timeout(SILENCE_DURATION * 2, requests.recv())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_stops_thinking_when_the_game_ends() {
+        let (updates, mut requests) = slow_bot(Player::Black);
+        updates.send(switch(Player::Black, AFTER_E4_FEN)).await.unwrap();
+        updates
+            .send(ChessUpdate::Outcome {
+                outcome: Some(ChessOutcome::Aborted {
+                    who: Some(Player::White),
+                }),
+            })
+            .await
+            .unwrap();
+        assert!(
+            tokio::time::timeout(SILENCE_DURATION * 2, requests.recv())
+                .await
+                .is_err()
+        );
+        drop(updates);
+        assert_eq!(
+            tokio::time::timeout(DEFAULT_TIMEOUT, requests.recv())
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_follows_moves_and_undos() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let (updates, mut requests) = {
+            let seen = seen.clone();
+            spawn_bot(Player::Black, Duration::from_millis(0), move |history, _, _| {
+                let moves: Vec<String> = history.moves().iter().map(|m| m.stringify()).collect();
+                seen.lock().unwrap().push(moves);
+                history.board().generate_moves()[0]
+            })
+        };
+        let moved = |source: &str, destination: &str| ChessUpdate::PlayerMovedAPiece {
+            player: Player::White,
+            moved_piece_source: sq(source),
+            moved_piece_destination: sq(destination),
+            moved_piece_promotion: None,
+        };
+        updates.send(switch(Player::White, START_FEN)).await.unwrap();
+        updates.send(moved("E2", "E4")).await.unwrap();
+        updates.send(switch(Player::Black, AFTER_E4_FEN)).await.unwrap();
+        next_request(&mut requests).await;
+        // Undone and played differently
+        updates.send(switch(Player::White, START_FEN)).await.unwrap();
+        updates.send(moved("D2", "D4")).await.unwrap();
+        let after_d4 = "rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq - 0 1";
+        updates.send(switch(Player::Black, after_d4)).await.unwrap();
+        next_request(&mut requests).await;
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![vec!["e2e4".to_owned()], vec!["d2d4".to_owned()]]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_ponders_until_the_opponent_moved() {
+        let pondered = Arc::new(Mutex::new(vec![]));
+        let (updates, mut requests) = {
+            let pondered = pondered.clone();
+            spawn_pondering_bot(
+                Player::Black,
+                Duration::from_millis(0),
+                |history, _, _| history.board().generate_moves()[0],
+                move |history, still_wanted| {
+                    pondered.lock().unwrap().push(history.board().fen());
+                    while still_wanted() {
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                },
+            )
+        };
+        updates.send(switch(Player::White, START_FEN)).await.unwrap();
+        tokio::time::sleep(SILENCE_DURATION).await;
+        updates.send(switch(Player::Black, AFTER_E4_FEN)).await.unwrap();
+        // Would hang if the pondering went on
+        next_request(&mut requests).await;
+
+        // Not after the game ended
+        updates
+            .send(ChessUpdate::Outcome {
+                outcome: Some(ChessOutcome::Draw),
+            })
+            .await
+            .unwrap();
+        updates.send(switch(Player::White, START_FEN)).await.unwrap();
+        tokio::time::sleep(SILENCE_DURATION).await;
+        assert_eq!(*pondered.lock().unwrap(), vec![START_FEN.to_owned()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pondering_bot_plays_like_without_pondering() {
+        let bot_config = BotConfig {
+            ponder: Some(true),
+            ..SearchLimits::depth(3).into()
+        };
+        let (updates, mut requests) =
+            create_bot::<AlphaBetaSearcher>(Player::White, bot_config, Duration::from_millis(0))
+                .await
+                .unwrap();
+        let mut game = ChessGame::default();
+        let play = |game: &mut ChessGame, player: Player, bit_move: BitMove| {
+            let (source, destination) = (bit_move.get_src().into(), bit_move.get_dest().into());
+            game.move_piece(source, destination, None).unwrap();
+            vec![
+                ChessUpdate::PlayerMovedAPiece {
+                    player,
+                    moved_piece_source: source,
+                    moved_piece_destination: destination,
+                    moved_piece_promotion: None,
+                },
+                switch(player.other_player(), &game.fen()),
+            ]
+        };
+        updates.send(switch(Player::White, &game.fen())).await.unwrap();
+        for _ in 0..2 {
+            let (source, destination) = next_move(&mut requests).await;
+            let bit_move = game.find_move(source, destination, None).unwrap();
+            assert_eq!(bit_move, AlphaBetaSearcher::best_move(game.board(), 3));
+            for update in play(&mut game, Player::White, bit_move) {
+                updates.send(update).await.unwrap();
+            }
+            // The reply it ponders on
+            let reply = AlphaBetaSearcher::best_move(game.board(), 2);
+            tokio::time::sleep(SILENCE_DURATION).await;
+            for update in play(&mut game, Player::Black, reply) {
+                updates.send(update).await.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_reports_every_depth() {
+        let (updates, mut requests) = create_bot::<AlphaBetaSearcher>(
+            Player::White,
+            SearchLimits::depth(3).into(),
+            Duration::from_millis(0),
+        )
+        .await
+        .unwrap();
+        updates.send(switch(Player::White, START_FEN)).await.unwrap();
+        let mut depths = vec![];
+        loop {
+            match next_request(&mut requests).await {
+                ChessRequest::ReportThinking { thinking } => {
+                    assert!(thinking.best_move.is_some());
+                    depths.push(thinking.depth);
+                }
+                ChessRequest::MovePiece { .. } => break,
+                request => panic!("Unexpected request {:?}", request),
+            }
+        }
+        assert_eq!(depths, vec![1, 2, 3]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_reports_nodes_and_score() {
+        let (updates, mut requests) = create_bot::<AlphaBetaSearcher>(
+            Player::White,
+            SearchLimits::depth(2).into(),
+            Duration::from_millis(0),
+        )
+        .await
+        .unwrap();
+        // Scholar's mate on the board
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+        updates.send(switch(Player::White, fen)).await.unwrap();
+        let mut reports = vec![];
+        loop {
+            match next_request(&mut requests).await {
+                ChessRequest::ReportThinking { thinking } => reports.push(thinking),
+                ChessRequest::MovePiece { .. } => break,
+                request => panic!("Unexpected request {:?}", request),
+            }
+        }
+        assert_eq!(reports.len(), 2);
+        for thinking in &reports {
+            assert!(thinking.nodes.is_some_and(|nodes| nodes > 0));
+            assert!(thinking.nodes_per_second.is_some());
+        }
+        assert!(reports[1].nodes > reports[0].nodes);
+        assert!(matches!(reports[0].score, Some(Score::Centipawns(_))));
+        assert_eq!(reports[1].score, Some(Score::Mate(1)));
+        assert_eq!(reports[1].best_move.as_deref(), Some("Qxf7#"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn thinking_is_broadcast_on_the_own_turn() {
+        let mut game = TestGame::start(config());
+        skip_game_start(&mut game).await;
+        let thinking = BotThinking {
+            depth: 5,
+            nodes: Some(1000),
+            nodes_per_second: Some(2000),
+            best_move: Some("e4".to_owned()),
+            score: Some(Score::Centipawns(20)),
+        };
+        // Too late, it's White's turn
+        game.black
+            .send(ChessRequest::ReportThinking {
+                thinking: thinking.clone(),
+            })
+            .await;
+        game.white
+            .send(ChessRequest::ReportThinking {
+                thinking: thinking.clone(),
+            })
+            .await;
+        let update = ChessUpdate::BotThinking {
+            player: Player::White,
+            thinking,
+        };
+        game.white.expect(update.clone()).await;
+        game.black.expect(update.clone()).await;
+        game.spectators.expect(update).await;
+        game.black.expect_silence().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bot_plays_from_its_book() {
+        // Polyglot entry for the start position: g1f3 with weight 1
+        let mut entry = 0x463b96181691fc9cu64.to_be_bytes().to_vec();
+        entry.extend_from_slice(&((6u16 << 6) | 21).to_be_bytes());
+        entry.extend_from_slice(&1u16.to_be_bytes());
+        entry.extend_from_slice(&0u32.to_be_bytes());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.bin");
+        std::fs::write(&path, entry).unwrap();
+
+        let (mut black, black_connection) = ScriptedPlayer::new("Black");
+        let bot_config = BotConfig {
+            limits: SearchLimits::depth(2),
+            book: Some(BookConfig {
+                path,
+                selection: BookSelection::Best,
+            }),
+            tablebase: None,
+            ponder: None,
+        };
+        let bot =
+            create_bot::<AlphaBetaSearcher>(Player::White, bot_config, Duration::from_millis(0))
+                .await
+                .unwrap();
+        let _game = spawn_game(bot, black_connection, stubbed_spectator(), config());
+
+        black
+            .skip_until("book move", |u| {
+                *u == ChessUpdate::PlayerMovedAPiece {
+                    player: Player::White,
+                    moved_piece_source: sq("G1"),
+                    moved_piece_destination: sq("F3"),
+                    moved_piece_promotion: None,
+                }
+            })
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs the Syzygy tables of testdata/syzygy/download.sh"]
+    async fn bot_converts_endgame_with_tablebase() {
+        let tables = syzygy_tables();
+        let (mut black, black_connection) = ScriptedPlayer::new("Black");
+        let bot_config = BotConfig {
+            tablebase: Some(tables.clone()),
+            ..SearchLimits::depth(1).into()
+        };
+        let bot =
+            create_bot::<AlphaBetaSearcher>(Player::White, bot_config, Duration::from_millis(0))
+                .await
+                .unwrap();
+        let _game = spawn_game(
+            bot,
+            black_connection,
+            stubbed_spectator(),
+            ChessConfig {
+                starting_fen: Some("8/8/8/3k4/8/8/8/3QK3 w - - 0 1".to_owned()),
+                tablebase: Some(tables),
+                ..config()
+            },
+        );
+
+        let white_wins = |u: &ChessUpdate| match u {
+            ChessUpdate::Evaluation {
+                evaluation: Evaluation::Tablebase(verdict),
+            } => verdict.winner() == Some(Player::White),
+            _ => false,
+        };
+        black.skip_until("verdict of the start", white_wins).await;
+        // Black just follows the king around until mated
+        for _ in 0..30 {
+            let possible_moves = match black.next().await {
+                ChessUpdate::PossibleMoves { possible_moves } => possible_moves,
+                ChessUpdate::Outcome { outcome } => {
+                    assert_eq!(
+                        outcome,
+                        Some(ChessOutcome::Checkmate {
+                            winner: Player::White
+                        })
+                    );
+                    return;
+                }
+                update @ ChessUpdate::Evaluation { .. } => {
+                    assert!(white_wins(&update), "Lost the win: {:?}", update);
+                    continue;
+                }
+                _ => continue,
+            };
+            let (source, destination) = possible_moves[0];
+            black
+                .send(ChessRequest::MovePiece {
+                    source,
+                    destination,
+                    promotion: None,
+                })
+                .await;
+        }
+        panic!("The tablebase didn't mate");
+    }
+
+    #[test]
+    fn waits_for_a_lock_until_the_deadline() {
+        let mutex = Mutex::new(());
+        let guard = mutex.lock().unwrap();
+        let started = Instant::now();
+        assert!(lock_until(&mutex, Some(started + Duration::from_millis(50))).is_none());
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        drop(guard);
+        assert!(lock_until(&mutex, Some(started)).is_some());
+    }
+
+    #[test]
+    fn search_budget() {
+        assert_eq!(SearchLimits::depth(4).budget(None), None);
+        let limits = SearchLimits {
+            movetime: Some(2000),
+            clock: Some(60_000),
+            increment: 1000,
+            ..SearchLimits::depth(8)
+        };
+        assert_eq!(limits.budget(None), Some(Duration::from_millis(2000)));
+        assert_eq!(limits.budget(Some(30_000)), Some(Duration::from_millis(1500)));
+        assert_eq!(limits.budget(Some(600_000)), Some(Duration::from_millis(2000)));
+    }
+}
@@ -1,84 +1,142 @@
 use crate::game::ChessGame;
 pub use crate::game::{ChessOutcome, SQ};
-use crate::{Square};
+use crate::pgn;
+use crate::Square;
+use anyhow::{Context, Result};
 use chess_pgn_parser::{GameMove, Move, GameTermination, Game};
 use chess_pgn_parser::Piece as LocalPiece;
-use chess_pgn_parser::Rank as LocalRank;
-use chess_pgn_parser::File as LocalFile;
-use pleco::{Piece, Rank, File};
-use pleco::core::Piece::{WhitePawn, WhiteKnight, WhiteBishop, WhiteRook, WhiteQueen, WhiteKing, BlackPawn, BlackKing, BlackKnight, BlackBishop, BlackRook, BlackQueen};
 
-const FEN_TAG: &str = "FEN";
+/// "1" if the game starts from the position in the FEN tag
+const SET_UP_TAG: &str = "SetUp";
 
 pub struct ReplayResponse {
     pub fen: String,
     pub comment: Option<String>,
     pub last_move_from: Option<Square>,
     pub last_move_to: Option<Square>,
+    /// The replayed move differs from how the PGN writes it (e.g. a wrong
+    /// check mark)
+    pub warning: Option<String>,
+    /// Annotation symbol of the replayed move (e.g. "!?")
+    pub annotation_symbol: Option<&'static str>,
+    /// What the NAG of the replayed move stands for (e.g. "White is slightly
+    /// better")
+    pub nag: Option<String>,
+    /// The comment before the first move (only at the start of the game)
+    pub game_comment: Option<String>,
+}
+
+/// A variation the replay went into. It replaces the move at `index` of the
+/// line it branches off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Branch {
+    index: usize,
+    variation: usize,
 }
 
 pub struct Replay {
     active_game: ChessGame,
     replay_info: Game,
+    /// Variations entered from the main line on (empty on the main line)
+    branches: Vec<Branch>,
+    /// Moves played of the current line
     replay_moves_played_offset: usize,
     player_moves_played_offset: usize,
-    is_white_turn: bool,
 }
 
 impl Replay {
+    /// Fails if the game starts from an invalid FEN
     pub fn new(
         replay_info: Game
-    ) -> Self {
-        let starting_fen = replay_info.tags.iter().find(|tag| tag.to_owned().0 == FEN_TAG);
-        let active_game_state = match starting_fen {
-            Some(fen) =>
-                ChessGame::from_fen(&fen.1).unwrap(),
-            _ => {
-                ChessGame::default()
-            }
-        };
-        Self {
+    ) -> Result<Self> {
+        let active_game_state = start_position(&replay_info)?;
+        Ok(Self {
             active_game: active_game_state,
             replay_info,
+            branches: vec![],
             replay_moves_played_offset: 0,
             player_moves_played_offset: 0,
-            is_white_turn: true,
-        }
+        })
+    }
+
+    /// The current position
+    pub fn fen(&self) -> String {
+        self.active_game.fen()
     }
 
     pub fn possible_moves(&self) -> pleco::MoveList {
         self.active_game.possible_moves()
     }
 
+    /// The moves of the current line (the main line or a variation)
+    fn line(&self) -> &[GameMove] {
+        let mut line = &self.replay_info.moves[..];
+        for branch in &self.branches {
+            line = &line[branch.index].variations[branch.variation].moves;
+        }
+        line
+    }
+
+    pub fn in_variation(&self) -> bool {
+        !self.branches.is_empty()
+    }
+
+    /// The moves that can be replayed next in SAN. The first is the one of
+    /// the current line, the others start its variations. Empty at the end
+    /// of a line or after manual moves.
+    pub fn alternatives(&self) -> Vec<String> {
+        if self.player_moves_played_offset > 0 {
+            return vec![];
+        }
+        match self.line().get(self.replay_moves_played_offset) {
+            Some(next) => std::iter::once(next)
+                .chain(next.variations.iter().filter_map(|variation| variation.moves.first()))
+                .map(|game_move| pgn::san(&game_move.move_))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Plays the next move of the current line
     pub fn play_replay_move(&mut self) -> ReplayResponse {
+        self.play_alternative(0)
+    }
+
+    /// Plays one of `alternatives()`. Any but the first enters a variation.
+    pub fn play_alternative(&mut self, alternative: usize) -> ReplayResponse {
         let mut comment: Option<String> = None;
         let mut last_move_from: Option<Square> = None;
         let mut last_move_to: Option<Square> = None;
-        if self.replay_moves_played_offset + 1 <= self.replay_info.moves.len() && self.player_moves_played_offset == 0 {
-            let played_move: GameMove = self.replay_info.moves[self.replay_moves_played_offset].clone();
-            comment = played_move.comment;
-            let played_move = played_move.move_.move_;
-            let played_piece = match &played_move {
-                Move::BasicMove { piece, .. } => to_pleco_piece(piece, self.is_white_turn),
-                _ => if self.is_white_turn { WhiteKing } else { BlackKing }
-            };
-            let destination = match &played_move {
-                Move::BasicMove { to, .. } => SQ::make(to_pleco_file(to.file()).unwrap(), to_pleco_rank(to.rank()).unwrap()),
-                Move::CastleKingside => if self.is_white_turn { SQ::make(File::H, Rank::R1) } else { SQ::make(File::H, Rank::R8) }
-                Move::CastleQueenside => if self.is_white_turn { SQ::make(File::A, Rank::R1) } else { SQ::make(File::A, Rank::R8) }
-            };
-            let (src_col, src_row) = match &played_move {
-                Move::BasicMove { from, .. } => (to_pleco_file(from.file()), to_pleco_rank(from.rank())),
-                Move::CastleKingside => (Some(File::E), if self.is_white_turn { Some(Rank::R1) } else { Some(Rank::R8) }),
-                Move::CastleQueenside => (Some(File::E), if self.is_white_turn { Some(Rank::R1) } else { Some(Rank::R8) })
+        let mut warning: Option<String> = None;
+        let mut annotation_symbol: Option<&'static str> = None;
+        let mut nag: Option<String> = None;
+        let next = self.line().get(self.replay_moves_played_offset).and_then(|next| {
+            if alternative == 0 {
+                Some((None, next.clone()))
+            } else {
+                let variation = next.variations.get(alternative - 1)?;
+                Some((variation.comment.clone(), variation.moves.first()?.clone()))
+            }
+        });
+        if let (Some((variation_comment, played_move)), 0) = (next, self.player_moves_played_offset) {
+            let previous_offset = self.replay_moves_played_offset;
+            if alternative > 0 {
+                self.branches.push(Branch { index: previous_offset, variation: alternative - 1 });
+                self.replay_moves_played_offset = 0;
+            }
+            comment = match (variation_comment, played_move.comment.clone()) {
+                (Some(variation_comment), Some(move_comment)) => Some(format!("{} {}", variation_comment, move_comment)),
+                (variation_comment, move_comment) => variation_comment.or(move_comment),
             };
-            match self.active_game.move_piece_by_type(played_piece, Square::from(destination), src_col, src_row) {
-                Ok((src, dest)) => {
+            match self.play_game_move(&played_move) {
+                Ok((src, dest, move_warning)) => {
+                    warning = move_warning;
+                    annotation_symbol = annotation_symbol_of(&played_move);
+                    nag = nag_of(&played_move);
                     last_move_from = Some(src);
                     last_move_to = Some(dest);
-                    self.is_white_turn = !self.is_white_turn;
-                    self.replay_moves_played_offset = self.replay_moves_played_offset + 1;
-                    if self.replay_moves_played_offset == self.replay_info.moves.len() {
+                    self.replay_moves_played_offset += 1;
+                    if !self.in_variation() && self.replay_moves_played_offset == self.replay_info.moves.len() {
                         let termination_string = termination_string_from(self.replay_info.termination);
                         let mut last_move_comment = comment.unwrap_or("".into());
                         last_move_comment.push_str(termination_string);
@@ -86,23 +144,223 @@ impl Replay {
                     }
                 }
                 Err(_) => {
+                    if alternative > 0 {
+                        self.branches.pop();
+                        self.replay_moves_played_offset = previous_offset;
+                    }
                     comment = Some("Error playing replay move, please check your PGN's validity".into())
                 }
             }
         } else if self.player_moves_played_offset > 0 {
             comment = Some("Undo Manual Moves before proceeding with replay".into())
         }
-        return ReplayResponse { fen: self.active_game.fen(), comment, last_move_from, last_move_to };
+        ReplayResponse {
+            fen: self.active_game.fen(),
+            comment,
+            last_move_from,
+            last_move_to,
+            warning,
+            annotation_symbol,
+            nag,
+            game_comment: None,
+        }
+    }
+
+    /// Plays the move as written in the PGN (including the piece a pawn
+    /// promotes to). Returns a warning if the check or mate marks don't fit.
+    fn play_game_move(&mut self, played_move: &GameMove) -> Result<(Square, Square, Option<String>)> {
+        let board = self.active_game.board();
+        let written = &played_move.move_;
+        let bit_move = pgn::find_move(&board, &written.move_)
+            .or_else(|| match &written.move_ {
+                // Some PGNs leave out the queen
+                Move::BasicMove { piece, to, from, is_capture, promoted_to: None } => pgn::find_move(
+                    &board,
+                    &Move::BasicMove {
+                        piece: *piece,
+                        to: to.clone(),
+                        from: from.clone(),
+                        is_capture: *is_capture,
+                        promoted_to: Some(LocalPiece::Queen),
+                    },
+                ),
+                _ => None,
+            })
+            .context("Move not found as possibility")?;
+        let (src, dest, _) = self.active_game.move_piece_uci(&bit_move.stringify())?;
+
+        let mut actual = pgn::marked_move(&board, bit_move);
+        let warning = if (actual.is_check, actual.is_checkmate) != (written.is_check, written.is_checkmate) {
+            actual.annotation_symbol = written.annotation_symbol.clone();
+            let warning = format!("{} should be written {}", pgn::san(written), pgn::san(&actual));
+            warn!("Invalid PGN move: {}", warning);
+            Some(warning)
+        } else {
+            None
+        };
+        Ok((src, dest, warning))
+    }
+
+    /// Goes back to where the current variation branched off (before its
+    /// first move)
+    pub fn leave_variation(&mut self) -> ReplayResponse {
+        if let Some(branch) = self.branches.pop() {
+            let moves = self.player_moves_played_offset + self.replay_moves_played_offset;
+            self.active_game.undo(moves as u16).ok();
+            self.player_moves_played_offset = 0;
+            self.replay_moves_played_offset = branch.index;
+            self.return_from_finished_variations();
+        }
+        self.position_response()
+    }
+
+    /// A variation without played moves is left at once since its start is
+    /// the position of the line it branches off
+    fn return_from_finished_variations(&mut self) {
+        while self.replay_moves_played_offset == 0 {
+            match self.branches.pop() {
+                Some(branch) => self.replay_moves_played_offset = branch.index,
+                None => break,
+            }
+        }
+    }
+
+    /// Moves replayed since the start of the game (along the variations
+    /// entered)
+    pub fn ply(&self) -> usize {
+        self.branches.iter().map(|branch| branch.index).sum::<usize>() + self.replay_moves_played_offset
+    }
+
+    /// The whole current line from the start of the game in SAN, numbered
+    /// like in PGN (e.g. "1. e4", "e5"). The moves before a variation are
+    /// the ones of the lines it branches off.
+    pub fn line_moves(&self) -> Vec<String> {
+        let mut moves: Vec<&GameMove> = vec![];
+        let mut line = &self.replay_info.moves[..];
+        for branch in &self.branches {
+            moves.extend(&line[..branch.index]);
+            line = &line[branch.index].variations[branch.variation].moves;
+        }
+        moves.extend(line);
+
+        // Plies before the replay started (e.g. when it starts from a FEN)
+        let played = self.active_game.board().moves_played() as usize;
+        let first_ply = played.saturating_sub(self.player_moves_played_offset + self.ply());
+        moves
+            .into_iter()
+            .enumerate()
+            .map(|(i, game_move)| {
+                let ply = first_ply + i;
+                let san = pgn::san(&game_move.move_);
+                if ply.is_multiple_of(2) {
+                    format!("{}. {}", ply / 2 + 1, san)
+                } else if i == 0 {
+                    format!("{}... {}", ply / 2 + 1, san)
+                } else {
+                    san
+                }
+            })
+            .collect()
+    }
+
+    /// Replays the current line up to `ply` moves (or as far as it goes)
+    /// without manual moves. Going back before a variation returns to the
+    /// line it branches off.
+    pub fn go_to_ply(&mut self, ply: usize) -> ReplayResponse {
+        if self.player_moves_played_offset > 0 {
+            self.active_game.undo(self.player_moves_played_offset as u16).ok();
+            self.player_moves_played_offset = 0;
+        }
+        // The variations to enter again when going forward
+        let path = self.branches.clone();
+        while self.ply() > ply {
+            self.undo_move();
+        }
+        let mut played = None;
+        while self.ply() < ply {
+            let depth = self.branches.len();
+            let alternative = match path.get(depth) {
+                Some(branch) if branch.index == self.replay_moves_played_offset => branch.variation + 1,
+                _ => 0,
+            };
+            if self.line().get(self.replay_moves_played_offset).is_none() {
+                break;
+            }
+            let before = self.ply();
+            let response = self.play_alternative(alternative);
+            if self.ply() == before {
+                // Not playable
+                return response;
+            }
+            played = Some(response);
+        }
+        played.unwrap_or_else(|| self.current_response())
+    }
+
+    pub fn go_to_start(&mut self) -> ReplayResponse {
+        self.go_to_ply(0)
+    }
+
+    /// Goes to the last move of the current line
+    pub fn go_to_end(&mut self) -> ReplayResponse {
+        self.go_to_ply(usize::MAX)
+    }
+
+    /// The position with the last move and its comment as if it was just
+    /// replayed
+    fn current_response(&self) -> ReplayResponse {
+        let last_played = match self.replay_moves_played_offset {
+            0 => None,
+            offset => Some(&self.line()[offset - 1]),
+        };
+        let last_move = self.active_game.board().last_move();
+        ReplayResponse {
+            fen: self.active_game.fen(),
+            comment: last_played.and_then(|played| played.comment.clone()),
+            last_move_from: last_move.map(|bit_move| bit_move.get_src().into()),
+            last_move_to: last_move.map(|bit_move| bit_move.get_dest().into()),
+            warning: None,
+            annotation_symbol: last_played.and_then(annotation_symbol_of),
+            nag: last_played.and_then(nag_of),
+            game_comment: self.game_comment(),
+        }
+    }
+
+    /// The current position without a replayed move
+    fn position_response(&self) -> ReplayResponse {
+        ReplayResponse {
+            fen: self.active_game.fen(),
+            comment: None,
+            last_move_from: None,
+            last_move_to: None,
+            warning: None,
+            annotation_symbol: None,
+            nag: None,
+            game_comment: self.game_comment(),
+        }
+    }
+
+    /// The comment before the first move while at the start of the game
+    fn game_comment(&self) -> Option<String> {
+        if self.ply() == 0 && self.player_moves_played_offset == 0 {
+            self.replay_info.comment.clone()
+        } else {
+            None
+        }
     }
 
     pub fn player_move(&mut self, source: Square, destination: Square) -> ReplayResponse {
-        match self.active_game.move_piece(source, destination) {
+        match self.active_game.move_piece(source, destination, None) {
             Ok(_) => {
                 self.player_moves_played_offset = self.player_moves_played_offset + 1;
             }
             Err(_) => {}
         }
-        ReplayResponse { fen: self.active_game.fen(), comment: None, last_move_from: Some(source), last_move_to: Some(destination) }
+        ReplayResponse {
+            last_move_from: Some(source),
+            last_move_to: Some(destination),
+            ..self.position_response()
+        }
     }
 
     pub fn undo_move(&mut self) -> ReplayResponse {
@@ -112,72 +370,47 @@ impl Replay {
         } else if self.replay_moves_played_offset > 0 {
             self.active_game.undo(1).ok();
             self.replay_moves_played_offset = self.replay_moves_played_offset - 1;
-            self.is_white_turn = !self.is_white_turn;
+            self.return_from_finished_variations();
         }
-        return ReplayResponse { fen: self.active_game.fen(), comment: None, last_move_from: None, last_move_to: None };
+        self.position_response()
     }
 
+    /// Back to the start position of the game
     pub fn reset(&mut self) -> ReplayResponse {
-        self.active_game = ChessGame::default();
+        // Could be read when the replay got created
+        self.active_game = start_position(&self.replay_info).unwrap_or_default();
+        self.branches.clear();
         self.replay_moves_played_offset = 0;
         self.player_moves_played_offset = 0;
-        self.is_white_turn = true;
-        return ReplayResponse { fen: self.active_game.fen(), comment: None, last_move_from: None, last_move_to: None };
+        self.position_response()
     }
 }
 
-fn to_pleco_piece(piece: &LocalPiece, is_white_turn: bool) -> Piece {
-    if is_white_turn {
-        match piece {
-            LocalPiece::Pawn => WhitePawn,
-            LocalPiece::Knight => WhiteKnight,
-            LocalPiece::Bishop => WhiteBishop,
-            LocalPiece::Rook => WhiteRook,
-            LocalPiece::Queen => WhiteQueen,
-            LocalPiece::King => WhiteKing
-        }
-    } else {
-        match piece {
-            LocalPiece::Pawn => BlackPawn,
-            LocalPiece::Knight => BlackKnight,
-            LocalPiece::Bishop => BlackBishop,
-            LocalPiece::Rook => BlackRook,
-            LocalPiece::Queen => BlackQueen,
-            LocalPiece::King => BlackKing
+/// The position in the FEN tag (unless the SetUp tag says it's not used) or
+/// the usual one
+fn start_position(replay_info: &Game) -> Result<ChessGame> {
+    let tag = |name: &str| {
+        replay_info.tags.iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.trim())
+    };
+    match (tag(pgn::FEN_TAG), tag(SET_UP_TAG)) {
+        (Some(fen), set_up) if set_up != Some("0") => {
+            ChessGame::from_fen(fen).with_context(|| format!("Invalid FEN tag \"{}\"", fen))
         }
+        _ => Ok(ChessGame::default()),
     }
 }
 
-fn to_pleco_rank(rank: Option<LocalRank>) -> Option<Rank> {
-    match rank {
-        Some(LocalRank::R1) => Some(Rank::R1),
-        Some(LocalRank::R2) => Some(Rank::R2),
-        Some(LocalRank::R3) => Some(Rank::R3),
-        Some(LocalRank::R4) => Some(Rank::R4),
-        Some(LocalRank::R5) => Some(Rank::R5),
-        Some(LocalRank::R6) => Some(Rank::R6),
-        Some(LocalRank::R7) => Some(Rank::R7),
-        Some(LocalRank::R8) => Some(Rank::R8),
-        _ => None
-    }
+fn annotation_symbol_of(game_move: &GameMove) -> Option<&'static str> {
+    game_move.move_.annotation_symbol.as_ref().map(pgn::annotation_symbol)
 }
 
-fn to_pleco_file(file: Option<LocalFile>) -> Option<File> {
-    if file.is_some() {
-        match file {
-            Some(LocalFile::A) => Some(File::A),
-            Some(LocalFile::B) => Some(File::B),
-            Some(LocalFile::C) => Some(File::C),
-            Some(LocalFile::D) => Some(File::D),
-            Some(LocalFile::E) => Some(File::E),
-            Some(LocalFile::F) => Some(File::F),
-            Some(LocalFile::G) => Some(File::G),
-            Some(LocalFile::H) => Some(File::H),
-            _ => None
-        }
-    } else {
-        None
-    }
+/// The NAG as text ("$" and its number if it's not a common one)
+fn nag_of(game_move: &GameMove) -> Option<String> {
+    game_move.nag.as_ref().map(|nag| {
+        pgn::nag_text(nag).map_or_else(|| format!("${}", nag.0), str::to_owned)
+    })
 }
 
 fn termination_string_from(term_info: GameTermination) -> &'static str {
@@ -188,3 +421,150 @@ fn termination_string_from(term_info: GameTermination) -> &'static str {
         GameTermination::Unknown => { " Game Over: Unknown" }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess_pgn_parser::read_games;
+
+    const AFTER_E4_E5: &str = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2";
+    const AFTER_E4: &str = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+    const AFTER_E4_C5: &str = "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2";
+
+    fn replay(pgn: &str) -> Replay {
+        Replay::new(read_games(pgn).unwrap().remove(0)).unwrap()
+    }
+
+    #[test]
+    fn enters_and_leaves_variations() {
+        let mut replay =
+            replay("1. e4 e5 ({Sicilian} 1... c5 2. Nf3 (2. Nc3) d6) 2. Nf3 *\n");
+        assert_eq!(replay.alternatives(), vec!["e4"]);
+        replay.play_replay_move();
+        assert_eq!(replay.alternatives(), vec!["e5", "c5"]);
+
+        let response = replay.play_alternative(1);
+        assert_eq!(response.fen, AFTER_E4_C5);
+        assert_eq!(response.comment, Some("Sicilian".to_owned()));
+        assert!(replay.in_variation());
+        assert_eq!(replay.alternatives(), vec!["Nf3", "Nc3"]);
+        replay.play_replay_move();
+        replay.play_replay_move();
+        assert!(replay.alternatives().is_empty());
+
+        let response = replay.leave_variation();
+        assert!(!replay.in_variation());
+        assert_eq!(replay.alternatives(), vec!["e5", "c5"]);
+        assert_eq!(response.fen, AFTER_E4);
+        assert_eq!(replay.play_replay_move().fen, AFTER_E4_E5);
+        assert_eq!(replay.alternatives(), vec!["Nf3"]);
+    }
+
+    #[test]
+    fn undo_returns_from_variations() {
+        let mut replay = replay("1. e4 e5 (1... c5 2. Nf3 (2. Nc3 Nc6) d6) 2. Nf3 *\n");
+        replay.play_replay_move();
+        replay.play_alternative(1);
+        replay.play_alternative(1);
+        assert_eq!(replay.alternatives(), vec!["Nc6"]);
+
+        // Back into the first variation and then to the main line
+        replay.undo_move();
+        assert_eq!(replay.alternatives(), vec!["Nf3", "Nc3"]);
+        assert_eq!(replay.undo_move().fen, AFTER_E4);
+        assert!(!replay.in_variation());
+        assert_eq!(replay.alternatives(), vec!["e5", "c5"]);
+        assert_eq!(replay.play_replay_move().fen, AFTER_E4_E5);
+    }
+
+    #[test]
+    fn jumps_to_plies() {
+        let mut replay = replay("1. e4 e5 2. Nf3 {Main} Nc6 3. Bb5 *\n");
+        assert_eq!(replay.line_moves(), vec!["1. e4", "e5", "2. Nf3", "Nc6", "3. Bb5"]);
+        let end = replay.go_to_end();
+        assert_eq!(replay.ply(), 5);
+        assert_eq!(end.comment, Some(" Game Over: Unknown".to_owned()));
+
+        let response = replay.go_to_ply(3);
+        assert_eq!(replay.ply(), 3);
+        assert_eq!(response.comment, Some("Main".to_owned()));
+        assert_eq!(response.last_move_from, Some("G1".parse().unwrap()));
+        assert_eq!(response.last_move_to, Some("F3".parse().unwrap()));
+
+        assert_eq!(replay.go_to_start().fen, ChessGame::default().fen());
+        assert_eq!(replay.go_to_ply(2).fen, AFTER_E4_E5);
+    }
+
+    #[test]
+    fn jumps_within_variations() {
+        let mut replay = replay("1. e4 e5 (1... c5 2. Nf3 d6) 2. Nf3 *\n");
+        replay.play_replay_move();
+        replay.play_alternative(1);
+        assert_eq!(replay.line_moves(), vec!["1. e4", "c5", "2. Nf3", "d6"]);
+        replay.go_to_end();
+        assert_eq!(replay.ply(), 4);
+        assert!(replay.in_variation());
+
+        assert_eq!(replay.go_to_ply(2).fen, AFTER_E4_C5);
+        assert!(replay.in_variation());
+        // Before the variation
+        assert_eq!(replay.go_to_ply(1).fen, AFTER_E4);
+        assert!(!replay.in_variation());
+        assert_eq!(replay.line_moves(), vec!["1. e4", "e5", "2. Nf3"]);
+    }
+
+    #[test]
+    fn starts_from_the_fen_tag() {
+        let mut replay = replay(&format!(
+            "[SetUp \"1\"]\n[FEN \"{}\"]\n\n1... e5 2. Nf3 *\n",
+            AFTER_E4
+        ));
+        assert_eq!(replay.fen(), AFTER_E4);
+        assert_eq!(replay.line_moves(), vec!["1... e5", "2. Nf3"]);
+        replay.play_replay_move();
+        assert_eq!(replay.play_replay_move().last_move_to, Some("F3".parse().unwrap()));
+        assert_eq!(replay.reset().fen, AFTER_E4);
+        assert_eq!(replay.play_replay_move().fen, AFTER_E4_E5);
+    }
+
+    #[test]
+    fn invalid_fen_tag_is_an_error() {
+        let game = read_games("[FEN \"not a position\"]\n\n1. e4 *\n").unwrap().remove(0);
+        assert!(Replay::new(game).is_err());
+    }
+
+    #[test]
+    fn promotes_to_the_written_piece() {
+        let mut replay = replay(
+            "[SetUp \"1\"]\n[FEN \"8/4P1k1/8/8/8/8/8/4K3 w - - 0 1\"]\n\n1. e8=N+ *\n",
+        );
+        let response = replay.play_replay_move();
+        assert_eq!(response.fen, "4N3/6k1/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(response.warning, None);
+    }
+
+    #[test]
+    fn wrong_check_marks_are_warned_about() {
+        let mut replay = replay("1. e4+ e5 2. Qh5 *\n");
+        assert_eq!(replay.play_replay_move().warning, Some("e4+ should be written e4".to_owned()));
+        assert_eq!(replay.play_replay_move().warning, None);
+        assert_eq!(replay.play_replay_move().warning, None);
+    }
+
+    #[test]
+    fn gives_annotations_and_the_game_comment() {
+        let mut replay = replay("{Open game} 1. e4! $14 e5 $200 2. Nf3 *\n");
+        assert_eq!(replay.go_to_start().game_comment, Some("Open game".to_owned()));
+
+        let response = replay.play_replay_move();
+        assert_eq!(response.annotation_symbol, Some("!"));
+        assert_eq!(response.nag, Some("White is slightly better".to_owned()));
+        assert_eq!(response.game_comment, None);
+        let response = replay.play_replay_move();
+        assert_eq!(response.annotation_symbol, None);
+        assert_eq!(response.nag, Some("$200".to_owned()));
+
+        assert_eq!(replay.go_to_ply(1).nag, Some("White is slightly better".to_owned()));
+        assert_eq!(replay.undo_move().game_comment, Some("Open game".to_owned()));
+    }
+}
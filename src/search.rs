@@ -0,0 +1,520 @@
+//! Searching with a deadline on top of pleco's depth based searchers
+
+use anyhow::{Context, Result};
+use pleco::bots::eval_board;
+use pleco::bots::{
+    AlphaBetaSearcher, IterativeSearcher, JamboreeSearcher, MiniMaxSearcher,
+    ParallelMiniMaxSearcher,
+};
+use pleco::core::score::{DRAW, INFINITE, MATE};
+use pleco::tools::Searcher;
+use pleco::{BitMove, Board, ScoringMove};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Assumed moves until the next time control if nobody tells
+pub const DEFAULT_MOVES_TO_GO: u64 = 30;
+/// How much longer the next depth is expected to take than the previous one
+pub const DEPTH_GROWTH_ESTIMATE: u32 = 5;
+
+const INFINITE_V: i16 = INFINITE as i16;
+
+/// Which of pleco's searchers to use where it can't be a type parameter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SearchAlgorithm {
+    /// Single threaded without pruning (only usable for low depths)
+    Minimax,
+    /// Multithreaded without pruning
+    ParallelMinimax,
+    /// Single threaded
+    #[default]
+    AlphaBeta,
+    /// Multithreaded
+    Jamboree,
+    /// Multithreaded with ordered moves and its own iterative deepening
+    Iterative,
+}
+
+impl SearchAlgorithm {
+    pub const ALL: [SearchAlgorithm; 5] = [
+        SearchAlgorithm::Minimax,
+        SearchAlgorithm::ParallelMinimax,
+        SearchAlgorithm::AlphaBeta,
+        SearchAlgorithm::Jamboree,
+        SearchAlgorithm::Iterative,
+    ];
+
+    pub fn best_move(self, board: Board, depth: u16) -> BitMove {
+        match self {
+            SearchAlgorithm::Minimax => remembered_best_move::<MiniMaxSearcher>(board, depth),
+            SearchAlgorithm::ParallelMinimax => {
+                remembered_best_move::<ParallelMiniMaxSearcher>(board, depth)
+            }
+            SearchAlgorithm::AlphaBeta => remembered_best_move::<AlphaBetaSearcher>(board, depth),
+            SearchAlgorithm::Jamboree => remembered_best_move::<JamboreeSearcher>(board, depth),
+            SearchAlgorithm::Iterative => remembered_best_move::<IterativeSearcher>(board, depth),
+        }
+    }
+
+    /// The one of pleco's searcher `T`
+    pub fn of<T: Searcher>() -> Option<SearchAlgorithm> {
+        SearchAlgorithm::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.searcher_name() == T::name())
+    }
+
+    fn searcher_name(self) -> &'static str {
+        match self {
+            SearchAlgorithm::Minimax => MiniMaxSearcher::name(),
+            SearchAlgorithm::ParallelMinimax => ParallelMiniMaxSearcher::name(),
+            SearchAlgorithm::AlphaBeta => AlphaBetaSearcher::name(),
+            SearchAlgorithm::Jamboree => JamboreeSearcher::name(),
+            SearchAlgorithm::Iterative => IterativeSearcher::name(),
+        }
+    }
+
+    /// Whether it uses the threads given to `configure()`
+    pub fn is_multithreaded(self) -> bool {
+        match self {
+            SearchAlgorithm::Minimax | SearchAlgorithm::AlphaBeta => false,
+            SearchAlgorithm::ParallelMinimax
+            | SearchAlgorithm::Jamboree
+            | SearchAlgorithm::Iterative => true,
+        }
+    }
+}
+
+impl std::str::FromStr for SearchAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        SearchAlgorithm::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Search algorithm is none of {}",
+                    SearchAlgorithm::ALL
+                        .iter()
+                        .map(|algorithm| format!("\"{}\"", algorithm))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+impl std::fmt::Display for SearchAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Best moves of searched positions. pleco's searchers forget everything
+/// between calls, so this spares searching a position again (e.g. for a
+/// hint after the analysis or after an undo).
+pub struct TranspositionTable {
+    entries: Mutex<Vec<TableEntry>>,
+}
+
+#[derive(Clone, Copy)]
+struct TableEntry {
+    key: u64,
+    /// Zero for empty entries
+    depth: u16,
+    best_move: BitMove,
+}
+
+impl TranspositionTable {
+    /// A table using about `megabytes` of memory (none stores nothing)
+    pub fn new(megabytes: usize) -> Self {
+        let len = megabytes * 1024 * 1024 / std::mem::size_of::<TableEntry>();
+        Self {
+            entries: Mutex::new(vec![
+                TableEntry {
+                    key: 0,
+                    depth: 0,
+                    best_move: BitMove::null(),
+                };
+                len
+            ]),
+        }
+    }
+
+    /// Number of positions it can hold
+    pub fn capacity(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// The move of a search at least `depth` deep
+    pub fn probe(&self, key: u64, depth: u16) -> Option<BitMove> {
+        let entries = self.entries.lock().unwrap();
+        if entries.is_empty() {
+            return None;
+        }
+        let entry = entries[(key % entries.len() as u64) as usize];
+        if entry.depth >= depth.max(1) && entry.key == key {
+            Some(entry.best_move)
+        } else {
+            None
+        }
+    }
+
+    /// Keeps the deeper search of the same position and otherwise the newer
+    /// position
+    pub fn store(&self, key: u64, depth: u16, best_move: BitMove) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.is_empty() || depth == 0 {
+            return;
+        }
+        let index = (key % entries.len() as u64) as usize;
+        let entry = &mut entries[index];
+        if entry.key != key || entry.depth < depth {
+            *entry = TableEntry {
+                key,
+                depth,
+                best_move,
+            };
+        }
+    }
+}
+
+static TABLE: OnceLock<TranspositionTable> = OnceLock::new();
+
+/// Sets the threads of the multithreaded searchers and the size of the
+/// transposition table (in megabytes) for the rest of the process. Can only
+/// be done once and before the first search.
+pub fn configure(threads: usize, table_size: usize) -> Result<()> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.max(1))
+        .thread_name(|index| format!("search_{}", index))
+        .build_global()
+        .context("Create search threads")?;
+    TABLE
+        .set(TranspositionTable::new(table_size))
+        .map_err(|_| anyhow!("Transposition table was already created"))
+}
+
+/// `T::best_move()` looked up in and remembered by the table of
+/// `configure()` (if there is one)
+pub fn remembered_best_move<T: Searcher>(board: Board, depth: u16) -> BitMove {
+    let table = match TABLE.get() {
+        Some(table) => table,
+        None => return T::best_move(board, depth),
+    };
+    // The searchers may find different moves
+    let key = board.zobrist() ^ fxhash::hash64(T::name());
+    if let Some(best_move) = table.probe(key, depth) {
+        return best_move;
+    }
+    let best_move = T::best_move(board, depth);
+    table.store(key, depth, best_move);
+    best_move
+}
+
+/// Thinking time for one move when playing with a clock (all in milliseconds)
+pub fn clock_budget(time: u64, increment: u64, moves_to_go: Option<u64>) -> Duration {
+    let moves_to_go = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+    // Never plan to use more than half of the remaining time
+    Duration::from_millis((time / moves_to_go + increment / 2).min(time / 2))
+}
+
+/// Search one depth after another until the end of `depths` or until the
+/// next depth would likely miss the deadline. `on_depth` gets every result
+/// (with the time since the start) and stops the search by returning false.
+/// Returns the move of the deepest finished search.
+pub fn deepen(
+    board: &Board,
+    depths: RangeInclusive<u16>,
+    deadline: Option<Instant>,
+    search: impl Fn(Board, u16) -> BitMove,
+    mut on_depth: impl FnMut(u16, BitMove, Duration) -> bool,
+) -> Option<BitMove> {
+    let started = Instant::now();
+    let mut best_move = None;
+    for depth in depths {
+        let depth_started = Instant::now();
+        let found = search(board.shallow_clone(), depth);
+        best_move = Some(found);
+        if !on_depth(depth, found, started.elapsed()) {
+            break;
+        }
+        if let Some(deadline) = deadline {
+            if Instant::now() + depth_started.elapsed() * DEPTH_GROWTH_ESTIMATE > deadline {
+                break;
+            }
+        }
+    }
+    best_move
+}
+
+/// `first_move` followed by the expected replies, each searched one ply
+/// less deep than the one before. At most `length` moves.
+pub fn principal_variation(
+    board: &Board,
+    first_move: BitMove,
+    depth: u16,
+    length: usize,
+    search: impl Fn(Board, u16) -> BitMove,
+) -> Vec<BitMove> {
+    let mut board = board.shallow_clone();
+    let mut line = vec![first_move];
+    board.apply_move(first_move);
+    while line.len() < length && !board.generate_moves().is_empty() {
+        let depth = depth.saturating_sub(line.len() as u16).max(1);
+        let reply = search(board.shallow_clone(), depth);
+        if reply.is_null() {
+            break;
+        }
+        board.apply_move(reply);
+        line.push(reply);
+    }
+    line
+}
+
+/// Alpha-beta search like pleco's `AlphaBetaSearcher` that gives up as soon
+/// as `cancelled` is set (`None` then). With `parallel`, the moves after the
+/// first one are searched on the threads of `configure()`, similar to
+/// `JamboreeSearcher`.
+pub fn cancellable_best_move(
+    board: Board,
+    depth: u16,
+    parallel: bool,
+    cancelled: &AtomicBool,
+) -> Option<BitMove> {
+    cancellable_search(board, depth, parallel, cancelled).map(|best| best.bit_move)
+}
+
+/// `cancellable_best_move()` with its score for the side to move
+pub fn cancellable_search(
+    board: Board,
+    depth: u16,
+    parallel: bool,
+    cancelled: &AtomicBool,
+) -> Option<ScoringMove> {
+    counting_search(board, depth, parallel, cancelled, &AtomicU64::new(0))
+}
+
+/// `cancellable_search()` that adds the positions it visits to `nodes`
+pub fn counting_search(
+    board: Board,
+    depth: u16,
+    parallel: bool,
+    cancelled: &AtomicBool,
+    nodes: &AtomicU64,
+) -> Option<ScoringMove> {
+    let mut board = board;
+    let moves = board.generate_moves();
+    let (first, rest) = match moves.split_first() {
+        Some(split) => split,
+        None => {
+            return cancellable_alpha_beta(&mut board, -INFINITE_V, INFINITE_V, 1, cancelled, nodes)
+        }
+    };
+    let score_move = |board: &mut Board, bit_move: BitMove, alpha: i16| {
+        board.apply_move(bit_move);
+        let reply = cancellable_alpha_beta(
+            board,
+            -INFINITE_V,
+            -alpha,
+            depth.max(1) - 1,
+            cancelled,
+            nodes,
+        );
+        board.undo_move();
+        reply.map(|reply| ScoringMove {
+            bit_move,
+            score: -reply.score,
+        })
+    };
+
+    let mut best = score_move(&mut board, *first, -INFINITE_V)?;
+    if parallel {
+        let alpha = best.score;
+        let scored = rest
+            .par_iter()
+            .map(|bit_move| score_move(&mut board.parallel_clone(), *bit_move, alpha))
+            .collect::<Option<Vec<_>>>()?;
+        for scored in scored {
+            if scored.score > best.score {
+                best = scored;
+            }
+        }
+    } else {
+        for bit_move in rest {
+            let scored = score_move(&mut board, *bit_move, best.score)?;
+            if scored.score > best.score {
+                best = scored;
+            }
+        }
+    }
+    Some(best)
+}
+
+fn cancellable_alpha_beta(
+    board: &mut Board,
+    mut alpha: i16,
+    beta: i16,
+    depth: u16,
+    cancelled: &AtomicBool,
+    nodes: &AtomicU64,
+) -> Option<ScoringMove> {
+    if cancelled.load(Ordering::Relaxed) {
+        return None;
+    }
+    nodes.fetch_add(1, Ordering::Relaxed);
+    if depth == 0 {
+        return Some(eval_board(board));
+    }
+    let moves = board.generate_moves();
+    if moves.is_empty() {
+        let score = if board.in_check() { -MATE } else { DRAW };
+        return Some(ScoringMove::blank(score as i16));
+    }
+
+    let mut best_move = ScoringMove::blank(alpha);
+    for bit_move in moves.iter() {
+        board.apply_move(*bit_move);
+        let reply = cancellable_alpha_beta(board, -beta, -alpha, depth - 1, cancelled, nodes);
+        board.undo_move();
+        let score = -reply?.score;
+        if score > alpha {
+            alpha = score;
+            best_move = ScoringMove {
+                bit_move: *bit_move,
+                score,
+            };
+            if alpha >= beta {
+                break;
+            }
+        }
+    }
+    Some(best_move)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algorithms_parse_from_their_names() {
+        for algorithm in SearchAlgorithm::ALL.iter() {
+            assert_eq!(
+                algorithm.to_string().parse::<SearchAlgorithm>().unwrap(),
+                *algorithm
+            );
+        }
+        assert_eq!(
+            "jamboree".parse::<SearchAlgorithm>().unwrap(),
+            SearchAlgorithm::Jamboree
+        );
+        assert!("Stockfish".parse::<SearchAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn algorithms_are_found_by_their_searcher() {
+        assert_eq!(
+            SearchAlgorithm::of::<JamboreeSearcher>(),
+            Some(SearchAlgorithm::Jamboree)
+        );
+        assert_eq!(
+            SearchAlgorithm::of::<MiniMaxSearcher>(),
+            Some(SearchAlgorithm::Minimax)
+        );
+        assert_eq!(SearchAlgorithm::of::<pleco::bots::RandomBot>(), None);
+    }
+
+    #[test]
+    fn table_keeps_deeper_searches() {
+        let board = Board::start_pos();
+        let moves = board.generate_moves();
+        let table = TranspositionTable::new(1);
+        assert!(table.capacity() > 0);
+
+        table.store(42, 3, moves[0]);
+        assert_eq!(table.probe(42, 2), Some(moves[0]));
+        assert_eq!(table.probe(42, 3), Some(moves[0]));
+        assert_eq!(table.probe(42, 4), None);
+
+        table.store(42, 2, moves[1]);
+        assert_eq!(table.probe(42, 3), Some(moves[0]));
+        table.store(42, 4, moves[1]);
+        assert_eq!(table.probe(42, 4), Some(moves[1]));
+
+        // Same slot, other position
+        let other = 42 + table.capacity() as u64;
+        assert_eq!(table.probe(other, 1), None);
+        table.store(other, 1, moves[2]);
+        assert_eq!(table.probe(other, 1), Some(moves[2]));
+        assert_eq!(table.probe(42, 1), None);
+    }
+
+    #[test]
+    fn table_without_memory_stores_nothing() {
+        let table = TranspositionTable::new(0);
+        table.store(42, 3, Board::start_pos().generate_moves()[0]);
+        assert_eq!(table.probe(42, 1), None);
+    }
+
+    #[test]
+    fn cancellable_search_plays_like_alpha_beta() {
+        let board =
+            Board::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4")
+                .unwrap();
+        let cancelled = AtomicBool::new(false);
+        for depth in 1..=3 {
+            assert_eq!(
+                cancellable_best_move(board.shallow_clone(), depth, false, &cancelled),
+                Some(AlphaBetaSearcher::best_move(board.shallow_clone(), depth))
+            );
+        }
+        let mate = cancellable_best_move(board.shallow_clone(), 3, true, &cancelled);
+        assert_eq!(mate.unwrap().stringify(), "h5f7");
+    }
+
+    #[test]
+    fn parallel_search_plays_like_sequential() {
+        let cancelled = AtomicBool::new(false);
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R b KQkq - 3 3",
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            for depth in 1..=3 {
+                assert_eq!(
+                    cancellable_best_move(board.shallow_clone(), depth, true, &cancelled),
+                    cancellable_best_move(board.shallow_clone(), depth, false, &cancelled),
+                    "{} at depth {}",
+                    fen,
+                    depth
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cancelled_search_stops_within_the_depth() {
+        let cancelled = AtomicBool::new(false);
+        let started = Instant::now();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(100));
+                cancelled.store(true, Ordering::Relaxed);
+            });
+            for parallel in [false, true] {
+                assert_eq!(
+                    cancellable_best_move(Board::start_pos(), 30, parallel, &cancelled),
+                    None
+                );
+            }
+        });
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,284 @@
+//! Bots that play like humans of a certain strength: They pick among the
+//! better moves instead of always the best, overlook deeper tactics now and
+//! then and like developing their pieces in the opening.
+
+use crate::proto::{spawn_bot, ChessRequest, ChessUpdate};
+use crate::Player;
+use anyhow::Result;
+use pleco::bots::alphabeta::alpha_beta_search;
+use pleco::core::score::INFINITE;
+use pleco::tools::prng::PRNG;
+use pleco::{BitMove, Board, File, PieceType, Rank};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+/// Plies in which developing moves get a bonus
+const OPENING_PLIES: u16 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Strength {
+    Beginner,
+    Casual,
+    Club,
+    Advanced,
+}
+
+impl Strength {
+    /// Rough Elo rating the level plays at
+    pub fn rating(&self) -> u16 {
+        match self {
+            Strength::Beginner => 600,
+            Strength::Casual => 1000,
+            Strength::Club => 1400,
+            Strength::Advanced => 1800,
+        }
+    }
+
+    pub fn settings(&self) -> StrengthSettings {
+        match self {
+            Strength::Beginner => StrengthSettings {
+                depth: 1,
+                top_moves: 6,
+                temperature: 150,
+                oversight_percent: 30,
+                development_bonus: 40,
+            },
+            Strength::Casual => StrengthSettings {
+                depth: 2,
+                top_moves: 5,
+                temperature: 80,
+                oversight_percent: 20,
+                development_bonus: 30,
+            },
+            Strength::Club => StrengthSettings {
+                depth: 3,
+                top_moves: 3,
+                temperature: 40,
+                oversight_percent: 10,
+                development_bonus: 20,
+            },
+            Strength::Advanced => StrengthSettings {
+                depth: 4,
+                top_moves: 2,
+                temperature: 15,
+                oversight_percent: 5,
+                development_bonus: 10,
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for Strength {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "beginner" => Ok(Strength::Beginner),
+            "casual" => Ok(Strength::Casual),
+            "club" => Ok(Strength::Club),
+            "advanced" => Ok(Strength::Advanced),
+            _ => Err(anyhow!(
+                "Strength is neither \"Beginner\", \"Casual\", \"Club\" nor \"Advanced\""
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Strength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} (~{})", self, self.rating())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StrengthSettings {
+    /// Search depth when seeing everything
+    pub depth: u16,
+    /// How many of the best moves are considered at all
+    pub top_moves: usize,
+    /// Centipawns a move may be worse than the best one to still be
+    /// chosen about 1/e times as often (higher is more random)
+    pub temperature: u16,
+    /// Chance to only look one ply ahead and miss deeper tactics
+    pub oversight_percent: u8,
+    /// Centipawns added to natural opening moves (developing knights and
+    /// bishops, central pawns and castling)
+    pub development_bonus: i16,
+}
+
+/// Picks moves according to `StrengthSettings`. The same seed always
+/// results in the same moves for the same positions.
+pub struct HumanMoves {
+    settings: StrengthSettings,
+    rng: PRNG,
+}
+
+impl HumanMoves {
+    pub fn new(settings: StrengthSettings, seed: u64) -> Self {
+        Self {
+            settings,
+            // The generator doesn't work with zero
+            rng: PRNG::init(seed.max(1)),
+        }
+    }
+
+    /// Number in 0..1
+    fn random(&mut self) -> f64 {
+        (self.rng.rand() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn choose(&mut self, board: &Board) -> BitMove {
+        let overlooking = self.random() * 100.0 < self.settings.oversight_percent as f64;
+        let depth = if overlooking {
+            1
+        } else {
+            self.settings.depth.max(1)
+        };
+
+        let mut board = board.shallow_clone();
+        let in_opening = board.moves_played() < OPENING_PLIES;
+        let mut scored: Vec<(BitMove, i32)> = board
+            .generate_moves()
+            .iter()
+            .map(|bit_move| {
+                let bonus = if in_opening {
+                    development_bonus(&board, *bit_move, self.settings.development_bonus)
+                } else {
+                    0
+                };
+                board.apply_move(*bit_move);
+                let score =
+                    -(alpha_beta_search(&mut board, -INFINITE as i16, INFINITE as i16, depth - 1)
+                        .score as i32);
+                board.undo_move();
+                (*bit_move, score + bonus as i32)
+            })
+            .collect();
+        if scored.is_empty() {
+            return BitMove::null();
+        }
+        // Stable, so equal moves keep the generator's order
+        scored.sort_by_key(|(_, score)| -score);
+        scored.truncate(self.settings.top_moves.max(1));
+
+        let best_score = scored[0].1;
+        let temperature = self.settings.temperature.max(1) as f64;
+        let weights: Vec<f64> = scored
+            .iter()
+            .map(|(_, score)| (-((best_score - score) as f64) / temperature).exp())
+            .collect();
+        let mut pick = self.random() * weights.iter().sum::<f64>();
+        for ((bit_move, score), weight) in scored.iter().zip(&weights) {
+            if pick < *weight {
+                debug!(
+                    "Human bot plays {} ({} of best {}, overlooking: {})",
+                    bit_move, score, best_score, overlooking
+                );
+                return *bit_move;
+            }
+            pick -= weight;
+        }
+        scored[0].0
+    }
+}
+
+/// Bonus for moves that a human would consider natural in the opening
+fn development_bonus(board: &Board, bit_move: BitMove, bonus: i16) -> i16 {
+    let me = board.turn();
+    let source = bit_move.get_src();
+    let from_home = me.relative_rank_of_sq(source) == Rank::R1;
+    match board.moved_piece(bit_move).type_of() {
+        _ if bit_move.is_castle() => bonus,
+        PieceType::N | PieceType::B if from_home => bonus,
+        PieceType::P if source.file() == File::D || source.file() == File::E => bonus / 2,
+        // Taught to beginners: Don't bring the queen out early
+        PieceType::Q => -bonus / 2,
+        PieceType::K => -bonus,
+        _ => 0,
+    }
+}
+
+/// Like `proto::create_bot()` but playing like a human of the given strength
+pub async fn create_human_bot(
+    me: Player,
+    settings: StrengthSettings,
+    seed: u64,
+    min_reaction_delay: Duration,
+) -> Result<(Sender<ChessUpdate>, Receiver<ChessRequest>)> {
+    let mut moves = HumanMoves::new(settings, seed);
+    Ok(spawn_bot(me, min_reaction_delay, move |history, _still_wanted, _report| {
+        moves.choose(&history.board())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::ChessGame;
+
+    const SCHOLARS_MATE_FEN: &str =
+        "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+
+    fn strict(depth: u16) -> StrengthSettings {
+        StrengthSettings {
+            depth,
+            top_moves: 1,
+            temperature: 1,
+            oversight_percent: 0,
+            development_bonus: 0,
+        }
+    }
+
+    /// Let the bot play against itself
+    fn play(moves: &mut HumanMoves, plies: usize) -> Vec<String> {
+        let mut game = ChessGame::default();
+        (0..plies)
+            .map(|_| {
+                let bit_move = moves.choose(&game.board());
+                game.move_piece_uci(&bit_move.stringify()).unwrap();
+                bit_move.stringify()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_plays_the_same_moves() {
+        let settings = Strength::Beginner.settings();
+        let first = play(&mut HumanMoves::new(settings, 42), 12);
+        assert_eq!(play(&mut HumanMoves::new(settings, 42), 12), first);
+        // Not guaranteed for any seed, but for this one
+        assert_ne!(play(&mut HumanMoves::new(settings, 7), 12), first);
+    }
+
+    #[test]
+    fn without_imperfection_it_finds_mate() {
+        let board = Board::from_fen(SCHOLARS_MATE_FEN).unwrap();
+        let mut moves = HumanMoves::new(strict(2), 1);
+        assert_eq!(moves.choose(&board).stringify(), "h5f7");
+    }
+
+    #[test]
+    fn prefers_developing_moves() {
+        let mut settings = strict(1);
+        settings.development_bonus = 100;
+        let mut moves = HumanMoves::new(settings, 1);
+        let board = Board::start_pos();
+        let bit_move = moves.choose(&board);
+        let piece = board.moved_piece(bit_move).type_of();
+        assert!(
+            piece == PieceType::N || piece == PieceType::P,
+            "Played {}",
+            bit_move
+        );
+        assert!(development_bonus(&board, bit_move, 100) > 0);
+    }
+
+    #[test]
+    fn levels_are_labeled() {
+        assert_eq!(Strength::Casual.to_string(), "Casual (~1000)");
+        assert!(Strength::Beginner.rating() < Strength::Advanced.rating());
+        assert_eq!("club".parse::<Strength>().unwrap(), Strength::Club);
+    }
+}
@@ -0,0 +1,237 @@
+//! [Syzygy endgame tablebases](https://syzygy-tables.info/) for perfect play
+//! when only a few pieces are left.
+
+use crate::game::{shakmaty_position, BitMove, Board};
+use crate::Player;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use shakmaty::{CastlingMode, Chess};
+use shakmaty_syzygy::{SyzygyError, Wdl as SyzygyWdl};
+use std::fmt;
+use std::path::Path;
+
+/// Result with perfect play from the view of the player to move
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Wdl {
+    Win,
+    /// Would be a win but the fifty-move rule draws it
+    CursedWin,
+    Draw,
+    /// Would be a loss but the fifty-move rule saves it
+    BlessedLoss,
+    Loss,
+}
+
+impl From<SyzygyWdl> for Wdl {
+    fn from(wdl: SyzygyWdl) -> Self {
+        match wdl {
+            SyzygyWdl::Win => Wdl::Win,
+            SyzygyWdl::CursedWin => Wdl::CursedWin,
+            SyzygyWdl::Draw => Wdl::Draw,
+            SyzygyWdl::BlessedLoss => Wdl::BlessedLoss,
+            SyzygyWdl::Loss => Wdl::Loss,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TablebaseVerdict {
+    /// Player to move
+    pub player: Player,
+    pub wdl: Wdl,
+    /// Plies until the next capture or pawn move with perfect play
+    /// (`None` if the tables only know it roughly)
+    pub dtz: Option<i32>,
+}
+
+impl TablebaseVerdict {
+    pub fn winner(&self) -> Option<Player> {
+        match self.wdl {
+            Wdl::Win => Some(self.player),
+            Wdl::Loss => Some(self.player.other_player()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TablebaseVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.winner() {
+            Some(winner) => write!(f, "{} wins", winner)?,
+            None if self.wdl == Wdl::Draw => write!(f, "Draw")?,
+            None => write!(f, "Draw by the fifty-move rule")?,
+        }
+        match self.dtz {
+            Some(dtz) if dtz != 0 => write!(f, " (DTZ {})", dtz.abs()),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct Tablebase {
+    tables: shakmaty_syzygy::Tablebase<Chess>,
+}
+
+impl Tablebase {
+    /// Fails if the directory contains no tables
+    pub fn open(directory: &Path) -> Result<Self> {
+        let mut tables = shakmaty_syzygy::Tablebase::new();
+        let count = tables
+            .add_directory(directory)
+            .with_context(|| format!("Read Syzygy tables in {:?}", directory))?;
+        ensure!(count > 0, "No Syzygy tables in {:?}", directory);
+        info!(
+            "Opened {} Syzygy tables for up to {} pieces",
+            count,
+            tables.max_pieces()
+        );
+        Ok(Self { tables })
+    }
+
+    /// Most pieces of a position the tables might know
+    pub fn max_pieces(&self) -> usize {
+        self.tables.max_pieces()
+    }
+
+    /// Verdict for the position. `None` if the tables don't cover it.
+    pub fn verdict(&self, board: &Board) -> Option<TablebaseVerdict> {
+        let position = self.covered_position(board)?;
+        let probed = self
+            .tables
+            .probe_wdl(&position)
+            .map(|wdl| wdl.after_zeroing())
+            .and_then(|wdl| Ok((wdl, self.tables.probe_dtz(&position)?)));
+        match probed {
+            Ok((wdl, dtz)) => Some(TablebaseVerdict {
+                player: board.turn().into(),
+                wdl: wdl.into(),
+                dtz: dtz.precise().map(|dtz| dtz.0),
+            }),
+            Err(e) => not_covered(e),
+        }
+    }
+
+    /// The move with perfect play. `None` if the tables don't cover
+    /// the position.
+    pub fn best_move(&self, board: &Board) -> Option<BitMove> {
+        let position = self.covered_position(board)?;
+        let (best_move, _dtz) = match self.tables.best_move(&position) {
+            Ok(best) => best?,
+            Err(e) => return not_covered(e),
+        };
+        let uci_move = best_move.to_uci(CastlingMode::Standard).to_string();
+        board
+            .generate_moves()
+            .iter()
+            .find(|bit_move| bit_move.stringify() == uci_move)
+            .copied()
+    }
+
+    fn covered_position(&self, board: &Board) -> Option<Chess> {
+        if board.count_all_pieces() as usize > self.max_pieces() {
+            return None;
+        }
+        match shakmaty_position(board) {
+            Ok(position) => Some(position),
+            Err(e) => {
+                warn!("Can't probe tablebase: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+fn not_covered<T>(error: SyzygyError) -> Option<T> {
+    match error {
+        // Just not part of the tables
+        SyzygyError::Castling | SyzygyError::TooManyPieces | SyzygyError::MissingTable { .. } => {
+            debug!("Not in tablebase: {}", error)
+        }
+        _ => warn!("Tablebase probe failed: {}", error),
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{ChessGame, ChessOutcome};
+    use crate::test_harness::syzygy_tables;
+    use shakmaty::Position;
+
+    fn tables() -> Tablebase {
+        Tablebase::open(&syzygy_tables()).unwrap()
+    }
+
+    fn verdict(tables: &Tablebase, fen: &str) -> Option<TablebaseVerdict> {
+        tables.verdict(&Board::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    #[ignore = "needs the Syzygy tables of testdata/syzygy/download.sh"]
+    fn verdicts_of_small_endgames() {
+        let tables = tables();
+        let queen = verdict(&tables, "4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        assert_eq!(queen.wdl, Wdl::Win);
+        assert_eq!(queen.winner(), Some(Player::White));
+        let rook = verdict(&tables, "4k3/8/8/8/8/8/8/R3K3 b - - 0 1").unwrap();
+        assert_eq!(rook.wdl, Wdl::Loss);
+        assert_eq!(rook.winner(), Some(Player::White));
+        let bishop = verdict(&tables, "4k3/8/8/8/8/8/8/3BK3 w - - 0 1").unwrap();
+        assert_eq!(bishop.wdl, Wdl::Draw);
+        assert_eq!(bishop.to_string(), "Draw");
+        // Too many pieces
+        assert_eq!(verdict(&tables, &ChessGame::default().fen()), None);
+    }
+
+    #[test]
+    #[ignore = "needs the Syzygy tables of testdata/syzygy/download.sh"]
+    fn perfect_play_mates() {
+        let tables = tables();
+        let mut game = ChessGame::from_fen("8/8/8/3k4/8/8/8/3QK3 w - - 0 1").unwrap();
+        for _ in 0..40 {
+            if game.outcome().is_some() {
+                break;
+            }
+            let bit_move = tables.best_move(&game.board()).unwrap();
+            game.move_piece_uci(&bit_move.stringify()).unwrap();
+        }
+        assert_eq!(
+            game.outcome(),
+            Some(ChessOutcome::Checkmate {
+                winner: Player::White
+            })
+        );
+    }
+
+    #[test]
+    fn en_passant_square_without_capture() {
+        let board = Board::from_fen("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").unwrap();
+        let position = shakmaty_position(&board).unwrap();
+        assert_eq!(position.ep_square(shakmaty::EnPassantMode::Legal), None);
+    }
+
+    #[test]
+    fn directory_without_tables_is_an_error() {
+        let directory = tempfile::tempdir().unwrap();
+        assert!(Tablebase::open(directory.path()).is_err());
+        assert!(Tablebase::open(&directory.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn verdicts_are_readable() {
+        let verdict = TablebaseVerdict {
+            player: Player::Black,
+            wdl: Wdl::Loss,
+            dtz: Some(-13),
+        };
+        assert_eq!(verdict.to_string(), "White wins (DTZ 13)");
+        let cursed = TablebaseVerdict {
+            wdl: Wdl::CursedWin,
+            dtz: None,
+            ..verdict
+        };
+        assert_eq!(cursed.winner(), None);
+        assert_eq!(cursed.to_string(), "Draw by the fifty-move rule");
+    }
+}
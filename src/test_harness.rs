@@ -0,0 +1,366 @@
+//! Scripted players to test `proto::create_game` and `proto::create_bot`.
+//!
+//! A `ScriptedPlayer` is one end of the channels a game gets for a player
+//! (or the spectators). It can send requests and assert on the updates it
+//! receives. Every wait is limited by a timeout so a broken game loop
+//! fails the test instead of hanging it.
+//!
+//! A `FakeEngine` stands in for the engine process of `uci::create_uci_bot`
+//! and `cecp::create_cecp_bot`.
+
+use crate::game::ChessGame;
+use crate::proto::{create_game, ChessConfig, ChessRequest, ChessUpdate, SearchLimits};
+use crate::tablebase::Tablebase;
+use crate::Player;
+use anyhow::Result;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `expect_silence()` listens for unexpected updates
+pub const SILENCE_DURATION: Duration = Duration::from_millis(200);
+
+pub type Connection = (Sender<ChessUpdate>, Receiver<ChessRequest>);
+
+/// One step of a script for `ScriptedPlayer::run()`
+pub enum Step {
+    Send(ChessRequest),
+    /// The next update has to be exactly this
+    Expect(ChessUpdate),
+    /// The next update has to fulfill the predicate (described by the str)
+    ExpectMatching(&'static str, fn(&ChessUpdate) -> bool),
+    /// Ignore updates until one fulfills the predicate
+    SkipUntil(&'static str, fn(&ChessUpdate) -> bool),
+    ExpectSilence,
+}
+
+pub struct ScriptedPlayer {
+    pub name: &'static str,
+    requests: Option<Sender<ChessRequest>>,
+    updates: Receiver<ChessUpdate>,
+    pub timeout: Duration,
+}
+
+impl ScriptedPlayer {
+    /// Returns the player and the connection to hand to the game.
+    pub fn new(name: &'static str) -> (Self, Connection) {
+        let (update_tx, update_rx) = channel::<ChessUpdate>(256);
+        let (request_tx, request_rx) = channel::<ChessRequest>(256);
+        let player = Self {
+            name,
+            requests: Some(request_tx),
+            updates: update_rx,
+            timeout: DEFAULT_TIMEOUT,
+        };
+        (player, (update_tx, request_rx))
+    }
+
+    pub async fn send(&self, request: ChessRequest) {
+        let requests = self.requests.as_ref().unwrap_or_else(|| {
+            panic!("{} is disconnected and can't send {:?}", self.name, request)
+        });
+        if requests.send(request.clone()).await.is_err() {
+            panic!(
+                "{} failed to send {:?}. The game is gone.",
+                self.name, request
+            );
+        }
+    }
+
+    /// Drop the sending side. The game sees this as a lost connection.
+    pub fn disconnect(&mut self) {
+        self.requests = None;
+    }
+
+    pub async fn next(&mut self) -> ChessUpdate {
+        match timeout(self.timeout, self.updates.recv()).await {
+            Ok(Some(update)) => update,
+            Ok(None) => panic!(
+                "{} expected an update but the game closed the channel",
+                self.name
+            ),
+            Err(_) => panic!(
+                "{} didn't receive any update within {:?}",
+                self.name, self.timeout
+            ),
+        }
+    }
+
+    pub async fn expect(&mut self, expected: ChessUpdate) {
+        let actual = self.next().await;
+        assert_eq!(
+            actual, expected,
+            "{} received an unexpected update",
+            self.name
+        );
+    }
+
+    pub async fn expect_matching(
+        &mut self,
+        what: &str,
+        predicate: impl Fn(&ChessUpdate) -> bool,
+    ) -> ChessUpdate {
+        let actual = self.next().await;
+        if !predicate(&actual) {
+            panic!("{} expected {} but received {:?}", self.name, what, actual);
+        }
+        actual
+    }
+
+    pub async fn skip_until(
+        &mut self,
+        what: &str,
+        predicate: impl Fn(&ChessUpdate) -> bool,
+    ) -> ChessUpdate {
+        loop {
+            match timeout(self.timeout, self.updates.recv()).await {
+                Ok(Some(update)) if predicate(&update) => return update,
+                Ok(Some(_)) => {}
+                Ok(None) => panic!(
+                    "{} waited for {} but the game closed the channel",
+                    self.name, what
+                ),
+                Err(_) => panic!(
+                    "{} didn't receive {} within {:?}",
+                    self.name, what, self.timeout
+                ),
+            }
+        }
+    }
+
+    /// Assert that no update arrives for a short while
+    pub async fn expect_silence(&mut self) {
+        if let Ok(Some(update)) = timeout(SILENCE_DURATION, self.updates.recv()).await {
+            panic!("{} expected no update but received {:?}", self.name, update);
+        }
+    }
+
+    /// Assert that the game dropped this channel (after skipping leftover updates)
+    pub async fn expect_closed(&mut self) {
+        loop {
+            match timeout(self.timeout, self.updates.recv()).await {
+                Ok(Some(_)) => {}
+                Ok(None) => return,
+                Err(_) => panic!("{} is still connected after {:?}", self.name, self.timeout),
+            }
+        }
+    }
+
+    /// Play all steps in order. Returns the player so the test can continue with it.
+    pub async fn run(mut self, script: Vec<Step>) -> Self {
+        for step in script {
+            match step {
+                Step::Send(request) => self.send(request).await,
+                Step::Expect(update) => self.expect(update).await,
+                Step::ExpectMatching(what, predicate) => {
+                    self.expect_matching(what, predicate).await;
+                }
+                Step::SkipUntil(what, predicate) => {
+                    self.skip_until(what, predicate).await;
+                }
+                Step::ExpectSilence => self.expect_silence().await,
+            }
+        }
+        self
+    }
+}
+
+/// A game where both players and the spectators are scripted
+pub struct TestGame {
+    pub white: ScriptedPlayer,
+    pub black: ScriptedPlayer,
+    pub spectators: ScriptedPlayer,
+    pub game: JoinHandle<Result<()>>,
+}
+
+impl TestGame {
+    pub fn start(config: ChessConfig) -> Self {
+        let (white, white_connection) = ScriptedPlayer::new("White");
+        let (black, black_connection) = ScriptedPlayer::new("Black");
+        let (spectators, spectators_connection) = ScriptedPlayer::new("Spectators");
+        let game = spawn_game(
+            white_connection,
+            black_connection,
+            spectators_connection,
+            config,
+        );
+        Self {
+            white,
+            black,
+            spectators,
+            game,
+        }
+    }
+}
+
+pub fn spawn_game(
+    white: Connection,
+    black: Connection,
+    spectators: Connection,
+    config: ChessConfig,
+) -> JoinHandle<Result<()>> {
+    tokio::spawn(create_game(white, black, spectators, config))
+}
+
+/// Wait for the game task to end and return its result
+pub async fn game_result(game: JoinHandle<Result<()>>) -> Result<()> {
+    match timeout(DEFAULT_TIMEOUT, game).await {
+        Ok(result) => result.expect("Game task panicked"),
+        Err(_) => panic!("The game didn't end within {:?}", DEFAULT_TIMEOUT),
+    }
+}
+
+/// Config allowing everything, to be adjusted by the test
+pub fn config() -> ChessConfig {
+    ChessConfig {
+        starting_fen: None,
+        can_black_undo: true,
+        can_white_undo: true,
+        allow_undo_after_loose: true,
+        event_log: None,
+        tablebase: None,
+        hints: Some(SearchLimits::depth(2).into()),
+        hints_taken: [0, 0],
+    }
+}
+
+/// Directory with the small Syzygy tables of `testdata/syzygy/download.sh`
+/// (or `SYZYGY_PATH`). The tests using them are ignored by default.
+pub fn syzygy_tables() -> PathBuf {
+    let directory = std::env::var_os("SYZYGY_PATH")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/syzygy"));
+    if let Err(e) = Tablebase::open(&directory) {
+        panic!("Run testdata/syzygy/download.sh first: {:#}", e);
+    }
+    directory
+}
+
+/// A shell script posing as an engine. It gets the file to log what it
+/// receives to as first argument and the moves to answer with as the others
+/// (see `args()`).
+pub struct FakeEngine {
+    dir: TempDir,
+}
+
+impl FakeEngine {
+    pub fn new(script: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("engine.sh");
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        Self { dir }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.path().join("engine.sh")
+    }
+
+    /// The arguments for the script to answer with `moves`
+    pub fn args(&self, moves: &[&str]) -> Vec<String> {
+        let log_path = self.log_path().display().to_string();
+        std::iter::once(log_path)
+            .chain(moves.iter().map(|engine_move| engine_move.to_string()))
+            .collect()
+    }
+
+    fn log_path(&self) -> PathBuf {
+        self.dir.path().join("received.log")
+    }
+
+    /// Wait until the engine received `line`
+    pub async fn wait_for(&self, line: &str) {
+        let received = async {
+            while !self.received().iter().any(|received| received == line) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        timeout(DEFAULT_TIMEOUT, received)
+            .await
+            .unwrap_or_else(|_| panic!("The engine didn't receive {:?}", line));
+    }
+
+    /// Wait for the bot to end the engine
+    pub async fn wait_for_quit(&self) {
+        self.wait_for("quit").await;
+    }
+
+    /// The lines the engine received so far
+    pub fn received(&self) -> Vec<String> {
+        fs::read_to_string(self.log_path())
+            .unwrap_or_default()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Shorthand for a `ChessRequest::MovePiece` (e.g. `mv("E2", "E4")`)
+pub fn mv(source: &str, destination: &str) -> ChessRequest {
+    ChessRequest::MovePiece {
+        source: source.parse().expect("Invalid source square"),
+        destination: destination.parse().expect("Invalid destination square"),
+        promotion: None,
+    }
+}
+
+/// Shorthand for a `ChessUpdate::PlayerMovedAPiece` without promotion
+pub fn moved(player: Player, source: &str, destination: &str) -> ChessUpdate {
+    ChessUpdate::PlayerMovedAPiece {
+        player,
+        moved_piece_source: source.parse().expect("Invalid source square"),
+        moved_piece_destination: destination.parse().expect("Invalid destination square"),
+        moved_piece_promotion: None,
+    }
+}
+
+pub fn switch(player: Player, fen: &str) -> ChessUpdate {
+    ChessUpdate::PlayerSwitch {
+        player,
+        fen: fen.to_owned(),
+    }
+}
+
+/// The next request of a bot that isn't a progress report
+pub async fn next_bot_move(requests: &mut Receiver<ChessRequest>) -> ChessRequest {
+    loop {
+        let request = timeout(DEFAULT_TIMEOUT, requests.recv())
+            .await
+            .expect("No request in time")
+            .expect("Bot has ended");
+        if !matches!(request, ChessRequest::ReportThinking { .. }) {
+            return request;
+        }
+    }
+}
+
+/// Play `uci_move` in `game` and send it to a bot like the game does
+pub async fn send_move(updates: &Sender<ChessUpdate>, game: &mut ChessGame, uci_move: &str) {
+    let player = game.turn();
+    let (source, destination, promotion) = game.move_piece_uci(uci_move).unwrap();
+    let moved = ChessUpdate::PlayerMovedAPiece {
+        player,
+        moved_piece_source: source,
+        moved_piece_destination: destination,
+        moved_piece_promotion: promotion,
+    };
+    updates.send(moved).await.unwrap();
+    updates
+        .send(switch(game.turn(), &game.fen()))
+        .await
+        .unwrap();
+}
+
+pub fn is_possible_moves(update: &ChessUpdate) -> bool {
+    matches!(update, ChessUpdate::PossibleMoves { .. })
+}
+
+pub fn is_player_switch(update: &ChessUpdate) -> bool {
+    matches!(update, ChessUpdate::PlayerSwitch { .. })
+}
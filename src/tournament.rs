@@ -0,0 +1,761 @@
+//! Matches between two bots (e.g. to compare searchers or depths) without
+//! the tablet
+
+use crate::analysis::evaluate;
+use crate::game::{shakmaty_position, BitMove, Board, ChessGame, Promotion};
+use crate::pgn::{from_moves, set_tag};
+use crate::proto::{create_bot_with_algorithm, BotConfig, ChessRequest, ChessUpdate, Score};
+use crate::search::SearchAlgorithm;
+use crate::tablebase::Tablebase;
+use crate::uci::{create_uci_bot, UciEngineConfig};
+use crate::Player;
+use anyhow::{Context, Result};
+use chess_pgn_parser::{Game, GameTermination};
+use serde::{Deserialize, Serialize};
+use shakmaty::Position;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task;
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Two sided confidence of `Tally::error_margin()`
+const CONFIDENCE_Z: f64 = 1.959964;
+
+/// One side of a match
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Contender {
+    pub name: String,
+    #[serde(flatten)]
+    pub engine: Engine,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// One of pleco's searchers as played by `create_bot()`
+    Bot {
+        #[serde(default)]
+        algorithm: SearchAlgorithm,
+        #[serde(flatten)]
+        config: BotConfig,
+    },
+    /// External engine as played by `create_uci_bot()`
+    Uci(UciEngineConfig),
+}
+
+impl Contender {
+    async fn join(&self, me: Player) -> Result<(Sender<ChessUpdate>, Receiver<ChessRequest>)> {
+        let no_delay = Duration::from_millis(0);
+        match self.engine {
+            Engine::Bot {
+                algorithm,
+                ref config,
+            } => create_bot_with_algorithm(algorithm, me, config.clone(), no_delay).await,
+            Engine::Uci(ref config) => create_uci_bot(me, config.clone(), no_delay).await,
+        }
+        .with_context(|| format!("Start {}", self.name))
+    }
+}
+
+/// When the referee ends a game early. Scores are in centipawns and
+/// checked by its own search after every move.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Adjudication {
+    /// Draw after this many moves (of both players)
+    #[serde(default)]
+    pub max_moves: Option<u16>,
+    /// A player loses when the score is at least this much against them
+    #[serde(default)]
+    pub resign_score: Option<i32>,
+    /// Plies in a row the resign score has to hold
+    #[serde(default = "default_resign_plies")]
+    pub resign_plies: u16,
+    /// Draw when the score stays within this much of equal
+    #[serde(default)]
+    pub draw_score: Option<i32>,
+    /// Plies in a row the draw score has to hold
+    #[serde(default = "default_draw_plies")]
+    pub draw_plies: u16,
+    /// No draw by score before this move
+    #[serde(default = "default_draw_from_move")]
+    pub draw_from_move: u16,
+    /// Depth of the referee's search
+    #[serde(default = "default_eval_depth")]
+    pub eval_depth: u16,
+    /// Directory with Syzygy tables. Games end as soon as they know the
+    /// result.
+    #[serde(default)]
+    pub tablebase: Option<PathBuf>,
+}
+
+fn default_resign_plies() -> u16 {
+    4
+}
+
+fn default_draw_plies() -> u16 {
+    12
+}
+
+fn default_draw_from_move() -> u16 {
+    40
+}
+
+fn default_eval_depth() -> u16 {
+    3
+}
+
+impl Default for Adjudication {
+    fn default() -> Self {
+        Self {
+            max_moves: None,
+            resign_score: None,
+            resign_plies: default_resign_plies(),
+            draw_score: None,
+            draw_plies: default_draw_plies(),
+            draw_from_move: default_draw_from_move(),
+            eval_depth: default_eval_depth(),
+            tablebase: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchConfig {
+    pub first: Contender,
+    pub second: Contender,
+    /// Games in total. The contenders take turns playing White.
+    #[serde(default = "default_games")]
+    pub games: u32,
+    /// Positions (FEN) the games start from. Each is played with both
+    /// colours before the next one. The usual starting position if empty.
+    #[serde(default)]
+    pub openings: Vec<String>,
+    #[serde(default)]
+    pub adjudication: Adjudication,
+}
+
+fn default_games() -> u32 {
+    2
+}
+
+/// How a game ended
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ending {
+    /// `None` for a draw
+    pub winner: Option<Player>,
+    /// Value of the PGN tag "Termination" (e.g. "normal" or "adjudication")
+    pub termination: &'static str,
+    /// E.g. "checkmate" or "threefold repetition"
+    pub reason: String,
+}
+
+impl Ending {
+    fn new(winner: Option<Player>, termination: &'static str, reason: impl Into<String>) -> Self {
+        Self {
+            winner,
+            termination,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn result(&self) -> GameTermination {
+        match self.winner {
+            Some(Player::White) => GameTermination::WhiteWins,
+            Some(Player::Black) => GameTermination::BlackWins,
+            None => GameTermination::DrawnGame,
+        }
+    }
+}
+
+pub struct PlayedGame {
+    /// Starting at 1
+    pub round: u32,
+    pub first_is_white: bool,
+    pub ending: Ending,
+    pub pgn: Game,
+}
+
+impl PlayedGame {
+    /// Winner of the game (by contender instead of colour)
+    pub fn first_won(&self) -> Option<bool> {
+        self.ending
+            .winner
+            .map(|winner| (winner == Player::White) == self.first_is_white)
+    }
+}
+
+/// Results from the first contender's view
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tally {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Tally {
+    pub fn add(&mut self, game: &PlayedGame) {
+        match game.first_won() {
+            Some(true) => self.wins += 1,
+            Some(false) => self.losses += 1,
+            None => self.draws += 1,
+        }
+    }
+
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    /// Points per game (a draw is half a point)
+    pub fn score(&self) -> f64 {
+        if self.games() == 0 {
+            return 0.5;
+        }
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    /// Elo difference of the first contender to the second one that
+    /// explains the score (infinite after winning or losing every game)
+    pub fn elo_difference(&self) -> f64 {
+        elo_difference(self.score())
+    }
+
+    /// Half the width of the 95% confidence interval of the elo
+    /// difference. `None` while it can't be told (e.g. all games won).
+    pub fn error_margin(&self) -> Option<f64> {
+        let games = self.games() as f64;
+        let score = self.score();
+        let deviation = |share: u32, points: f64| share as f64 / games * (points - score).powi(2);
+        let variance =
+            deviation(self.wins, 1.0) + deviation(self.losses, 0.0) + deviation(self.draws, 0.5);
+        let standard_error = (variance / games).sqrt();
+        let lowest = score - CONFIDENCE_Z * standard_error;
+        let highest = score + CONFIDENCE_Z * standard_error;
+        if games == 0.0 || lowest <= 0.0 || highest >= 1.0 {
+            return None;
+        }
+        Some((elo_difference(highest) - elo_difference(lowest)) / 2.0)
+    }
+}
+
+fn elo_difference(score: f64) -> f64 {
+    400.0 * (score / (1.0 - score)).log10()
+}
+
+/// Table of the results with the elo difference
+pub struct Standings<'a> {
+    pub config: &'a MatchConfig,
+    pub tally: Tally,
+}
+
+impl fmt::Display for Standings<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tally = self.tally;
+        let width = self
+            .config
+            .first
+            .name
+            .len()
+            .max(self.config.second.name.len())
+            .max(4);
+        writeln!(
+            f,
+            "{:<width$}  {:>5}  {:>5}  {:>6}  {:>5}  {:>6}",
+            "Name",
+            "Games",
+            "Wins",
+            "Losses",
+            "Draws",
+            "Score",
+            width = width
+        )?;
+        let rows = [
+            (&self.config.first.name, tally.wins, tally.losses),
+            (&self.config.second.name, tally.losses, tally.wins),
+        ];
+        for (name, wins, losses) in rows.iter() {
+            let score = if tally.games() == 0 {
+                0.0
+            } else {
+                (*wins as f64 + tally.draws as f64 / 2.0) / tally.games() as f64
+            };
+            writeln!(
+                f,
+                "{:<width$}  {:>5}  {:>5}  {:>6}  {:>5}  {:>5.1}%",
+                name,
+                tally.games(),
+                wins,
+                losses,
+                tally.draws,
+                score * 100.0,
+                width = width
+            )?;
+        }
+        write!(f, "Elo difference: {:+.1} +/- ", tally.elo_difference())?;
+        match tally.error_margin() {
+            Some(margin) => write!(f, "{:.1}", margin),
+            None => write!(f, "?"),
+        }
+    }
+}
+
+/// Starting positions from lines of FEN or EPD (which has no clocks).
+/// Empty lines and lines starting with '#' are skipped.
+pub fn read_openings(text: &str) -> Result<Vec<String>> {
+    let mut openings = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        ensure!(fields.len() >= 4, "Line {} is no FEN or EPD", index + 1);
+        let has_clocks = fields.len() >= 6
+            && fields[4].parse::<u16>().is_ok()
+            && fields[5].parse::<u16>().is_ok();
+        let fen = if has_clocks {
+            fields[..6].join(" ")
+        } else {
+            format!("{} 0 1", fields[..4].join(" "))
+        };
+        Board::from_fen(&fen)
+            .map_err(|e| anyhow!("Line {} is no valid position: {:?}", index + 1, e))?;
+        openings.push(fen);
+    }
+    Ok(openings)
+}
+
+/// Plays all games of the match one after another. `on_game` gets each
+/// game once it is over.
+pub async fn play_match(
+    config: &MatchConfig,
+    mut on_game: impl FnMut(&PlayedGame),
+) -> Result<Tally> {
+    let tablebase = match config.adjudication.tablebase {
+        Some(ref directory) => Some(Tablebase::open(directory)?),
+        None => None,
+    };
+    let openings = if config.openings.is_empty() {
+        vec![START_FEN.to_owned()]
+    } else {
+        config.openings.clone()
+    };
+    let mut tally = Tally::default();
+    for index in 0..config.games {
+        let opening = &openings[(index as usize / 2) % openings.len()];
+        let first_is_white = index % 2 == 0;
+        let (white, black) = if first_is_white {
+            (&config.first, &config.second)
+        } else {
+            (&config.second, &config.first)
+        };
+        let (moves, ending) = play_game(
+            white,
+            black,
+            opening,
+            &config.adjudication,
+            tablebase.as_ref(),
+        )
+        .await?;
+
+        let starting_fen = if opening == START_FEN {
+            None
+        } else {
+            Some(opening.as_str())
+        };
+        let mut pgn = from_moves(starting_fen, &moves, ending.result())?;
+        let round = index + 1;
+        set_tag(&mut pgn, "Event", "chessmarkable match");
+        set_tag(&mut pgn, "Round", &round.to_string());
+        set_tag(&mut pgn, "White", &white.name);
+        set_tag(&mut pgn, "Black", &black.name);
+        set_tag(&mut pgn, "Termination", ending.termination);
+        match pgn.moves.last_mut() {
+            Some(last) => last.comment = Some(ending.reason.clone()),
+            None => pgn.comment = Some(ending.reason.clone()),
+        }
+
+        let played = PlayedGame {
+            round,
+            first_is_white,
+            ending,
+            pgn,
+        };
+        tally.add(&played);
+        on_game(&played);
+    }
+    Ok(tally)
+}
+
+/// Referees a game between the contenders like `create_game()` but
+/// without undo or hints. Returns the moves and how it ended.
+pub async fn play_game(
+    white: &Contender,
+    black: &Contender,
+    opening: &str,
+    adjudication: &Adjudication,
+    tablebase: Option<&Tablebase>,
+) -> Result<(Vec<BitMove>, Ending)> {
+    let mut game = ChessGame::from_fen(opening)?;
+    let mut moves = vec![];
+    let mut referee = Referee::new(adjudication, tablebase);
+    if let Some(ending) = referee.judge(&game, 0).await {
+        return Ok((moves, ending));
+    }
+    let (white_tx, mut white_rx) = white.join(Player::White).await?;
+    let (black_tx, mut black_rx) = black.join(Player::Black).await?;
+
+    macro_rules! send_to_both {
+        ($msg: expr) => {
+            let msg: ChessUpdate = $msg;
+            white_tx.send(msg.clone()).await.ok();
+            black_tx.send(msg).await.ok();
+        };
+    }
+
+    send_to_both!(ChessUpdate::PlayerSwitch {
+        player: game.turn(),
+        fen: game.fen(),
+    });
+    let ending = loop {
+        let mover = game.turn();
+        let request = match mover {
+            Player::White => white_rx.recv().await,
+            Player::Black => black_rx.recv().await,
+        };
+        let (source, destination, promotion) = match request {
+            Some(ChessRequest::MovePiece {
+                source,
+                destination,
+                promotion,
+            }) => (source, destination, promotion),
+            Some(ChessRequest::Abort { message }) => {
+                break Ending::new(Some(mover.other_player()), "abandoned", message);
+            }
+            Some(_) => continue,
+            None => {
+                let reason = format!("{} stopped playing", mover);
+                break Ending::new(Some(mover.other_player()), "abandoned", reason);
+            }
+        };
+        let played = game
+            .find_move(source, destination, promotion)
+            .ok_or_else(|| anyhow!("Move not found as possibility"))
+            .and_then(|bit_move| {
+                game.move_piece(source, destination, promotion)
+                    .map(|_| bit_move)
+            });
+        // The promotion actually played (if the bot didn't name one)
+        let promotion = match played {
+            Ok(bit_move) => {
+                moves.push(bit_move);
+                Promotion::of(bit_move)
+            }
+            Err(e) => {
+                let reason = format!("Illegal move by {}: {}", mover, e);
+                break Ending::new(Some(mover.other_player()), "rules infraction", reason);
+            }
+        };
+        send_to_both!(ChessUpdate::PlayerMovedAPiece {
+            player: mover,
+            moved_piece_source: source,
+            moved_piece_destination: destination,
+            moved_piece_promotion: promotion,
+        });
+        if let Some(ending) = referee.judge(&game, moves.len()).await {
+            break ending;
+        }
+        send_to_both!(ChessUpdate::PlayerSwitch {
+            player: game.turn(),
+            fen: game.fen(),
+        });
+    };
+    // Dropping the channels ends the bots
+    Ok((moves, ending))
+}
+
+/// Keeps track of what's needed to end a game
+struct Referee<'a> {
+    adjudication: &'a Adjudication,
+    tablebase: Option<&'a Tablebase>,
+    /// How often each position occurred (pieces, side to move, castling
+    /// rights and en passant square of the FEN)
+    positions: HashMap<String, u8>,
+    /// Plies in a row the score was beyond the resign score for a winner
+    resign_streak: (Option<Player>, u16),
+    /// Plies in a row the score was within the draw score
+    draw_streak: u16,
+}
+
+impl<'a> Referee<'a> {
+    fn new(adjudication: &'a Adjudication, tablebase: Option<&'a Tablebase>) -> Self {
+        Self {
+            adjudication,
+            tablebase,
+            positions: HashMap::new(),
+            resign_streak: (None, 0),
+            draw_streak: 0,
+        }
+    }
+
+    /// Judges the position after `plies` moves of this game
+    async fn judge(&mut self, game: &ChessGame, plies: usize) -> Option<Ending> {
+        let board = game.board();
+        if board.checkmate() {
+            let winner = game.turn().other_player();
+            return Some(Ending::new(Some(winner), "normal", "Checkmate"));
+        }
+        if board.stalemate() {
+            return Some(Ending::new(None, "normal", "Stalemate"));
+        }
+        let fen = game.fen();
+        let position = fen.split_whitespace().take(4).collect::<Vec<_>>().join(" ");
+        let occurrences = self.positions.entry(position).or_insert(0);
+        *occurrences += 1;
+        if *occurrences >= 3 {
+            return Some(Ending::new(None, "normal", "Threefold repetition"));
+        }
+        if board.rule_50() >= 100 {
+            return Some(Ending::new(None, "normal", "Fifty-move rule"));
+        }
+        if shakmaty_position(&board)
+            .map(|position| position.is_insufficient_material())
+            .unwrap_or(false)
+        {
+            return Some(Ending::new(None, "normal", "Insufficient material"));
+        }
+
+        if let Some(verdict) = self
+            .tablebase
+            .and_then(|tablebase| tablebase.verdict(&board))
+        {
+            let reason = format!("Tablebase: {}", verdict);
+            return Some(Ending::new(verdict.winner(), "adjudication", reason));
+        }
+        if let Some(max_moves) = self.adjudication.max_moves {
+            if plies >= max_moves as usize * 2 {
+                return Some(Ending::new(None, "adjudication", "Move limit"));
+            }
+        }
+        if self.adjudication.resign_score.is_none() && self.adjudication.draw_score.is_none() {
+            return None;
+        }
+        let depth = self.adjudication.eval_depth;
+        let scoring_board = board.shallow_clone();
+        let score = task::spawn_blocking(move || evaluate(&scoring_board, depth))
+            .await
+            .ok()
+            .flatten()
+            .map(|(score, _line)| score)?;
+        let centipawns = match score {
+            Score::Centipawns(centipawns) => centipawns,
+            Score::Mate(moves) if moves > 0 => i32::MAX,
+            Score::Mate(_) => -i32::MAX,
+        };
+
+        if let Some(resign_score) = self.adjudication.resign_score {
+            let ahead = if centipawns >= resign_score {
+                Some(Player::White)
+            } else if centipawns <= -resign_score {
+                Some(Player::Black)
+            } else {
+                None
+            };
+            self.resign_streak = match (ahead, self.resign_streak) {
+                (Some(ahead), (Some(before), plies)) if ahead == before => (Some(ahead), plies + 1),
+                (ahead, _) => (ahead, ahead.map(|_| 1).unwrap_or(0)),
+            };
+            if let (Some(winner), plies) = self.resign_streak {
+                if plies >= self.adjudication.resign_plies {
+                    let reason = format!("Score {} for {} plies", score, plies);
+                    return Some(Ending::new(Some(winner), "adjudication", reason));
+                }
+            }
+        }
+        if let Some(draw_score) = self.adjudication.draw_score {
+            let from_ply = (self.adjudication.draw_from_move as usize).saturating_sub(1) * 2;
+            if plies >= from_ply && centipawns.abs() <= draw_score {
+                self.draw_streak += 1;
+            } else {
+                self.draw_streak = 0;
+            }
+            if self.draw_streak >= self.adjudication.draw_plies {
+                let reason = format!("Score within {} for {} plies", draw_score, self.draw_streak);
+                return Some(Ending::new(None, "adjudication", reason));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::SearchLimits;
+
+    /// White mates with Qxf7#
+    const SCHOLARS_MATE_FEN: &str =
+        "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+
+    fn bot(name: &str, depth: u16) -> Contender {
+        Contender {
+            name: name.to_owned(),
+            engine: Engine::Bot {
+                algorithm: SearchAlgorithm::AlphaBeta,
+                config: SearchLimits::depth(depth).into(),
+            },
+        }
+    }
+
+    #[test]
+    fn reads_the_match_config() {
+        let config: MatchConfig = serde_yaml::from_str(
+            r#"
+first:
+  name: Quick
+  bot:
+    depth: 2
+second:
+  name: Fish
+  uci:
+    path: /usr/bin/stockfish
+    movetime: 100
+games: 10
+adjudication:
+  max_moves: 150
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.first, bot("Quick", 2));
+        match config.second.engine {
+            Engine::Uci(ref uci) => assert_eq!(uci.movetime, Some(100)),
+            ref engine => panic!("Unexpected {:?}", engine),
+        }
+        assert_eq!(config.games, 10);
+        assert_eq!(config.adjudication.max_moves, Some(150));
+        assert_eq!(config.adjudication.eval_depth, 3);
+    }
+
+    #[test]
+    fn reads_fen_and_epd() {
+        let openings = read_openings(
+            "# Openings\n\n\
+             rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1\n\
+             rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - id \"Open game\";\n",
+        )
+        .unwrap();
+        assert_eq!(
+            openings,
+            vec![
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+                "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 1",
+            ]
+        );
+        assert!(read_openings("not a position").is_err());
+    }
+
+    #[test]
+    fn estimates_the_elo_difference() {
+        let even = Tally {
+            wins: 10,
+            losses: 10,
+            draws: 0,
+        };
+        assert_eq!(even.elo_difference(), 0.0);
+        let better = Tally {
+            wins: 50,
+            losses: 30,
+            draws: 20,
+        };
+        assert!((better.elo_difference() - 70.4).abs() < 0.1);
+        let margin = better.error_margin().unwrap();
+        assert!((margin - 62.6).abs() < 0.1, "Margin {}", margin);
+        let perfect = Tally {
+            wins: 3,
+            losses: 0,
+            draws: 0,
+        };
+        assert!(perfect.elo_difference().is_infinite());
+        assert_eq!(perfect.error_margin(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn alternates_colours() {
+        let config = MatchConfig {
+            first: bot("First", 2),
+            second: bot("Second", 2),
+            games: 2,
+            openings: vec![SCHOLARS_MATE_FEN.to_owned()],
+            adjudication: Adjudication::default(),
+        };
+        let mut games = vec![];
+        let tally = play_match(&config, |game| games.push(crate::pgn::write(&game.pgn)))
+            .await
+            .unwrap();
+        // Whoever has White mates at once
+        assert_eq!(
+            tally,
+            Tally {
+                wins: 1,
+                losses: 1,
+                draws: 0
+            }
+        );
+        assert!(games[0].contains("[White \"First\"]"), "{}", games[0]);
+        assert!(games[1].contains("[White \"Second\"]"), "{}", games[1]);
+        assert!(
+            games[1].contains("4. Qxf7# {Checkmate} 1-0"),
+            "{}",
+            games[1]
+        );
+
+        let standings = Standings {
+            config: &config,
+            tally,
+        }
+        .to_string();
+        assert!(standings.contains("\nFirst       2      1       1      0   50.0%\n"));
+        assert!(
+            standings.ends_with("Elo difference: +0.0 +/- ?"),
+            "{}",
+            standings
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn adjudicates_long_games() {
+        let adjudication = Adjudication {
+            max_moves: Some(2),
+            ..Adjudication::default()
+        };
+        let (moves, ending) = play_game(
+            &bot("White", 1),
+            &bot("Black", 1),
+            START_FEN,
+            &adjudication,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(moves.len(), 4);
+        assert_eq!(ending, Ending::new(None, "adjudication", "Move limit"));
+
+        let adjudication = Adjudication {
+            resign_score: Some(500),
+            resign_plies: 2,
+            ..Adjudication::default()
+        };
+        // Black has no queen
+        let fen = "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let (moves, ending) =
+            play_game(&bot("White", 1), &bot("Black", 1), fen, &adjudication, None)
+                .await
+                .unwrap();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(ending.winner, Some(Player::White));
+        assert_eq!(ending.termination, "adjudication");
+    }
+}
@@ -0,0 +1,782 @@
+//! Use an external engine speaking the
+//! [Universal Chess Interface](https://backscattering.de/chess/uci/)
+//! (e.g. Stockfish) as bot opponent.
+
+use crate::engine_process::{EngineProcess, Position, Search, START_FEN};
+use crate::game::{san_line, ChessGame, ChessOutcome, Promotion};
+use crate::proto::{BotThinking, ChessRequest, ChessUpdate, Score, MAX_REJECTED_MOVES};
+use crate::Player;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task;
+use tokio::time::timeout;
+
+/// How long an engine may take to answer "uci" and "isready"
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Thinking time per move if neither `depth` nor `movetime` is configured
+pub const DEFAULT_MOVETIME: u64 = 1000;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UciOptionValue {
+    Bool(bool),
+    Number(i64),
+    Text(String),
+}
+
+impl fmt::Display for UciOptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UciOptionValue::Bool(value) => write!(f, "{}", value),
+            UciOptionValue::Number(value) => write!(f, "{}", value),
+            UciOptionValue::Text(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UciEngineConfig {
+    /// Executable of the engine
+    pub path: PathBuf,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Sent with "setoption" (e.g. "Skill Level", "UCI_Elo", "Threads" or "Hash")
+    #[serde(default)]
+    pub options: BTreeMap<String, UciOptionValue>,
+    /// Search depth per move
+    #[serde(default)]
+    pub depth: Option<u16>,
+    /// Thinking time per move in milliseconds
+    #[serde(default)]
+    pub movetime: Option<u64>,
+}
+
+impl UciEngineConfig {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            args: vec![],
+            options: BTreeMap::new(),
+            depth: None,
+            movetime: None,
+        }
+    }
+
+    fn go_command(&self) -> String {
+        match (self.depth, self.movetime) {
+            (Some(depth), Some(movetime)) => format!("go depth {} movetime {}", depth, movetime),
+            (Some(depth), None) => format!("go depth {}", depth),
+            (None, movetime) => format!("go movetime {}", movetime.unwrap_or(DEFAULT_MOVETIME)),
+        }
+    }
+}
+
+/// A running engine process
+struct UciEngine {
+    process: EngineProcess,
+}
+
+impl UciEngine {
+    async fn start(config: &UciEngineConfig) -> Result<Self> {
+        let mut engine = Self {
+            process: EngineProcess::spawn(&config.path, &config.args).await?,
+        };
+
+        engine.send("uci").await?;
+        let name = timeout(
+            HANDSHAKE_TIMEOUT,
+            engine.read_until(|line| line.starts_with("id name ") || line == "uciok"),
+        )
+        .await
+        .context("Engine didn't answer \"uci\"")??;
+        if let Some(name) = name.strip_prefix("id name ") {
+            engine.process.name = name.to_owned();
+            timeout(HANDSHAKE_TIMEOUT, engine.read_until(|line| line == "uciok"))
+                .await
+                .context("Engine didn't send \"uciok\"")??;
+        }
+
+        for (name, value) in &config.options {
+            engine
+                .send(&format!("setoption name {} value {}", name, value))
+                .await?;
+        }
+        engine.send("ucinewgame").await?;
+        engine.ready().await?;
+        Ok(engine)
+    }
+
+    async fn send(&mut self, command: &str) -> Result<()> {
+        self.process.send(command).await
+    }
+
+    async fn read_until(&mut self, predicate: impl Fn(&str) -> bool) -> Result<String> {
+        self.process.read_until(predicate).await
+    }
+
+    async fn ready(&mut self) -> Result<()> {
+        self.send("isready").await?;
+        timeout(HANDSHAKE_TIMEOUT, self.read_until(|line| line == "readyok"))
+            .await
+            .context("Engine didn't answer \"isready\"")??;
+        Ok(())
+    }
+
+    /// `None` if the engine found no move. The "info" lines while it
+    /// searches are passed to `on_info`. Once `stop` fires (or its sender
+    /// is dropped) the engine gets "stop" and answers with what it found.
+    async fn best_move(
+        &mut self,
+        position: &str,
+        go: &str,
+        stop: oneshot::Receiver<()>,
+        on_info: impl Fn(&str),
+    ) -> Result<Option<String>> {
+        self.send(position).await?;
+        self.send(go).await?;
+        let is_best_move = |line: &str| {
+            if line.starts_with("info ") {
+                on_info(line);
+            }
+            line.starts_with("bestmove")
+        };
+        let answered = tokio::select! {
+            line = self.read_until(&is_best_move) => Some(line),
+            _ = stop => None,
+        };
+        let line = match answered {
+            Some(line) => line?,
+            None => {
+                self.send("stop").await?;
+                self.read_until(&is_best_move).await?
+            }
+        };
+        Ok(line
+            .split_whitespace()
+            .nth(1)
+            .filter(|best_move| *best_move != "(none)" && *best_move != "0000")
+            .map(String::from))
+    }
+
+    async fn quit(self) {
+        self.process.quit("quit").await;
+    }
+}
+
+/// Let `engine` search `current` and report progress as long as
+/// `positions` stays at `position`
+fn start_search(
+    mut engine: UciEngine,
+    current: &Position,
+    go: String,
+    position: u64,
+    positions: Arc<AtomicU64>,
+    request_tx: Sender<ChessRequest>,
+    min_reaction_delay: Duration,
+) -> Search<UciEngine, Option<String>> {
+    let command = current.command();
+    let game = current.game.clone();
+    Search::spawn(position, |stop| async move {
+        let still_wanted = || positions.load(Ordering::SeqCst) == position;
+        let report = |line: &str| {
+            if let Some(thinking) = parse_info(line, &game).filter(|_| still_wanted()) {
+                // Dropped rather than holding up the engine
+                request_tx
+                    .try_send(ChessRequest::ReportThinking { thinking })
+                    .ok();
+            }
+        };
+        let started = SystemTime::now();
+        let best_move = engine.best_move(&command, &go, stop, report).await;
+        let elapsed = started.elapsed().unwrap_or(Duration::new(0, 0));
+        if elapsed < min_reaction_delay && still_wanted() {
+            tokio::time::sleep(min_reaction_delay - elapsed).await;
+        }
+        (engine, best_move)
+    })
+}
+
+impl Position {
+    /// The position as the engine gets it (e.g. "position startpos moves e2e4")
+    fn command(&self) -> String {
+        let mut command = if self.fen == START_FEN {
+            "position startpos".to_owned()
+        } else {
+            format!("position fen {}", self.fen)
+        };
+        if !self.moves.is_empty() {
+            command.push_str(" moves ");
+            command.push_str(&self.moves.join(" "));
+        }
+        command
+    }
+}
+
+/// The progress in an "info" line of a search in `game`. `None` for lines
+/// without depth or principal variation (e.g. only the current move).
+fn parse_info(line: &str, game: &ChessGame) -> Option<BotThinking> {
+    let mut words = line.split_whitespace().skip(1);
+    let mut depth = None;
+    let mut nodes = None;
+    let mut nodes_per_second = None;
+    let mut score = None;
+    let mut best_move = None;
+    // The engine scores for the side to move
+    let sign = match game.turn() {
+        Player::White => 1,
+        Player::Black => -1,
+    };
+    while let Some(word) = words.next() {
+        match word {
+            "depth" => depth = words.next().and_then(|value| value.parse().ok()),
+            "nodes" => nodes = words.next().and_then(|value| value.parse().ok()),
+            "nps" => nodes_per_second = words.next().and_then(|value| value.parse().ok()),
+            "score" => {
+                let kind = words.next();
+                let value = words.next().and_then(|value| value.parse::<i32>().ok());
+                score = match (kind, value) {
+                    (Some("cp"), Some(centipawns)) => Some(Score::Centipawns(sign * centipawns)),
+                    (Some("mate"), Some(moves)) => Some(Score::Mate(sign * moves)),
+                    _ => None,
+                };
+            }
+            // Both take the rest of the line
+            "pv" => {
+                best_move = words
+                    .next()
+                    .and_then(|uci| game.find_uci_move(uci))
+                    .and_then(|bit_move| san_line(&game.board(), &[bit_move]).pop());
+                break;
+            }
+            "string" => return None,
+            _ => {}
+        }
+    }
+    Some(BotThinking {
+        depth: depth?,
+        nodes,
+        nodes_per_second,
+        best_move: Some(best_move?),
+        score,
+    })
+}
+
+/// Like `proto::create_bot()` but the moves come from an external UCI engine.
+/// Fails if the engine can't be started. Should the engine stop working
+/// later on, the bot aborts the game. The engine searches on its own task,
+/// so it gets "stop" once the position changes (e.g. by an undo) and its
+/// move is dropped. A rejected move is searched again (up to
+/// `MAX_REJECTED_MOVES` times) as long as the position stays.
+pub async fn create_uci_bot(
+    me: Player,
+    config: UciEngineConfig,
+    min_reaction_delay: Duration,
+) -> Result<(Sender<ChessUpdate>, Receiver<ChessRequest>)> {
+    let engine = UciEngine::start(&config).await?;
+    let (update_tx, mut update_rx) = channel::<ChessUpdate>(256);
+    let (request_tx, request_rx) = channel::<ChessRequest>(256);
+
+    task::spawn(async move {
+        let name = engine.process.name.clone();
+        info!("UCI bot ({}) spawned for {}", name, me);
+        // Taken by the running search
+        let mut engine = Some(engine);
+        let mut position: Option<Position> = None;
+        let mut current_outcome: Option<ChessOutcome> = None;
+        // Increased for every position to drop searches of old ones
+        let positions = Arc::new(AtomicU64::new(0));
+        let mut search: Option<Search<UciEngine, Option<String>>> = None;
+        // Position the bot has to move in and how often its move got rejected
+        let mut own_turn: Option<(String, u32)> = None;
+        // Started once the engine is back from the search of an old position
+        let mut search_wanted = false;
+
+        macro_rules! abort {
+            ($message: expr) => {
+                let message: String = $message;
+                error!("{}", message);
+                request_tx.send(ChessRequest::Abort { message }).await.ok();
+                break;
+            };
+        }
+
+        loop {
+            if search_wanted && search.is_none() {
+                search_wanted = false;
+                search = Some(start_search(
+                    engine.take().expect("Only the search takes the engine"),
+                    position.as_ref().expect("Synced on the own turn"),
+                    config.go_command(),
+                    positions.load(Ordering::SeqCst),
+                    positions.clone(),
+                    request_tx.clone(),
+                    min_reaction_delay,
+                ));
+            }
+            let searched = async {
+                match search {
+                    Some(ref mut search) => (&mut search.task).await,
+                    None => future::pending().await,
+                }
+            };
+            let update = tokio::select! {
+                update = update_rx.recv() => update,
+                searched = searched => {
+                    let searched_position = search.take().map(|search| search.position);
+                    let best_move = match searched {
+                        Ok((returned, best_move)) => {
+                            engine = Some(returned);
+                            best_move
+                        }
+                        Err(e) => {
+                            abort!(format!("Engine {} failed to search: {}", name, e));
+                        }
+                    };
+                    if searched_position != Some(positions.load(Ordering::SeqCst)) {
+                        info!("UCI bot dropped the search of an old position");
+                        continue;
+                    }
+                    let best_move = match best_move {
+                        Ok(Some(best_move)) => best_move,
+                        Ok(None) => {
+                            warn!("Engine found no move");
+                            continue;
+                        }
+                        Err(e) => {
+                            abort!(format!("Engine {} failed: {:#}", name, e));
+                        }
+                    };
+                    let bit_move = position
+                        .as_ref()
+                        .and_then(|current| current.game.find_uci_move(&best_move));
+                    let bit_move = match bit_move {
+                        Some(bit_move) => bit_move,
+                        None => {
+                            abort!(format!("Engine wants to play illegal move {}", best_move));
+                        }
+                    };
+                    if request_tx
+                        .send(ChessRequest::MovePiece {
+                            source: bit_move.get_src().into(),
+                            destination: bit_move.get_dest().into(),
+                            promotion: Promotion::of(bit_move),
+                        })
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let update = match update {
+                Some(update) => update,
+                None => break,
+            };
+            match update {
+                ChessUpdate::PlayerMovedAPiece {
+                    moved_piece_source,
+                    moved_piece_destination,
+                    moved_piece_promotion,
+                    ..
+                } => {
+                    if let Some(ref mut current) = position {
+                        let played = current.play(
+                            moved_piece_source,
+                            moved_piece_destination,
+                            moved_piece_promotion,
+                        );
+                        if let Err(e) = played {
+                            warn!("Engine position is out of sync: {:?}", e);
+                            position = None;
+                        }
+                    }
+                }
+                ChessUpdate::MovesUndone { moves, .. } => {
+                    if let Some(ref mut current) = position {
+                        if let Err(e) = current.undo(moves) {
+                            warn!("Engine position is out of sync: {:?}", e);
+                            position = None;
+                        }
+                    }
+                }
+                ChessUpdate::PlayerSwitch { player, ref fen } => {
+                    positions.fetch_add(1, Ordering::SeqCst);
+                    if let Some(ref mut search) = search {
+                        search.stop();
+                    }
+                    own_turn = None;
+                    search_wanted = false;
+                    // Start over from the board if the moves don't add up (or at the start)
+                    if position.as_ref().map(|current| current.game.fen()) != Some(fen.clone()) {
+                        position = match Position::new(fen) {
+                            Ok(position) => Some(position),
+                            Err(e) => {
+                                abort!(format!("Engine got an invalid board: {}", e));
+                            }
+                        };
+                    }
+                    if player == me && current_outcome.is_none() {
+                        own_turn = Some((fen.clone(), 0));
+                        search_wanted = true;
+                    }
+                }
+                ChessUpdate::MovePieceFailedResponse { message, fen } => {
+                    error!("A move from the engine was rejected: {}", message);
+                    // Still its turn in the same position. Otherwise it keeps
+                    // playing once it's its turn again.
+                    let rejected = match own_turn {
+                        Some((ref own_fen, ref mut rejected)) if *own_fen == fen => rejected,
+                        _ => continue,
+                    };
+                    *rejected += 1;
+                    if *rejected > MAX_REJECTED_MOVES
+                        || search.is_some()
+                        || current_outcome.is_some()
+                    {
+                        continue;
+                    }
+                    info!("UCI bot searches its position again");
+                    search_wanted = true;
+                }
+                ChessUpdate::Outcome { outcome } => {
+                    if outcome.is_some() {
+                        positions.fetch_add(1, Ordering::SeqCst);
+                        if let Some(ref mut search) = search {
+                            search.stop();
+                        }
+                        search_wanted = false;
+                    }
+                    current_outcome = outcome;
+                }
+                _ => {}
+            }
+        }
+        // Get the engine back from a running search to quit it
+        if let Some(search) = search.take() {
+            engine = search.finish().await;
+        }
+        if let Some(engine) = engine {
+            engine.quit().await;
+        }
+        info!("UCI bot task has ended");
+    });
+
+    Ok((update_tx, request_rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_harness::*;
+
+    /// Logs everything it receives to the file given as first argument
+    /// and answers each "go" with the next of the other arguments. Moves
+    /// starting with "~" are only answered on "stop". Crashes when running
+    /// out of moves.
+    const FAKE_ENGINE: &str = r#"#!/bin/sh
+log="$1"
+shift
+while read -r line; do
+    echo "$line" >> "$log"
+    case "$line" in
+        uci) echo "id name Fake Engine"; echo "option name Skill Level type spin default 20 min 0 max 20"; echo "uciok" ;;
+        isready) echo "readyok" ;;
+        go*)
+            [ $# -eq 0 ] && exit 1
+            case "$1" in
+                "~"*) thinking="${1#"~"}" ;;
+                *) echo "info depth 1 score cp 13 pv $1"; echo "bestmove $1" ;;
+            esac
+            shift ;;
+        stop) [ -n "$thinking" ] && echo "bestmove $thinking"; thinking="" ;;
+        quit) exit 0 ;;
+    esac
+done
+"#;
+
+    fn fake_engine_config(engine: &FakeEngine, best_moves: &[&str]) -> UciEngineConfig {
+        let mut config = UciEngineConfig::new(engine.path());
+        config.args = engine.args(best_moves);
+        config.movetime = Some(100);
+        config
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn engine_answers_moves_and_gets_options() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let mut engine_config = fake_engine_config(&engine, &["e7e5", "b8c6"]);
+        engine_config
+            .options
+            .insert("Skill Level".to_owned(), UciOptionValue::Number(3));
+        engine_config
+            .options
+            .insert("UCI_LimitStrength".to_owned(), UciOptionValue::Bool(true));
+        let bot = create_uci_bot(Player::Black, engine_config, Duration::from_millis(0))
+            .await
+            .unwrap();
+
+        let (white, white_connection) = ScriptedPlayer::new("White");
+        let (spectators, spectators_connection) = ScriptedPlayer::new("Spectators");
+        let game = spawn_game(white_connection, bot, spectators_connection, config());
+        let mut white = white
+            .run(vec![
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(mv("E2", "E4")),
+                Step::SkipUntil("the engine move", |update| {
+                    *update == moved(Player::Black, "E7", "E5")
+                }),
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(mv("G1", "F3")),
+                Step::SkipUntil("the second engine move", |update| {
+                    *update == moved(Player::Black, "B8", "C6")
+                }),
+            ])
+            .await;
+        white.disconnect();
+        game_result(game).await.unwrap();
+        drop(spectators);
+        engine.wait_for_quit().await;
+
+        assert_eq!(
+            engine.received(),
+            vec![
+                "uci",
+                "setoption name Skill Level value 3",
+                "setoption name UCI_LimitStrength value true",
+                "ucinewgame",
+                "isready",
+                "position startpos moves e2e4",
+                "go movetime 100",
+                "position startpos moves e2e4 e7e5 g1f3",
+                "go movetime 100",
+                "quit",
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn engine_castles_from_a_fen() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let mut engine_config = fake_engine_config(&engine, &["e1g1"]);
+        engine_config.depth = Some(5);
+        engine_config.movetime = None;
+        let bot = create_uci_bot(Player::White, engine_config, Duration::from_millis(0))
+            .await
+            .unwrap();
+
+        let fen = "r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1";
+        let (black, black_connection) = ScriptedPlayer::new("Black");
+        let (spectators, spectators_connection) = ScriptedPlayer::new("Spectators");
+        let mut game_config = config();
+        game_config.starting_fen = Some(fen.to_owned());
+        let game = spawn_game(bot, black_connection, spectators_connection, game_config);
+        // pleco moves the king onto the rook when castling
+        let black = black
+            .run(vec![Step::SkipUntil("castling", |update| {
+                *update == moved(Player::White, "E1", "H1")
+            })])
+            .await;
+        black
+            .send(ChessRequest::Abort {
+                message: "Done".to_owned(),
+            })
+            .await;
+        game_result(game).await.unwrap();
+        drop(spectators);
+        engine.wait_for_quit().await;
+
+        let received = engine.received();
+        assert!(received.contains(&format!("position fen {}", fen)));
+        assert!(received.contains(&"go depth 5".to_owned()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn engine_underpromotes() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let bot = create_uci_bot(
+            Player::White,
+            fake_engine_config(&engine, &["e7e8n", "e1d2"]),
+            Duration::from_millis(0),
+        )
+        .await
+        .unwrap();
+
+        let fen = "8/4P1k1/8/8/8/8/8/4K3 w - - 0 1";
+        let (black, black_connection) = ScriptedPlayer::new("Black");
+        let (spectators, spectators_connection) = ScriptedPlayer::new("Spectators");
+        let mut game_config = config();
+        game_config.starting_fen = Some(fen.to_owned());
+        let game = spawn_game(bot, black_connection, spectators_connection, game_config);
+        let black = black
+            .run(vec![
+                Step::SkipUntil("the knight promotion", |update| {
+                    *update
+                        == ChessUpdate::PlayerMovedAPiece {
+                            player: Player::White,
+                            moved_piece_source: "E7".parse().unwrap(),
+                            moved_piece_destination: "E8".parse().unwrap(),
+                            moved_piece_promotion: Some(Promotion::Knight),
+                        }
+                }),
+                Step::SkipUntil("a knight on the board", |update| {
+                    matches!(update, ChessUpdate::PlayerSwitch { fen, .. } if fen.starts_with("4N3/"))
+                }),
+                Step::Send(mv("G7", "G6")),
+                Step::SkipUntil("the next engine move", |update| {
+                    *update == moved(Player::White, "E1", "D2")
+                }),
+            ])
+            .await;
+        black
+            .send(ChessRequest::Abort {
+                message: "Done".to_owned(),
+            })
+            .await;
+        game_result(game).await.unwrap();
+        drop(spectators);
+        engine.wait_for_quit().await;
+
+        // The engine follows the game with the knight
+        let position = format!("position fen {} moves e7e8n g7g6", fen);
+        assert!(engine.received().contains(&position));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn undo_stops_the_engine_and_drops_its_move() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let engine_config = fake_engine_config(&engine, &["~e7e5", "d7d5"]);
+        let (updates, mut requests) =
+            create_uci_bot(Player::Black, engine_config, Duration::from_millis(0))
+                .await
+                .unwrap();
+
+        let mut game = ChessGame::default();
+        updates
+            .send(switch(Player::White, &game.fen()))
+            .await
+            .unwrap();
+        send_move(&updates, &mut game, "e2e4").await;
+        engine.wait_for("go movetime 100").await;
+
+        // Taken back while the engine thinks (in the order the game sends it)
+        game.undo(1).unwrap();
+        updates
+            .send(switch(Player::White, &game.fen()))
+            .await
+            .unwrap();
+        let undone = ChessUpdate::MovesUndone {
+            who: Player::White,
+            moves: 1,
+        };
+        updates.send(undone).await.unwrap();
+        engine.wait_for("stop").await;
+        send_move(&updates, &mut game, "d2d4").await;
+        assert_eq!(next_bot_move(&mut requests).await, mv("D7", "D5"));
+
+        drop(updates);
+        engine.wait_for_quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejected_engine_move_is_searched_again() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        let engine_config = fake_engine_config(&engine, &["e7e5", "c7c5"]);
+        let (updates, mut requests) =
+            create_uci_bot(Player::Black, engine_config, Duration::from_millis(0))
+                .await
+                .unwrap();
+
+        let mut game = ChessGame::default();
+        updates
+            .send(switch(Player::White, &game.fen()))
+            .await
+            .unwrap();
+        send_move(&updates, &mut game, "e2e4").await;
+        assert_eq!(next_bot_move(&mut requests).await, mv("E7", "E5"));
+        let rejected = ChessUpdate::MovePieceFailedResponse {
+            message: "Denied".to_owned(),
+            fen: game.fen(),
+        };
+        updates.send(rejected).await.unwrap();
+        assert_eq!(next_bot_move(&mut requests).await, mv("C7", "C5"));
+
+        drop(updates);
+        engine.wait_for_quit().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn engine_failing_aborts_the_game() {
+        let engine = FakeEngine::new(FAKE_ENGINE);
+        // Has no move to answer with and crashes instead
+        let bot = create_uci_bot(
+            Player::Black,
+            fake_engine_config(&engine, &[]),
+            Duration::from_millis(0),
+        )
+        .await
+        .unwrap();
+
+        let (white, white_connection) = ScriptedPlayer::new("White");
+        let (spectators, spectators_connection) = ScriptedPlayer::new("Spectators");
+        let game = spawn_game(white_connection, bot, spectators_connection, config());
+        let mut white = white
+            .run(vec![
+                Step::SkipUntil("possible moves", is_possible_moves),
+                Step::Send(mv("E2", "E4")),
+            ])
+            .await;
+        // The bot aborts which ends the game
+        white.expect_closed().await;
+        game_result(game).await.unwrap();
+        drop(spectators);
+    }
+
+    #[tokio::test]
+    async fn missing_engine_is_an_error() {
+        let engine_config = UciEngineConfig::new(PathBuf::from("/nonexistent/stockfish"));
+        assert!(
+            create_uci_bot(Player::Black, engine_config, Duration::from_millis(0))
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn info_lines_are_progress() {
+        let mut game = ChessGame::default();
+        game.move_piece_uci("e2e4").unwrap();
+        assert_eq!(
+            parse_info(
+                "info depth 12 seldepth 16 score cp 31 lowerbound nodes 52000 nps 104000 pv c7c5 g1f3",
+                &game
+            ),
+            Some(BotThinking {
+                depth: 12,
+                nodes: Some(52000),
+                nodes_per_second: Some(104000),
+                best_move: Some("c5".to_owned()),
+                // Black is better
+                score: Some(Score::Centipawns(-31)),
+            })
+        );
+        assert_eq!(
+            parse_info("info depth 3 score mate 2 pv g8f6", &game).map(|thinking| thinking.score),
+            Some(Some(Score::Mate(-2)))
+        );
+        assert_eq!(
+            parse_info("info depth 12 currmove e7e5 currmovenumber 3", &game),
+            None
+        );
+        assert_eq!(parse_info("info string depth 3 pv e7e5", &game), None);
+    }
+}
@@ -0,0 +1,8 @@
+#!/bin/sh -e
+# Small Syzygy tables used by the tablebase tests (about 1 MB)
+
+cd "$(dirname "$0")"
+for table in KBvK KQvK KRvK; do
+    wget -nc "https://tablebase.lichess.ovh/tables/standard/3-4-5-wdl/$table.rtbw"
+    wget -nc "https://tablebase.lichess.ovh/tables/standard/3-4-5-dtz/$table.rtbz"
+done